@@ -1,47 +1,277 @@
 use crate::error::ErrorReport;
-use std::{collections::HashMap, fs, path::Path};
+#[cfg(feature = "rsakeys")]
+use crate::rsakeys::{RsaKeys, SealedBox};
+#[cfg(feature = "rsakeys")]
+use openssl::{pkey::Public, rsa::Rsa};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
 
 pub const CONFIG_FILE_DELIMITER: char = '=';
+/// Reserved variable substituted with the application root path at load time
+pub const ROOT_VARIABLE: &str = "${ROOT}";
+
+mod lint;
+
+pub use lint::{edit_distance, ConfigSchema, LintFinding, LintSeverity, ValueKind};
 
 #[derive(Debug)]
 pub struct AppConfig {
     values: HashMap<String, String>,
+    root_path: Option<PathBuf>,
+    /// Key-value lines as written in the file, for `lint`
+    entries: Vec<RawEntry>,
+}
+
+#[derive(Debug)]
+struct RawEntry {
+    line: usize,
+    key: String,
+    value: String,
 }
 
 impl AppConfig {
     const COMMENT_START: char = '#';
 
     pub fn init(file_path: &Path, delimiter: char) -> Result<Self, ErrorReport> {
-        Ok(AppConfig {
-            values: fs::read_to_string(file_path)?
-                .lines()
-                .map(|line| line.trim())
+        Self::load(file_path, delimiter, None)
+    }
+
+    /// Same as `init`, but every `${ROOT}` occurrence in the values is replaced with `root_path`
+    /// and relative values returned by `get_path` are resolved against it
+    pub fn init_with_root(
+        file_path: &Path,
+        delimiter: char,
+        root_path: &Path,
+    ) -> Result<Self, ErrorReport> {
+        Self::load(file_path, delimiter, Some(root_path))
+    }
+
+    /// Loads a config file produced by `encrypt_config_file`
+    #[cfg(feature = "rsakeys")]
+    pub fn init_encrypted(
+        file_path: &Path,
+        keys: &RsaKeys,
+        delimiter: char,
+    ) -> Result<Self, ErrorReport> {
+        Self::load_encrypted(file_path, keys, delimiter, None)
+    }
+
+    /// Same as `init_encrypted`, with `${ROOT}` resolved as by `init_with_root`
+    #[cfg(feature = "rsakeys")]
+    pub fn init_encrypted_with_root(
+        file_path: &Path,
+        keys: &RsaKeys,
+        delimiter: char,
+        root_path: &Path,
+    ) -> Result<Self, ErrorReport> {
+        Self::load_encrypted(file_path, keys, delimiter, Some(root_path))
+    }
+
+    #[cfg(feature = "rsakeys")]
+    fn load_encrypted(
+        file_path: &Path,
+        keys: &RsaKeys,
+        delimiter: char,
+        root_path: Option<&Path>,
+    ) -> Result<Self, ErrorReport> {
+        let text = keys.open(&SealedBox::from_bytes(&fs::read(file_path)?)?)?;
+        Self::parse(&String::from_utf8(text)?, delimiter, root_path)
+    }
+
+    fn load(
+        file_path: &Path,
+        delimiter: char,
+        root_path: Option<&Path>,
+    ) -> Result<Self, ErrorReport> {
+        Self::parse(&fs::read_to_string(file_path)?, delimiter, root_path)
+    }
+
+    fn parse(text: &str, delimiter: char, root_path: Option<&Path>) -> Result<Self, ErrorReport> {
+        let mut values = HashMap::new();
+        let mut entries = Vec::new();
+        for (line, raw_line) in text.lines().enumerate() {
+            let (k, v) = match Some(raw_line.trim())
                 .filter(|line| !(line.is_empty() && line.starts_with(Self::COMMENT_START)))
-                .filter_map(|line| {
-                    if let Some((k, v)) = line.split_once(delimiter) {
-                        Some((k.to_owned(), v.to_owned()))
-                    } else {
-                        None
+                .and_then(|line| line.split_once(delimiter))
+            {
+                Some(val) => val,
+                None => continue,
+            };
+            entries.push(RawEntry {
+                line: line + 1,
+                key: k.to_owned(),
+                value: raw_line
+                    .split_once(delimiter)
+                    .map_or(v, |(_, val)| val)
+                    .to_owned(),
+            });
+
+            let value = if v.contains(ROOT_VARIABLE) {
+                match root_path {
+                    Some(root) => v.replace(ROOT_VARIABLE, &root.to_string_lossy()),
+                    None => {
+                        return Err(ErrorReport::ConfigRootMissing { key: k.to_owned() });
                     }
-                })
-                .collect(),
+                }
+            } else {
+                v.to_owned()
+            };
+            values.insert(k.to_owned(), value);
+        }
+
+        Ok(AppConfig {
+            values,
+            root_path: root_path.map(|val| val.to_path_buf()),
+            entries,
         })
     }
 
     pub fn get_var(&self, name: &str) -> Option<&String> {
         self.values.get(name)
     }
+
+    /// All the keys, in no particular order
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.values.keys()
+    }
+
+    /// Value as a path; relative values are joined to the root path, when one was provided
+    pub fn get_path(&self, name: &str) -> Option<PathBuf> {
+        self.values.get(name).map(|val| match &self.root_path {
+            Some(root) => root.join(val),
+            None => PathBuf::from(val),
+        })
+    }
+}
+
+/// Encrypts a whole config file for the holder of the matching private key, see
+/// `AppConfig::init_encrypted`
+#[cfg(feature = "rsakeys")]
+pub fn encrypt_config_file(
+    input: &Path,
+    output: &Path,
+    recipient_public: &Rsa<Public>,
+) -> Result<(), ErrorReport> {
+    let sealed = SealedBox::seal(recipient_public, &fs::read(input)?)?;
+    fs::write(output, sealed.to_bytes())?;
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::{AppConfig, CONFIG_FILE_DELIMITER};
+    use crate::error::ErrorReport;
     use std::path::Path;
 
     #[test]
     fn create() {
         let config_path = Path::new(env!("APP_CONFIG_FILE_PATH"));
         let res = AppConfig::init(config_path, CONFIG_FILE_DELIMITER).unwrap();
-        assert_eq!(res.values.is_empty(), false, "Env vars map is empty");
+        assert!(!res.values.is_empty(), "Env vars map is empty");
+    }
+
+    #[test]
+    fn root_interpolation() {
+        let root_dir = Path::new(env!("WORKSPACE_ROOT_PATH"));
+        let config_path = root_dir.join("assets/test/config_root.txt");
+        let res = AppConfig::init_with_root(&config_path, CONFIG_FILE_DELIMITER, root_dir).unwrap();
+
+        assert_eq!(
+            res.get_var("MAIL:TEMPLATE_DIR").unwrap(),
+            &format!("{}/assets/mail", root_dir.to_str().unwrap())
+        );
+        assert_eq!(
+            res.get_var("MAIL:SMTP_SERVER").unwrap(),
+            "smtp.example.com",
+            "Value without root variable was changed"
+        );
+        assert_eq!(
+            res.get_path("MAIL:TEMPLATE_DIR").unwrap(),
+            root_dir.join("assets/mail")
+        );
+        assert_eq!(
+            res.get_path("RSA:PRIV_KEY_PATH").unwrap(),
+            root_dir.join("keys/private.pem")
+        );
+    }
+
+    #[cfg(feature = "rsakeys")]
+    #[test]
+    fn encrypted_config() {
+        use super::encrypt_config_file;
+        use crate::rsakeys::RsaKeys;
+        use openssl::rsa::Rsa;
+        use std::fs;
+
+        let root_dir = Path::new(env!("WORKSPACE_ROOT_PATH"));
+        let temp_dir = Path::new(env!("TEMP_DIR_PATH"));
+        let keys = RsaKeys::init(
+            "test",
+            root_dir.join("assets/test/keys/private.pem"),
+            root_dir.join("assets/test/keys/public.pem"),
+        )
+        .unwrap();
+        let input = root_dir.join("assets/test/config_healthcheck.txt");
+        let output = temp_dir.join("config_encrypted.bin");
+
+        encrypt_config_file(&input, &output, keys.get_public_key()).unwrap();
+        let encrypted = fs::read(&output).unwrap();
+        assert!(
+            !String::from_utf8_lossy(&encrypted).contains("MAIL:SMTP_SERVER"),
+            "Config file is not opaque"
+        );
+        let res = AppConfig::init_encrypted(&output, &keys, CONFIG_FILE_DELIMITER).unwrap();
+        let plain = AppConfig::init(&input, CONFIG_FILE_DELIMITER).unwrap();
+        assert_eq!(res.values, plain.values);
+
+        let mut tampered = encrypted.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 0x01;
+        fs::write(&output, &tampered).unwrap();
+        assert!(matches!(
+            AppConfig::init_encrypted(&output, &keys, CONFIG_FILE_DELIMITER),
+            Err(ErrorReport::CryptoEnvelope { .. })
+        ));
+
+        let other_key = Rsa::generate(2048).unwrap();
+        let other_public =
+            Rsa::public_key_from_pem(&other_key.public_key_to_pem().unwrap()).unwrap();
+        encrypt_config_file(&input, &output, &other_public).unwrap();
+        assert!(matches!(
+            AppConfig::init_encrypted(&output, &keys, CONFIG_FILE_DELIMITER),
+            Err(ErrorReport::CryptoEnvelope { .. })
+        ));
+
+        let input = root_dir.join("assets/test/config_root.txt");
+        encrypt_config_file(&input, &output, keys.get_public_key()).unwrap();
+        let res =
+            AppConfig::init_encrypted_with_root(&output, &keys, CONFIG_FILE_DELIMITER, root_dir)
+                .unwrap();
+        assert_eq!(
+            res.get_path("MAIL:TEMPLATE_DIR").unwrap(),
+            root_dir.join("assets/mail")
+        );
+        assert_eq!(
+            res.get_path("RSA:PRIV_KEY_PATH").unwrap(),
+            root_dir.join("keys/private.pem")
+        );
+        match AppConfig::init_encrypted(&output, &keys, CONFIG_FILE_DELIMITER) {
+            Err(ErrorReport::ConfigRootMissing { key }) => assert_eq!(key, "MAIL:TEMPLATE_DIR"),
+            other => panic!("Expected missing root error, got {:?}", other),
+        }
+        fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn root_missing() {
+        let root_dir = Path::new(env!("WORKSPACE_ROOT_PATH"));
+        let config_path = root_dir.join("assets/test/config_root.txt");
+        match AppConfig::init(&config_path, CONFIG_FILE_DELIMITER) {
+            Err(ErrorReport::ConfigRootMissing { key }) => assert_eq!(key, "MAIL:TEMPLATE_DIR"),
+            other => panic!("Expected missing root error, got {:?}", other),
+        }
     }
 }
@@ -0,0 +1,381 @@
+use super::{AppConfig, ROOT_VARIABLE};
+use std::{collections::HashMap, fmt};
+
+/// Values that look like a secret was never filled in
+const PLACEHOLDER_SECRETS: [&str; 9] = [
+    "changeme",
+    "change_me",
+    "change-me",
+    "password",
+    "secret",
+    "todo",
+    "xxx",
+    "placeholder",
+    "<password>",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LintSeverity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for LintSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LintSeverity::Warning => f.write_str("warning"),
+            LintSeverity::Error => f.write_str("error"),
+        }
+    }
+}
+
+/// Something suspicious in a config file, see `AppConfig::lint`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintFinding {
+    pub severity: LintSeverity,
+    pub key: String,
+    /// Line of the file, `None` for keys that are missing
+    pub line: Option<usize>,
+    pub message: String,
+    pub suggestion: Option<String>,
+}
+
+impl fmt::Display for LintFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.severity)?;
+        if let Some(line) = self.line {
+            write!(f, " (line {})", line)?;
+        }
+        write!(f, " {}: {}", self.key, self.message)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, ", did you mean {}?", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    Text,
+    Integer,
+    Port,
+    /// `true` or `false`
+    Bool,
+    Path,
+    /// Comma separated values
+    List,
+    /// Password or token, checked for placeholder values
+    Secret,
+}
+
+#[derive(Debug, Clone)]
+struct SchemaKey {
+    name: String,
+    kind: ValueKind,
+    required: bool,
+}
+
+/// Keys an application expects in its config file
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSchema {
+    keys: Vec<SchemaKey>,
+}
+
+impl ConfigSchema {
+    pub fn new() -> Self {
+        ConfigSchema::default()
+    }
+
+    pub fn key(self, name: impl Into<String>, kind: ValueKind) -> Self {
+        self.add(name.into(), kind, false)
+    }
+
+    pub fn required(self, name: impl Into<String>, kind: ValueKind) -> Self {
+        self.add(name.into(), kind, true)
+    }
+
+    fn add(mut self, name: String, kind: ValueKind, required: bool) -> Self {
+        self.keys.retain(|val| val.name != name);
+        self.keys.push(SchemaKey {
+            name,
+            kind,
+            required,
+        });
+        self
+    }
+
+    /// The keys read by this crate: `MAIL:*`, `RSA:*` and `LOG:DIR`
+    pub fn utils() -> Self {
+        ConfigSchema::new()
+            .required("MAIL:FROM_ADDRS", ValueKind::Text)
+            .key("MAIL:REPLY_TO", ValueKind::Text)
+            .key("MAIL:FROM_NAME", ValueKind::Text)
+            .key("MAIL:ENVELOPE_FROM", ValueKind::Text)
+            .key("MAIL:MESSAGE_ID_DOMAIN", ValueKind::Text)
+            .key("MAIL:SMTP_HELLO_NAME", ValueKind::Text)
+            .required("MAIL:SMTP_SERVER", ValueKind::Text)
+            .required("MAIL:SMTP_PORT", ValueKind::Port)
+            .key("MAIL:SMTP_TLS", ValueKind::Text)
+            .key("MAIL:SMTP_ALLOW_INVALID_CERTS", ValueKind::Bool)
+            .required("MAIL:SMTP_USER", ValueKind::Text)
+            .required("MAIL:SMTP_PASS", ValueKind::Secret)
+            .required("MAIL:TEMPLATE_DIR", ValueKind::Path)
+            .required("MAIL:TEMPLATE_NAME_FORMAT", ValueKind::Text)
+            .required("MAIL:LANGS", ValueKind::List)
+            .required("MAIL:LANG_DEFAULT", ValueKind::Text)
+            .key("MAIL:SUBJECT_PREFIX", ValueKind::Text)
+            .key("MAIL:FOOTER_NAME_FORMAT", ValueKind::Text)
+            .key("MAIL:SUBJECTS_NAME_FORMAT", ValueKind::Text)
+            .key("MAIL:CATALOG_NAME_FORMAT", ValueKind::Text)
+            .key("MAIL:SKIP_TEMPLATE_VALIDATION", ValueKind::Bool)
+            .key("MAIL:AUTO_PLAINTEXT", ValueKind::Bool)
+            .key("MAIL:ESCAPE_CONTENTS", ValueKind::Bool)
+            .key("MAIL:DEDUPE_RECIPIENTS", ValueKind::Bool)
+            .key("MAIL:MASK_LOG_ADDRESSES", ValueKind::Bool)
+            .key("MAIL:MAX_ATTACHMENT_BYTES", ValueKind::Integer)
+            .key("MAIL:MAX_MESSAGE_BYTES", ValueKind::Integer)
+            .key("MAIL:MAX_RECIPIENTS_PER_MESSAGE", ValueKind::Integer)
+            .key("MAIL:SMTP_POOL_MAX_SIZE", ValueKind::Integer)
+            .key("MAIL:SMTP_POOL_MIN_IDLE", ValueKind::Integer)
+            .key("MAIL:SMTP_POOL_IDLE_TIMEOUT_SECS", ValueKind::Integer)
+            .key("MAIL:DELIVERY_DIR", ValueKind::Path)
+            .key("MAIL:SENDMAIL_PATH", ValueKind::Path)
+            .key("RSA:PASS", ValueKind::Secret)
+            .key("RSA:PRIV_KEY_PATH", ValueKind::Path)
+            .key("RSA:PUB_KEY_PATH", ValueKind::Path)
+            .key("LOG:DIR", ValueKind::Path)
+    }
+
+    fn get(&self, name: &str) -> Option<&SchemaKey> {
+        self.keys.iter().find(|val| val.name == name)
+    }
+
+    /// Known key closest to an unknown one, if it is close enough to be a typo
+    fn closest(&self, name: &str) -> Option<&str> {
+        let name = name.to_uppercase();
+        self.keys
+            .iter()
+            .map(|val| (edit_distance(&name, &val.name.to_uppercase()), &val.name))
+            .filter(|(distance, _)| *distance <= 3.max(name.chars().count() / 5))
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, name)| name.as_str())
+    }
+}
+
+/// Levenshtein distance between two strings, counted in characters
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+impl AppConfig {
+    /// Looks for suspicious keys and values: whitespace around them, duplicates, unresolved
+    /// `${VAR}` references and placeholder secrets; with a schema also unknown keys (suggesting
+    /// the closest known one), missing required keys and values of the wrong kind.
+    ///
+    /// Analysis only, the loaded values are not changed.
+    pub fn lint(&self, schema: Option<&ConfigSchema>) -> Vec<LintFinding> {
+        let mut findings = Vec::new();
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+
+        for entry in &self.entries {
+            let key = entry.key.trim();
+            let mut finding = |severity, message: String, suggestion: Option<String>| {
+                findings.push(LintFinding {
+                    severity,
+                    key: key.to_owned(),
+                    line: Some(entry.line),
+                    message,
+                    suggestion,
+                })
+            };
+
+            if key != entry.key {
+                finding(
+                    LintSeverity::Error,
+                    "key has trailing whitespace and will not be found".to_owned(),
+                    Some(key.to_owned()),
+                );
+            }
+            if entry.value.trim() != entry.value {
+                finding(
+                    LintSeverity::Warning,
+                    format!("value '{}' has surrounding whitespace", entry.value),
+                    Some(entry.value.trim().to_owned()),
+                );
+            }
+            if let Some(line) = seen.insert(&entry.key, entry.line) {
+                finding(
+                    LintSeverity::Warning,
+                    format!("overrides the value defined on line {}", line),
+                    None,
+                );
+            }
+
+            let value = entry.value.trim();
+            let references = value.replace(ROOT_VARIABLE, "");
+            if let Some(start) = references.find("${") {
+                if references[start..].contains('}') {
+                    finding(
+                        LintSeverity::Warning,
+                        format!("value '{}' looks like an unresolved reference", value),
+                        None,
+                    );
+                }
+            }
+
+            let known = schema.and_then(|schema| schema.get(key));
+            if let (Some(schema), None) = (schema, known) {
+                finding(
+                    LintSeverity::Warning,
+                    "unknown key".to_owned(),
+                    schema.closest(key).map(|val| val.to_owned()),
+                );
+            }
+            let kind = match known {
+                Some(known) => known.kind,
+                None if is_secret_name(key) => ValueKind::Secret,
+                None => ValueKind::Text,
+            };
+            if let Some(message) = check_kind(kind, value) {
+                let suggestion = match kind {
+                    ValueKind::Bool => Some("true or false".to_owned()),
+                    _ => None,
+                };
+                finding(LintSeverity::Error, message, suggestion);
+            }
+        }
+
+        if let Some(schema) = schema {
+            for key in schema.keys.iter().filter(|val| val.required) {
+                if !seen.contains_key(key.name.as_str()) {
+                    findings.push(LintFinding {
+                        severity: LintSeverity::Error,
+                        key: key.name.clone(),
+                        line: None,
+                        message: "required key is missing".to_owned(),
+                        suggestion: None,
+                    });
+                }
+            }
+        }
+
+        findings
+    }
+}
+
+fn is_secret_name(key: &str) -> bool {
+    let key = key.to_uppercase();
+    ["PASS", "SECRET", "TOKEN"]
+        .iter()
+        .any(|val| key.contains(val))
+}
+
+/// Problem with a value of the given kind, if any
+fn check_kind(kind: ValueKind, value: &str) -> Option<String> {
+    match kind {
+        ValueKind::Integer if value.parse::<i64>().is_err() => {
+            Some(format!("value '{}' is not an integer", value))
+        }
+        ValueKind::Port if !matches!(value.parse::<u16>(), Ok(port) if port > 0) => {
+            Some(format!("value '{}' is not a port number", value))
+        }
+        ValueKind::Bool if value != "true" && value != "false" => {
+            Some(format!("value '{}' is not a boolean", value))
+        }
+        ValueKind::Path if value.is_empty() => Some("path is empty".to_owned()),
+        ValueKind::List if value.split(',').any(|val| val.trim().is_empty()) => {
+            Some(format!("list '{}' has empty items", value))
+        }
+        ValueKind::Secret if value.is_empty() => Some("secret is empty".to_owned()),
+        ValueKind::Secret if PLACEHOLDER_SECRETS.contains(&value.to_lowercase().as_str()) => {
+            Some(format!("secret '{}' looks like a placeholder", value))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{edit_distance, ConfigSchema, LintSeverity};
+    use crate::envars::{AppConfig, CONFIG_FILE_DELIMITER};
+    use std::path::Path;
+
+    #[test]
+    fn distance() {
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("MAIL:SMPT_SERVER", "MAIL:SMTP_SERVER"), 2);
+        assert_eq!(edit_distance("ștefan", "stefan"), 1);
+    }
+
+    #[test]
+    fn messy_config() {
+        let config_path =
+            Path::new(env!("WORKSPACE_ROOT_PATH")).join("assets/test/config_messy.txt");
+        let config = AppConfig::init(&config_path, CONFIG_FILE_DELIMITER).unwrap();
+        let findings = config.lint(Some(
+            &ConfigSchema::utils().key("APP:DEBUG", super::ValueKind::Bool),
+        ));
+        let lines: Vec<String> = findings.iter().map(|val| val.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "warning (line 3) MAIL:SMPT_SERVER: unknown key, did you mean MAIL:SMTP_SERVER?",
+                "warning (line 4) MAIL:SMTP_PORT: value '587 ' has surrounding whitespace, did you mean 587?",
+                "error (line 5) MAIL:SMTP_USER: key has trailing whitespace and will not be found, did you mean MAIL:SMTP_USER?",
+                "error (line 6) MAIL:SMTP_PASS: secret 'changeme' looks like a placeholder",
+                "warning (line 7) MAIL:TEMPLATE_DIR: value ' /srv/mail ' has surrounding whitespace, did you mean /srv/mail?",
+                "error (line 9) MAIL:LANGS: list 'ro,,en' has empty items",
+                "warning (line 11) MAIL:REPLY_TO: value '${REPLY_ADDRESS}' looks like an unresolved reference",
+                "error (line 12) APP:DEBUG: value 'ja' is not a boolean, did you mean true or false?",
+                "warning (line 13) APP:API_TOKEN: unknown key",
+                "error (line 13) APP:API_TOKEN: secret is empty",
+                "warning (line 14) MAIL:LANGS: overrides the value defined on line 9",
+                "error (line 15) MAIL:LANGS: key has trailing whitespace and will not be found, did you mean MAIL:LANGS?",
+                "error MAIL:SMTP_SERVER: required key is missing",
+                "error MAIL:SMTP_USER: required key is missing",
+            ]
+        );
+        assert_eq!(
+            findings
+                .iter()
+                .filter(|val| val.severity == LintSeverity::Error)
+                .count(),
+            8
+        );
+
+        let without_schema = config.lint(None);
+        assert!(without_schema
+            .iter()
+            .all(|val| val.message != "unknown key"));
+        assert!(without_schema.iter().any(|val| val.key == "MAIL:SMTP_PASS"));
+        assert!(without_schema
+            .iter()
+            .all(|val| !val.message.contains("port")));
+
+        let clean_path =
+            Path::new(env!("WORKSPACE_ROOT_PATH")).join("assets/test/config_healthcheck.txt");
+        let clean = AppConfig::init(&clean_path, CONFIG_FILE_DELIMITER).unwrap();
+        assert_eq!(clean.lint(Some(&ConfigSchema::utils())), vec![]);
+
+        let root_dir = Path::new(env!("WORKSPACE_ROOT_PATH"));
+        let root_config = AppConfig::init_with_root(
+            &root_dir.join("assets/test/config_root.txt"),
+            CONFIG_FILE_DELIMITER,
+            root_dir,
+        )
+        .unwrap();
+        assert_eq!(root_config.lint(None), vec![]);
+    }
+}
@@ -1,3 +1,4 @@
+use lettre::transport::smtp::response::{Response, Severity};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -10,20 +11,269 @@ pub enum ErrorReport {
     MailHeaderContentType(#[from] lettre::message::header::ContentTypeErr),
     #[error("MailTransportSmtp - {0}")]
     MailTransportSmtp(#[from] lettre::transport::smtp::Error),
+    #[error("MailTransportFile - {0}")]
+    MailTransportFile(#[from] lettre::transport::file::Error),
+    #[error("MailSendmail - {command} failed: {msg}")]
+    MailSendmail { command: String, msg: String },
     #[error("MailContent - {0}")]
     MailContent(#[from] lettre::error::Error),
-    #[error("MailSentResponse - {}", .msg)]
-    MailSentResponse { msg: String },
+    #[error("MailSentResponse - {code} {}", .msg)]
+    MailSentResponse {
+        code: u16,
+        severity: Severity,
+        msg: String,
+    },
+    #[error("MailAttemptsFailed - gave up after {attempts} attempts: {last}")]
+    MailAttemptsFailed {
+        attempts: u32,
+        last: Box<ErrorReport>,
+    },
     #[error("FlexiLogger - {0}")]
     FlexiLogger(#[from] flexi_logger::FlexiLoggerError),
+    #[error("LogIntegrity - line {line}: {msg}")]
+    LogIntegrity { line: usize, msg: String },
     #[error("OpenSslErrorStack - {0}")]
     OpenSslErrorStack(#[from] openssl::error::ErrorStack),
     #[error("DataEncodingDecode - {0}")]
     DataEncodingDecode(#[from] data_encoding::DecodeError),
+    #[error("ConfigRootMissing - key {key} references ${{ROOT}} but no root path was provided")]
+    ConfigRootMissing { key: String },
+    #[error("ConfigMissingKey - key {key} is not defined")]
+    ConfigMissingKey { key: String },
+    #[error("ConfigInvalidValue - key {key}: {msg}")]
+    ConfigInvalidValue { key: String, msg: String },
+    #[error("ConfigTenant - tenant {tenant}: {msg}")]
+    ConfigTenant { tenant: String, msg: String },
+    #[cfg(feature = "dkim")]
+    #[error("MailDkimKey - {0}")]
+    MailDkimKey(#[from] lettre::message::dkim::DkimSigningKeyError),
+    #[cfg(feature = "smime")]
+    #[error("MailSmimeKey - {msg}")]
+    MailSmimeKey { msg: String },
+    #[error(
+        "MailTokenRejected - the OAuth2 access token was refused, refresh it and retry: {msg}"
+    )]
+    MailTokenRejected { msg: String },
+    #[error(
+        "MailRateLimited - {max_messages} messages per {} ms already sent, a slot frees in {} ms",
+        .per.as_millis(),
+        .retry_after.as_millis()
+    )]
+    MailRateLimited {
+        max_messages: u32,
+        per: std::time::Duration,
+        retry_after: std::time::Duration,
+    },
+    #[error("MailQueueFull - {capacity} message(s) are already waiting to be sent")]
+    MailQueueFull { capacity: usize },
+    #[error("MailQueueClosed - the mail queue was shut down")]
+    MailQueueClosed,
+    #[error(
+        "MailQueueTimeout - {pending} queued message(s) still unsent after {} ms",
+        .timeout.as_millis()
+    )]
+    MailQueueTimeout {
+        pending: usize,
+        timeout: std::time::Duration,
+    },
+    #[error("MailShutDown - the mailer was shut down")]
+    MailShutDown,
+    #[error(
+        "MailShutdownTimeout - {in_flight} message(s) still being sent after {} ms",
+        .timeout.as_millis()
+    )]
+    MailShutdownTimeout {
+        in_flight: usize,
+        timeout: std::time::Duration,
+    },
+    #[error("MailConfigInvalid - {}", .problems.join("; "))]
+    MailConfigInvalid { problems: Vec<String> },
+    #[error("MailMessageInvalid - {}", .problems.join("; "))]
+    MailMessageInvalid { problems: Vec<String> },
+    #[error("MailAddressInvalid - '{token}' is not a valid mailbox: {msg}")]
+    MailAddressInvalid { token: String, msg: String },
+    #[error(
+        "MailAttachmentTooLarge - attachment '{name}' has {size} bytes, over the limit of {limit}"
+    )]
+    MailAttachmentTooLarge { name: String, size: u64, limit: u64 },
+    #[error("MailArchive - {dir} could not be zipped: {msg}")]
+    MailArchive { dir: String, msg: String },
+    #[error("MailMessageTooLarge - attachment '{name}' brings the message to {size} bytes, over the limit of {limit}")]
+    MailMessageTooLarge { name: String, size: u64, limit: u64 },
+    #[error(
+        "MailSplitFailed - {} of {parts} messages for the split recipient list not sent: {}",
+        .failed.len(),
+        .failed.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+    )]
+    MailSplitFailed {
+        parts: usize,
+        /// `Message-ID` of the messages sent
+        sent: Vec<String>,
+        failed: Vec<SplitFailure>,
+    },
+    #[error("MailUnresolvedPlaceholders - no value for {}", .names.join(", "))]
+    MailUnresolvedPlaceholders { names: Vec<String> },
+    #[error(
+        "MailDeadlineExceeded - not delivered within {} ms, {} attempt(s): {}",
+        .budget.as_millis(),
+        .attempts.len(),
+        .attempts.join("; ")
+    )]
+    MailDeadlineExceeded {
+        budget: std::time::Duration,
+        attempts: Vec<String>,
+    },
+    #[error(
+        "MailSendAbandoned - no answer within {} ms, the message may still be delivered",
+        .timeout.as_millis()
+    )]
+    MailSendAbandoned { timeout: std::time::Duration },
+    #[error("MailConnection - could not connect to SMTP server {server}")]
+    MailConnection { server: String },
+    #[error("MailConnectionCheck - SMTP server {server} failed the connection check: {msg}")]
+    MailConnectionCheck { server: String, msg: String },
+    #[error("MailTlsMismatch - connection to {server} failed with TLS mode {mode}, the port usually takes {expected}; check the TLS mode: {msg}")]
+    MailTlsMismatch {
+        server: String,
+        mode: String,
+        expected: String,
+        msg: String,
+    },
+    #[error("MailTemplateEncoding - template {path} is not UTF-8 (looks like {detected}), save it as UTF-8")]
+    MailTemplateEncoding { path: String, detected: String },
+    #[error("MailTemplateInclude - {template} includes '{partial}': {msg}")]
+    MailTemplateInclude {
+        template: String,
+        partial: String,
+        msg: String,
+    },
+    #[error("MailCatalogInvalid - {path}:{line}: {msg}")]
+    MailCatalogInvalid {
+        path: String,
+        line: usize,
+        msg: String,
+    },
+    #[cfg(feature = "handlebars")]
+    #[error("MailTemplateCompile - {0}")]
+    MailTemplateCompile(#[from] handlebars::TemplateError),
+    #[cfg(feature = "handlebars")]
+    #[error("MailTemplateRender - {0}")]
+    MailTemplateRender(#[from] handlebars::RenderError),
+    #[error("MailTemplatesMissing - {}", .paths.join(", "))]
+    MailTemplatesMissing { paths: Vec<String> },
+    #[error("MailTemplatesInvalid - {}", .problems.join("; "))]
+    MailTemplatesInvalid { problems: Vec<String> },
+    #[error("CryptoEnvelope - {msg}")]
+    CryptoEnvelope { msg: String },
+    #[error("CryptoKeyLength - AES keys are 16, 24 or 32 bytes long, got {len}")]
+    CryptoKeyLength { len: usize },
+    #[error("RsaKeySize - RSA keys need at least {min} bits, got {bits}")]
+    RsaKeySize { bits: u32, min: u32 },
+    #[error("RsaDecrypt - the data could not be decrypted with {padding} padding, the key or padding does not match")]
+    RsaDecrypt { padding: String },
+    #[error("RsaPrivateKeyMissing - the keys were loaded without the private key")]
+    RsaPrivateKeyMissing,
+    #[error("RsaKeyFormat - {format} key could not be read: {msg}")]
+    RsaKeyFormat { format: String, msg: String },
+    #[error("RsaKeyMismatch - the public key does not belong to the private key")]
+    RsaKeyMismatch,
+    #[error("RsaSelfCheck - {msg}")]
+    RsaSelfCheck { msg: String },
+    #[error("VerifierKey - {format} key could not be parsed: {msg}")]
+    VerifierKey { format: String, msg: String },
+    #[error("SignatureInvalid - {msg}")]
+    SignatureInvalid { msg: String },
+    #[cfg(any(feature = "mailer", feature = "rsakeys"))]
+    #[error("SerdeJson - {0}")]
+    SerdeJson(#[from] serde_json::Error),
 }
 
-pub fn error_mail_sent_response(msg: &(dyn ToString)) -> ErrorReport {
+impl ErrorReport {
+    /// Whether the error comes from a timeout, of a single operation or of a whole send
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            ErrorReport::Io(e) => e.kind() == std::io::ErrorKind::TimedOut,
+            ErrorReport::MailTransportSmtp(e) => e.is_timeout(),
+            ErrorReport::MailDeadlineExceeded { .. } | ErrorReport::MailSendAbandoned { .. } => {
+                true
+            }
+            ErrorReport::MailAttemptsFailed { last, .. } => last.is_timeout(),
+            ErrorReport::MailSplitFailed { failed, .. } => {
+                failed.iter().all(|val| val.error.is_timeout())
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether a failed send may succeed later: a 4xx answer of the server, a connection
+    /// failure, an access token to refresh or a spent rate limit; the rest is permanent
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ErrorReport::MailSentResponse { severity, .. } => {
+                *severity == Severity::TransientNegativeCompletion
+            }
+            ErrorReport::MailTransportSmtp(e) if e.is_transient() => true,
+            ErrorReport::MailTokenRejected { .. }
+            | ErrorReport::MailRateLimited { .. }
+            | ErrorReport::MailConnection { .. } => true,
+            ErrorReport::MailAttemptsFailed { last, .. } => last.is_transient(),
+            ErrorReport::MailSplitFailed { failed, .. } => {
+                failed.iter().all(|val| val.error.is_transient())
+            }
+            _ => self.is_connection_error(),
+        }
+    }
+
+    /// Failures of the connection itself rather than answers of the server, worth a new
+    /// connection
+    pub fn is_connection_error(&self) -> bool {
+        match self {
+            ErrorReport::MailTransportSmtp(e) => {
+                !(e.is_response()
+                    || e.is_client()
+                    || e.is_transient()
+                    || e.is_permanent()
+                    || e.is_tls())
+            }
+            ErrorReport::Io(e) => matches!(
+                e.kind(),
+                std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::BrokenPipe
+                    | std::io::ErrorKind::UnexpectedEof
+            ),
+            _ => false,
+        }
+    }
+}
+
+/// One of the messages of a split recipient list that was not sent, numbered from 1
+#[derive(Debug)]
+pub struct SplitFailure {
+    pub part: usize,
+    pub recipients: Vec<String>,
+    pub error: ErrorReport,
+}
+
+impl std::fmt::Display for SplitFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "message {} to {}: {}",
+            self.part,
+            self.recipients.join(", "),
+            self.error
+        )
+    }
+}
+
+/// Negative answer of the server to a send, with its reply code and the lines of its message
+pub fn error_mail_sent_response(res: &Response) -> ErrorReport {
+    let code = res.code();
     ErrorReport::MailSentResponse {
-        msg: msg.to_string(),
+        code: code.severity as u16 * 100 + code.category as u16 * 10 + code.detail as u16,
+        severity: code.severity,
+        msg: res.message().fold(String::new(), |t, s| t + s + "\n"),
     }
 }
@@ -0,0 +1,185 @@
+#[cfg(feature = "mailer")]
+use crate::mailer::{Config, Mailer};
+#[cfg(feature = "rsakeys")]
+use crate::rsakeys::RsaKeys;
+use crate::{envars::AppConfig, error::ErrorReport};
+use std::{fmt, fs, path::PathBuf};
+
+/// Config key holding the directory the logger writes to
+pub const LOG_DIR_KEY: &str = "LOG:DIR";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Component {
+    MailerConnection,
+    MailTemplates,
+    RsaKeys,
+    LogDirectory,
+}
+
+impl fmt::Display for Component {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Component::MailerConnection => "mailer connection",
+            Component::MailTemplates => "mail templates",
+            Component::RsaKeys => "rsa keys",
+            Component::LogDirectory => "log directory",
+        })
+    }
+}
+
+/// Components to check; all are off by default
+#[derive(Debug, Clone, Default)]
+pub struct CheckOptions {
+    pub mailer_connection: bool,
+    pub mail_templates: bool,
+    pub rsa_keys: bool,
+    pub log_directory: bool,
+}
+
+#[derive(Debug)]
+pub struct ComponentResult {
+    pub component: Component,
+    pub result: Result<(), ErrorReport>,
+}
+
+#[derive(Debug)]
+pub struct HealthReport {
+    pub results: Vec<ComponentResult>,
+}
+
+impl HealthReport {
+    pub fn is_ok(&self) -> bool {
+        self.results.iter().all(|res| res.result.is_ok())
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &ComponentResult> {
+        self.results.iter().filter(|res| res.result.is_err())
+    }
+}
+
+impl fmt::Display for HealthReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for res in &self.results {
+            match &res.result {
+                Ok(()) => writeln!(f, "[ OK ] {}", res.component)?,
+                Err(e) => writeln!(f, "[FAIL] {} - {}", res.component, e)?,
+            }
+        }
+        let failed = self.failures().count();
+        write!(
+            f,
+            "{} of {} checks passed",
+            self.results.len() - failed,
+            self.results.len()
+        )
+    }
+}
+
+/// Runs every selected check; a failing check never stops the following ones
+pub fn check_all(app_config: &AppConfig, options: &CheckOptions) -> HealthReport {
+    let mut results = Vec::new();
+    let mut run = |component: Component, check: &dyn Fn() -> Result<(), ErrorReport>| {
+        results.push(ComponentResult {
+            component,
+            result: check(),
+        })
+    };
+
+    #[cfg(feature = "mailer")]
+    {
+        if options.mailer_connection {
+            run(Component::MailerConnection, &|| {
+                Mailer::init(Config::from_app_config(app_config)?).test_connection()
+            });
+        }
+        if options.mail_templates {
+            run(Component::MailTemplates, &|| {
+                Mailer::init(Config::from_app_config(app_config)?)
+                    .validate_templates()
+                    .map(|_| ())
+            });
+        }
+    }
+    #[cfg(feature = "rsakeys")]
+    if options.rsa_keys {
+        run(Component::RsaKeys, &|| check_rsa_keys(app_config));
+    }
+    if options.log_directory {
+        run(Component::LogDirectory, &|| check_log_directory(app_config));
+    }
+
+    HealthReport { results }
+}
+
+#[cfg(feature = "rsakeys")]
+fn check_rsa_keys(app_config: &AppConfig) -> Result<(), ErrorReport> {
+    let path = |key: &str| -> Result<PathBuf, ErrorReport> {
+        app_config
+            .get_path(key)
+            .ok_or_else(|| ErrorReport::ConfigMissingKey {
+                key: key.to_owned(),
+            })
+    };
+    let passphrase =
+        app_config
+            .get_var("RSA:PASS")
+            .ok_or_else(|| ErrorReport::ConfigMissingKey {
+                key: "RSA:PASS".to_owned(),
+            })?;
+    let rsa = RsaKeys::init(
+        passphrase,
+        path("RSA:PRIV_KEY_PATH")?,
+        path("RSA:PUB_KEY_PATH")?,
+    )?;
+
+    let probe = "healthcheck";
+    if rsa.priv_decrypt(&rsa.pub_encrypt(probe)?)? != probe {
+        return Err(ErrorReport::RsaSelfCheck {
+            msg: "decrypted text does not match the input".to_owned(),
+        });
+    }
+    Ok(())
+}
+
+fn check_log_directory(app_config: &AppConfig) -> Result<(), ErrorReport> {
+    let dir = app_config
+        .get_path(LOG_DIR_KEY)
+        .ok_or_else(|| ErrorReport::ConfigMissingKey {
+            key: LOG_DIR_KEY.to_owned(),
+        })?;
+    let probe = dir.join(".healthcheck");
+    fs::write(&probe, b"")?;
+    fs::remove_file(&probe)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_all, CheckOptions, Component};
+    use crate::envars::{AppConfig, CONFIG_FILE_DELIMITER};
+    use std::path::Path;
+
+    #[test]
+    fn one_component_broken() {
+        let root_dir = Path::new(env!("WORKSPACE_ROOT_PATH"));
+        let config_path = root_dir.join("assets/test/config_healthcheck.txt");
+        let app_config =
+            AppConfig::init_with_root(&config_path, CONFIG_FILE_DELIMITER, root_dir).unwrap();
+
+        let report = check_all(
+            &app_config,
+            &CheckOptions {
+                mailer_connection: false,
+                mail_templates: true,
+                rsa_keys: true,
+                log_directory: true,
+            },
+        );
+
+        assert_eq!(report.results.len(), 3, "Not all checks were run");
+        assert!(!report.is_ok());
+        let failed: Vec<Component> = report.failures().map(|res| res.component).collect();
+        assert_eq!(failed, vec![Component::RsaKeys]);
+        assert!(report.to_string().contains("[FAIL] rsa keys"));
+    }
+}
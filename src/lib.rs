@@ -1,6 +1,8 @@
 #[cfg(feature = "envars")]
 pub mod envars;
 pub mod error;
+#[cfg(feature = "envars")]
+pub mod healthcheck;
 #[cfg(feature = "logger")]
 pub mod logger;
 #[cfg(feature = "mailer")]
@@ -1,16 +1,48 @@
 use crate::error::ErrorReport;
+#[cfg(feature = "rsakeys")]
+use data_encoding::HEXLOWER;
+#[cfg(feature = "rsakeys")]
+use flexi_logger::{
+    default_format,
+    writers::{FileLogWriter, LogWriter},
+    DeferredNow,
+};
 use flexi_logger::{
     Age, Cleanup, Criterion, Duplicate, FileSpec, Logger, LoggerHandle, Naming, WriteMode,
 };
+#[cfg(feature = "rsakeys")]
+use log::Record;
+#[cfg(feature = "rsakeys")]
+use openssl::{
+    hash::MessageDigest,
+    pkey::{PKey, Private},
+    sign::Signer,
+};
+use std::path::Path;
+#[cfg(feature = "rsakeys")]
+use std::{fs, io, sync::Mutex};
+
+/// Starts every chain of an integrity protected log file, followed by its time and the last HMAC
+/// of the chain before it
+#[cfg(feature = "rsakeys")]
+pub const CHAIN_HEADER: &str = "# log integrity chain started";
+#[cfg(feature = "rsakeys")]
+const MAC_SEPARATOR: &str = " #hmac=";
+#[cfg(feature = "rsakeys")]
+const ANCHOR_SEPARATOR: &str = " prev=";
+#[cfg(feature = "rsakeys")]
+const MAC_LEN: usize = 32;
 
-pub fn init_logger(directory_path: &String) -> Result<LoggerHandle, ErrorReport> {
-    let file_specs = FileSpec::default()
+fn file_spec(directory_path: &Path) -> FileSpec {
+    FileSpec::default()
         .directory(directory_path)
         .basename("log")
-        .suffix("log");
+        .suffix("log")
+}
 
+pub fn init_logger(directory_path: impl AsRef<Path>) -> Result<LoggerHandle, ErrorReport> {
     let result = Logger::try_with_str("info")?
-        .log_to_file(file_specs)
+        .log_to_file(file_spec(directory_path.as_ref()))
         .duplicate_to_stderr(Duplicate::Warn)
         .write_mode(WriteMode::Async)
         .rotate(
@@ -22,37 +54,397 @@ pub fn init_logger(directory_path: &String) -> Result<LoggerHandle, ErrorReport>
     Ok(result)
 }
 
-#[cfg(test)]
+/// Same as `init_logger`, with every line followed by an HMAC-SHA256 of the previous line's HMAC
+/// and the line itself, so a changed, inserted or removed line breaks the chain; see
+/// `verify_log_file`. The key would typically be a data key unwrapped with `AesKey::from_wrapped`.
+///
+/// Each file starts a new chain with a `CHAIN_HEADER` record, files rotate daily. A chain starts
+/// from the last HMAC of the one before it, also across files and restarts, so removing a whole
+/// chain or file breaks the next one. Line breaks inside messages are escaped as `\n`.
+#[cfg(feature = "rsakeys")]
+pub fn init_logger_with_integrity(
+    directory_path: impl AsRef<Path>,
+    key: &[u8],
+) -> Result<LoggerHandle, ErrorReport> {
+    let directory_path = directory_path.as_ref();
+    let previous = last_mac(directory_path)?;
+    let writer = ChainedLogWriter::new(
+        FileLogWriter::builder(file_spec(directory_path))
+            .rotate(
+                Criterion::Age(Age::Day),
+                Naming::Timestamps,
+                Cleanup::KeepLogFiles(7),
+            )
+            .format(|w, _, record| write!(w, "{}", record.args()))
+            .try_build()?,
+        key,
+        previous,
+    )?;
+    let result = Logger::try_with_str("info")?
+        .log_to_writer(Box::new(writer))
+        .duplicate_to_stderr(Duplicate::Warn)
+        .start()?;
+    Ok(result)
+}
+
+/// Writes HMAC chained lines to a file writer that outputs its records verbatim
+#[cfg(feature = "rsakeys")]
+struct ChainedLogWriter {
+    inner: FileLogWriter,
+    key: PKey<Private>,
+    chain: Mutex<Chain>,
+}
+
+#[cfg(feature = "rsakeys")]
+struct Chain {
+    previous: [u8; MAC_LEN],
+    /// Day of the current chain, a new day means a new file
+    day: Option<String>,
+}
+
+#[cfg(feature = "rsakeys")]
+impl ChainedLogWriter {
+    fn new(inner: FileLogWriter, key: &[u8], previous: [u8; MAC_LEN]) -> Result<Self, ErrorReport> {
+        Ok(ChainedLogWriter {
+            inner,
+            key: PKey::hmac(key)?,
+            chain: Mutex::new(Chain {
+                previous,
+                day: None,
+            }),
+        })
+    }
+
+    fn append(&self, chain: &mut Chain, now: &mut DeferredNow, line: &str) -> io::Result<()> {
+        let mac = chain_mac(&self.key, &chain.previous, line).map_err(io::Error::other)?;
+        chain.previous = mac;
+        self.inner.write(
+            now,
+            &Record::builder()
+                .args(format_args!(
+                    "{}{}{}",
+                    line,
+                    MAC_SEPARATOR,
+                    HEXLOWER.encode(&mac)
+                ))
+                .build(),
+        )
+    }
+}
+
+#[cfg(feature = "rsakeys")]
+impl LogWriter for ChainedLogWriter {
+    fn write(&self, now: &mut DeferredNow, record: &Record) -> io::Result<()> {
+        let mut line = Vec::new();
+        default_format(&mut line, now, record)?;
+        let line = String::from_utf8_lossy(&line).replace('\n', "\\n");
+
+        let mut chain = self.chain.lock().unwrap();
+        let day = now.format("%Y-%m-%d").to_string();
+        if chain.day.as_ref() != Some(&day) {
+            chain.day = Some(day);
+            let header = format!(
+                "{} {}{}{}",
+                CHAIN_HEADER,
+                now.format("%Y-%m-%dT%H:%M:%S%.3f%:z"),
+                ANCHOR_SEPARATOR,
+                HEXLOWER.encode(&chain.previous)
+            );
+            self.append(&mut chain, now, &header)?;
+        }
+        self.append(&mut chain, now, &line)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn shutdown(&self) {
+        self.inner.shutdown();
+    }
+}
+
+#[cfg(feature = "rsakeys")]
+fn chain_mac(
+    key: &PKey<Private>,
+    previous: &[u8; MAC_LEN],
+    line: &str,
+) -> Result<[u8; MAC_LEN], ErrorReport> {
+    let mut signer = Signer::new(MessageDigest::sha256(), key)?;
+    signer.update(previous)?;
+    signer.update(line.as_bytes())?;
+    let mut mac = [0; MAC_LEN];
+    signer.sign(&mut mac)?;
+    Ok(mac)
+}
+
+#[cfg(feature = "rsakeys")]
+fn decode_mac(hex: &str) -> Option<[u8; MAC_LEN]> {
+    HEXLOWER.decode(hex.as_bytes()).ok()?.try_into().ok()
+}
+
+/// Last HMAC of the most recently written log file in the directory, or zeros without one
+#[cfg(feature = "rsakeys")]
+fn last_mac(directory_path: &Path) -> Result<[u8; MAC_LEN], ErrorReport> {
+    let mut newest = None;
+    if let Ok(entries) = fs::read_dir(directory_path) {
+        for entry in entries {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+            if !meta.is_file()
+                || meta.len() == 0
+                || entry.path().extension() != Some("log".as_ref())
+            {
+                continue;
+            }
+            let modified = meta.modified()?;
+            if newest.as_ref().is_none_or(|(time, _)| modified > *time) {
+                newest = Some((modified, entry.path()));
+            }
+        }
+    }
+    let Some((_, path)) = newest else {
+        return Ok([0; MAC_LEN]);
+    };
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .last()
+        .and_then(|line| line.rsplit_once(MAC_SEPARATOR))
+        .and_then(|(_, mac)| decode_mac(mac))
+        .unwrap_or([0; MAC_LEN]))
+}
+
+/// Walks the HMAC chain of a log file written by `init_logger_with_integrity`; returns the number
+/// of verified lines, or a `LogIntegrity` error with the first line that does not verify.
+///
+/// Every chain after the first must start from the last HMAC of the one before it. Lines removed
+/// from the end of the file cannot be detected, nor can files removed before it; see
+/// `verify_log_files`.
+#[cfg(feature = "rsakeys")]
+pub fn verify_log_file(path: impl AsRef<Path>, key: &[u8]) -> Result<usize, ErrorReport> {
+    let key = PKey::hmac(key)?;
+    let (count, _) = verify_chain(&fs::read_to_string(path)?, &key, None)?;
+    Ok(count)
+}
+
+/// Same as `verify_log_file` over rotated files in the order they were written, where each file
+/// must also start from the last HMAC of the one before it, so a removed file is detected. Errors
+/// name the file.
+#[cfg(feature = "rsakeys")]
+pub fn verify_log_files(
+    paths: impl IntoIterator<Item = impl AsRef<Path>>,
+    key: &[u8],
+) -> Result<usize, ErrorReport> {
+    let key = PKey::hmac(key)?;
+    let mut anchor = None;
+    let mut total = 0;
+    for path in paths {
+        let path = path.as_ref();
+        let (count, last) =
+            verify_chain(&fs::read_to_string(path)?, &key, anchor).map_err(|e| match e {
+                ErrorReport::LogIntegrity { line, msg } => ErrorReport::LogIntegrity {
+                    line,
+                    msg: format!("{}: {}", path.display(), msg),
+                },
+                e => e,
+            })?;
+        total += count;
+        anchor = last.or(anchor);
+    }
+    Ok(total)
+}
+
+/// Verifies the chains of one file, starting from `anchor` when the HMAC before the file is
+/// known; returns the number of lines and the last HMAC
+#[cfg(feature = "rsakeys")]
+fn verify_chain(
+    text: &str,
+    key: &PKey<Private>,
+    anchor: Option<[u8; MAC_LEN]>,
+) -> Result<(usize, Option<[u8; MAC_LEN]>), ErrorReport> {
+    let mut previous = anchor.unwrap_or([0; MAC_LEN]);
+    let mut anchored = anchor.is_some();
+    let mut count = 0;
+    for (index, raw) in text.lines().enumerate() {
+        let broken = |msg: &str| ErrorReport::LogIntegrity {
+            line: index + 1,
+            msg: msg.to_owned(),
+        };
+        let (line, mac) = raw
+            .rsplit_once(MAC_SEPARATOR)
+            .ok_or_else(|| broken("no HMAC"))?;
+        if line.starts_with(CHAIN_HEADER) {
+            let start = line
+                .rsplit_once(ANCHOR_SEPARATOR)
+                .and_then(|(_, hex)| decode_mac(hex))
+                .ok_or_else(|| broken("the chain header has no previous HMAC"))?;
+            if anchored && start != previous {
+                return Err(broken(
+                    "the chain does not follow the one before it, lines before it were removed",
+                ));
+            }
+            previous = start;
+        } else if index == 0 {
+            return Err(broken("the file does not start with a chain header"));
+        }
+        let expected = chain_mac(key, &previous, line)?;
+        if decode_mac(mac) != Some(expected) {
+            return Err(broken(
+                "HMAC does not match, the line or one before it was changed",
+            ));
+        }
+        previous = expected;
+        anchored = true;
+        count += 1;
+    }
+    Ok((count, anchored.then_some(previous)))
+}
+
+#[cfg(all(test, feature = "rsakeys"))]
 mod tests {
-    use super::init_logger;
-    use std::{fs, io::BufRead, thread, time};
+    use std::fs;
 
     #[test]
-    fn logging_async() {
-        let dir_path = env!("TEMP_DIR_PATH");
-        let _logger = init_logger(&String::from(dir_path)).unwrap();
-
-        //log::set_logger(logger);
-        log::info!("Testing info logging {}", 1);
-        log::warn!("Testing warn logging {}", 2);
-        log::error!("Testing error logging {}", 3);
-
-        thread::sleep(time::Duration::from_secs(3)); //wait for the async logger to finish the job
-
-        let file_path = fs::read_dir(&dir_path)
-            .unwrap()
-            .find(|entry| {
-                let entry = entry.as_ref().unwrap();
-                let meta = &entry.metadata().unwrap();
-                meta.is_file() && entry.file_name().to_str().unwrap().ends_with("CURRENT.log")
+    fn integrity_chain() {
+        use super::{
+            file_spec, last_mac, verify_log_file, verify_log_files, ChainedLogWriter, CHAIN_HEADER,
+            MAC_LEN,
+        };
+        use crate::error::ErrorReport;
+        use flexi_logger::{
+            writers::{FileLogWriter, LogWriter},
+            DeferredNow,
+        };
+        use log::{Level, Record};
+        use std::{ops::Range, path::Path, time::Instant};
+
+        let dir_path = Path::new(env!("TEMP_DIR_PATH")).join("integrity");
+        let _ = fs::remove_dir_all(&dir_path);
+        let key = b"0123456789abcdef0123456789abcdef";
+        let writer = ChainedLogWriter::new(
+            FileLogWriter::builder(file_spec(&dir_path).suppress_timestamp())
+                .format(|w, _, record| write!(w, "{}", record.args()))
+                .try_build()
+                .unwrap(),
+            key,
+            [0; MAC_LEN],
+        )
+        .unwrap();
+
+        let started = Instant::now();
+        for i in 0..10_000 {
+            writer
+                .write(
+                    &mut DeferredNow::new(),
+                    &Record::builder()
+                        .level(Level::Info)
+                        .args(format_args!("line {}\nwith a break", i))
+                        .build(),
+                )
+                .unwrap();
+        }
+        writer.flush().unwrap();
+        let elapsed = started.elapsed();
+        assert!(
+            elapsed.as_secs() < 10,
+            "10000 chained lines took {:?}",
+            elapsed
+        );
+
+        let file_path = dir_path.join("log.log");
+        assert_eq!(verify_log_file(&file_path, key).unwrap(), 10_001);
+        let text = fs::read_to_string(&file_path).unwrap();
+        assert!(text.starts_with(CHAIN_HEADER));
+        assert!(text.contains("line 7\\nwith a break #hmac="));
+        assert!(matches!(
+            verify_log_file(&file_path, b"another key"),
+            Err(ErrorReport::LogIntegrity { line: 1, .. })
+        ));
+
+        let tampered = text.replacen("line 5000\\n", "line 5001\\n", 1);
+        fs::write(&file_path, tampered).unwrap();
+        match verify_log_file(&file_path, key) {
+            Err(ErrorReport::LogIntegrity { line, .. }) => assert_eq!(line, 5002),
+            other => panic!("Expected broken chain, got {:?}", other),
+        }
+
+        let removed: Vec<&str> = text
+            .lines()
+            .filter(|line| !line.contains("line 42\\n"))
+            .collect();
+        fs::write(&file_path, removed.join("\n")).unwrap();
+        match verify_log_file(&file_path, key) {
+            Err(ErrorReport::LogIntegrity { line, .. }) => assert_eq!(line, 44),
+            other => panic!("Expected broken chain, got {:?}", other),
+        }
+
+        // three chains, each starting from the last HMAC of the one before
+        let segments_path = dir_path.join("segments");
+        let writer = ChainedLogWriter::new(
+            FileLogWriter::builder(file_spec(&segments_path).suppress_timestamp())
+                .format(|w, _, record| write!(w, "{}", record.args()))
+                .try_build()
+                .unwrap(),
+            key,
+            [0; MAC_LEN],
+        )
+        .unwrap();
+        for i in 0..30 {
+            if i % 10 == 0 {
+                writer.chain.lock().unwrap().day = None;
+            }
+            writer
+                .write(
+                    &mut DeferredNow::new(),
+                    &Record::builder()
+                        .level(Level::Info)
+                        .args(format_args!("segment line {}", i))
+                        .build(),
+                )
+                .unwrap();
+        }
+        writer.flush().unwrap();
+        let file_path = segments_path.join("log.log");
+        assert_eq!(verify_log_file(&file_path, key).unwrap(), 33);
+        assert_eq!(
+            last_mac(&segments_path).unwrap(),
+            writer.chain.lock().unwrap().previous
+        );
+
+        let text = fs::read_to_string(&file_path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        let without = |removed: Range<usize>| {
+            lines
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| !removed.contains(index))
+                .map(|(_, line)| *line)
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        // the whole second chain, then the end of the first one
+        for (removed, broken_line) in [(11..22, 12), (8..11, 9)] {
+            fs::write(&file_path, without(removed)).unwrap();
+            match verify_log_file(&file_path, key) {
+                Err(ErrorReport::LogIntegrity { line, .. }) => assert_eq!(line, broken_line),
+                other => panic!("Expected broken chain, got {:?}", other),
+            }
+        }
+
+        let files: Vec<_> = [0..11, 11..22, 22..33]
+            .into_iter()
+            .enumerate()
+            .map(|(index, range)| {
+                let path = segments_path.join(format!("rotated_{}.log", index));
+                fs::write(&path, lines[range].join("\n")).unwrap();
+                path
             })
-            .unwrap()
-            .unwrap()
-            .path();
-
-        let text = fs::read(&file_path).unwrap();
-        let line_count = text.lines().count();
-        fs::remove_file(&file_path).unwrap();
-        assert_eq!(line_count, 3, "Log file didn't contain all logs");
+            .collect();
+        assert_eq!(verify_log_files(&files, key).unwrap(), 33);
+        match verify_log_files([&files[0], &files[2]], key) {
+            Err(ErrorReport::LogIntegrity { line: 1, msg }) => assert!(msg.contains("rotated_2")),
+            other => panic!("Expected broken chain, got {:?}", other),
+        }
+        fs::remove_dir_all(&dir_path).unwrap();
     }
 }
@@ -1,230 +1,6566 @@
+#[cfg(feature = "envars")]
+use crate::envars::AppConfig;
 use crate::error::ErrorReport;
 use lettre::{
-    message::{Attachment, Mailbox, MultiPart, SinglePart},
-    transport::smtp::{authentication::Credentials, response::Severity},
-    Message, SmtpTransport, Transport,
+    address::Envelope,
+    message::{
+        header::{ContentDisposition, ContentType},
+        Attachment, Mailbox, MultiPart, SinglePart,
+    },
+    transport::smtp::{
+        client::{Tls, TlsParameters, TlsParametersBuilder},
+        extension::ClientId,
+        response::Response,
+        PoolConfig, SmtpTransportBuilder,
+    },
+    Address, Message, SmtpTransport,
 };
-use std::{fs, path::Path};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
+use uuid::Uuid;
+
+mod archive;
+#[cfg(feature = "async")]
+mod asynchronous;
+mod audit;
+mod auth;
+#[cfg(feature = "serde")]
+mod config_serde;
+mod deadline;
+#[cfg(feature = "dkim")]
+mod dkim;
+#[cfg(test)]
+mod golden;
+#[cfg(feature = "handlebars")]
+mod hbs;
+mod hooks;
+mod ics;
+mod lifecycle;
+mod mailbox;
+mod message;
+mod metrics;
+mod mime;
+mod queue;
+mod rate;
+#[cfg(feature = "envars")]
+mod registry;
+mod retry;
+#[cfg(feature = "smime")]
+mod smime;
+mod split;
+mod template;
+pub mod testing;
+mod text;
+mod transport;
+
+pub use archive::{ZipOptions, DEFAULT_MAX_ZIP_BYTES};
+use audit::{AuditAttachment, AuditLog, AuditRecord};
+pub use auth::{SmtpAuth, TokenProvider};
+#[cfg(feature = "serde")]
+pub use config_serde::WithPassword;
+use deadline::Deadline;
+#[cfg(feature = "dkim")]
+pub use dkim::{DkimKey, DkimSigner};
+#[cfg(feature = "handlebars")]
+pub use hbs::TemplateEngine;
+use hooks::Hook;
+pub use hooks::{FailedMessage, SendReport, SentMessage};
+pub use ics::IcsEvent;
+use lifecycle::Lifecycle;
+pub use mailbox::{parse_mailbox, parse_mailbox_list};
+use message::RawHeader;
+pub use message::{MailMessage, MailMessageBuilder, Priority, ReadReceipt};
+use metrics::MailerMetrics;
+pub use metrics::{MetricsSnapshot, LATENCY_BUCKETS};
+pub use queue::{Backpressure, MailQueue, QueueTicket};
+pub use rate::RateLimit;
+use rate::RateLimiter;
+#[cfg(feature = "envars")]
+pub use registry::MailerRegistry;
+pub use retry::RetryPolicy;
+use retry::{Retries, Sleep};
+#[cfg(feature = "smime")]
+pub use smime::SmimeSigner;
+use template::TemplateCache;
+pub use text::html_to_text;
+pub use transport::MailTransport;
+use transport::{Connector, FileDelivery, KeepAlive, SendmailDelivery, TransportSlot};
+
+/// How the connection to the SMTP server is secured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TlsMode {
+    /// Plaintext connection upgraded with `STARTTLS`, usually on port 587
+    #[default]
+    StartTls,
+    /// TLS from the first byte (SMTPS), usually on port 465
+    Wrapped,
+    /// Plaintext only, for relays on a trusted network; credentials are refused
+    None,
+}
+
+impl TlsMode {
+    /// Mode servers expect on the well-known submission ports
+    fn usual(port: u16) -> Option<TlsMode> {
+        match port {
+            465 => Some(TlsMode::Wrapped),
+            25 | 587 => Some(TlsMode::StartTls),
+            _ => None,
+        }
+    }
+
+    /// Why the mode cannot be used with the given user name, if it cannot
+    fn problem(self, user_name: &str) -> Option<String> {
+        match self {
+            TlsMode::None if !user_name.is_empty() => Some(
+                "TLS mode none would send the SMTP credentials in plaintext, remove them or use TLS"
+                    .to_owned(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TlsMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TlsMode::StartTls => "starttls",
+            TlsMode::Wrapped => "wrapped",
+            TlsMode::None => "none",
+        })
+    }
+}
+
+impl std::str::FromStr for TlsMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "starttls" => Ok(TlsMode::StartTls),
+            "wrapped" | "tls" | "smtps" => Ok(TlsMode::Wrapped),
+            "none" => Ok(TlsMode::None),
+            other => Err(format!(
+                "'{}' is not a TLS mode, expected starttls, wrapped or none",
+                other
+            )),
+        }
+    }
+}
+
+/// Binary of `Delivery::Sendmail` when none is given
+pub const DEFAULT_SENDMAIL_PATH: &str = "/usr/sbin/sendmail";
+
+/// Where the messages go
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Delivery {
+    /// To the configured SMTP server
+    #[default]
+    Smtp,
+    /// Into `.eml` files in this directory, for development; nothing is sent
+    File(PathBuf),
+    /// Piped into a sendmail binary, `DEFAULT_SENDMAIL_PATH` unless given, on hosts without an
+    /// SMTP relay; the SMTP settings go unused
+    Sendmail(Option<PathBuf>),
+}
+
+/// Mailer settings; prefer `Config::builder()`, which validates the values
+#[derive(Clone)]
+pub struct Config {
+    pub from_addrs: Mailbox,
+    /// Addresses of the `Reply-To` header, which is left out when there are none
+    pub reply_to: Vec<Mailbox>,
+    /// Sender of the SMTP envelope (`MAIL FROM`), where bounces go instead of `from_addrs`; the
+    /// `From` header is unchanged. `MailMessageBuilder::envelope_from` overrides it.
+    pub envelope_from: Option<Address>,
+    /// Domain of the generated `Message-ID`s, e.g. `mail.example.com`; without it lettre uses
+    /// the host name of the machine, which may be an internal one
+    pub message_id_domain: Option<String>,
+    /// Host name sent with `EHLO`, e.g. one the relay can resolve back to this host; without it
+    /// lettre sends the local host name
+    pub hello_name: Option<String>,
+    pub server: String,
+    pub port: u16,
+    pub tls_mode: TlsMode,
+    /// Skip the certificate and host name checks of the server, for self-signed certificates in
+    /// development only; every transport built with it logs a warning
+    pub allow_invalid_certs: bool,
+    pub user_name: String,
+    pub password: String,
+    /// Password login unless set otherwise
+    pub auth: SmtpAuth,
+    /// Holds the templates, footers and the partials they include with `{{> partials/header}}`
+    pub template_dir_path: String,
+    /// `{lang}` stands for the language and an optional `{name}` for the message's template
+    pub template_name_format: String,
+    pub languages: Vec<String>,
+    pub default_language: String,
+    /// Apply the template placeholders to the attachment names too
+    pub attachment_name_placeholders: bool,
+    /// Fail instead of dropping placeholders that have no value, overrides `lenient_placeholders`
+    pub strict_placeholders: bool,
+    /// Opening and closing placeholder delimiters, `{{` and `}}` by default
+    pub placeholder_delimiters: (String, String),
+    /// Placeholders that may be left in a rendered body, e.g. for a client side template
+    pub allowed_placeholders: Vec<String>,
+    /// Drop the unresolved placeholders of the body instead of failing the send
+    pub lenient_placeholders: bool,
+    /// Check the idle pooled connection at this interval from a background thread
+    pub keep_alive_interval: Option<Duration>,
+    /// JSON lines file receiving one record per send attempt
+    pub audit_log_path: Option<PathBuf>,
+    /// Put in front of every subject, e.g. `[STAGING]`
+    pub subject_prefix: Option<String>,
+    /// File name of the footer templates in the template directory, `{lang}` is replaced with the
+    /// language; a missing translation falls back to the default language
+    pub footer_name_format: Option<String>,
+    /// File name of the subjects files in the template directory, `{lang}` is replaced with the
+    /// language; see `MailMessageBuilder::subject_key`
+    pub subjects_name_format: Option<String>,
+    /// File name of the string catalogs in the template directory, `{lang}` is replaced with the
+    /// language; their `key=value` lines are the `{{catalog.key}}` placeholders of the templates,
+    /// a key missing from a language falls back to the default language
+    pub catalog_name_format: Option<String>,
+    /// Leave out the template checks of `Mailer::try_init`, for tooling that needs a fast start
+    pub skip_template_validation: bool,
+    /// Add a plain-text alternative converted from the final HTML with `html_to_text` to messages
+    /// without an explicit one; when off the `contents` value is used, and messages without one
+    /// are sent as HTML only
+    pub auto_plaintext: bool,
+    /// HTML-escape the `contents` value before it goes into a placeholder template, so text typed
+    /// by users shows as written; messages passing HTML opt out with
+    /// `MailMessageBuilder::escape_contents`. Handlebars templates escape values themselves.
+    pub escape_contents: bool,
+    /// Send once to an address listed more than once, keeping it in To, then Cc, then Bcc; the
+    /// domains are compared ignoring case
+    pub dedupe_recipients: bool,
+    /// Log the recipients as `c***a@example.com`, on by default; turn off in development
+    pub mask_log_addresses: bool,
+    /// Ceiling for a whole send, all connection attempts included
+    pub send_deadline: Option<Duration>,
+    /// Retries of sends that failed transiently, none by default
+    pub retry_policy: RetryPolicy,
+    /// Budget of messages per window shared by the mailer's clones, unlimited by default
+    pub rate_limit: Option<RateLimit>,
+    /// Build a new transport, and so a new connection, for every send instead of reusing the
+    /// pooled one
+    pub transport_per_send: bool,
+    /// Largest attachment accepted, in bytes before encoding; `None` or 0 for no limit
+    pub max_attachment_bytes: Option<u64>,
+    /// Largest message accepted, bodies and attachments in bytes before encoding; `None` or 0 for
+    /// no limit
+    pub max_message_bytes: Option<u64>,
+    /// Most recipients of a single message, To, Cc and Bcc together; a message with more goes
+    /// out as several, each with a share of the recipients in their role. `None` or 0 for no
+    /// limit
+    pub max_recipients_per_message: Option<usize>,
+    pub delivery: Delivery,
+    /// Limit for opening the SMTP connection; `None` keeps lettre's default of 60 seconds
+    pub connect_timeout: Option<Duration>,
+    /// Limit for every SMTP command to be answered; `None` keeps lettre's default of 60 seconds
+    pub command_timeout: Option<Duration>,
+    /// Most SMTP connections the mailer and its clones hold open at once, 10 by default; a send
+    /// finding all of them busy opens no other and waits for one to be returned. Keep it below
+    /// the provider's limit of concurrent connections per account or client IP, shared with
+    /// every other process sending through the same account, or sends fail with a 421 when the
+    /// relay turns the extra connections away. Unused with `transport_per_send`
+    pub pool_max_size: u32,
+    /// Connections kept open even when idle, 0 by default; they count towards the provider's
+    /// limit like busy ones
+    pub pool_min_idle: u32,
+    /// Idle connections beyond `pool_min_idle` are closed after this long, 60 seconds by
+    /// default; keep it below the relay's own idle timeout
+    pub pool_idle_timeout: Duration,
+    /// Signs every message, its key loaded by `ConfigBuilder::build`
+    #[cfg(feature = "dkim")]
+    pub dkim: Option<DkimSigner>,
+    /// Signs the body of every message for S/MIME clients, before any DKIM signature
+    #[cfg(feature = "smime")]
+    pub smime: Option<SmimeSigner>,
+    /// Placeholder replacement unless set otherwise
+    #[cfg(feature = "handlebars")]
+    pub template_engine: TemplateEngine,
+}
+
+impl Config {
+    const SECRET_MASK: &'static str = "***";
+
+    /// One line summary without secrets, meant for startup logging
+    pub fn redacted(&self) -> String {
+        format!(
+            "{}://{}@{}:{} from {} reply-to {}, templates {}, languages [{}] (default {})",
+            match self.tls_mode {
+                TlsMode::Wrapped => "smtps",
+                _ => "smtp",
+            },
+            self.user_name,
+            self.server,
+            self.port,
+            self.from_addrs,
+            match self.reply_to.is_empty() {
+                true => "none".to_owned(),
+                false => self
+                    .reply_to
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            },
+            Path::new(&self.template_dir_path)
+                .join(&self.template_name_format)
+                .display(),
+            self.languages.join(", "),
+            self.default_language
+        )
+    }
+}
+
+impl Config {
+    /// Name for `EHLO`, `None` for lettre's default; checked again for configs not made by the
+    /// builder
+    fn client_id(&self) -> Result<Option<ClientId>, ErrorReport> {
+        let Some(name) = &self.hello_name else {
+            return Ok(None);
+        };
+        match hello_name_problem(name.trim()) {
+            Some(problem) => Err(ErrorReport::MailConfigInvalid {
+                problems: vec![problem],
+            }),
+            None => Ok(Some(ClientId::Domain(name.trim().to_owned()))),
+        }
+    }
+
+    /// TLS parameters replacing lettre's defaults, only when certificate checks are disabled
+    fn tls_parameters(&self) -> Result<Option<TlsParameters>, ErrorReport> {
+        match self.invalid_certs_parameters() {
+            Some(builder) => {
+                log::warn!(
+                    "SMTP transport for {} accepts invalid certificates and host names, \
+                     never use allow_invalid_certs in production",
+                    self.server
+                );
+                Ok(Some(builder.build()?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn invalid_certs_parameters(&self) -> Option<TlsParametersBuilder> {
+        (self.allow_invalid_certs && self.tls_mode != TlsMode::None).then(|| {
+            TlsParameters::builder(self.server.clone())
+                .dangerous_accept_invalid_certs(true)
+                .dangerous_accept_invalid_hostnames(true)
+        })
+    }
+
+    /// Wraps the parameters as the TLS mode requires
+    fn tls(&self, parameters: TlsParameters) -> Tls {
+        match self.tls_mode {
+            TlsMode::Wrapped => Tls::Wrapper(parameters),
+            TlsMode::StartTls => Tls::Required(parameters),
+            TlsMode::None => Tls::None,
+        }
+    }
+
+    /// Timeout of the transport sockets; lettre has a single one for connecting and for every
+    /// read and write, so the longer of the two is used, `None` when lettre's default is kept
+    fn socket_timeout(&self) -> Option<Duration> {
+        match (self.connect_timeout, self.command_timeout) {
+            (Some(connect), Some(command)) => Some(connect.max(command)),
+            (connect, command) => connect.or(command),
+        }
+    }
+
+    /// Pooling of the SMTP connections, shared by the blocking and the async transports
+    fn pool_config(&self) -> PoolConfig {
+        PoolConfig::new()
+            .max_size(self.pool_max_size)
+            .min_idle(self.pool_min_idle)
+            .idle_timeout(self.pool_idle_timeout)
+    }
+
+    /// A failed send or check as reported to callers: a refused token becomes
+    /// `MailTokenRejected`, and a connection that breaks or stalls with a TLS mode the port does
+    /// not usually take becomes `MailTlsMismatch`
+    fn classify(&self, error: ErrorReport) -> ErrorReport {
+        let error = self.auth.classify(error);
+        let broken = match &error {
+            ErrorReport::MailTransportSmtp(e) => e.is_tls() || e.is_timeout(),
+            _ => false,
+        } || error.is_connection_error();
+        match TlsMode::usual(self.port) {
+            Some(expected) if broken && expected != self.tls_mode => ErrorReport::MailTlsMismatch {
+                server: format!("{}:{}", self.server, self.port),
+                mode: self.tls_mode.to_string(),
+                expected: expected.to_string(),
+                msg: error.to_string(),
+            },
+            _ => error,
+        }
+    }
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("Config");
+        debug
+            .field("from_addrs", &self.from_addrs)
+            .field("reply_to", &self.reply_to)
+            .field("envelope_from", &self.envelope_from)
+            .field("message_id_domain", &self.message_id_domain)
+            .field("hello_name", &self.hello_name)
+            .field("server", &self.server)
+            .field("port", &self.port)
+            .field("tls_mode", &self.tls_mode)
+            .field("allow_invalid_certs", &self.allow_invalid_certs)
+            .field("user_name", &self.user_name)
+            .field("password", &Self::SECRET_MASK)
+            .field("auth", &self.auth)
+            .field("template_dir_path", &self.template_dir_path)
+            .field("template_name_format", &self.template_name_format)
+            .field("languages", &self.languages)
+            .field("default_language", &self.default_language)
+            .field(
+                "attachment_name_placeholders",
+                &self.attachment_name_placeholders,
+            )
+            .field("strict_placeholders", &self.strict_placeholders)
+            .field("placeholder_delimiters", &self.placeholder_delimiters)
+            .field("allowed_placeholders", &self.allowed_placeholders)
+            .field("lenient_placeholders", &self.lenient_placeholders)
+            .field("keep_alive_interval", &self.keep_alive_interval)
+            .field("audit_log_path", &self.audit_log_path)
+            .field("subject_prefix", &self.subject_prefix)
+            .field("footer_name_format", &self.footer_name_format)
+            .field("subjects_name_format", &self.subjects_name_format)
+            .field("catalog_name_format", &self.catalog_name_format)
+            .field("skip_template_validation", &self.skip_template_validation)
+            .field("auto_plaintext", &self.auto_plaintext)
+            .field("escape_contents", &self.escape_contents)
+            .field("dedupe_recipients", &self.dedupe_recipients)
+            .field("mask_log_addresses", &self.mask_log_addresses)
+            .field("send_deadline", &self.send_deadline)
+            .field("retry_policy", &self.retry_policy)
+            .field("rate_limit", &self.rate_limit)
+            .field("transport_per_send", &self.transport_per_send)
+            .field("max_attachment_bytes", &self.max_attachment_bytes)
+            .field("max_message_bytes", &self.max_message_bytes)
+            .field(
+                "max_recipients_per_message",
+                &self.max_recipients_per_message,
+            )
+            .field("delivery", &self.delivery)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("command_timeout", &self.command_timeout)
+            .field("pool_max_size", &self.pool_max_size)
+            .field("pool_min_idle", &self.pool_min_idle)
+            .field("pool_idle_timeout", &self.pool_idle_timeout);
+        #[cfg(feature = "dkim")]
+        debug.field("dkim", &self.dkim);
+        #[cfg(feature = "smime")]
+        debug.field("smime", &self.smime);
+        #[cfg(feature = "handlebars")]
+        debug.field("template_engine", &self.template_engine);
+        debug.finish()
+    }
+}
+
+#[cfg(feature = "envars")]
+impl Config {
+    /// Reads the `MAIL:*` keys of the application config; without `MAIL:REPLY_TO` no Reply-To
+    /// header is set
+    pub fn from_app_config(app_config: &AppConfig) -> Result<Self, ErrorReport> {
+        Self::from_app_config_section(app_config, "")
+    }
+
+    /// Reads the `MAIL:*` keys that follow the given prefix, e.g. `TENANT:acme:MAIL:SMTP_SERVER`
+    /// for the prefix `TENANT:acme:`
+    pub fn from_app_config_section(
+        app_config: &AppConfig,
+        prefix: &str,
+    ) -> Result<Self, ErrorReport> {
+        let key = |name: &str| format!("{}{}", prefix, name);
+        let get = |name: &str| -> Result<&String, ErrorReport> {
+            app_config
+                .get_var(&key(name))
+                .ok_or_else(|| ErrorReport::ConfigMissingKey { key: key(name) })
+        };
+
+        let languages = get("MAIL:LANGS")?;
+        if languages.split(',').all(|val| val.trim().is_empty()) {
+            return Err(ErrorReport::ConfigInvalidValue {
+                key: key("MAIL:LANGS"),
+                msg: "no mail language was provided".to_owned(),
+            });
+        }
+
+        let mut builder =
+            Config::builder()
+                .from_addrs(get("MAIL:FROM_ADDRS")?)
+                .reply_to(
+                    app_config
+                        .get_var(&key("MAIL:REPLY_TO"))
+                        .map_or("", |val| val.as_str()),
+                )
+                .server(get("MAIL:SMTP_SERVER")?)
+                .port(get("MAIL:SMTP_PORT")?.trim().parse().map_err(
+                    |e: std::num::ParseIntError| ErrorReport::ConfigInvalidValue {
+                        key: key("MAIL:SMTP_PORT"),
+                        msg: e.to_string(),
+                    },
+                )?)
+                .credentials(get("MAIL:SMTP_USER")?, get("MAIL:SMTP_PASS")?)
+                .template_dir_path(app_config.get_path(&key("MAIL:TEMPLATE_DIR")).ok_or_else(
+                    || ErrorReport::ConfigMissingKey {
+                        key: key("MAIL:TEMPLATE_DIR"),
+                    },
+                )?)
+                .template_name_format(get("MAIL:TEMPLATE_NAME_FORMAT")?)
+                .languages(languages.split(','))
+                .default_language(get("MAIL:LANG_DEFAULT")?);
+        if let Some(name) = app_config.get_var(&key("MAIL:FROM_NAME")) {
+            builder = builder.from_name(name);
+        }
+        if let Some(prefix) = app_config.get_var(&key("MAIL:SUBJECT_PREFIX")) {
+            builder = builder.subject_prefix(prefix);
+        }
+        if let Some(format) = app_config.get_var(&key("MAIL:FOOTER_NAME_FORMAT")) {
+            builder = builder.footer_name_format(format);
+        }
+        if let Some(address) = app_config.get_var(&key("MAIL:ENVELOPE_FROM")) {
+            builder = builder.envelope_from(address);
+        }
+        if let Some(domain) = app_config.get_var(&key("MAIL:MESSAGE_ID_DOMAIN")) {
+            builder = builder.message_id_domain(domain);
+        }
+        if let Some(name) = app_config.get_var(&key("MAIL:SMTP_HELLO_NAME")) {
+            builder = builder.hello_name(name);
+        }
+        if let Some(format) = app_config.get_var(&key("MAIL:SUBJECTS_NAME_FORMAT")) {
+            builder = builder.subjects_name_format(format);
+        }
+        if let Some(format) = app_config.get_var(&key("MAIL:CATALOG_NAME_FORMAT")) {
+            builder = builder.catalog_name_format(format);
+        }
+        if let Some(mode) = parse(app_config, &key("MAIL:SMTP_TLS"))? {
+            builder = builder.tls_mode(mode);
+        }
+        if let Some(limit) = parse(app_config, &key("MAIL:MAX_ATTACHMENT_BYTES"))? {
+            builder = builder.max_attachment_bytes(limit);
+        }
+        if let Some(limit) = parse(app_config, &key("MAIL:MAX_MESSAGE_BYTES"))? {
+            builder = builder.max_message_bytes(limit);
+        }
+        if let Some(limit) = parse(app_config, &key("MAIL:MAX_RECIPIENTS_PER_MESSAGE"))? {
+            builder = builder.max_recipients_per_message(limit);
+        }
+        if let Some(size) = parse(app_config, &key("MAIL:SMTP_POOL_MAX_SIZE"))? {
+            builder = builder.pool_max_size(size);
+        }
+        if let Some(size) = parse(app_config, &key("MAIL:SMTP_POOL_MIN_IDLE"))? {
+            builder = builder.pool_min_idle(size);
+        }
+        if let Some(secs) = parse(app_config, &key("MAIL:SMTP_POOL_IDLE_TIMEOUT_SECS"))? {
+            builder = builder.pool_idle_timeout(Duration::from_secs(secs));
+        }
+        if let Some(dir) = app_config.get_path(&key("MAIL:DELIVERY_DIR")) {
+            builder = builder.delivery(Delivery::File(dir));
+        } else if let Some(path) = app_config.get_path(&key("MAIL:SENDMAIL_PATH")) {
+            builder = builder.delivery(Delivery::Sendmail(Some(path)));
+        }
+        if let Some(allow) = parse(app_config, &key("MAIL:SMTP_ALLOW_INVALID_CERTS"))? {
+            builder = builder.allow_invalid_certs(allow);
+        }
+        if let Some(skip) = parse(app_config, &key("MAIL:SKIP_TEMPLATE_VALIDATION"))? {
+            builder = builder.skip_template_validation(skip);
+        }
+        if let Some(auto) = parse(app_config, &key("MAIL:AUTO_PLAINTEXT"))? {
+            builder = builder.auto_plaintext(auto);
+        }
+        if let Some(escape) = parse(app_config, &key("MAIL:ESCAPE_CONTENTS"))? {
+            builder = builder.escape_contents(escape);
+        }
+        if let Some(dedupe) = parse(app_config, &key("MAIL:DEDUPE_RECIPIENTS"))? {
+            builder = builder.dedupe_recipients(dedupe);
+        }
+        if let Some(mask) = parse(app_config, &key("MAIL:MASK_LOG_ADDRESSES"))? {
+            builder = builder.mask_log_addresses(mask);
+        }
+        builder.build()
+    }
+}
+
+/// Value of an optional key, an error naming the key when it does not parse
+#[cfg(feature = "envars")]
+fn parse<T>(app_config: &AppConfig, key: &str) -> Result<Option<T>, ErrorReport>
+where
+    T: std::str::FromStr,
+    T::Err: fmt::Display,
+{
+    app_config
+        .get_var(key)
+        .map(|val| {
+            val.trim()
+                .parse()
+                .map_err(|e: T::Err| ErrorReport::ConfigInvalidValue {
+                    key: key.to_owned(),
+                    msg: e.to_string(),
+                })
+        })
+        .transpose()
+}
+
+impl Config {
+    /// Preferred way of creating a config, validated by `ConfigBuilder::build`
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+}
+
+/// SMTP timeouts of a built config unless set otherwise
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Most pooled SMTP connections unless set otherwise, as lettre has it
+pub const DEFAULT_POOL_MAX_SIZE: u32 = 10;
+
+/// Time after which idle pooled connections are closed unless set otherwise, as lettre has it
+pub const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Password or passphrase held by a `ConfigBuilder`, masked in its `Debug` output like the
+/// password of a `Config`
+#[derive(Clone, Default)]
+struct Secret(String);
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(Config::SECRET_MASK, f)
+    }
+}
+
+/// Fluent construction of a mailer `Config`.
+///
+/// Defaults: port 587 with STARTTLS, default language `en`, template name format `email_{lang}.html`,
+/// 30 second SMTP timeouts; without an explicit list the default language is the only one.
+#[derive(Debug, Clone)]
+pub struct ConfigBuilder {
+    from_addrs: Option<String>,
+    reply_to: Option<String>,
+    from_name: Option<String>,
+    envelope_from: Option<String>,
+    message_id_domain: Option<String>,
+    hello_name: Option<String>,
+    server: String,
+    port: u16,
+    tls_mode: Option<TlsMode>,
+    allow_invalid_certs: bool,
+    user_name: String,
+    password: Secret,
+    auth: SmtpAuth,
+    template_dir_path: Option<PathBuf>,
+    template_name_format: String,
+    languages: Vec<String>,
+    default_language: String,
+    attachment_name_placeholders: bool,
+    strict_placeholders: bool,
+    placeholder_delimiters: (String, String),
+    allowed_placeholders: Vec<String>,
+    lenient_placeholders: bool,
+    keep_alive_interval: Option<Duration>,
+    audit_log_path: Option<PathBuf>,
+    subject_prefix: Option<String>,
+    footer_name_format: Option<String>,
+    subjects_name_format: Option<String>,
+    catalog_name_format: Option<String>,
+    skip_template_validation: bool,
+    auto_plaintext: bool,
+    escape_contents: bool,
+    dedupe_recipients: bool,
+    mask_log_addresses: bool,
+    send_deadline: Option<Duration>,
+    retry_policy: RetryPolicy,
+    rate_limit: Option<RateLimit>,
+    transport_per_send: bool,
+    max_attachment_bytes: Option<u64>,
+    max_message_bytes: Option<u64>,
+    max_recipients_per_message: Option<usize>,
+    delivery: Delivery,
+    connect_timeout: Option<Duration>,
+    command_timeout: Option<Duration>,
+    pool_max_size: u32,
+    pool_min_idle: u32,
+    pool_idle_timeout: Duration,
+    #[cfg(feature = "dkim")]
+    dkim: Option<(String, String, DkimKey, Option<Secret>)>,
+    #[cfg(feature = "smime")]
+    smime: Option<(PathBuf, PathBuf, Option<Secret>)>,
+    #[cfg(feature = "handlebars")]
+    template_engine: TemplateEngine,
+}
+
+impl Default for ConfigBuilder {
+    fn default() -> Self {
+        ConfigBuilder {
+            from_addrs: None,
+            reply_to: None,
+            from_name: None,
+            envelope_from: None,
+            message_id_domain: None,
+            hello_name: None,
+            server: String::new(),
+            port: 587,
+            tls_mode: None,
+            allow_invalid_certs: false,
+            user_name: String::new(),
+            password: Secret::default(),
+            auth: SmtpAuth::Password,
+            template_dir_path: None,
+            template_name_format: "email_{lang}.html".to_owned(),
+            languages: Vec::new(),
+            default_language: "en".to_owned(),
+            attachment_name_placeholders: false,
+            strict_placeholders: false,
+            placeholder_delimiters: (
+                template::DELIMITERS.0.to_owned(),
+                template::DELIMITERS.1.to_owned(),
+            ),
+            allowed_placeholders: Vec::new(),
+            lenient_placeholders: false,
+            keep_alive_interval: None,
+            audit_log_path: None,
+            subject_prefix: None,
+            footer_name_format: None,
+            subjects_name_format: None,
+            catalog_name_format: None,
+            skip_template_validation: false,
+            auto_plaintext: true,
+            escape_contents: true,
+            dedupe_recipients: true,
+            mask_log_addresses: true,
+            send_deadline: None,
+            retry_policy: RetryPolicy::default(),
+            rate_limit: None,
+            transport_per_send: false,
+            max_attachment_bytes: None,
+            max_message_bytes: None,
+            max_recipients_per_message: None,
+            delivery: Delivery::Smtp,
+            connect_timeout: Some(DEFAULT_TIMEOUT),
+            command_timeout: Some(DEFAULT_TIMEOUT),
+            pool_max_size: DEFAULT_POOL_MAX_SIZE,
+            pool_min_idle: 0,
+            pool_idle_timeout: DEFAULT_POOL_IDLE_TIMEOUT,
+            #[cfg(feature = "dkim")]
+            dkim: None,
+            #[cfg(feature = "smime")]
+            smime: None,
+            #[cfg(feature = "handlebars")]
+            template_engine: TemplateEngine::Placeholders,
+        }
+    }
+}
+
+impl ConfigBuilder {
+    /// Sender, as `user@domain` or `Name <user@domain>`
+    pub fn from_addrs(mut self, mailbox: impl Into<String>) -> Self {
+        self.from_addrs = Some(mailbox.into());
+        self
+    }
+
+    /// Reply-to addresses, a single one or a list separated by commas or semicolons; an empty
+    /// value leaves the `Reply-To` header out, the sender is used when not set
+    pub fn reply_to(mut self, mailboxes: impl Into<String>) -> Self {
+        self.reply_to = Some(mailboxes.into());
+        self
+    }
+
+    /// Name sent with `EHLO`, see `Config::hello_name`; an empty one is refused
+    pub fn hello_name(mut self, name: impl Into<String>) -> Self {
+        self.hello_name = Some(name.into());
+        self
+    }
+
+    /// Domain of the generated `Message-ID`s, see `Config::message_id_domain`
+    pub fn message_id_domain(mut self, domain: impl Into<String>) -> Self {
+        self.message_id_domain = Some(domain.into());
+        self
+    }
+
+    /// Display name for the sender and reply-to addresses that do not carry one
+    pub fn from_name(mut self, name: impl Into<String>) -> Self {
+        self.from_name = Some(name.into());
+        self
+    }
+
+    /// Bounce address given in `MAIL FROM`, as `user@domain`; the sender when not set
+    pub fn envelope_from(mut self, address: impl Into<String>) -> Self {
+        self.envelope_from = Some(address.into());
+        self
+    }
+
+    pub fn server(mut self, server: impl Into<String>) -> Self {
+        self.server = server.into();
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// STARTTLS when not set, or wrapped TLS when the port is 465
+    pub fn tls_mode(mut self, mode: TlsMode) -> Self {
+        self.tls_mode = Some(mode);
+        self
+    }
+
+    /// Accepts self-signed certificates and mismatched host names, never enable in production
+    pub fn allow_invalid_certs(mut self, enabled: bool) -> Self {
+        self.allow_invalid_certs = enabled;
+        self
+    }
+
+    pub fn credentials(
+        mut self,
+        user_name: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        self.user_name = user_name.into();
+        self.password = Secret(password.into());
+        self
+    }
+
+    /// How to log in with the user name, password login by default; see `SmtpAuth::Xoauth2`
+    pub fn auth(mut self, auth: SmtpAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    pub fn template_dir_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.template_dir_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// File name of the templates, `{lang}` is replaced with the language; with a `{name}` too,
+    /// each message picks one of several templates by name, see `MailMessageBuilder::template`
+    pub fn template_name_format(mut self, format: impl Into<String>) -> Self {
+        self.template_name_format = format.into();
+        self
+    }
+
+    pub fn languages<I, S>(mut self, languages: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.languages = languages
+            .into_iter()
+            .map(|val| val.as_ref().trim().to_lowercase())
+            .filter(|val| !val.is_empty())
+            .collect();
+        self
+    }
+
+    pub fn default_language(mut self, language: impl AsRef<str>) -> Self {
+        self.default_language = language.as_ref().trim().to_lowercase();
+        self
+    }
+
+    pub fn attachment_name_placeholders(mut self, enabled: bool) -> Self {
+        self.attachment_name_placeholders = enabled;
+        self
+    }
+
+    pub fn strict_placeholders(mut self, enabled: bool) -> Self {
+        self.strict_placeholders = enabled;
+        self
+    }
+
+    /// Placeholder delimiters of the templates, e.g. `[[` and `]]`
+    pub fn placeholder_delimiters(
+        mut self,
+        open: impl Into<String>,
+        close: impl Into<String>,
+    ) -> Self {
+        self.placeholder_delimiters = (open.into(), close.into());
+        self
+    }
+
+    /// Placeholders that may be left unresolved in the body without failing the send
+    pub fn allowed_placeholders<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.allowed_placeholders = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Drops the unresolved placeholders of the body instead of failing the send
+    pub fn lenient_placeholders(mut self, enabled: bool) -> Self {
+        self.lenient_placeholders = enabled;
+        self
+    }
+
+    pub fn keep_alive(mut self, interval: Duration) -> Self {
+        self.keep_alive_interval = Some(interval);
+        self
+    }
+
+    /// Appends a JSON record of every send attempt to this file
+    pub fn audit_log(mut self, path: impl AsRef<Path>) -> Self {
+        self.audit_log_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn subject_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.subject_prefix = Some(prefix.into());
+        self
+    }
+
+    /// File name of the footer templates, `{lang}` is replaced with the language
+    pub fn footer_name_format(mut self, format: impl Into<String>) -> Self {
+        self.footer_name_format = Some(format.into());
+        self
+    }
+
+    /// File name of the subjects files, `{lang}` is replaced with the language, e.g.
+    /// `subjects_{lang}.txt`
+    pub fn subjects_name_format(mut self, format: impl Into<String>) -> Self {
+        self.subjects_name_format = Some(format.into());
+        self
+    }
+
+    /// File name of the string catalogs, `{lang}` is replaced with the language, e.g.
+    /// `strings_{lang}.properties`
+    pub fn catalog_name_format(mut self, format: impl Into<String>) -> Self {
+        self.catalog_name_format = Some(format.into());
+        self
+    }
+
+    /// Skips the template checks of `Mailer::try_init`, off by default
+    pub fn skip_template_validation(mut self, skip: bool) -> Self {
+        self.skip_template_validation = skip;
+        self
+    }
+
+    /// Convert the HTML into the plain-text alternative when none is given, on by default
+    pub fn auto_plaintext(mut self, enabled: bool) -> Self {
+        self.auto_plaintext = enabled;
+        self
+    }
+
+    /// HTML-escape the `contents` value, on by default; see `Config::escape_contents`
+    pub fn escape_contents(mut self, enabled: bool) -> Self {
+        self.escape_contents = enabled;
+        self
+    }
+
+    /// Drop the repeated recipients of a message, on by default; see `Config::dedupe_recipients`
+    pub fn dedupe_recipients(mut self, enabled: bool) -> Self {
+        self.dedupe_recipients = enabled;
+        self
+    }
+
+    /// Mask the recipient addresses in the log records, on by default; see
+    /// `Config::mask_log_addresses`
+    pub fn mask_log_addresses(mut self, enabled: bool) -> Self {
+        self.mask_log_addresses = enabled;
+        self
+    }
+
+    /// Upper bound for a whole send, see `Config::send_deadline`
+    pub fn send_deadline(mut self, deadline: Duration) -> Self {
+        self.send_deadline = Some(deadline);
+        self
+    }
+
+    /// Retries sends that failed with a 4xx answer or a lost connection
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Spaces the sends out to stay within the budget of the SMTP provider
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// Refuses attachments larger than this, 0 for no limit
+    pub fn max_attachment_bytes(mut self, limit: u64) -> Self {
+        self.max_attachment_bytes = Some(limit).filter(|val| *val > 0);
+        self
+    }
+
+    /// Refuses messages larger than this, bodies and attachments together; 0 for no limit
+    pub fn max_message_bytes(mut self, limit: u64) -> Self {
+        self.max_message_bytes = Some(limit).filter(|val| *val > 0);
+        self
+    }
+
+    /// Splits messages with more recipients than this over several, e.g. for relays refusing
+    /// too many `RCPT TO`; 0 for no limit
+    pub fn max_recipients_per_message(mut self, limit: usize) -> Self {
+        self.max_recipients_per_message = Some(limit).filter(|val| *val > 0);
+        self
+    }
+
+    pub fn delivery(mut self, delivery: Delivery) -> Self {
+        self.delivery = delivery;
+        self
+    }
+
+    /// Limit for opening the SMTP connection, 30 seconds by default; `None` for lettre's default
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Limit for every SMTP command to be answered, 30 seconds by default; `None` for lettre's
+    /// default
+    pub fn command_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.command_timeout = timeout;
+        self
+    }
+
+    /// Most SMTP connections open at once, 10 by default; see `Config::pool_max_size` before
+    /// raising it for a larger worker pool
+    pub fn pool_max_size(mut self, size: u32) -> Self {
+        self.pool_max_size = size;
+        self
+    }
+
+    /// Connections kept open while idle, 0 by default
+    pub fn pool_min_idle(mut self, size: u32) -> Self {
+        self.pool_min_idle = size;
+        self
+    }
+
+    /// Idle connections are closed after this long, 60 seconds by default
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_idle_timeout = timeout;
+        self
+    }
+
+    /// Connects anew for every send, as before transports were pooled
+    pub fn transport_per_send(mut self, enabled: bool) -> Self {
+        self.transport_per_send = enabled;
+        self
+    }
+
+    /// Signs the messages for the domain with the key published under the selector, e.g.
+    /// `mail2024._domainkey.example.com`; the passphrase is the one of an encrypted PEM
+    #[cfg(feature = "dkim")]
+    pub fn dkim(
+        mut self,
+        selector: impl Into<String>,
+        domain: impl Into<String>,
+        key: DkimKey,
+        passphrase: Option<&str>,
+    ) -> Self {
+        self.dkim = Some((
+            selector.into(),
+            domain.into(),
+            key,
+            passphrase.map(|val| Secret(val.to_owned())),
+        ));
+        self
+    }
+
+    /// Signs the messages with the certificate and its private key, both PEM files; the
+    /// certificate file may hold the intermediate certificates after the signer's
+    #[cfg(feature = "smime")]
+    pub fn smime(
+        mut self,
+        certificate_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+        passphrase: Option<&str>,
+    ) -> Self {
+        self.smime = Some((
+            certificate_path.as_ref().to_path_buf(),
+            key_path.as_ref().to_path_buf(),
+            passphrase.map(|val| Secret(val.to_owned())),
+        ));
+        self
+    }
+
+    /// Renders the templates with Handlebars instead of replacing their placeholders
+    #[cfg(feature = "handlebars")]
+    pub fn template_engine(mut self, engine: TemplateEngine) -> Self {
+        self.template_engine = engine;
+        self
+    }
+
+    /// Validates the values, reporting every problem found
+    pub fn build(self) -> Result<Config, ErrorReport> {
+        let mut problems = Vec::new();
+        let mut mailbox = |label: &str, value: Option<&String>| -> Option<Mailbox> {
+            let value = match value {
+                Some(val) => val,
+                None => {
+                    problems.push(format!("{} address is missing", label));
+                    return None;
+                }
+            };
+            match mailbox::parse(value) {
+                Ok(mut mailbox) => {
+                    if mailbox.name.is_none() {
+                        mailbox.name = self.from_name.clone();
+                    }
+                    Some(mailbox)
+                }
+                Err(e) => {
+                    problems.push(format!("{} address '{}' is invalid: {}", label, value, e));
+                    None
+                }
+            }
+        };
+        let from_addrs = mailbox("from", self.from_addrs.as_ref());
+        let reply_to = match &self.reply_to {
+            Some(list) => mailbox::split(list)
+                .into_iter()
+                .map(|val| mailbox("reply-to", Some(&val.to_owned())))
+                .collect(),
+            None => vec![from_addrs.clone()],
+        };
+        let envelope_from = match &self.envelope_from {
+            Some(address) => match address.trim().parse::<Address>() {
+                Ok(address) => Some(address),
+                Err(e) => {
+                    problems.push(format!("envelope sender '{}' is invalid: {}", address, e));
+                    None
+                }
+            },
+            None => None,
+        };
+        let message_id_domain = self
+            .message_id_domain
+            .map(|val| val.trim().to_owned())
+            .filter(|val| !val.is_empty());
+        if let Some(domain) = &message_id_domain {
+            if let Err(e) = Address::new("id", domain) {
+                problems.push(format!("Message-ID domain '{}' is invalid: {}", domain, e));
+            }
+        }
+        let hello_name = self.hello_name.map(|val| val.trim().to_owned());
+        if let Some(problem) = hello_name.as_deref().and_then(hello_name_problem) {
+            problems.push(problem);
+        }
+
+        if self.server.trim().is_empty() {
+            problems.push("server is empty".to_owned());
+        }
+        let tls_mode = self
+            .tls_mode
+            .or(TlsMode::usual(self.port))
+            .unwrap_or_default();
+        if let Some(problem) = tls_mode.problem(&self.user_name) {
+            problems.push(problem);
+        }
+        if self.port == 0 {
+            problems.push("port must not be 0".to_owned());
+        }
+        let languages = if self.languages.is_empty() {
+            vec![self.default_language.clone()]
+        } else {
+            self.languages
+        };
+        problems.extend(language_problem(&self.default_language, &languages));
+        if self.keep_alive_interval == Some(Duration::ZERO) {
+            problems.push("keep-alive interval must not be 0".to_owned());
+        }
+        if self.send_deadline == Some(Duration::ZERO) {
+            problems.push("send deadline must not be 0".to_owned());
+        }
+        if self.connect_timeout == Some(Duration::ZERO) {
+            problems.push("connect timeout must not be 0".to_owned());
+        }
+        if self.command_timeout == Some(Duration::ZERO) {
+            problems.push("command timeout must not be 0".to_owned());
+        }
+        if self.pool_max_size == 0 {
+            problems.push("pool max size must not be 0".to_owned());
+        } else if self.pool_min_idle > self.pool_max_size {
+            problems.push(format!(
+                "pool min idle {} is larger than the pool max size {}",
+                self.pool_min_idle, self.pool_max_size
+            ));
+        }
+        if self.pool_idle_timeout.is_zero() {
+            problems.push("pool idle timeout must not be 0".to_owned());
+        }
+        problems.extend(self.retry_policy.problem());
+        problems.extend(self.rate_limit.as_ref().and_then(RateLimit::problem));
+        let (open, close) = &self.placeholder_delimiters;
+        if open.trim().is_empty() || close.trim().is_empty() {
+            problems.push("placeholder delimiters must not be empty".to_owned());
+        }
+        if let Some(dir) = self.audit_log_path.as_ref().and_then(|val| val.parent()) {
+            if !dir.as_os_str().is_empty() && !dir.is_dir() {
+                problems.push(format!(
+                    "audit log directory {} does not exist",
+                    dir.display()
+                ));
+            }
+        }
+        #[cfg(feature = "dkim")]
+        let dkim = match &self.dkim {
+            Some((selector, domain, key, passphrase)) => {
+                let (selector, domain) = (selector.trim(), domain.trim());
+                if selector.is_empty() || domain.is_empty() {
+                    problems.push("DKIM selector and domain must not be empty".to_owned());
+                }
+                match DkimSigner::new(
+                    selector,
+                    domain,
+                    key,
+                    passphrase.as_ref().map(|val| val.0.as_str()),
+                ) {
+                    Ok(signer) => Some(signer),
+                    Err(e) => {
+                        problems.push(format!("DKIM key could not be loaded: {}", e));
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        #[cfg(feature = "smime")]
+        let smime = match &self.smime {
+            Some((certificate, key, passphrase)) => {
+                match SmimeSigner::from_pem_files(
+                    certificate,
+                    key,
+                    passphrase.as_ref().map(|val| val.0.as_str()),
+                ) {
+                    Ok(signer) => Some(signer),
+                    Err(e) => {
+                        problems.push(format!("S/MIME certificate could not be loaded: {}", e));
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        if let Some(path) = &self.template_dir_path {
+            if !path.is_dir() {
+                problems.push(format!(
+                    "template directory {} does not exist",
+                    path.display()
+                ));
+            }
+        }
+
+        match (from_addrs, reply_to.into_iter().collect::<Option<Vec<_>>>()) {
+            (Some(from_addrs), Some(reply_to)) if problems.is_empty() => Ok(Config {
+                from_addrs,
+                reply_to,
+                envelope_from,
+                message_id_domain,
+                hello_name,
+                server: self.server.trim().to_owned(),
+                port: self.port,
+                tls_mode,
+                allow_invalid_certs: self.allow_invalid_certs,
+                user_name: self.user_name,
+                password: self.password.0,
+                auth: self.auth,
+                template_dir_path: self
+                    .template_dir_path
+                    .map(|val| val.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                template_name_format: self.template_name_format,
+                languages,
+                default_language: self.default_language,
+                attachment_name_placeholders: self.attachment_name_placeholders,
+                strict_placeholders: self.strict_placeholders,
+                placeholder_delimiters: self.placeholder_delimiters,
+                allowed_placeholders: self.allowed_placeholders,
+                lenient_placeholders: self.lenient_placeholders,
+                keep_alive_interval: self.keep_alive_interval,
+                audit_log_path: self.audit_log_path,
+                subject_prefix: self
+                    .subject_prefix
+                    .map(|val| val.trim().to_owned())
+                    .filter(|val| !val.is_empty()),
+                footer_name_format: self.footer_name_format,
+                subjects_name_format: self.subjects_name_format,
+                catalog_name_format: self.catalog_name_format,
+                skip_template_validation: self.skip_template_validation,
+                auto_plaintext: self.auto_plaintext,
+                escape_contents: self.escape_contents,
+                dedupe_recipients: self.dedupe_recipients,
+                mask_log_addresses: self.mask_log_addresses,
+                send_deadline: self.send_deadline,
+                retry_policy: self.retry_policy,
+                rate_limit: self.rate_limit,
+                transport_per_send: self.transport_per_send,
+                max_attachment_bytes: self.max_attachment_bytes,
+                max_message_bytes: self.max_message_bytes,
+                max_recipients_per_message: self.max_recipients_per_message,
+                delivery: self.delivery,
+                connect_timeout: self.connect_timeout,
+                command_timeout: self.command_timeout,
+                pool_max_size: self.pool_max_size,
+                pool_min_idle: self.pool_min_idle,
+                pool_idle_timeout: self.pool_idle_timeout,
+                #[cfg(feature = "dkim")]
+                dkim,
+                #[cfg(feature = "smime")]
+                smime,
+                #[cfg(feature = "handlebars")]
+                template_engine: self.template_engine,
+            }),
+            _ => Err(ErrorReport::MailConfigInvalid { problems }),
+        }
+    }
+}
+
+fn hello_name_problem(name: &str) -> Option<String> {
+    if name.is_empty() {
+        return Some("hello name is empty".to_owned());
+    }
+    Address::new("hello", name)
+        .err()
+        .map(|e| format!("hello name '{}' is invalid: {}", name, e))
+}
+
+fn language_problem(default_language: &str, languages: &[String]) -> Option<String> {
+    if default_language.is_empty() {
+        return Some("default language is empty".to_owned());
+    }
+    match languages.iter().any(|val| val == default_language) {
+        true => None,
+        false => Some(format!(
+            "default language '{}' is not one of the languages [{}]",
+            default_language,
+            languages.join(", ")
+        )),
+    }
+}
+
+/// Where the body of an attachment comes from
+#[derive(Clone)]
+pub enum AttachmentSource<'a> {
+    /// File read when the message is built
+    Path(&'a str),
+    /// Content generated in memory, e.g. a PDF invoice, borrowed or owned
+    Bytes(Cow<'a, [u8]>),
+}
+
+impl AttachmentSource<'_> {
+    fn read(&self) -> Result<Cow<'_, [u8]>, ErrorReport> {
+        match self {
+            AttachmentSource::Path(path) => Ok(Cow::Owned(fs::read(path)?)),
+            AttachmentSource::Bytes(bytes) => Ok(Cow::Borrowed(bytes)),
+        }
+    }
+
+    fn size(&self) -> u64 {
+        match self {
+            AttachmentSource::Path(path) => fs::metadata(path).map_or(0, |val| val.len()),
+            AttachmentSource::Bytes(bytes) => bytes.len() as u64,
+        }
+    }
+}
+
+impl<'a> From<&'a str> for AttachmentSource<'a> {
+    fn from(path: &'a str) -> Self {
+        AttachmentSource::Path(path)
+    }
+}
+
+impl<'a> From<&'a [u8]> for AttachmentSource<'a> {
+    fn from(bytes: &'a [u8]) -> Self {
+        AttachmentSource::Bytes(Cow::Borrowed(bytes))
+    }
+}
+
+impl From<Vec<u8>> for AttachmentSource<'_> {
+    fn from(bytes: Vec<u8>) -> Self {
+        AttachmentSource::Bytes(Cow::Owned(bytes))
+    }
+}
+
+impl fmt::Debug for AttachmentSource<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttachmentSource::Path(path) => f.debug_tuple("Path").field(path).finish(),
+            AttachmentSource::Bytes(bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MailAttachment<'a> {
+    pub source: AttachmentSource<'a>,
+    pub name: &'a str,
+    /// Content type, guessed from the extension of the name when empty
+    pub mime: &'a str,
+    /// Shown inline where the HTML refers to it, e.g. `logo` for `<img src="cid:logo">`,
+    /// instead of as a file
+    pub content_id: Option<&'a str>,
+    /// How clients offer a part without `content_id`; parts with one are always inline
+    pub disposition: Disposition,
+}
+
+/// `Content-Disposition` of an attachment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Disposition {
+    /// Offered as a file to save
+    #[default]
+    Attachment,
+    /// Shown in the message where the client can, e.g. an image after the body
+    Inline,
+}
+
+impl<'a> MailAttachment<'a> {
+    /// Attaches a file with the given name and content type, as the `path` field used to
+    pub fn new(path: &'a str, name: &'a str, mime: &'a str) -> Self {
+        MailAttachment {
+            source: AttachmentSource::Path(path),
+            name,
+            mime,
+            content_id: None,
+            disposition: Disposition::Attachment,
+        }
+    }
+
+    /// Attaches a file under its own name, with the content type guessed from its extension;
+    /// set `mime` afterwards to override the guess
+    pub fn from_path(path: &'a str) -> Self {
+        let name = Path::new(path)
+            .file_name()
+            .and_then(|val| val.to_str())
+            .unwrap_or(path);
+        MailAttachment {
+            source: AttachmentSource::Path(path),
+            name,
+            mime: mime::from_name(name),
+            content_id: None,
+            disposition: Disposition::Attachment,
+        }
+    }
+
+    fn content_type(&self) -> &str {
+        match self.mime.trim() {
+            "" => mime::from_name(self.name),
+            val => val,
+        }
+    }
+}
+
+/// What a send attempt records before the message is built, for the audit log and the hooks
+struct Attempt {
+    started: SystemTime,
+    timer: Instant,
+    to: Vec<Mailbox>,
+    cc: Vec<Mailbox>,
+    subject: String,
+    attachments: Vec<AuditAttachment>,
+}
+
+/// Sends templated mail; clones share the transport and its pooled connections
+#[derive(Clone)]
+pub struct Mailer {
+    config: Config,
+    connector: Connector,
+    transport: Arc<TransportSlot>,
+    keep_alive: Arc<Mutex<Option<KeepAlive>>>,
+    lifecycle: Arc<Lifecycle>,
+    metrics: Arc<MailerMetrics>,
+    audit: Option<Arc<AuditLog>>,
+    on_sent: Option<Hook<SentMessage>>,
+    on_failed: Option<Hook<FailedMessage>>,
+    sleep: Sleep,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    /// Templates read by `init_with_preload` or `reload_templates`, shared by the clones
+    templates: Arc<RwLock<Option<Arc<TemplateCache>>>>,
+    /// SMTP transport of `send_async`, `None` for mailers with a custom transport
+    #[cfg(feature = "async")]
+    async_transport: Option<Arc<asynchronous::AsyncTransportSlot>>,
+}
+
+impl fmt::Debug for Mailer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mailer")
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Mailer {
+    /// The SMTP transport is built on first use and shared by all the sends of this mailer and
+    /// its clones, lettre pools its connections; see `Config::transport_per_send`. With
+    /// `Delivery::File` the messages are written to disk instead, with `Delivery::Sendmail` piped
+    /// into sendmail.
+    pub fn init(config: Config) -> Self {
+        let connector: Connector = match config.delivery.clone() {
+            Delivery::Smtp => {
+                let smtp_config = config.clone();
+                Arc::new(move || Ok(Arc::new(Self::smtp_transport(&smtp_config)?)))
+            }
+            Delivery::File(dir) => Arc::new(move || Ok(Arc::new(FileDelivery::new(dir.clone())?))),
+            Delivery::Sendmail(path) => {
+                let command = path.unwrap_or_else(|| PathBuf::from(DEFAULT_SENDMAIL_PATH));
+                Arc::new(move || Ok(Arc::new(SendmailDelivery::new(command.clone()))))
+            }
+        };
+        #[allow(unused_mut)]
+        let mut mailer = Self::init_with_connector(config, connector);
+        #[cfg(feature = "async")]
+        if mailer.config.delivery == Delivery::Smtp {
+            mailer.async_transport = Some(Arc::new(RwLock::new(None)));
+        }
+        mailer
+    }
+
+    /// Same as `init`, with the SMTP transport built right away, so a relay that cannot be set up
+    /// fails here rather than at the first send; a config whose default language is not one of its
+    /// languages is refused as well, as are broken templates, see `validate`
+    pub fn try_init(config: Config) -> Result<Self, ErrorReport> {
+        if let Some(problem) = language_problem(&config.default_language, &config.languages) {
+            return Err(ErrorReport::MailConfigInvalid {
+                problems: vec![problem],
+            });
+        }
+        let mailer = Self::init(config);
+        if !mailer.config.skip_template_validation {
+            mailer.validate()?;
+        }
+        mailer.transport()?;
+        Ok(mailer)
+    }
+
+    /// Mailer delivering through the given transport instead of SMTP
+    pub fn init_with_transport(config: Config, transport: Arc<dyn MailTransport>) -> Self {
+        Self::init_with_connector(config, Arc::new(move || Ok(transport.clone())))
+    }
+
+    fn init_with_connector(config: Config, connector: Connector) -> Self {
+        let audit = config
+            .audit_log_path
+            .as_ref()
+            .map(|path| Arc::new(AuditLog::new(path)));
+        let rate_limiter = config
+            .rate_limit
+            .map(|limit| Arc::new(RateLimiter::new(limit)));
+        Mailer {
+            config,
+            connector,
+            transport: Arc::new(RwLock::new(None)),
+            keep_alive: Arc::new(Mutex::new(None)),
+            lifecycle: Arc::new(Lifecycle::default()),
+            metrics: Arc::new(MailerMetrics::default()),
+            audit,
+            on_sent: None,
+            on_failed: None,
+            sleep: retry::thread_sleep(),
+            rate_limiter,
+            templates: Arc::new(RwLock::new(None)),
+            #[cfg(feature = "async")]
+            async_transport: None,
+        }
+    }
+
+    /// Same as `init`, with the templates and footers of all the configured languages read once
+    /// up front, of every name found with `template_names`; fails with `MailTemplatesMissing`
+    /// when any of them is missing. Sends then use the cached copies, see `reload_templates`.
+    pub fn init_with_preload(config: Config) -> Result<Self, ErrorReport> {
+        let mailer = Self::init(config);
+        mailer.reload_templates()?;
+        Ok(mailer)
+    }
+
+    /// Reads the templates again, e.g. after they were edited, for this mailer and its clones;
+    /// on failure the previously cached templates stay in use
+    pub fn reload_templates(&self) -> Result<(), ErrorReport> {
+        let mut cache = TemplateCache::default();
+        let mut missing = Vec::new();
+        let names = self.template_units()?;
+        for lang in &self.config.languages {
+            let templates = names.iter().map(|name| {
+                (
+                    template::key(name.as_deref(), lang),
+                    self.template_file(name.as_deref(), lang),
+                )
+            });
+            let footer = self.footer_path(lang).map(|path| (String::new(), path));
+            for (key, path) in templates.chain(footer) {
+                if !path.is_file() {
+                    let path = path.to_string_lossy().into_owned();
+                    if !missing.contains(&path) {
+                        missing.push(path);
+                    }
+                    continue;
+                }
+                let text = self.load_source(&path, lang)?;
+                match key.is_empty() {
+                    false => cache.templates.insert(key, text),
+                    true => cache.footers.insert(lang.clone(), text),
+                };
+            }
+        }
+        if !missing.is_empty() {
+            return Err(ErrorReport::MailTemplatesMissing { paths: missing });
+        }
+        // subjects are optional, a missing one falls back to the default language
+        for lang in &self.config.languages {
+            cache
+                .subjects
+                .insert(lang.clone(), self.read_subjects(lang));
+        }
+        for lang in &self.config.languages {
+            cache
+                .catalogs
+                .insert(lang.clone(), self.read_catalog(lang)?);
+        }
+        #[cfg(feature = "handlebars")]
+        if self.config.template_engine == TemplateEngine::Handlebars {
+            let mut registry = hbs::registry(self.config.strict_placeholders);
+            for (key, text) in &cache.templates {
+                hbs::compile(&mut registry, &hbs::body_name(key), text)?;
+            }
+            for (lang, text) in &cache.footers {
+                hbs::compile(&mut registry, &hbs::footer_name(lang), text)?;
+            }
+            cache.compiled = Some(registry);
+        }
+        *self.templates.write().unwrap() = Some(Arc::new(cache));
+        Ok(())
+    }
+
+    /// Called with every message the server accepted, e.g. to archive it.
+    ///
+    /// Hooks run synchronously on the sending thread, so a slow hook delays the return of `send`;
+    /// a panicking hook is caught and never fails the send.
+    pub fn on_sent(mut self, hook: impl Fn(&SentMessage) + Send + Sync + 'static) -> Self {
+        self.on_sent = Some(Arc::new(hook));
+        self
+    }
+
+    /// Called with every failed send attempt, same rules as `on_sent`
+    pub fn on_failed(mut self, hook: impl Fn(&FailedMessage) + Send + Sync + 'static) -> Self {
+        self.on_failed = Some(Arc::new(hook));
+        self
+    }
+
+    /// Replaces the blocking wait between retries, e.g. to record the delays in tests
+    pub fn with_sleep(mut self, sleep: impl Fn(Duration) + Send + Sync + 'static) -> Self {
+        self.sleep = Arc::new(sleep);
+        self
+    }
+
+    pub fn get_config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Languages tried for a requested one, in order: the tag trimmed and lowercased, with `_`
+    /// read as `-`, then the tag without its last subtag until only the primary one is left
+    /// (`pt-br`, `pt`), then the default language
+    pub fn language_chain(&self, requested: Option<&str>) -> Vec<String> {
+        let mut chain = Vec::new();
+        let requested = requested
+            .map(|val| val.trim().to_lowercase().replace('_', "-"))
+            .unwrap_or_default();
+        let mut tag = requested.trim_matches('-');
+        while !tag.is_empty() {
+            chain.push(tag.to_owned());
+            tag = tag
+                .rfind('-')
+                .map_or("", |end| tag[..end].trim_end_matches('-'));
+        }
+        if !chain.contains(&self.config.default_language) {
+            chain.push(self.config.default_language.clone());
+        }
+        chain
+    }
+
+    /// First configured language of the `language_chain`; the chain is logged at debug level
+    pub fn language(&self, requested: Option<&str>) -> String {
+        let chain = self.language_chain(requested);
+        let language = self.first_configured(&chain);
+        let requested = match requested {
+            Some(val) => val,
+            None => return language,
+        };
+        log::debug!(
+            "Mail language '{}' resolved to '{}', tried [{}]",
+            requested,
+            language,
+            chain.join(", ")
+        );
+        if language == self.config.default_language && chain[0] != language {
+            log::warn!(
+                "Mail language '{}' is not configured, using '{}'",
+                requested,
+                self.config.default_language
+            );
+        }
+        language
+    }
+
+    fn first_configured(&self, chain: &[String]) -> String {
+        chain
+            .iter()
+            .find(|val| self.config.languages.contains(val))
+            .unwrap_or(&self.config.default_language)
+            .clone()
+    }
+
+    /// Path of the template file for the given language
+    pub fn template_path(&self, language: &str) -> PathBuf {
+        self.template_file(None, language)
+    }
+
+    /// Path of a named template for the given language, see `MailMessageBuilder::template`
+    pub fn named_template_path(&self, name: &str, language: &str) -> PathBuf {
+        self.template_file(Some(name), language)
+    }
+
+    fn template_file(&self, name: Option<&str>, language: &str) -> PathBuf {
+        let mut file_name = self.config.template_name_format.replace("{lang}", language);
+        if let Some(name) = name {
+            file_name = file_name.replace("{name}", name);
+        }
+        Path::new(&self.config.template_dir_path).join(file_name)
+    }
+
+    /// Names of the templates in the template directory, for a `Config::template_name_format`
+    /// with a `{name}`: the part of the file names standing for it, with any configured language
+    pub fn template_names(&self) -> Result<Vec<String>, ErrorReport> {
+        let format = &self.config.template_name_format;
+        if !format.contains("{name}") {
+            return Ok(Vec::new());
+        }
+        let patterns: Vec<String> = self
+            .config
+            .languages
+            .iter()
+            .map(|lang| format.replace("{lang}", lang))
+            .collect();
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.config.template_dir_path)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let file_name = entry.file_name();
+            let file_name = match file_name.to_str() {
+                Some(val) => val,
+                None => continue,
+            };
+            for pattern in &patterns {
+                let (prefix, suffix) = pattern.split_once("{name}").unwrap_or((pattern, ""));
+                let name = file_name
+                    .strip_prefix(prefix)
+                    .and_then(|val| val.strip_suffix(suffix))
+                    .filter(|val| template::is_name(val));
+                if let Some(name) = name {
+                    if !names.iter().any(|val| val == name) {
+                        names.push(name.to_owned());
+                    }
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// The templates to read for every language: the discovered names, or the single unnamed
+    /// template; a format with `{name}` and no template on disk is reported missing
+    fn template_units(&self) -> Result<Vec<Option<String>>, ErrorReport> {
+        if !self.config.template_name_format.contains("{name}") {
+            return Ok(vec![None]);
+        }
+        let names = self.template_names()?;
+        if names.is_empty() {
+            return Err(ErrorReport::MailTemplatesMissing {
+                paths: vec![self
+                    .template_path(&self.config.default_language)
+                    .to_string_lossy()
+                    .into_owned()],
+            });
+        }
+        Ok(names.into_iter().map(Some).collect())
+    }
+
+    /// Name of the message's template, which it must have exactly when the format has a `{name}`
+    fn template_name<'m>(&self, message: &'m MailMessage) -> Result<Option<&'m str>, ErrorReport> {
+        let named = self.config.template_name_format.contains("{name}");
+        let problem = match (named, message.template.as_deref()) {
+            (true, None) => "no template name, the template name format has a {name}".to_owned(),
+            (false, Some(name)) => format!(
+                "template name '{}' given, the template name format has no {{name}}",
+                name
+            ),
+            (_, name) => return Ok(name),
+        };
+        Err(ErrorReport::MailMessageInvalid {
+            problems: vec![problem],
+        })
+    }
+
+    /// Path of the footer template for the given language, falling back to the default language
+    /// when there is no translation; `None` when no footer is configured
+    pub fn footer_path(&self, language: &str) -> Option<PathBuf> {
+        let format = self.config.footer_name_format.as_ref()?;
+        let path =
+            Path::new(&self.config.template_dir_path).join(format.replace("{lang}", language));
+        if path.is_file() {
+            return Some(path);
+        }
+        Some(
+            Path::new(&self.config.template_dir_path)
+                .join(format.replace("{lang}", &self.config.default_language)),
+        )
+    }
+
+    /// Path of the subjects file of the given language, `None` when none is configured
+    pub fn subjects_path(&self, language: &str) -> Option<PathBuf> {
+        let format = self.config.subjects_name_format.as_ref()?;
+        Some(Path::new(&self.config.template_dir_path).join(format.replace("{lang}", language)))
+    }
+
+    /// Subject of the key in the subjects file of the language, else of the default language, else
+    /// the key itself; placeholders are replaced with the variables, as in the body
+    pub fn localized_subject(
+        &self,
+        key: &str,
+        language: Option<&str>,
+        vars: &HashMap<String, String>,
+    ) -> String {
+        let lang = self.language(language);
+        let cache = self.templates.read().unwrap().clone();
+        let mut languages = vec![lang.as_str()];
+        if lang != self.config.default_language {
+            languages.push(&self.config.default_language);
+        }
+        let subject = languages
+            .into_iter()
+            .find_map(|lang| match cache.as_ref() {
+                Some(cache) => cache
+                    .subjects
+                    .get(lang)
+                    .and_then(|val| val.get(key))
+                    .cloned(),
+                None => self.read_subjects(lang).remove(key),
+            })
+            .unwrap_or_else(|| key.to_owned());
+        template::render(&subject, vars, false, self.delimiters()).text
+    }
+
+    /// Subjects of a language, none when the file is missing or unreadable
+    fn read_subjects(&self, language: &str) -> HashMap<String, String> {
+        let path = match self.subjects_path(language) {
+            Some(val) if val.is_file() => val,
+            _ => return HashMap::new(),
+        };
+        match template::load(&path) {
+            Ok(text) => template::parse_subjects(&text),
+            Err(e) => {
+                log::warn!("Subjects file {} skipped: {}", path.display(), e);
+                HashMap::new()
+            }
+        }
+    }
+
+    /// Path of the string catalog of the given language, `None` when none is configured
+    pub fn catalog_path(&self, language: &str) -> Option<PathBuf> {
+        let format = self.config.catalog_name_format.as_ref()?;
+        Some(Path::new(&self.config.template_dir_path).join(format.replace("{lang}", language)))
+    }
+
+    /// Catalog entries of the language over those of the default language
+    fn catalog(&self, language: &str) -> Result<HashMap<String, String>, ErrorReport> {
+        let cache = self.templates.read().unwrap().clone();
+        let mut languages = vec![self.config.default_language.as_str()];
+        if language != self.config.default_language {
+            languages.push(language);
+        }
+        let mut catalog = HashMap::new();
+        for lang in languages {
+            match cache.as_ref().and_then(|val| val.catalogs.get(lang)) {
+                Some(entries) => catalog.extend(entries.clone()),
+                None => catalog.extend(self.read_catalog(lang)?),
+            }
+        }
+        Ok(catalog)
+    }
+
+    /// Catalog of a language, none when the file is missing; unlike subjects, a file that cannot
+    /// be read or parsed fails the send
+    fn read_catalog(&self, language: &str) -> Result<HashMap<String, String>, ErrorReport> {
+        match self.catalog_path(language) {
+            Some(path) if path.is_file() => template::parse_catalog(&template::load(&path)?, &path),
+            _ => Ok(HashMap::new()),
+        }
+    }
+
+    /// Checks the files `validate_templates` looks for, then that every template is readable, that
+    /// an unnamed one holds the `{{contents}}` placeholder the body goes into, and that the string
+    /// catalog of every language parses. Fails with `MailTemplatesInvalid` listing every problem
+    /// found
+    pub fn validate(&self) -> Result<(), ErrorReport> {
+        let mut problems = match self.validate_templates() {
+            Ok(_) => Vec::new(),
+            Err(ErrorReport::MailTemplatesMissing { paths }) => paths
+                .iter()
+                .map(|path| format!("{} does not exist", path))
+                .collect(),
+            Err(e) => return Err(e),
+        };
+        let names = self.template_units()?;
+        let delimiters = self.delimiters();
+        for lang in &self.config.languages {
+            for name in &names {
+                let path = self.template_file(name.as_deref(), lang);
+                if !path.is_file() {
+                    continue;
+                }
+                match self.load_source(&path, lang) {
+                    Ok(source) => {
+                        let has_contents = template::unresolved(&source, delimiters)
+                            .iter()
+                            .any(|val| val == "contents");
+                        if name.is_none() && !has_contents {
+                            problems.push(format!(
+                                "{} has no {}contents{} placeholder",
+                                path.display(),
+                                delimiters.0,
+                                delimiters.1
+                            ));
+                        }
+                    }
+                    Err(e) => problems.push(format!("{} cannot be read: {}", path.display(), e)),
+                }
+            }
+            if let Err(e) = self.read_catalog(lang) {
+                problems.push(e.to_string());
+            }
+        }
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(ErrorReport::MailTemplatesInvalid { problems }),
+        }
+    }
+
+    /// Checks that a template exists for every configured language, of every name found with
+    /// `template_names`, and the default footer when one is configured.
+    ///
+    /// Fails with a report of all the missing files; on success returns the languages that have a
+    /// template on disk but are not configured
+    pub fn validate_templates(&self) -> Result<Vec<String>, ErrorReport> {
+        let names = self.template_units()?;
+        let missing: Vec<String> = self
+            .config
+            .languages
+            .iter()
+            .flat_map(|lang| {
+                names
+                    .iter()
+                    .map(move |name| self.template_file(name.as_deref(), lang))
+            })
+            .chain(self.footer_path(&self.config.default_language))
+            .filter(|path| !path.is_file())
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+        if !missing.is_empty() {
+            return Err(ErrorReport::MailTemplatesMissing { paths: missing });
+        }
+
+        let patterns: Vec<String> = names
+            .iter()
+            .map(|name| {
+                let format = &self.config.template_name_format;
+                match name {
+                    Some(name) => format.replace("{name}", name),
+                    None => format.clone(),
+                }
+            })
+            .collect();
+        let mut orphans = Vec::new();
+        for entry in fs::read_dir(&self.config.template_dir_path)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let file_name = entry.file_name();
+            for pattern in &patterns {
+                let (prefix, suffix) = pattern.split_once("{lang}").unwrap_or((pattern, ""));
+                let lang = file_name
+                    .to_str()
+                    .and_then(|name| name.strip_prefix(prefix))
+                    .and_then(|name| name.strip_suffix(suffix));
+                if let Some(lang) = lang {
+                    if !lang.is_empty()
+                        && !self.config.languages.iter().any(|val| val == lang)
+                        && !orphans.iter().any(|val| val == lang)
+                    {
+                        orphans.push(lang.to_owned());
+                    }
+                }
+            }
+        }
+        orphans.sort();
+        Ok(orphans)
+    }
+
+    /// Opens a connection to the SMTP server without sending anything: EHLO, login, NOOP and
+    /// QUIT, with the TLS mode and timeouts of the sends. A failure names the server.
+    pub fn test_connection(&self) -> Result<(), ErrorReport> {
+        match self.transport()?.test_connection() {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(ErrorReport::MailConnection {
+                server: self.config.server.clone(),
+            }),
+            Err(e) => Err(ErrorReport::MailConnectionCheck {
+                server: format!("{}:{}", self.config.server, self.config.port),
+                msg: self.config.classify(e).to_string(),
+            }),
+        }
+    }
+
+    /// Counts of the messages sent, failed and retried by this mailer and its clones, with the
+    /// time the sends took
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    /// Opens and authenticates a pooled connection ahead of the first send
+    pub fn warm_up(&self) -> Result<(), ErrorReport> {
+        self.test_connection()
+    }
+
+    /// Refuses new sends of this mailer and its clones with `MailShutDown`, waits up to `timeout`
+    /// for the ones in flight, then stops the keep-alive thread and drops the transport, whose
+    /// pooled connections are closed with a `QUIT`. On timeout `MailShutdownTimeout` tells how
+    /// many messages were still being sent; they keep their connection until they are done.
+    ///
+    /// Shut down any `MailQueue` first: its worker sends with a clone of the mailer. Dropping the
+    /// last clone without shutdown closes the connections right away and logs a warning.
+    pub fn shutdown(self, timeout: Duration) -> Result<(), ErrorReport> {
+        let in_flight = self.lifecycle.close(timeout);
+        self.disconnect();
+        match in_flight {
+            0 => Ok(()),
+            in_flight => {
+                log::warn!(
+                    "Mailer shut down with {} message(s) still being sent",
+                    in_flight
+                );
+                Err(ErrorReport::MailShutdownTimeout { in_flight, timeout })
+            }
+        }
+    }
+
+    /// Stops the keep-alive thread and releases the transport of this mailer and its clones;
+    /// a later send starts over with a new transport
+    pub fn disconnect(&self) {
+        self.keep_alive.lock().unwrap().take();
+        self.transport.write().unwrap().take();
+        #[cfg(feature = "async")]
+        if let Some(slot) = &self.async_transport {
+            slot.write().unwrap().take();
+        }
+    }
+
+    /// Drops the shared transport and its pooled connections and builds a new one, e.g. when
+    /// the connections went stale
+    pub fn reconnect(&self) -> Result<(), ErrorReport> {
+        self.transport.write().unwrap().take();
+        self.transport().map(|_| ())
+    }
+
+    fn transport(&self) -> Result<Arc<dyn MailTransport>, ErrorReport> {
+        if self.config.transport_per_send {
+            return (self.connector)();
+        }
+        if let Some(interval) = self.config.keep_alive_interval {
+            let mut keep_alive = self.keep_alive.lock().unwrap();
+            if keep_alive.is_none() {
+                *keep_alive = Some(KeepAlive::start(
+                    interval,
+                    Arc::downgrade(&self.transport),
+                    self.connector.clone(),
+                )?);
+            }
+        }
+
+        if let Some(transport) = self.transport.read().unwrap().as_ref() {
+            return Ok(transport.clone());
+        }
+        let mut slot = self.transport.write().unwrap();
+        if let Some(transport) = slot.as_ref() {
+            return Ok(transport.clone());
+        }
+        let transport = (self.connector)()?;
+        *slot = Some(transport.clone());
+        Ok(transport)
+    }
+
+    fn smtp_transport(config: &Config) -> Result<SmtpTransport, ErrorReport> {
+        Ok(Self::smtp_transport_builder(config)?.build())
+    }
+
+    fn smtp_transport_builder(config: &Config) -> Result<SmtpTransportBuilder, ErrorReport> {
+        if let Some(problem) = config.tls_mode.problem(&config.user_name) {
+            return Err(ErrorReport::MailConfigInvalid {
+                problems: vec![problem],
+            });
+        }
+        let builder = match config.tls_mode {
+            TlsMode::StartTls => SmtpTransport::starttls_relay(&config.server)?,
+            TlsMode::Wrapped => SmtpTransport::relay(&config.server)?,
+            TlsMode::None => SmtpTransport::builder_dangerous(&config.server),
+        };
+        let mut builder = builder.port(config.port).pool_config(config.pool_config());
+        if let Some(name) = config.client_id()? {
+            builder = builder.hello_name(name);
+        }
+        if let Some(timeout) = config.socket_timeout() {
+            builder = builder.timeout(Some(timeout));
+        }
+        if let Some(parameters) = config.tls_parameters()? {
+            builder = builder.tls(config.tls(parameters));
+        }
+        if config.tls_mode != TlsMode::None {
+            let (credentials, mechanisms) = config
+                .auth
+                .credentials(&config.user_name, &config.password)?;
+            builder = builder.credentials(credentials).authentication(mechanisms);
+        }
+        Ok(builder)
+    }
+
+    /// Renders a template file with the given `{{name}}` values, exactly as `send` does, without
+    /// the footer
+    pub fn render_fixture(
+        template_path: &Path,
+        vars: &[(&str, &str)],
+    ) -> Result<String, ErrorReport> {
+        let vars = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Ok(template::render(
+            &template::load(template_path)?,
+            &vars,
+            false,
+            template::DELIMITERS,
+        )
+        .text)
+    }
+
+    /// Renders the HTML body `send_message` would send, footer included
+    pub fn render_html(
+        &self,
+        vars: &HashMap<String, String>,
+        language: Option<&str>,
+    ) -> Result<String, ErrorReport> {
+        self.render_template(None, vars, language)
+    }
+
+    /// Same as `render_html`, with a named template
+    pub fn render_named_html(
+        &self,
+        name: &str,
+        vars: &HashMap<String, String>,
+        language: Option<&str>,
+    ) -> Result<String, ErrorReport> {
+        self.render_template(Some(name), vars, language)
+    }
+
+    fn render_template(
+        &self,
+        name: Option<&str>,
+        vars: &HashMap<String, String>,
+        language: Option<&str>,
+    ) -> Result<String, ErrorReport> {
+        let mut lang = self.language(language);
+        let delimiters = self.delimiters();
+        let catalog = self.catalog(&lang)?;
+        let vars = match catalog.is_empty() {
+            true => Cow::Borrowed(vars),
+            // the message's own variables win over the catalog
+            false => {
+                let mut merged: HashMap<String, String> = catalog
+                    .into_iter()
+                    .map(|(key, value)| (format!("catalog.{}", key), value))
+                    .collect();
+                merged.extend(vars.iter().map(|(k, v)| (k.clone(), v.clone())));
+                Cow::Owned(merged)
+            }
+        };
+        let vars = vars.as_ref();
+        let cache = self.templates.read().unwrap().clone();
+        let key = template::key(name, &lang);
+        let source = match cache.as_ref().and_then(|val| val.templates.get(&key)) {
+            Some(text) => text.clone(),
+            None => {
+                let (used, source) = self.load_template(name, &lang)?;
+                lang = used;
+                source
+            }
+        };
+        let html = template::render(&source, vars, false, delimiters);
+
+        match self.footer_source(cache.as_deref(), &lang)? {
+            Some(footer) => {
+                let footer = template::render(&footer, vars, false, delimiters);
+                Ok(template::inject_footer(
+                    &html.text,
+                    &footer.text,
+                    delimiters,
+                ))
+            }
+            None => Ok(html.text),
+        }
+    }
+
+    /// Footer of the language from the cache, else from its file; `None` without a footer
+    fn footer_source(
+        &self,
+        cache: Option<&TemplateCache>,
+        lang: &str,
+    ) -> Result<Option<String>, ErrorReport> {
+        match cache.and_then(|val| val.footers.get(lang)) {
+            Some(text) => Ok(Some(text.clone())),
+            None => self
+                .footer_path(lang)
+                .map(|path| self.load_source(&path, lang))
+                .transpose(),
+        }
+    }
+
+    /// The `html` body of a message with the footer of its language, unless it goes without
+    fn raw_html(&self, message: &MailMessage, html: &str) -> Result<String, ErrorReport> {
+        if message.without_footer {
+            return Ok(html.to_owned());
+        }
+        let lang = self.first_configured(&self.language_chain(message.language.as_deref()));
+        let cache = self.templates.read().unwrap().clone();
+        let footer = match self.footer_source(cache.as_deref(), &lang)? {
+            Some(footer) => footer,
+            None => return Ok(html.to_owned()),
+        };
+        #[cfg(feature = "handlebars")]
+        if self.config.template_engine == TemplateEngine::Handlebars {
+            let context = match &message.context {
+                Some(context) => context.clone(),
+                None => hbs::vars_context(&message.vars),
+            };
+            let mut registry = hbs::registry(self.config.strict_placeholders);
+            hbs::compile(&mut registry, &hbs::footer_name(&lang), &footer)?;
+            let footer = hbs::render(&registry, &hbs::footer_name(&lang), &context)?;
+            return Ok(template::inject_footer(html, &footer, self.delimiters()));
+        }
+        let footer = template::render(&footer, &message.vars, false, self.delimiters());
+        Ok(template::inject_footer(
+            html,
+            &footer.text,
+            self.delimiters(),
+        ))
+    }
+
+    /// Language and source of the template, from the default language when the file of the
+    /// given one does not exist; other read errors are not covered up
+    fn load_template(
+        &self,
+        name: Option<&str>,
+        lang: &str,
+    ) -> Result<(String, String), ErrorReport> {
+        let not_found = |e: &ErrorReport| matches!(e, ErrorReport::Io(e) if e.kind() == io::ErrorKind::NotFound);
+        let path = self.template_file(name, lang);
+        let mut paths = vec![path.to_string_lossy().into_owned()];
+        let default = &self.config.default_language;
+        match self.load_source(&path, lang) {
+            Ok(source) => return Ok((lang.to_owned(), source)),
+            Err(e) if !not_found(&e) => return Err(e),
+            Err(_) if lang == default => {}
+            Err(_) => {
+                let fallback = self.template_file(name, default);
+                match self.load_source(&fallback, default) {
+                    Ok(source) => {
+                        log::warn!(
+                            "Mail template {} is missing, using {}",
+                            path.display(),
+                            fallback.display()
+                        );
+                        return Ok((default.clone(), source));
+                    }
+                    Err(e) if !not_found(&e) => return Err(e),
+                    Err(_) => paths.push(fallback.to_string_lossy().into_owned()),
+                }
+            }
+        }
+        Err(ErrorReport::MailTemplatesMissing { paths })
+    }
+
+    /// A template or footer file with its `{{> partial}}` directives replaced, see
+    /// `template::expand_includes`
+    fn load_source(&self, path: &Path, lang: &str) -> Result<String, ErrorReport> {
+        template::expand_includes(
+            &template::load(path)?,
+            path,
+            Path::new(&self.config.template_dir_path),
+            lang,
+            self.delimiters(),
+        )
+    }
+
+    /// Body of a message from the configured templates
+    fn render_message(&self, message: &MailMessage) -> Result<String, ErrorReport> {
+        let name = self.template_name(message)?;
+        #[cfg(feature = "handlebars")]
+        if self.config.template_engine == TemplateEngine::Handlebars {
+            let context = match &message.context {
+                Some(context) => context.clone(),
+                None => hbs::vars_context(&message.vars),
+            };
+            return self.render_handlebars(name, &context, message.language.as_deref());
+        }
+        let vars = match (self.escapes_contents(message), message.vars.get("contents")) {
+            (true, Some(contents)) => {
+                let mut vars = message.vars.clone();
+                vars.insert("contents".to_owned(), template::escape_html(contents));
+                Cow::Owned(vars)
+            }
+            _ => Cow::Borrowed(&message.vars),
+        };
+        self.resolve_leftovers(self.render_template(name, &vars, message.language.as_deref())?)
+    }
+
+    fn escapes_contents(&self, message: &MailMessage) -> bool {
+        message
+            .escape_contents
+            .unwrap_or(self.config.escape_contents)
+    }
+
+    /// Renders with the templates compiled by `reload_templates`, or compiles them for this send;
+    /// the footer goes before the closing `</body>` tag
+    #[cfg(feature = "handlebars")]
+    fn render_handlebars(
+        &self,
+        name: Option<&str>,
+        context: &serde_json::Value,
+        language: Option<&str>,
+    ) -> Result<String, ErrorReport> {
+        let mut lang = self.language(language);
+        let catalog = self.catalog(&lang)?;
+        let context = match context {
+            serde_json::Value::Object(map)
+                if !catalog.is_empty() && !map.contains_key("catalog") =>
+            {
+                let mut map = map.clone();
+                map.insert("catalog".to_owned(), serde_json::json!(catalog));
+                Cow::Owned(serde_json::Value::Object(map))
+            }
+            _ => Cow::Borrowed(context),
+        };
+        let context = context.as_ref();
+        let key = template::key(name, &lang);
+        let cache = self.templates.read().unwrap().clone();
+        let compiled;
+        let registry = match cache.as_ref().and_then(|val| val.compiled.as_ref()) {
+            Some(registry) => registry,
+            None => {
+                let mut registry = hbs::registry(self.config.strict_placeholders);
+                let (used, source) = self.load_template(name, &lang)?;
+                lang = used;
+                hbs::compile(&mut registry, &hbs::body_name(&key), &source)?;
+                if let Some(path) = self.footer_path(&lang) {
+                    hbs::compile(
+                        &mut registry,
+                        &hbs::footer_name(&lang),
+                        &self.load_source(&path, &lang)?,
+                    )?;
+                }
+                compiled = registry;
+                &compiled
+            }
+        };
+
+        let html = hbs::render(registry, &hbs::body_name(&key), context)?;
+        match registry.has_template(&hbs::footer_name(&lang)) {
+            true => {
+                let footer = hbs::render(registry, &hbs::footer_name(&lang), context)?;
+                Ok(template::inject_footer(&html, &footer, self.delimiters()))
+            }
+            false => Ok(html),
+        }
+    }
+
+    fn delimiters(&self) -> (&str, &str) {
+        let (open, close) = &self.config.placeholder_delimiters;
+        (open, close)
+    }
+
+    /// Deals with the placeholders the final body still holds, e.g. after a placeholder was renamed
+    /// in the template only: allowed ones are kept, the others fail with
+    /// `MailUnresolvedPlaceholders`, or are dropped when lenient and not strict
+    fn resolve_leftovers(&self, html: String) -> Result<String, ErrorReport> {
+        let names: Vec<String> = template::unresolved(&html, self.delimiters())
+            .into_iter()
+            .filter(|name| !self.config.allowed_placeholders.contains(name))
+            .collect();
+        if names.is_empty() {
+            return Ok(html);
+        }
+        if self.config.strict_placeholders || !self.config.lenient_placeholders {
+            return Err(ErrorReport::MailUnresolvedPlaceholders { names });
+        }
+        let blanks = names
+            .into_iter()
+            .map(|name| (name, String::new()))
+            .collect();
+        Ok(template::render(&html, &blanks, false, self.delimiters()).text)
+    }
+
+    /// Subject with the configured prefix
+    fn subject(&self, subject: &str) -> String {
+        match &self.config.subject_prefix {
+            Some(prefix) => format!("{} {}", prefix, subject),
+            None => subject.to_owned(),
+        }
+    }
+
+    /// Sends a message built with `MailMessage::builder()`.
+    ///
+    /// The HTML body comes with a plain-text alternative: the given plain text, otherwise the
+    /// body converted with `html_to_text`, or the whole rendered template when there is no body;
+    /// without `Config::auto_plaintext` only a given plain text is added.
+    ///
+    /// A message with more recipients than `Config::max_recipients_per_message` goes out as
+    /// several; the report is that of the first, with every report in `SendReport::split`. When
+    /// some fail the rest are still sent and `MailSplitFailed` tells which.
+    pub fn send_message(&self, message: MailMessage) -> Result<SendReport, ErrorReport> {
+        let _in_flight = self.lifecycle.enter()?;
+        let message = self.localize(message);
+        match self.split(&message) {
+            Some(parts) => split::merge(
+                parts
+                    .into_iter()
+                    .map(|part| (split::recipients(&part), self.send_localized_message(part)))
+                    .collect(),
+            ),
+            None => self.send_localized_message(message),
+        }
+    }
+
+    /// `send_message` of a single message, its subject already localized
+    fn send_localized_message(&self, message: MailMessage) -> Result<SendReport, ErrorReport> {
+        let attempt = self.begin(&message);
+        let (mail, res) = match self.build_message(&message) {
+            Ok(mail) => {
+                let res = self.deliver(&mail);
+                (Some(mail), res)
+            }
+            Err(e) => (None, Err(e)),
+        };
+        self.finish(attempt, mail.as_ref(), res)
+    }
+
+    /// The message exactly as `send_message` would hand it to the transport, in `.eml` form,
+    /// without sending it; binary parts are base64 encoded, so the text is always UTF-8
+    pub fn render(&self, message: MailMessage) -> Result<String, ErrorReport> {
+        let mail = self.build_message(&self.localize(message))?;
+        Ok(String::from_utf8(mail.formatted())?)
+    }
+
+    /// Parts of a message over `Config::max_recipients_per_message`, `None` when it goes out
+    /// whole
+    fn split<'a>(&self, message: &MailMessage<'a>) -> Option<Vec<MailMessage<'a>>> {
+        let limit = self.config.max_recipients_per_message?;
+        let parts = split::split(message, limit);
+        if parts.len() > 1 {
+            log::debug!(
+                "Splitting mail for {} recipients into {} messages",
+                message.to.len() + message.cc.len() + message.bcc.len(),
+                parts.len()
+            );
+        }
+        Some(parts).filter(|val| val.len() > 1)
+    }
+
+    /// Same as `send_message`, with the subject of the key in the subjects file of the message
+    /// language; see `localized_subject`
+    pub fn send_localized(
+        &self,
+        subject_key: &str,
+        mut message: MailMessage,
+    ) -> Result<SendReport, ErrorReport> {
+        message.subject_key = Some(subject_key.to_owned());
+        self.send_message(message)
+    }
+
+    /// Message with the subject of its key, if it has one
+    fn localize<'a>(&self, mut message: MailMessage<'a>) -> MailMessage<'a> {
+        if let Some(key) = message.subject_key.take() {
+            message.subject =
+                self.localized_subject(&key, message.language.as_deref(), &message.vars);
+        }
+        if self.config.dedupe_recipients {
+            message.dedupe_recipients();
+        }
+        message
+    }
+
+    /// Sends a separate copy of the message to every recipient, so no one sees the others'
+    /// addresses; the recipients replace the message's To, its Cc and Bcc get every copy.
+    ///
+    /// A failed copy does not stop the batch, the results come in the order of the recipients
+    pub fn send_individually(
+        &self,
+        recipients: Vec<Mailbox>,
+        message: &MailMessage,
+    ) -> Vec<(Mailbox, Result<SendReport, ErrorReport>)> {
+        recipients
+            .into_iter()
+            .map(|recipient| {
+                let mut copy = message.clone();
+                copy.to = vec![recipient.clone()];
+                let res = self.send_message(copy);
+                (recipient, res)
+            })
+            .collect()
+    }
+
+    /// Mail merge: sends a copy of the message to every recipient, rendered with the message's
+    /// placeholder values and the recipient's own, which win over them; the recipients replace
+    /// the message's To, its Cc and Bcc get every copy. The values of a recipient also go on the
+    /// top level of a Handlebars context.
+    ///
+    /// A failed copy does not stop the batch, the results come in the order of the recipients
+    pub fn send_merge(
+        &self,
+        recipients: Vec<(Mailbox, HashMap<String, String>)>,
+        message: &MailMessage,
+    ) -> Vec<(Mailbox, Result<SendReport, ErrorReport>)> {
+        recipients
+            .into_iter()
+            .map(|(recipient, vars)| {
+                let mut copy = message.clone();
+                copy.to = vec![recipient.clone()];
+                #[cfg(feature = "handlebars")]
+                if let Some(serde_json::Value::Object(context)) = &mut copy.context {
+                    for (key, value) in &vars {
+                        context.insert(key.clone(), value.clone().into());
+                    }
+                }
+                copy.vars.extend(vars);
+                let res = self.send_message(copy);
+                (recipient, res)
+            })
+            .collect()
+    }
+
+    /// Sends an HTML body rendered elsewhere, without loading any template but with the footer;
+    /// same as `send_message` with a message built with `html`
+    pub fn send_raw(
+        &self,
+        to_addrs: Vec<Mailbox>,
+        cc_addrs: Option<Vec<Mailbox>>,
+        subject: &str,
+        html_body: &str,
+        attachments: Option<Vec<MailAttachment>>,
+    ) -> Result<SendReport, ErrorReport> {
+        let mut message = MailMessage::from_parts(
+            to_addrs,
+            cc_addrs,
+            None,
+            subject,
+            HashMap::new(),
+            None,
+            None,
+            attachments,
+        );
+        message.html = Some(html_body.to_owned());
+        self.send_message(message)
+    }
+
+    /// Same as `send`, with recipients that get the message without showing in its headers
+    #[allow(clippy::too_many_arguments)]
+    pub fn send_with_bcc(
+        &self,
+        to_addrs: Vec<Mailbox>,
+        cc_addrs: Option<Vec<Mailbox>>,
+        bcc_addrs: Option<Vec<Mailbox>>,
+        subject: &str,
+        message: &str,
+        language: Option<&str>,
+        attachments: Option<Vec<MailAttachment>>,
+    ) -> Result<SendReport, ErrorReport> {
+        self.send_message(MailMessage::from_parts(
+            to_addrs,
+            cc_addrs,
+            bcc_addrs,
+            subject,
+            HashMap::from([("contents".to_owned(), message.to_owned())]),
+            None,
+            language,
+            attachments,
+        ))
+    }
+
+    #[deprecated(note = "build a `MailMessage` and use `send_message`")]
+    pub fn send(
+        &self,
+        to_addrs: Vec<Mailbox>,
+        cc_addrs: Option<Vec<Mailbox>>,
+        subject: &str,
+        message: &str,
+        language: Option<&str>,
+        attachments: Option<Vec<MailAttachment>>,
+    ) -> Result<(), ErrorReport> {
+        self.send_message(MailMessage::from_parts(
+            to_addrs,
+            cc_addrs,
+            None,
+            subject,
+            HashMap::from([("contents".to_owned(), message.to_owned())]),
+            None,
+            language,
+            attachments,
+        ))
+        .map(|_| ())
+    }
+
+    /// Same as `send`, with values for every `{{name}}` placeholder of the template; the message
+    /// itself goes under the `contents` key
+    #[deprecated(note = "build a `MailMessage` with `var` and use `send_message`")]
+    pub fn send_with_vars(
+        &self,
+        to_addrs: Vec<Mailbox>,
+        cc_addrs: Option<Vec<Mailbox>>,
+        subject: &str,
+        vars: &HashMap<String, String>,
+        language: Option<&str>,
+        attachments: Option<Vec<MailAttachment>>,
+    ) -> Result<(), ErrorReport> {
+        self.send_message(MailMessage::from_parts(
+            to_addrs,
+            cc_addrs,
+            None,
+            subject,
+            vars.clone(),
+            None,
+            language,
+            attachments,
+        ))
+        .map(|_| ())
+    }
+
+    /// Notes what the audit record and the hooks need before the message is built
+    fn begin(&self, message: &MailMessage) -> Attempt {
+        log::debug!(
+            "Building mail for {} recipient(s) in '{}' with {} attachment(s)",
+            message.to.len() + message.cc.len() + message.bcc.len(),
+            self.first_configured(&self.language_chain(message.language.as_deref())),
+            message.attachments.len()
+        );
+        let attachments = match self.audit {
+            Some(_) => message
+                .attachments
+                .iter()
+                .map(|attch| AuditAttachment {
+                    name: self
+                        .attachment_name(attch.name, &message.vars)
+                        .unwrap_or_else(|_| attch.name.to_owned()),
+                    size: attch.source.size(),
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        Attempt {
+            started: SystemTime::now(),
+            timer: Instant::now(),
+            to: message.to.clone(),
+            cc: message.cc.clone(),
+            subject: self.subject(&message.subject),
+            attachments,
+        }
+    }
+
+    /// Writes the audit record and calls the hooks; `mail` is `None` when it could not be built
+    fn finish(
+        &self,
+        attempt: Attempt,
+        mail: Option<&Message>,
+        res: Result<Response, ErrorReport>,
+    ) -> Result<SendReport, ErrorReport> {
+        let Attempt {
+            started,
+            timer,
+            to,
+            cc,
+            subject,
+            attachments,
+        } = attempt;
+        let message_id = mail
+            .and_then(|mail| mail.headers().get_raw("Message-ID"))
+            .map(|val| val.to_owned());
+        let res =
+            res.map(|response| SendReport::new(message_id.clone().unwrap_or_default(), &response));
+        match &res {
+            Ok(report) => log::info!(
+                "Mail {} sent to {}: {}",
+                report.message_id,
+                self.log_addresses(&to, &cc),
+                report.code
+            ),
+            Err(e) => log::error!("Mail to {} not sent: {}", self.log_addresses(&to, &cc), e),
+        }
+        self.metrics.record(res.is_ok(), timer.elapsed());
+
+        if let Some(audit) = &self.audit {
+            let mut record = AuditRecord::new(
+                started,
+                &self.config.from_addrs,
+                &to,
+                &cc,
+                &subject,
+                attachments,
+            );
+            record.message_id = message_id.clone();
+            record.finish(timer.elapsed(), &res);
+            // a failing audit write must not change the outcome of the send
+            let _ = audit.append(&record);
+        }
+
+        match res {
+            Ok(report) => {
+                hooks::invoke(
+                    &self.on_sent,
+                    &SentMessage {
+                        formatted: match self.on_sent {
+                            Some(_) => mail.map(Message::formatted).unwrap_or_default(),
+                            None => Vec::new(),
+                        },
+                        message_id,
+                        to,
+                        cc,
+                        subject,
+                        started,
+                        finished: SystemTime::now(),
+                    },
+                );
+                Ok(report)
+            }
+            Err(error) if self.on_failed.is_some() => {
+                let failed = FailedMessage {
+                    message_id,
+                    to,
+                    cc,
+                    subject,
+                    started,
+                    finished: SystemTime::now(),
+                    error,
+                };
+                hooks::invoke(&self.on_failed, &failed);
+                Err(failed.error)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Recipients as they go into the log records, masked unless `Config::mask_log_addresses` is
+    /// off
+    fn log_addresses(&self, to: &[Mailbox], cc: &[Mailbox]) -> String {
+        let addresses: Vec<String> = to
+            .iter()
+            .chain(cc)
+            .map(|val| match self.config.mask_log_addresses {
+                true => mailbox::mask(&val.email),
+                false => val.email.to_string(),
+            })
+            .collect();
+        addresses.join(", ")
+    }
+
+    /// Sends within `Config::send_deadline`; a failed connection is retried once on a fresh
+    /// transport right away, since the cached one may have gone stale, further transient failures
+    /// as the `Config::retry_policy` allows. With a deadline an attempt gets the command timeout,
+    /// bounded by what is left; one that runs past it is abandoned and not retried.
+    fn deliver(&self, mail: &Message) -> Result<Response, ErrorReport> {
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.acquire()?;
+        }
+        let mut deadline = Deadline::new(self.config.send_deadline);
+        let mut retries = Retries::new(self.config.retry_policy);
+        let mut reconnected = false;
+        loop {
+            retries.attempt();
+            // without a deadline the socket timeouts of the transport bound the attempt
+            let timeout = match self.config.send_deadline {
+                Some(_) => deadline.attempt_timeout(self.config.command_timeout),
+                None => None,
+            };
+            let error = match self
+                .transport()
+                .and_then(|transport| transport::send_within(transport, mail, timeout))
+            {
+                Ok(res) if res.is_positive() => return Ok(res),
+                Ok(res) => crate::error::error_mail_sent_response(&res),
+                Err(e) => self.config.classify(e),
+            };
+            // the next transport logs in with a token asked for anew
+            if matches!(error, ErrorReport::MailTokenRejected { .. }) {
+                self.transport.write().unwrap().take();
+            }
+            deadline.record(&error);
+            if deadline.is_exhausted() {
+                return Err(deadline.exceeded());
+            }
+            if matches!(error, ErrorReport::MailSendAbandoned { .. }) {
+                return Err(error);
+            }
+
+            let connection_lost = error.is_connection_error();
+            if connection_lost && !reconnected {
+                self.transport.write().unwrap().take();
+                reconnected = true;
+                continue;
+            }
+            let delay = match retries.next_delay(error.is_transient()) {
+                Some(val) => val,
+                None => return Err(retries.give_up(error)),
+            };
+            if deadline.remaining().is_some_and(|val| val <= delay) {
+                return Err(deadline.exceeded());
+            }
+            self.metrics.retried();
+            (self.sleep)(delay);
+            if connection_lost {
+                self.transport.write().unwrap().take();
+            }
+        }
+    }
+
+    /// Checks the attachments against the configured limits before any of them is read
+    fn check_sizes(
+        &self,
+        attachments: &[MailAttachment],
+        body_bytes: u64,
+    ) -> Result<(), ErrorReport> {
+        let limit = |val: Option<u64>| val.filter(|val| *val > 0);
+        let mut total = body_bytes;
+        for attch in attachments {
+            let size = attch.source.size();
+            if let Some(limit) = limit(self.config.max_attachment_bytes) {
+                if size > limit {
+                    return Err(ErrorReport::MailAttachmentTooLarge {
+                        name: attch.name.to_owned(),
+                        size,
+                        limit,
+                    });
+                }
+            }
+            total += size;
+            if let Some(limit) = limit(self.config.max_message_bytes) {
+                if total > limit {
+                    return Err(ErrorReport::MailMessageTooLarge {
+                        name: attch.name.to_owned(),
+                        size: total,
+                        limit,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Attachment name with the placeholders applied, when enabled, and made safe for the headers
+    fn attachment_name(
+        &self,
+        name: &str,
+        vars: &HashMap<String, String>,
+    ) -> Result<String, ErrorReport> {
+        if !self.config.attachment_name_placeholders {
+            return Ok(template::sanitize_file_name(name));
+        }
+        let rendered = template::render(name, vars, true, self.delimiters());
+        if self.config.strict_placeholders && !rendered.unresolved.is_empty() {
+            return Err(ErrorReport::MailUnresolvedPlaceholders {
+                names: rendered.unresolved,
+            });
+        }
+        Ok(template::sanitize_file_name(&rendered.text))
+    }
+
+    fn build_message(&self, message: &MailMessage) -> Result<Message, ErrorReport> {
+        let vars = &message.vars;
+        let attachement_part = |attachment: &MailAttachment| -> Result<SinglePart, ErrorReport> {
+            if let Some(cid) = attachment.content_id {
+                let cid = cid
+                    .trim_start_matches("cid:")
+                    .trim_matches(|c| c == '<' || c == '>');
+                return Ok(Attachment::new_inline(cid.to_owned()).body(
+                    attachment.source.read()?.into_owned(),
+                    attachment.content_type().parse()?,
+                ));
+            }
+            let name = self.attachment_name(attachment.name, vars)?;
+            let disposition = match attachment.disposition {
+                Disposition::Attachment => ContentDisposition::attachment(&name),
+                Disposition::Inline => ContentDisposition::inline_with_name(&name),
+            };
+            Ok(SinglePart::builder()
+                .header(disposition)
+                .header(ContentType::parse(&mime::with_name(
+                    attachment.content_type(),
+                    &name,
+                ))?)
+                .body(attachment.source.read()?.into_owned()))
+        };
+
+        let mut builder = Message::builder().from(self.config.from_addrs.clone());
+        for addr in &self.config.reply_to {
+            builder = builder.reply_to(addr.clone());
+        }
+        let message_id = self
+            .config
+            .message_id_domain
+            .as_ref()
+            .map(|domain| format!("<{}@{}>", Uuid::new_v4().simple(), domain));
+        let mut builder = builder
+            .message_id(message_id)
+            .subject(self.subject(&message.subject));
+        if let Some(id) = &message.in_reply_to {
+            builder = builder.in_reply_to(id.clone());
+        }
+        if !message.references.is_empty() {
+            builder = builder.references(message.references.join(" "));
+        }
+
+        for addr in &message.to {
+            builder = builder.to(addr.clone());
+        }
+
+        for addr in &message.cc {
+            builder = builder.cc(addr.clone());
+        }
+
+        // lettre keeps Bcc in the envelope only, it is not written to the headers
+        for addr in &message.bcc {
+            builder = builder.bcc(addr.clone());
+        }
+
+        // without an envelope sender lettre derives the envelope from the headers
+        if let Some(sender) = message
+            .envelope_from
+            .as_ref()
+            .or(self.config.envelope_from.as_ref())
+        {
+            let recipients = message
+                .to
+                .iter()
+                .chain(&message.cc)
+                .chain(&message.bcc)
+                .map(|mailbox| mailbox.email.clone())
+                .collect();
+            builder = builder.envelope(Envelope::new(Some(sender.clone()), recipients)?);
+        }
+
+        for header in message.headers.iter().cloned() {
+            builder = builder.header(header);
+        }
+        for header in message.priority.headers() {
+            builder = builder.header(header);
+        }
+        for header in message.unsubscribe_headers() {
+            builder = builder.header(header);
+        }
+        if let Some(receipt) = &message.read_receipt {
+            let mailbox = match receipt {
+                ReadReceipt::From => &self.config.from_addrs,
+                ReadReceipt::ReplyTo => self
+                    .config
+                    .reply_to
+                    .first()
+                    .unwrap_or(&self.config.from_addrs),
+                ReadReceipt::To(mailbox) => mailbox,
+            };
+            for name in ["Disposition-Notification-To", "Return-Receipt-To"] {
+                builder = builder.header(RawHeader::mailbox(name, mailbox));
+            }
+        }
+
+        let html = match &message.html {
+            Some(html) => self.raw_html(message, html)?,
+            None => self.render_message(message)?,
+        };
+        let mut text = match (message.plain_text.as_deref(), vars.get("contents")) {
+            (Some(text), _) => Some(text.to_owned()),
+            (None, _) if self.config.auto_plaintext => Some(html_to_text(&html)),
+            (None, Some(contents)) if self.escapes_contents(message) => {
+                Some(html_to_text(&template::escape_html(contents)))
+            }
+            (None, Some(contents)) => Some(html_to_text(contents)),
+            (None, None) => None,
+        };
+        let html = match &message.preheader {
+            Some(preheader) => {
+                text = text.map(|text| format!("{}\n\n{}", preheader, text));
+                template::inject_preheader(&html, preheader)
+            }
+            None => html,
+        };
+
+        let calendar = message.calendar.as_ref().map(IcsEvent::to_ics);
+        let body_bytes = html.len()
+            + text.as_ref().map_or(0, String::len)
+            + calendar.as_ref().map_or(0, String::len);
+        self.check_sizes(&message.attachments, body_bytes as u64)?;
+
+        // inline images go next to the HTML in a related part, the rest in the outer mixed part
+        let (inline, regular): (Vec<_>, Vec<_>) = message
+            .attachments
+            .iter()
+            .partition(|attch| attch.content_id.is_some());
+        let related = |html: String| -> Result<MultiPart, ErrorReport> {
+            let mut related = MultiPart::related().singlepart(SinglePart::html(html));
+            for attch in &inline {
+                related = related.singlepart(attachement_part(attch)?);
+            }
+            Ok(related)
+        };
+        let alternative = MultiPart::alternative();
+        let mut body = match (text, inline.is_empty()) {
+            (Some(text), true) => MultiPart::alternative_plain_html(text, html),
+            (Some(text), false) => alternative
+                .singlepart(SinglePart::plain(text))
+                .multipart(related(html)?),
+            (None, true) => alternative.singlepart(SinglePart::html(html)),
+            (None, false) => alternative.multipart(related(html)?),
+        };
+        // the invitation is one more alternative, where Outlook and Gmail look for it
+        if let Some(ics) = calendar {
+            body = body.singlepart(
+                SinglePart::builder()
+                    .header(ContentType::parse(
+                        "text/calendar; charset=utf-8; method=REQUEST",
+                    )?)
+                    .body(ics),
+            );
+        }
+
+        let part = match regular.is_empty() {
+            true => body,
+            false => {
+                let mut part = MultiPart::mixed().multipart(body);
+                for attch in regular {
+                    part = part.singlepart(attachement_part(attch)?);
+                }
+                part
+            }
+        };
+        #[cfg(feature = "smime")]
+        let part = match &self.config.smime {
+            Some(signer) => signer.sign(part)?,
+            None => part,
+        };
+        #[allow(unused_mut)]
+        let mut mail = builder.multipart(part)?;
+        #[cfg(feature = "dkim")]
+        if let Some(signer) = &self.config.dkim {
+            signer.sign(&mut mail);
+        }
+        Ok(mail)
+    }
+}
+
+#[cfg(test)]
+// the positional sends stay covered until they are removed
+#[allow(deprecated)]
+mod tests {
+    use super::{
+        golden::{assert_golden, normalize_eml},
+        testing::MockTransport,
+        AttachmentSource, Config, Delivery, Disposition, IcsEvent, MailAttachment, MailMessage,
+        MailTransport, Mailer, Priority, RateLimit, ReadReceipt, RetryPolicy, SentMessage,
+        SmtpAuth, TlsMode,
+    };
+    use crate::{
+        envars::{AppConfig, CONFIG_FILE_DELIMITER},
+        error::ErrorReport,
+    };
+    use lettre::{
+        message::Mailbox,
+        transport::smtp::{
+            client::Tls,
+            response::{Category, Code, Detail, Response, Severity},
+        },
+        Address, Message,
+    };
+    use serde_json::Value;
+    use std::{
+        collections::HashMap,
+        fs,
+        path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        thread,
+        time::{Duration, Instant, SystemTime},
+    };
+
+    #[derive(Default)]
+    struct CountingTransport {
+        sent: AtomicUsize,
+        checks: AtomicUsize,
+    }
+
+    impl MailTransport for CountingTransport {
+        fn send(&self, _message: &Message) -> Result<Response, ErrorReport> {
+            self.sent.fetch_add(1, Ordering::SeqCst);
+            Ok(Response::new(
+                Code::new(
+                    Severity::PositiveCompletion,
+                    Category::MailSystem,
+                    Detail::Zero,
+                ),
+                vec!["OK".to_owned()],
+            ))
+        }
+
+        fn test_connection(&self) -> Result<bool, ErrorReport> {
+            self.checks.fetch_add(1, Ordering::SeqCst);
+            Ok(true)
+        }
+    }
+
+    fn test_config(languages: &[&str]) -> Config {
+        Config::builder()
+            .from_addrs("Test Sender <noreply@example.com>")
+            .server("localhost")
+            .credentials("user", "secret-password")
+            .template_dir_path(Path::new(env!("WORKSPACE_ROOT_PATH")).join("assets/test/mail"))
+            .languages(languages)
+            .default_language(languages[0])
+            .connect_timeout(None)
+            .command_timeout(None)
+            .build()
+            .unwrap()
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn test_recipient() -> Vec<Mailbox> {
+        vec!["Ana Pop <ana@example.com>".parse().unwrap()]
+    }
+
+    fn test_file(name: &str) -> String {
+        Path::new(env!("WORKSPACE_ROOT_PATH"))
+            .join("assets/test")
+            .join(name)
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    #[test]
+    fn golden_render() {
+        let html = Mailer::render_fixture(
+            Path::new(&test_file("mail/email_ro.html")),
+            &[("contents", "Bună ziua, <b>Ștefan</b>!")],
+        )
+        .unwrap();
+        assert_golden("render_ro.html", &html);
+    }
+
+    #[test]
+    fn golden_eml_attachments() {
+        let txt_path = test_file("attachment.txt");
+        let bin_path = test_file("attachment.bin");
+        let mail = Mailer::init(test_config(&["ro", "en"]))
+            .build_message(&MailMessage::from_parts(
+                vec!["Ana Pop <ana@example.com>".parse().unwrap()],
+                Some(vec!["office@example.com".parse().unwrap()]),
+                Some(vec!["audit@example.com".parse().unwrap()]),
+                "Invoice 42",
+                vars(&[("contents", "Please find the invoice attached.")]),
+                None,
+                Some("en"),
+                Some(vec![
+                    MailAttachment {
+                        source: AttachmentSource::Path(&txt_path),
+                        name: "invoice.txt",
+                        mime: "text/plain",
+                        content_id: None,
+                        disposition: Disposition::Attachment,
+                    },
+                    MailAttachment {
+                        source: AttachmentSource::Path(&bin_path),
+                        name: "invoice.pdf",
+                        mime: "application/pdf",
+                        content_id: None,
+                        disposition: Disposition::Attachment,
+                    },
+                ]),
+            ))
+            .unwrap();
+        assert_golden("attachments.eml", &normalize_eml(&mail.formatted()));
+
+        // BCC recipients get the message but stay out of its headers
+        assert!(mail
+            .envelope()
+            .to()
+            .iter()
+            .any(|addr| addr.to_string() == "audit@example.com"));
+        assert_eq!(mail.envelope().to().len(), 3);
+        let formatted = String::from_utf8(mail.formatted()).unwrap();
+        assert!(!formatted.contains("audit@example.com"));
+        assert!(!formatted.to_ascii_lowercase().contains("bcc:"));
+
+        // the same invoice generated in memory makes the same message
+        let bin_bytes = fs::read(&bin_path).unwrap();
+        let mail = Mailer::init(test_config(&["ro", "en"]))
+            .build_message(
+                &MailMessage::builder()
+                    .to("Ana Pop <ana@example.com>".parse().unwrap())
+                    .cc("office@example.com".parse().unwrap())
+                    .subject("Invoice 42")
+                    .body("Please find the invoice attached.")
+                    .language("en")
+                    .attachment(MailAttachment::new(&txt_path, "invoice.txt", "text/plain"))
+                    .attachment(MailAttachment {
+                        source: AttachmentSource::from(&bin_bytes[..]),
+                        name: "invoice.pdf",
+                        mime: "application/pdf",
+                        content_id: None,
+                        disposition: Disposition::Attachment,
+                    })
+                    .build()
+                    .unwrap(),
+            )
+            .unwrap();
+        assert_golden("attachments.eml", &normalize_eml(&mail.formatted()));
+    }
+
+    #[test]
+    fn attachment_content_type() {
+        let pdf = MailAttachment::from_path("/var/invoices/invoice 42.pdf");
+        assert_eq!(pdf.name, "invoice 42.pdf");
+        assert_eq!(pdf.mime, "application/pdf");
+        assert!(matches!(
+            pdf.source,
+            AttachmentSource::Path("/var/invoices/invoice 42.pdf")
+        ));
+        let csv = MailAttachment {
+            mime: "text/plain",
+            ..MailAttachment::from_path("report.csv")
+        };
+        assert_eq!(csv.content_type(), "text/plain");
+
+        let txt_path = test_file("attachment.txt");
+        let eml = |attachment: MailAttachment| {
+            let message = MailMessage::builder()
+                .to("ana@example.com".parse().unwrap())
+                .subject("Report")
+                .body("Body")
+                .attachment(attachment)
+                .build()
+                .unwrap();
+            let mail = Mailer::init(test_config(&["ro"]))
+                .build_message(&message)
+                .unwrap();
+            String::from_utf8(mail.formatted()).unwrap()
+        };
+        assert!(eml(MailAttachment::from_path(&txt_path)).contains("Content-Type: text/plain"));
+        assert!(eml(MailAttachment {
+            source: AttachmentSource::Path(&txt_path),
+            name: "report.csv",
+            mime: "",
+            content_id: None,
+            disposition: Disposition::Attachment,
+        })
+        .contains("Content-Type: text/csv"));
+    }
+
+    #[test]
+    fn attachment_limits() {
+        let mut config = test_config(&["ro"]);
+        config.max_attachment_bytes = Some(4_000);
+        config.max_message_bytes = Some(10_000);
+        let mailer = Mailer::init(config);
+        let build = |sizes: &[usize]| {
+            let names: Vec<String> = (0..sizes.len()).map(|i| format!("part{}.bin", i)).collect();
+            let mut builder = MailMessage::builder()
+                .to("ana@example.com".parse().unwrap())
+                .subject("Export")
+                .body("Body");
+            for (size, name) in sizes.iter().zip(&names) {
+                builder = builder.attachment(MailAttachment {
+                    source: AttachmentSource::from(vec![b'x'; *size]),
+                    name,
+                    mime: "",
+                    content_id: None,
+                    disposition: Disposition::Attachment,
+                });
+            }
+            mailer.build_message(&builder.build().unwrap()).map(|_| ())
+        };
+
+        assert!(build(&[4_000, 4_000]).is_ok());
+        match build(&[100, 4_001]) {
+            Err(ErrorReport::MailAttachmentTooLarge { name, size, limit }) => {
+                assert_eq!((name.as_str(), size, limit), ("part1.bin", 4_001, 4_000))
+            }
+            other => panic!("Expected too large attachment, got {:?}", other),
+        }
+        match build(&[4_000, 4_000, 4_000]) {
+            Err(ErrorReport::MailMessageTooLarge { name, size, limit }) => {
+                assert_eq!(name, "part2.bin");
+                assert!(size > 12_000);
+                assert_eq!(limit, 10_000);
+            }
+            other => panic!("Expected too large message, got {:?}", other),
+        }
+
+        let unlimited = Config::builder()
+            .from_addrs("noreply@example.com")
+            .server("smtp.example.com")
+            .max_attachment_bytes(0)
+            .max_message_bytes(0)
+            .build()
+            .unwrap();
+        assert_eq!(unlimited.max_attachment_bytes, None);
+        assert_eq!(unlimited.max_message_bytes, None);
+    }
+
+    #[test]
+    fn golden_eml_inline_image() {
+        let txt_path = test_file("attachment.txt");
+        let logo_path = test_file("attachment.bin");
+        let mut message = MailMessage::from_parts(
+            test_recipient(),
+            None,
+            None,
+            "Logo",
+            vars(&[("contents", "<img src=\"cid:logo\"> Welcome")]),
+            None,
+            None,
+            Some(vec![
+                MailAttachment {
+                    source: AttachmentSource::Path(&logo_path),
+                    name: "logo.png",
+                    mime: "image/png",
+                    content_id: Some("logo"),
+                    disposition: Disposition::Inline,
+                },
+                MailAttachment {
+                    source: AttachmentSource::Path(&txt_path),
+                    name: "terms.txt",
+                    mime: "text/plain",
+                    content_id: None,
+                    disposition: Disposition::Attachment,
+                },
+            ]),
+        );
+        message.escape_contents = Some(false);
+        let mail = Mailer::init(test_config(&["en"]))
+            .build_message(&message)
+            .unwrap();
+        let eml = normalize_eml(&mail.formatted());
+        assert_golden("inline_image.eml", &eml);
+
+        let position = |needle: &str| {
+            eml.find(needle)
+                .unwrap_or_else(|| panic!("{} missing from {}", needle, eml))
+        };
+        assert!(position("multipart/mixed") < position("multipart/alternative"));
+        assert!(position("multipart/alternative") < position("multipart/related"));
+        assert!(position("Content-Type: text/html") < position("Content-ID: <logo>"));
+        assert!(position("Content-ID: <logo>") < position("BOUNDARY-3--"));
+        assert!(eml.contains("Content-Disposition: inline"));
+        assert!(position("BOUNDARY-3--") < position("filename=\"terms.txt\""));
+    }
+
+    #[test]
+    fn golden_attachment_headers() {
+        let txt_path = test_file("attachment.txt");
+        let attachment = |name, disposition| MailAttachment {
+            source: AttachmentSource::Path(&txt_path),
+            name,
+            mime: "",
+            content_id: None,
+            disposition,
+        };
+        let message = MailMessage::builder()
+            .to("ana@example.com".parse().unwrap())
+            .subject("Invoices")
+            .html("<p>Attached</p>")
+            .attachment(attachment("Invoice 42.pdf", Disposition::Attachment))
+            .attachment(attachment(
+                "Rechnungsübersicht.pdf",
+                Disposition::Attachment,
+            ))
+            .attachment(attachment("Übersicht.txt", Disposition::Inline))
+            .build()
+            .unwrap();
+        let mail = Mailer::init(test_config(&["ro"]))
+            .build_message(&message)
+            .unwrap();
+        let eml = normalize_eml(&mail.formatted());
+        let headers: Vec<&str> = eml
+            .split("--BOUNDARY-1\n")
+            .skip(2)
+            .map(|part| part.split_once("\n\n").unwrap().0)
+            .collect();
+        assert_golden("attachment_headers.txt", &headers.join("\n\n"));
+    }
+
+    #[test]
+    fn golden_eml_non_ascii() {
+        let mail = Mailer::init(test_config(&["ro"]))
+            .build_message(&MailMessage::from_parts(
+                vec![Mailbox::new(
+                    Some("Ștefan Țăran".to_owned()),
+                    Address::new("stefan", "example.com").unwrap(),
+                )],
+                None,
+                None,
+                "Factură nouă – număr 42",
+                vars(&[(
+                    "contents",
+                    "Bună ziua, găsiți factura în atașament. Mulțumim! 😀",
+                )]),
+                None,
+                None,
+                None,
+            ))
+            .unwrap();
+        assert_golden("non_ascii.eml", &normalize_eml(&mail.formatted()));
+    }
+
+    #[test]
+    fn plain_text_alternative() {
+        let mut config = test_config(&["en"]);
+        config.allowed_placeholders = vec!["contents".to_owned(), "company".to_owned()];
+        config.footer_name_format = Some("footer_{lang}.html".to_owned());
+        let text_part =
+            |mailer: &Mailer, vars: &HashMap<String, String>, plain_text: Option<&str>| {
+                let mut message = MailMessage::from_parts(
+                    test_recipient(),
+                    None,
+                    None,
+                    "Plain",
+                    vars.clone(),
+                    plain_text,
+                    None,
+                    Some(vec![]),
+                );
+                message.escape_contents = Some(false);
+                let mail = mailer.build_message(&message).unwrap();
+                let eml = normalize_eml(&mail.formatted());
+                assert!(eml.contains("Content-Type: multipart/alternative;"));
+                assert!(!eml.contains("multipart/mixed"));
+                let start = eml.find("Content-Type: text/plain").unwrap();
+                let end = eml.find("Content-Type: text/html").unwrap();
+                eml[start..end].to_owned()
+            };
+
+        let contents = vars(&[
+            ("contents", "Hello <b>Ana</b>, see the invoice"),
+            ("company", "Acme"),
+        ]);
+        let mailer = Mailer::init(config.clone());
+        let text = text_part(&mailer, &contents, Some("Hello Ana, plain"));
+        assert!(text.contains("\n\nHello Ana, plain") && !text.contains("Acme"));
+        // the whole rendered body is converted, footer included
+        let text = text_part(&mailer, &contents, None);
+        assert!(
+            text.contains("Hello Ana, see the invoice")
+                && text.contains("Acme Ltd, registered in Romania")
+                && !text.contains("<p"),
+            "{}",
+            text
+        );
+
+        config.auto_plaintext = false;
+        let mailer = Mailer::init(config.clone());
+        // without the conversion the message itself is the plain text
+        let text = text_part(&mailer, &contents, None);
+        assert!(
+            text.contains("Hello Ana, see the invoice") && !text.contains("Acme"),
+            "{}",
+            text
+        );
+        let mailer = Mailer::init(Config {
+            footer_name_format: None,
+            ..config
+        });
+        let eml = |plain_text: Option<&str>| {
+            let mut builder = MailMessage::builder()
+                .to("ana@example.com".parse().unwrap())
+                .subject("Plain")
+                .html("<p>Hello <a href=\"https://example.com\">Ana</a></p>");
+            if let Some(text) = plain_text {
+                builder = builder.plain_text(text);
+            }
+            let message = builder.build().unwrap();
+            normalize_eml(&mailer.build_message(&message).unwrap().formatted())
+        };
+        let html_only = eml(None);
+        assert!(!html_only.contains("text/plain"), "{}", html_only);
+        assert!(html_only.contains("Content-Type: text/html"));
+        assert!(eml(Some("Hello Ana")).contains("Content-Type: text/plain"));
+    }
+
+    #[test]
+    fn escaped_contents() {
+        let mailer = Mailer::init(test_config(&["en"]));
+        let message = |escape: Option<bool>| {
+            let mut builder = MailMessage::builder()
+                .to("ana@example.com".parse().unwrap())
+                .subject("Escaped")
+                .body("<b>hi</b> & \"x\"");
+            if let Some(escape) = escape {
+                builder = builder.escape_contents(escape);
+            }
+            builder.build().unwrap()
+        };
+
+        let html = mailer.render_message(&message(None)).unwrap();
+        assert!(
+            html.contains("&lt;b&gt;hi&lt;/b&gt; &amp; &quot;x&quot;"),
+            "{}",
+            html
+        );
+        let eml = normalize_eml(&mailer.build_message(&message(None)).unwrap().formatted());
+        assert!(eml.contains("<b>hi</b> & \"x\""), "{}", eml);
+
+        let html = mailer.render_message(&message(Some(false))).unwrap();
+        assert!(html.contains("<b>hi</b> & \"x\""), "{}", html);
+
+        let mut config = test_config(&["en"]);
+        config.escape_contents = false;
+        let mailer = Mailer::init(config);
+        let html = mailer.render_message(&message(None)).unwrap();
+        assert!(html.contains("<b>hi</b> & \"x\""), "{}", html);
+        let html = mailer.render_message(&message(Some(true))).unwrap();
+        assert!(html.contains("&lt;b&gt;hi&lt;/b&gt;"), "{}", html);
+    }
+
+    #[test]
+    fn message_builder() {
+        let problems = |builder: super::MailMessageBuilder| match builder.build() {
+            Err(ErrorReport::MailMessageInvalid { problems }) => problems,
+            other => panic!("Expected invalid message, got {:?}", other),
+        };
+        assert_eq!(
+            problems(MailMessage::builder().subject(" ")),
+            vec!["no recipient", "subject is empty"]
+        );
+        assert!(MailMessage::builder()
+            .bcc("audit@example.com".parse().unwrap())
+            .subject("Audit")
+            .build()
+            .is_ok());
+
+        let message = MailMessage::builder()
+            .to("Ana Pop <ana@example.com>".parse().unwrap())
+            .to("ion@example.com".parse().unwrap())
+            .cc("office@example.com".parse().unwrap())
+            .subject("Invoice 42")
+            .body("Please find the invoice attached.")
+            .plain_text("Invoice attached")
+            .build()
+            .unwrap();
+        assert_eq!(message.to().len(), 2);
+        assert_eq!(message.subject(), "Invoice 42");
+
+        let stub = Arc::new(CountingTransport::default());
+        let sent = Arc::new(Mutex::new(None));
+        let recorded = sent.clone();
+        let report = Mailer::init_with_transport(test_config(&["ro"]), stub.clone())
+            .on_sent(move |msg| *recorded.lock().unwrap() = Some(msg.clone()))
+            .send_message(message)
+            .unwrap();
+        assert_eq!(stub.sent.load(Ordering::SeqCst), 1);
+        let sent = sent.lock().unwrap().take().unwrap();
+        assert_eq!(sent.to.len(), 2);
+        assert_eq!(sent.cc[0].to_string(), "office@example.com");
+        let eml = String::from_utf8(sent.formatted).unwrap();
+        assert!(eml.contains("Subject: Invoice 42\r\n"));
+        assert!(eml.contains("Invoice attached"));
+
+        assert_eq!(report.code, 250);
+        assert_eq!(report.response_lines, vec!["OK"]);
+        assert!(report.message_id.starts_with('<'));
+        assert_eq!(Some(report.message_id.as_str()), sent.message_id.as_deref());
+        assert!(eml.contains(&format!("Message-ID: {}\r\n", report.message_id)));
+    }
+
+    #[test]
+    fn custom_headers() {
+        let message = MailMessage::builder()
+            .to("ana@example.com".parse().unwrap())
+            .subject("Newsletter")
+            .body("Body")
+            .header("X-Campaign-Id", "spring-2023")
+            .header("Auto-Submitted", "auto")
+            .header("auto-submitted", "auto-generated")
+            .build()
+            .unwrap();
+        let eml = String::from_utf8(
+            Mailer::init(test_config(&["ro"]))
+                .build_message(&message)
+                .unwrap()
+                .formatted(),
+        )
+        .unwrap();
+        let count = |header: &str| {
+            eml.lines()
+                .filter(|line| line.to_lowercase().starts_with(&header.to_lowercase()))
+                .count()
+        };
+        assert!(eml.contains("X-Campaign-Id: spring-2023\r\n"));
+        assert_eq!(count("X-Campaign-Id:"), 1);
+        assert_eq!(count("Auto-Submitted:"), 1);
+        assert!(eml.contains(": auto-generated\r\n"));
+
+        match MailMessage::builder()
+            .to("ana@example.com".parse().unwrap())
+            .subject("Newsletter")
+            .header("X Campaign", "1")
+            .header("subject", "Other")
+            .header("X-Note", "a\r\nBcc: all@example.com")
+            .build()
+        {
+            Err(ErrorReport::MailMessageInvalid { problems }) => assert_eq!(
+                problems,
+                vec![
+                    "header name 'X Campaign' is invalid",
+                    "header 'subject' is set by the mailer",
+                    "header 'X-Note' value contains a line break",
+                ]
+            ),
+            other => panic!("Expected invalid message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn threading_headers() {
+        let mailer = Mailer::init(test_config(&["ro"]));
+        let headers = |message: MailMessage| {
+            let eml =
+                String::from_utf8(mailer.build_message(&message).unwrap().formatted()).unwrap();
+            eml[..eml.find("\r\n\r\n").unwrap()].to_owned()
+        };
+        let builder = || {
+            MailMessage::builder()
+                .to("ana@example.com".parse().unwrap())
+                .subject("Re: Ticket 42")
+                .body("Body")
+        };
+
+        let one = headers(
+            builder()
+                .in_reply_to(" <a1@mail.example.com> ")
+                .reference("<a1@mail.example.com>")
+                .build()
+                .unwrap(),
+        );
+        assert!(
+            one.contains("\r\nIn-Reply-To: <a1@mail.example.com>\r\n"),
+            "{}",
+            one
+        );
+        assert!(
+            one.contains("\r\nReferences: <a1@mail.example.com>\r\n"),
+            "{}",
+            one
+        );
+
+        let many = headers(
+            builder()
+                .in_reply_to("<c3@mail.example.com>")
+                .reference("<a1@mail.example.com>")
+                .reference("<b2@mail.example.com>")
+                .reference("<c3@mail.example.com>")
+                .build()
+                .unwrap(),
+        );
+        // folded at the spaces like any long header
+        assert!(
+            many.contains(
+                "\r\nReferences: <a1@mail.example.com> <b2@mail.example.com>\r\n <c3@mail.example.com>\r\n"
+            ),
+            "{}",
+            many
+        );
+        assert_eq!(many.matches("In-Reply-To:").count(), 1);
+        assert!(!headers(builder().build().unwrap()).contains("References:"));
+
+        match builder()
+            .in_reply_to("a1@mail.example.com")
+            .reference("<a1 b@mail.example.com>")
+            .reference("<mail.example.com>")
+            .header("References", "<a1@mail.example.com>")
+            .build()
+        {
+            Err(ErrorReport::MailMessageInvalid { problems }) => assert_eq!(
+                problems,
+                vec![
+                    "message id 'a1@mail.example.com' is not of the form <id@domain>",
+                    "message id '<a1 b@mail.example.com>' is not of the form <id@domain>",
+                    "message id '<mail.example.com>' is not of the form <id@domain>",
+                    "header 'References' is set by the mailer",
+                ]
+            ),
+            other => panic!("Expected invalid message ids, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn priority_headers() {
+        let mailer = Mailer::init(test_config(&["ro"]));
+        let eml = |priority: Priority| {
+            let message = MailMessage::builder()
+                .to("ops@example.com".parse().unwrap())
+                .subject("Disk full")
+                .body("Body")
+                .priority(priority)
+                .build()
+                .unwrap();
+            String::from_utf8(mailer.build_message(&message).unwrap().formatted()).unwrap()
+        };
+        let high = eml(Priority::High);
+        assert!(high.contains("X-Priority: 1 (Highest)\r\n"));
+        assert!(high.contains("Importance: High\r\n"));
+        let low = eml(Priority::Low);
+        assert!(low.contains("X-Priority: 5 (Lowest)\r\n"));
+        assert!(low.contains("Importance: Low\r\n"));
+        let normal = eml(Priority::Normal);
+        assert!(!normal.contains("X-Priority"));
+        assert!(!normal.contains("Importance"));
+    }
+
+    #[test]
+    fn envelope_sender() {
+        let message = |envelope_from: Option<&str>| {
+            let mut builder = MailMessage::builder()
+                .to("Ana Pop <ana@example.com>".parse().unwrap())
+                .cc("office@example.com".parse().unwrap())
+                .bcc("audit@example.com".parse().unwrap())
+                .subject("Invoice 42")
+                .body("Please find the invoice attached.");
+            if let Some(address) = envelope_from {
+                builder = builder.envelope_from(address.parse().unwrap());
+            }
+            builder.build().unwrap()
+        };
+        let envelope = |config: Config, envelope_from: Option<&str>| {
+            let mail = Mailer::init(config)
+                .build_message(&message(envelope_from))
+                .unwrap();
+            let formatted = String::from_utf8(mail.formatted()).unwrap();
+            assert!(formatted.contains("From: \"Test Sender\" <noreply@example.com>\r\n"));
+            assert!(!formatted.contains("bounces"));
+            let envelope = mail.envelope();
+            let mut to: Vec<String> = envelope.to().iter().map(ToString::to_string).collect();
+            to.sort();
+            assert_eq!(
+                to,
+                vec!["ana@example.com", "audit@example.com", "office@example.com"]
+            );
+            envelope.from().map(ToString::to_string)
+        };
+
+        // unset, the envelope sender is the From address as before
+        let config = test_config(&["ro"]);
+        assert_eq!(
+            envelope(config.clone(), None).as_deref(),
+            Some("noreply@example.com")
+        );
+
+        let mut config = config;
+        config.envelope_from = Some("bounces@example.com".parse().unwrap());
+        assert_eq!(
+            envelope(config.clone(), None).as_deref(),
+            Some("bounces@example.com")
+        );
+        assert_eq!(
+            envelope(config, Some("bounces+spring@example.com")).as_deref(),
+            Some("bounces+spring@example.com")
+        );
+
+        let problems = match Config::builder()
+            .from_addrs("noreply@example.com")
+            .server("smtp.example.com")
+            .envelope_from("not an address")
+            .build()
+        {
+            Err(ErrorReport::MailConfigInvalid { problems }) => problems,
+            other => panic!("Expected invalid config, got {:?}", other),
+        };
+        assert_eq!(problems.len(), 1);
+        assert!(
+            problems[0].starts_with("envelope sender 'not an address' is invalid"),
+            "{:?}",
+            problems
+        );
+    }
+
+    #[test]
+    fn duplicate_recipients() {
+        let message = MailMessage::builder()
+            .to("Ana Pop <ana@Example.com>".parse().unwrap())
+            .to("ana@example.COM".parse().unwrap())
+            .cc("ANA@example.com".parse().unwrap())
+            .cc("office@example.com".parse().unwrap())
+            .cc("ana@EXAMPLE.com".parse().unwrap())
+            .bcc("Office@example.com".parse().unwrap())
+            .bcc("office@Example.com".parse().unwrap())
+            .bcc("audit@example.com".parse().unwrap())
+            .subject("Invoice 42")
+            .body("Please find the invoice attached.")
+            .build()
+            .unwrap();
+        let send = |config: Config| {
+            let mock = Arc::new(MockTransport::new());
+            Mailer::init_with_transport(config, mock.clone())
+                .send_message(message.clone())
+                .unwrap();
+            let mail = mock.sent().remove(0);
+            let formatted = String::from_utf8(mail.formatted()).unwrap();
+            let header = |name: &str| {
+                let start = formatted.find(&format!("\r\n{}: ", name)).unwrap() + name.len() + 4;
+                formatted[start..start + formatted[start..].find("\r\n").unwrap()].to_owned()
+            };
+            let envelope: Vec<String> = mail
+                .envelope()
+                .to()
+                .iter()
+                .map(ToString::to_string)
+                .collect();
+            (header("To"), header("Cc"), envelope)
+        };
+
+        // the local part is case sensitive, so ANA and Office are other mailboxes
+        let (to, cc, envelope) = send(test_config(&["ro"]));
+        assert_eq!(to, "\"Ana Pop\" <ana@Example.com>");
+        assert_eq!(cc, "ANA@example.com, office@example.com");
+        assert_eq!(
+            envelope,
+            vec![
+                "ana@Example.com",
+                "ANA@example.com",
+                "office@example.com",
+                "Office@example.com",
+                "audit@example.com"
+            ]
+        );
+
+        let mut config = test_config(&["ro"]);
+        config.dedupe_recipients = false;
+        let (to, cc, envelope) = send(config);
+        assert_eq!(to, "\"Ana Pop\" <ana@Example.com>, ana@example.COM");
+        assert_eq!(cc, "ANA@example.com, office@example.com, ana@EXAMPLE.com");
+        assert_eq!(envelope.len(), 8);
+    }
+
+    #[test]
+    fn list_unsubscribe() {
+        let mailer = Mailer::init(test_config(&["ro"]));
+        let eml = |builder: super::MailMessageBuilder| {
+            let message = builder
+                .to("ana@example.com".parse().unwrap())
+                .subject("Weekly digest")
+                .body("Body")
+                .build()
+                .unwrap();
+            String::from_utf8(mailer.build_message(&message).unwrap().formatted()).unwrap()
+        };
+        let both = eml(MailMessage::builder()
+            .unsubscribe_mailto("unsubscribe@example.com".parse().unwrap())
+            .unsubscribe_url(" https://example.com/unsubscribe?u=42 "));
+        // folded after the comma, being longer than a header line
+        assert!(both.contains(
+            "List-Unsubscribe: <mailto:unsubscribe@example.com>,\r\n \
+             <https://example.com/unsubscribe?u=42>\r\n"
+        ));
+        assert!(both.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click\r\n"));
+
+        let mailto =
+            eml(MailMessage::builder()
+                .unsubscribe_mailto("unsubscribe@example.com".parse().unwrap()));
+        assert!(mailto.contains("List-Unsubscribe: <mailto:unsubscribe@example.com>\r\n"));
+        assert!(!mailto.contains("List-Unsubscribe-Post"));
+
+        let url = eml(MailMessage::builder().unsubscribe_url("https://example.com/u/42"));
+        assert!(url.contains("List-Unsubscribe: <https://example.com/u/42>\r\n"));
+        assert!(url.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click\r\n"));
+        assert!(!eml(MailMessage::builder()).contains("List-Unsubscribe"));
+
+        assert!(matches!(
+            MailMessage::builder()
+                .to("ana@example.com".parse().unwrap())
+                .subject("Weekly digest")
+                .unsubscribe_url("http://example.com/u/42")
+                .build(),
+            Err(ErrorReport::MailMessageInvalid { problems })
+                if problems == vec!["unsubscribe URL 'http://example.com/u/42' is not an https URL"]
+        ));
+    }
+
+    #[cfg(feature = "dkim")]
+    #[test]
+    fn dkim_signature() {
+        use super::DkimKey;
+
+        let builder = |passphrase: &str| {
+            Config::builder()
+                .from_addrs("noreply@example.com")
+                .server("smtp.example.com")
+                .dkim(
+                    "mail2024",
+                    "example.com",
+                    DkimKey::Path(test_file("keys/private.pem").into()),
+                    Some(passphrase),
+                )
+                .build()
+        };
+        let mailer = Mailer::init(builder("test").unwrap());
+        let message = MailMessage::builder()
+            .to("ana@example.com".parse().unwrap())
+            .subject("Signed")
+            .html("<p>Body</p>")
+            .build()
+            .unwrap();
+        let eml = String::from_utf8(mailer.build_message(&message).unwrap().formatted()).unwrap();
+        assert!(eml.contains("\r\nDKIM-Signature: v=1; a=rsa-sha256; d=example.com; s=mail2024;"));
+        assert!(eml.contains(" h=From:Subject:To:Date;"));
+
+        match builder("wrong") {
+            Err(ErrorReport::MailConfigInvalid { problems }) => {
+                assert!(problems[0].starts_with("DKIM key could not be loaded"))
+            }
+            other => panic!("Expected invalid config, got {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "smime")]
+    #[test]
+    fn smime_signature() {
+        use openssl::{
+            pkcs7::{Pkcs7, Pkcs7Flags},
+            stack::Stack,
+            x509::{store::X509StoreBuilder, X509},
+        };
+
+        let builder = |passphrase: &str| {
+            Config::builder()
+                .from_addrs("noreply@example.com")
+                .server("smtp.example.com")
+                .smime(
+                    test_file("keys/smime_cert.pem"),
+                    test_file("keys/private.pem"),
+                    Some(passphrase),
+                )
+                .build()
+        };
+        let mailer = Mailer::init(builder("test").unwrap());
+        let message = MailMessage::builder()
+            .to("ana@example.com".parse().unwrap())
+            .subject("Invoice 42")
+            .html("<p>Invoice attached</p>")
+            .build()
+            .unwrap();
+        let eml = String::from_utf8(mailer.build_message(&message).unwrap().formatted()).unwrap();
+        let normalized = normalize_eml(eml.as_bytes());
+        assert!(normalized.contains(
+            "Content-Type: multipart/signed;\n boundary=\"BOUNDARY-1\";\n protocol=\"application/pkcs7-signature\"; micalg=\"sha-256\"\n"
+        ), "{}", normalized);
+
+        // the signed part is everything between the first two boundaries
+        let start = eml.find("boundary=\"").unwrap() + "boundary=\"".len();
+        let boundary = &eml[start..start + eml[start..].find('"').unwrap()];
+        let delimiter = format!("\r\n--{}\r\n", boundary);
+        let content_start = eml.find(&delimiter).unwrap() + delimiter.len();
+        let content_end = content_start + eml[content_start..].find(&delimiter).unwrap();
+        let content = &eml[content_start..content_end];
+        assert!(content.starts_with("Content-Type: multipart/alternative;"));
+
+        let signature = &eml[content_end + delimiter.len()..];
+        let (headers, body) = signature.split_once("\r\n\r\n").unwrap();
+        assert!(headers.contains("Content-Type: application/pkcs7-signature; name=\"smime.p7s\""));
+        assert!(headers.contains("Content-Disposition: attachment; filename=\"smime.p7s\""));
+        let encoded: String = body[..body.find("\r\n--").unwrap()]
+            .split_whitespace()
+            .collect();
+        let pkcs7 =
+            Pkcs7::from_der(&data_encoding::BASE64.decode(encoded.as_bytes()).unwrap()).unwrap();
+
+        let certificate =
+            X509::from_pem(&fs::read(test_file("keys/smime_cert.pem")).unwrap()).unwrap();
+        let mut store = X509StoreBuilder::new().unwrap();
+        store.add_cert(certificate).unwrap();
+        let store = store.build();
+        let certs = Stack::new().unwrap();
+        let verify = |content: &str| {
+            pkcs7.verify(
+                &certs,
+                &store,
+                Some(content.as_bytes()),
+                None,
+                Pkcs7Flags::BINARY,
+            )
+        };
+        verify(content).unwrap();
+        assert!(verify(&content.replace("Invoice", "Receipt")).is_err());
+
+        match builder("wrong") {
+            Err(ErrorReport::MailConfigInvalid { problems }) => {
+                assert!(problems[0].starts_with("S/MIME certificate could not be loaded"))
+            }
+            other => panic!("Expected invalid config, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn calendar_invite() {
+        let mailer = Mailer::init(test_config(&["ro"]));
+        let start = SystemTime::now() + Duration::from_secs(86_400);
+        let event = IcsEvent {
+            uid: "interview-42@example.com".to_owned(),
+            summary: "Interview".to_owned(),
+            dtstart: start,
+            dtend: start + Duration::from_secs(3600),
+            organizer: "hr@example.com".parse().unwrap(),
+            attendees: test_recipient(),
+            location: None,
+        };
+        let message = MailMessage::builder()
+            .to("ana@example.com".parse().unwrap())
+            .subject("Interview invitation")
+            .body("See you there")
+            .calendar(event.clone())
+            .build()
+            .unwrap();
+        let eml = String::from_utf8(mailer.build_message(&message).unwrap().formatted()).unwrap();
+        assert!(eml.contains("Content-Type: multipart/alternative"));
+        assert!(eml.contains("Content-Type: text/calendar; charset=utf-8; method=REQUEST\r\n"));
+        assert!(eml.contains("BEGIN:VCALENDAR\r\n"));
+        assert!(eml.contains("UID:interview-42@example.com\r\n"));
+        assert!(eml.find("text/html") < eml.find("text/calendar"));
+
+        let invalid = MailMessage::builder()
+            .to("ana@example.com".parse().unwrap())
+            .subject("Interview invitation")
+            .calendar(IcsEvent {
+                dtend: start - Duration::from_secs(60),
+                ..event
+            })
+            .build();
+        assert!(matches!(
+            invalid,
+            Err(ErrorReport::MailMessageInvalid { problems })
+                if problems == vec!["calendar event ends before it starts"]
+        ));
+    }
+
+    #[test]
+    fn reply_to_addresses() {
+        let reply_to = |list: &str| {
+            let config = Config::builder()
+                .from_addrs("Shop <noreply@example.com>")
+                .reply_to(list)
+                .server("localhost")
+                .build()
+                .unwrap();
+            let message = MailMessage::builder()
+                .to("ana@example.com".parse().unwrap())
+                .subject("Order 42")
+                .html("<p>Shipped</p>")
+                .build()
+                .unwrap();
+            let mail = Mailer::init(config).build_message(&message).unwrap();
+            String::from_utf8(mail.formatted())
+                .unwrap()
+                .lines()
+                .find(|line| line.starts_with("Reply-To: "))
+                .map(|line| line["Reply-To: ".len()..].to_owned())
+        };
+
+        assert_eq!(reply_to(" "), None);
+        assert_eq!(
+            reply_to("support@example.com").as_deref(),
+            Some("support@example.com")
+        );
+        assert_eq!(
+            reply_to("Support <support@example.com>; Sales <sales@example.com>").as_deref(),
+            Some("Support <support@example.com>, Sales <sales@example.com>")
+        );
+    }
+
+    #[test]
+    fn message_id_domain() {
+        let message = MailMessage::builder()
+            .to("ana@example.com".parse().unwrap())
+            .subject("Order 42")
+            .html("<p>Shipped</p>")
+            .build()
+            .unwrap();
+        let mock = Arc::new(MockTransport::new());
+        let mut config = test_config(&["en"]);
+        config.message_id_domain = Some("mail.example.com".to_owned());
+        let mailer = Mailer::init_with_transport(config, mock.clone());
+        let first = mailer.send_message(message.clone()).unwrap();
+        let second = mailer.send_message(message).unwrap();
+
+        assert!(first.message_id.starts_with('<'));
+        assert!(first.message_id.ends_with("@mail.example.com>"));
+        assert_ne!(first.message_id, second.message_id);
+        let formatted = String::from_utf8(mock.sent()[0].formatted()).unwrap();
+        assert!(formatted.contains(&format!("\r\nMessage-ID: {}\r\n", first.message_id)));
+
+        let invalid = Config::builder()
+            .from_addrs("noreply@example.com")
+            .server("localhost")
+            .message_id_domain("mail example.com")
+            .build();
+        assert!(matches!(
+            invalid,
+            Err(ErrorReport::MailConfigInvalid { problems })
+                if problems[0].starts_with("Message-ID domain 'mail example.com' is invalid")
+        ));
+    }
+
+    #[test]
+    fn hello_name() {
+        let config = Config::builder()
+            .from_addrs("noreply@example.com")
+            .server("localhost")
+            .hello_name(" app01.example.com ")
+            .build()
+            .unwrap();
+        assert_eq!(config.hello_name.as_deref(), Some("app01.example.com"));
+        let transport = format!("{:?}", Mailer::smtp_transport_builder(&config).unwrap());
+        assert!(
+            transport.contains("hello_name: Domain(\"app01.example.com\")"),
+            "{}",
+            transport
+        );
+
+        for (name, problem) in [
+            ("  ", "hello name is empty"),
+            ("app 01", "hello name 'app 01' is invalid"),
+        ] {
+            match Config::builder()
+                .from_addrs("noreply@example.com")
+                .server("localhost")
+                .hello_name(name)
+                .build()
+            {
+                Err(ErrorReport::MailConfigInvalid { problems }) => {
+                    assert!(problems[0].starts_with(problem), "{:?}", problems)
+                }
+                other => panic!("Expected an invalid hello name, got {:?}", other),
+            }
+        }
+        let mut config = test_config(&["en"]);
+        config.hello_name = Some(" ".to_owned());
+        assert!(matches!(
+            Mailer::try_init(config),
+            Err(ErrorReport::MailConfigInvalid { problems }) if problems == ["hello name is empty"]
+        ));
+    }
+
+    #[test]
+    fn pool_settings() {
+        let config = Config::builder()
+            .from_addrs("noreply@example.com")
+            .server("localhost")
+            .pool_max_size(32)
+            .pool_min_idle(2)
+            .pool_idle_timeout(Duration::from_secs(120))
+            .build()
+            .unwrap();
+        let transport = format!("{:?}", Mailer::smtp_transport_builder(&config).unwrap());
+        assert!(
+            transport.contains("min_idle: 2, max_size: 32, idle_timeout: 120s"),
+            "{}",
+            transport
+        );
+
+        for (builder, problem) in [
+            (
+                Config::builder().pool_max_size(0),
+                "pool max size must not be 0",
+            ),
+            (
+                Config::builder().pool_max_size(2).pool_min_idle(3),
+                "pool min idle 3 is larger than the pool max size 2",
+            ),
+            (
+                Config::builder().pool_idle_timeout(Duration::ZERO),
+                "pool idle timeout must not be 0",
+            ),
+        ] {
+            match builder
+                .from_addrs("noreply@example.com")
+                .server("localhost")
+                .build()
+            {
+                Err(ErrorReport::MailConfigInvalid { problems }) => {
+                    assert_eq!(problems, [problem])
+                }
+                other => panic!("Expected invalid pool settings, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn concurrent_sends() {
+        const WORKERS: usize = 8;
+
+        // every send waits until all the workers are inside the transport at once, which never
+        // happens if the mailer serializes them
+        #[derive(Default)]
+        struct GatedTransport {
+            arrived: Mutex<usize>,
+            all_arrived: std::sync::Condvar,
+            timed_out: AtomicUsize,
+        }
+
+        impl MailTransport for GatedTransport {
+            fn send(&self, _message: &Message) -> Result<Response, ErrorReport> {
+                let mut arrived = self.arrived.lock().unwrap();
+                *arrived += 1;
+                self.all_arrived.notify_all();
+                let (_arrived, wait) = self
+                    .all_arrived
+                    .wait_timeout_while(arrived, Duration::from_secs(2), |val| *val < WORKERS)
+                    .unwrap();
+                if wait.timed_out() {
+                    self.timed_out.fetch_add(1, Ordering::SeqCst);
+                }
+                Ok(Response::new(
+                    Code::new(
+                        Severity::PositiveCompletion,
+                        Category::MailSystem,
+                        Detail::Zero,
+                    ),
+                    vec!["OK".to_owned()],
+                ))
+            }
+        }
+
+        let stub = Arc::new(GatedTransport::default());
+        let mailer = Mailer::init_with_transport(test_config(&["ro"]), stub.clone());
+        let workers: Vec<_> = (0..WORKERS)
+            .map(|idx| {
+                let mailer = mailer.clone();
+                thread::spawn(move || {
+                    let message = MailMessage::builder()
+                        .to(format!("user{}@example.com", idx).parse().unwrap())
+                        .subject("Subject")
+                        .body("Body")
+                        .build()
+                        .unwrap();
+                    mailer.send_message(message).unwrap()
+                })
+            })
+            .collect();
+        workers.into_iter().for_each(|val| {
+            val.join().unwrap();
+        });
+        assert_eq!(*stub.arrived.lock().unwrap(), WORKERS);
+        assert_eq!(stub.timed_out.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn read_receipt() {
+        let mut config = test_config(&["ro"]);
+        config.reply_to = vec!["Help <help@example.com>".parse().unwrap()];
+        let mailer = Mailer::init(config);
+        let eml = |receipt: Option<ReadReceipt>| {
+            let mut builder = MailMessage::builder()
+                .to("ana@example.com".parse().unwrap())
+                .subject("Policy update")
+                .body("Body");
+            if let Some(receipt) = receipt {
+                builder = builder.read_receipt(receipt);
+            }
+            let mail = mailer.build_message(&builder.build().unwrap()).unwrap();
+            String::from_utf8(mail.formatted()).unwrap()
+        };
+
+        let from = eml(Some(ReadReceipt::From));
+        assert!(
+            from.contains("Disposition-Notification-To: \"Test Sender\" <noreply@example.com>\r\n")
+        );
+        assert!(from.contains("Return-Receipt-To: \"Test Sender\" <noreply@example.com>\r\n"));
+        assert!(eml(Some(ReadReceipt::ReplyTo))
+            .contains("Disposition-Notification-To: Help <help@example.com>\r\n"));
+
+        let explicit = eml(Some(ReadReceipt::To(Mailbox::new(
+            Some("Conformitate, Ștefan".to_owned()),
+            Address::new("conformitate", "example.com").unwrap(),
+        ))));
+        let header = explicit
+            .lines()
+            .find(|line| line.starts_with("Disposition-Notification-To: "))
+            .unwrap();
+        assert!(header.is_ascii());
+        assert!(header.contains("=?utf-8?"));
+        assert!(header.ends_with(" <conformitate@example.com>"));
+        assert!(!eml(None).contains("Disposition-Notification-To"));
+    }
+
+    #[test]
+    fn string_catalogs() {
+        let dir = Path::new(env!("TEMP_DIR_PATH")).join("catalogs");
+        fs::create_dir_all(&dir).unwrap();
+        let template = "<p>{{catalog.your_order}} {{order}}</p><p>{{catalog.regards}}</p>";
+        for lang in ["en", "ro"] {
+            fs::write(dir.join(format!("email_{}.html", lang)), template).unwrap();
+        }
+        fs::write(
+            dir.join("strings_en.properties"),
+            "regards = Best regards\nyour_order = Your order\n",
+        )
+        .unwrap();
+        fs::write(dir.join("strings_ro.properties"), "regards = Cu stimă\n").unwrap();
+        let mut config = test_config(&["en", "ro"]);
+        config.template_dir_path = dir.to_str().unwrap().to_owned();
+        config.catalog_name_format = Some("strings_{lang}.properties".to_owned());
+        config.strict_placeholders = true;
+        let message = |lang: &str| {
+            MailMessage::builder()
+                .to("ana@example.com".parse().unwrap())
+                .subject("Order")
+                .var("order", "42")
+                .language(lang)
+                .build()
+                .unwrap()
+        };
+
+        // a key missing from the language falls back to the default language
+        let mailer = Mailer::init(config.clone());
+        assert_eq!(
+            mailer.render_message(&message("ro")).unwrap(),
+            "<p>Your order 42</p><p>Cu stimă</p>"
+        );
+        assert_eq!(
+            mailer.render_message(&message("en")).unwrap(),
+            "<p>Your order 42</p><p>Best regards</p>"
+        );
+        let preloaded = Mailer::init_with_preload(config.clone()).unwrap();
+        assert_eq!(
+            preloaded.render_message(&message("ro")).unwrap(),
+            "<p>Your order 42</p><p>Cu stimă</p>"
+        );
+
+        // a key no catalog has stays unresolved, which strict mode refuses
+        fs::write(dir.join("email_ro.html"), "<p>{{catalog.closing}}</p>").unwrap();
+        assert!(matches!(
+            mailer.render_message(&message("ro")),
+            Err(ErrorReport::MailUnresolvedPlaceholders { names }) if names == ["catalog.closing"]
+        ));
+
+        fs::write(
+            dir.join("strings_ro.properties"),
+            "regards = Cu stimă\nclosing\n",
+        )
+        .unwrap();
+        for res in [
+            mailer.render_message(&message("ro")).map(|_| ()),
+            Mailer::init_with_preload(config).map(|_| ()),
+        ] {
+            match res {
+                Err(ErrorReport::MailCatalogInvalid { path, line: 2, .. }) => {
+                    assert!(path.ends_with("strings_ro.properties"), "{}", path)
+                }
+                other => panic!("Expected an invalid catalog, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn localized_subjects() {
+        let dir = Path::new(env!("TEMP_DIR_PATH")).join("subjects");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("subjects_en.txt"),
+            "order_confirmed = Order {{order}} confirmed\nshipped = Order {{order}} shipped\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("subjects_ro.txt"),
+            "order_confirmed = Comanda {{order}} a fost confirmată\n",
+        )
+        .unwrap();
+        let mut config = test_config(&["en", "ro", "de"]);
+        config.template_dir_path = dir.to_str().unwrap().to_owned();
+        config.subjects_name_format = Some("subjects_{lang}.txt".to_owned());
+        let order = vars(&[("order", "42")]);
+
+        let mailer = Mailer::init(config.clone());
+        let subject = |key: &str, lang: &str| mailer.localized_subject(key, Some(lang), &order);
+        assert_eq!(
+            subject("order_confirmed", "ro"),
+            "Comanda 42 a fost confirmată"
+        );
+        // a missing key, or a missing file, falls back to the default language, then to the key
+        assert_eq!(subject("shipped", "ro"), "Order 42 shipped");
+        assert_eq!(subject("order_confirmed", "de"), "Order 42 confirmed");
+        assert_eq!(subject("Refund issued", "ro"), "Refund issued");
+
+        // preloaded subjects are not read again
+        fs::write(dir.join("email_en.html"), "{{contents}}").unwrap();
+        fs::write(dir.join("email_ro.html"), "{{contents}}").unwrap();
+        fs::write(dir.join("email_de.html"), "{{contents}}").unwrap();
+        let preloaded = Mailer::init_with_preload(config.clone()).unwrap();
+        fs::remove_file(dir.join("subjects_ro.txt")).unwrap();
+        assert_eq!(
+            preloaded.localized_subject("order_confirmed", Some("ro"), &order),
+            "Comanda 42 a fost confirmată"
+        );
+
+        let stub = Arc::new(MockTransport::new());
+        let mailer = Mailer::init_with_transport(config, stub.clone());
+        let message = MailMessage::builder()
+            .to("ana@example.com".parse().unwrap())
+            .subject_key("shipped")
+            .var("order", "7")
+            .html("<p>Shipped</p>")
+            .build()
+            .unwrap();
+        mailer.send_message(message.clone()).unwrap();
+        mailer.send_localized("order_confirmed", message).unwrap();
+        let subjects: Vec<String> = stub
+            .sent()
+            .iter()
+            .map(|mail| {
+                let eml = String::from_utf8(mail.formatted()).unwrap();
+                eml.lines()
+                    .find_map(|line| line.strip_prefix("Subject: "))
+                    .unwrap()
+                    .to_owned()
+            })
+            .collect();
+        assert_eq!(subjects, vec!["Order 7 shipped", "Order 7 confirmed"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn send_raw() {
+        let mut config = test_config(&["ro"]);
+        config.template_dir_path = test_file("missing");
+        let stub = Arc::new(CountingTransport::default());
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let recorded = sent.clone();
+        let mailer = Mailer::init_with_transport(config, stub.clone())
+            .on_sent(move |msg| recorded.lock().unwrap().push(msg.formatted.clone()));
+        mailer
+            .send_raw(
+                test_recipient(),
+                Some(vec!["office@example.com".parse().unwrap()]),
+                "Weekly report",
+                "<html><body><h1>Report</h1><p>All {{systems}} green</p></body></html>",
+                Some(vec![MailAttachment::from_path(&test_file(
+                    "attachment.txt",
+                ))]),
+            )
+            .unwrap();
+        assert_eq!(stub.sent.load(Ordering::SeqCst), 1);
+
+        let eml = String::from_utf8(sent.lock().unwrap()[0].clone()).unwrap();
+        assert!(eml.contains("Subject: Weekly report\r\n"));
+        assert!(eml.contains("Content-Type: multipart/mixed;"));
+        assert!(eml.contains("Content-Type: multipart/alternative;"));
+        assert!(eml.contains("All {{systems}} green"));
+        assert!(eml.contains("filename=\"attachment.txt\""));
+
+        // a templated send still needs the missing template directory
+        assert!(mailer
+            .send_message(
+                MailMessage::builder()
+                    .to("ana@example.com".parse().unwrap())
+                    .subject("Templated")
+                    .body("Body")
+                    .build()
+                    .unwrap()
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn send_with_bcc() {
+        let mock = Arc::new(MockTransport::new());
+        let mailer = Mailer::init_with_transport(test_config(&["ro"]), mock.clone());
+        mailer
+            .send_with_bcc(
+                test_recipient(),
+                None,
+                Some(vec![
+                    "audit@example.com".parse().unwrap(),
+                    "archive@example.com".parse().unwrap(),
+                ]),
+                "Invoice 42",
+                "Body",
+                None,
+                None,
+            )
+            .unwrap();
+        mailer
+            .send_with_bcc(test_recipient(), None, None, "Plain", "Body", None, None)
+            .unwrap();
+
+        let sent = mock.sent();
+        let envelope: Vec<String> = sent[0]
+            .envelope()
+            .to()
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect();
+        assert!(envelope.contains(&"audit@example.com".to_owned()));
+        assert!(envelope.contains(&"archive@example.com".to_owned()));
+        let formatted = String::from_utf8(sent[0].formatted()).unwrap();
+        assert!(!formatted.contains("audit@") && !formatted.contains("archive@"));
+        assert!(!formatted.to_ascii_lowercase().contains("bcc:"));
+        assert_eq!(sent[1].envelope().to().len(), 1);
+    }
+
+    #[test]
+    fn file_delivery() {
+        let dir = Path::new(env!("TEMP_DIR_PATH")).join("mailer_outbox");
+        let _ = fs::remove_dir_all(&dir);
+        let mut config = test_config(&["ro"]);
+        config.delivery = Delivery::File(dir.clone());
+        let mailer = Mailer::init(config);
+        for subject in ["Testare", "Testare"] {
+            mailer
+                .send_message(
+                    MailMessage::builder()
+                        .to("Ana Pop <ana@example.com>".parse().unwrap())
+                        .subject(subject)
+                        .body("Rust is the best")
+                        .attachment(MailAttachment::from_path(&test_file("attachment.txt")))
+                        .build()
+                        .unwrap(),
+                )
+                .unwrap();
+        }
+
+        let files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 2, "Overwritten: {:?}", files);
+        for file in &files {
+            let name = file.file_name().unwrap().to_str().unwrap();
+            assert!(name.starts_with("20") && name.ends_with(".eml"), "{}", name);
+            let eml = fs::read_to_string(file).unwrap();
+            assert!(eml.contains("Subject: Testare\r\n"));
+            assert!(eml.contains("To: \"Ana Pop\" <ana@example.com>\r\n"));
+        }
+    }
+
+    #[test]
+    fn preheader() {
+        let mailer = Mailer::init(test_config(&["en"]));
+        let message = |html: &str| {
+            MailMessage::builder()
+                .to("ana@example.com".parse().unwrap())
+                .subject("Offers")
+                .html(html)
+                .plain_text("Hi")
+                .preheader("Sale ends today")
+                .build()
+                .unwrap()
+        };
+        let eml = mailer
+            .render(message(
+                "<html><body bgcolor=\"#fff\"><p>Hi</p></body></html>",
+            ))
+            .unwrap();
+        assert!(
+            eml.contains("\r\n\r\nSale ends today\r\n\r\nHi\r\n"),
+            "{}",
+            eml
+        );
+        assert!(
+            eml.contains("<body bgcolor=3D\"#fff\"><span style=3D"),
+            "{}",
+            eml
+        );
+        assert!(
+            eml.contains(">Sale ends today</span><p>Hi</p></body>"),
+            "{}",
+            eml
+        );
+
+        // the HTML is left alone, the plain text still gets it
+        let eml = mailer.render(message("<p>Hi</p>")).unwrap();
+        assert!(eml.contains("\r\n\r\n<p>Hi</p>\r\n"), "{}", eml);
+        assert!(eml.contains("\r\n\r\nSale ends today\r\n\r\nHi\r\n"));
+    }
+
+    #[test]
+    fn render_eml() {
+        let txt_path = test_file("attachment.txt");
+        let mock = Arc::new(MockTransport::new());
+        let mailer = Mailer::init_with_transport(test_config(&["ro", "en"]), mock.clone());
+        let message = MailMessage::builder()
+            .to("Ana Pop <ana@example.com>".parse().unwrap())
+            .bcc("audit@example.com".parse().unwrap())
+            .subject("Invoice 42")
+            .body("Please find the invoice attached.")
+            .language("en")
+            .attachment(MailAttachment {
+                source: AttachmentSource::Path(&txt_path),
+                name: "invoice.txt",
+                mime: "text/plain",
+                content_id: None,
+                disposition: Disposition::Attachment,
+            })
+            .build()
+            .unwrap();
+        let eml = mailer.render(message).unwrap();
+        assert_eq!(mock.attempts(), 0);
+
+        let headers = &eml[..eml.find("\r\n\r\n").unwrap()];
+        assert!(
+            headers.contains("\r\nTo: \"Ana Pop\" <ana@example.com>\r\n"),
+            "{}",
+            headers
+        );
+        assert!(headers.contains("\r\nSubject: Invoice 42\r\n"));
+        assert!(!headers.contains("audit@example.com"));
+        let normalized = normalize_eml(eml.as_bytes());
+        let parts: Vec<&str> = normalized
+            .lines()
+            .filter(|line| line.starts_with("Content-Type: ") || line.starts_with("--BOUNDARY"))
+            .collect();
+        assert_eq!(
+            parts,
+            vec![
+                "Content-Type: multipart/mixed;",
+                "--BOUNDARY-1",
+                "Content-Type: multipart/alternative;",
+                "--BOUNDARY-2",
+                "Content-Type: text/plain; charset=utf-8",
+                "--BOUNDARY-2",
+                "Content-Type: text/html; charset=utf-8",
+                "--BOUNDARY-2--",
+                "--BOUNDARY-1",
+                "Content-Type: text/plain; name=\"invoice.txt\"",
+                "--BOUNDARY-1--",
+            ]
+        );
+        assert!(normalized.contains("Content-Disposition: attachment; filename=\"invoice.txt\""));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn sendmail_delivery() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = Path::new(env!("TEMP_DIR_PATH")).join("sendmail_delivery");
+        fs::create_dir_all(&dir).unwrap();
+        let script = |name: &str, body: &str| {
+            let path = dir.join(name);
+            fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+            path
+        };
+        let recorded = dir.join("recorded.eml");
+        let sendmail = script(
+            "sendmail",
+            &format!("echo \"$@\" > {0}.args\ncat > {0}", recorded.display()),
+        );
+        let mailer = |command: PathBuf| {
+            let mut config = test_config(&["en"]);
+            config.delivery = Delivery::Sendmail(Some(command));
+            Mailer::init(config)
+        };
+        let message = || {
+            MailMessage::builder()
+                .to("ana@example.com".parse().unwrap())
+                .subject("Nightly report")
+                .html("<p>Done</p>")
+                .build()
+                .unwrap()
+        };
+
+        let mailer_ok = mailer(sendmail.clone());
+        mailer_ok.test_connection().unwrap();
+        let report = mailer_ok.send_message(message()).unwrap();
+        assert_eq!(report.code, 250);
+        let eml = fs::read_to_string(&recorded).unwrap();
+        assert!(eml.contains(&format!("Message-ID: {}", report.message_id)));
+        assert!(eml.contains("Subject: Nightly report"));
+        let args = fs::read_to_string(recorded.with_extension("eml.args")).unwrap();
+        assert_eq!(args.trim(), "-i -f noreply@example.com -- ana@example.com");
+
+        let failing = script(
+            "failing",
+            "cat > /dev/null\necho 'queue is full' >&2\nexit 75",
+        );
+        let error = mailer(failing.clone()).send_message(message()).unwrap_err();
+        match error {
+            ErrorReport::MailSendmail { command, msg } => {
+                assert_eq!(command, failing.to_string_lossy());
+                assert_eq!(msg, "queue is full");
+            }
+            other => panic!("Expected a sendmail error, got {:?}", other),
+        }
+
+        let missing = mailer(dir.join("missing"));
+        assert!(missing.test_connection().is_err());
+        assert!(matches!(
+            missing.send_message(message()),
+            Err(ErrorReport::MailSendmail { .. })
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn metrics() {
+        let message = || {
+            MailMessage::builder()
+                .to("ana@example.com".parse().unwrap())
+                .subject("Subject")
+                .body("Body")
+                .build()
+                .unwrap()
+        };
+        let mock = Arc::new(MockTransport::new());
+        let mut config = test_config(&["ro"]);
+        config.retry_policy = RetryPolicy::new(2, Duration::from_millis(1), 1.0);
+        let mailer = Mailer::init_with_transport(config, mock.clone()).with_sleep(|_| {});
+        assert_eq!(mailer.metrics().count(), 0);
+
+        mailer.send_message(message()).unwrap();
+        mock.fail_next(421);
+        mailer.clone().send_message(message()).unwrap();
+        mock.fail_next(554);
+        assert!(mailer.send_message(message()).is_err());
+
+        let metrics = mailer.metrics();
+        assert_eq!((metrics.sent, metrics.failed, metrics.retried), (2, 1, 1));
+        assert_eq!(metrics.latency_buckets.iter().sum::<u64>(), 3);
+        assert!(metrics.latency_min <= metrics.latency_avg());
+        assert!(metrics.latency_avg() <= metrics.latency_max);
+    }
+
+    #[test]
+    fn send_merge() {
+        let dir = Path::new(env!("TEMP_DIR_PATH")).join("mail_merge");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("email_en.html"),
+            "<p>{{greeting}} {{name}}, {{contents}} {{link}}</p>",
+        )
+        .unwrap();
+        let mut config = test_config(&["en"]);
+        config.template_dir_path = dir.to_str().unwrap().to_owned();
+        let mock = Arc::new(MockTransport::new());
+        let connects = Arc::new(AtomicUsize::new(0));
+        let (counted, transport) = (connects.clone(), mock.clone());
+        let mailer = Mailer::init_with_connector(
+            config,
+            Arc::new(move || {
+                counted.fetch_add(1, Ordering::SeqCst);
+                Ok(transport.clone() as Arc<dyn MailTransport>)
+            }),
+        );
+        let message = MailMessage::builder()
+            .to("placeholder@example.com".parse().unwrap())
+            .subject("Your account")
+            .body("your link is")
+            .var("greeting", "Hello")
+            .var("link", "https://example.com/start")
+            .build()
+            .unwrap();
+        let recipients: Vec<(Mailbox, HashMap<String, String>)> = vec![
+            (
+                "bounce@example.com".parse().unwrap(),
+                vars(&[("name", "Nobody")]),
+            ),
+            (
+                "ana@example.com".parse().unwrap(),
+                vars(&[
+                    ("name", "Ana"),
+                    ("greeting", "Hi"),
+                    ("link", "https://example.com/a/1"),
+                ]),
+            ),
+            ("ion@example.com".parse().unwrap(), vars(&[("name", "Ion")])),
+        ];
+
+        mock.fail_next(550);
+        let results = mailer.send_merge(recipients, &message);
+        assert!(matches!(
+            results[0],
+            (ref to, Err(ErrorReport::MailSentResponse { code: 550, .. }))
+                if to.email.to_string() == "bounce@example.com"
+        ));
+        assert!(results[1].1.is_ok() && results[2].1.is_ok());
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+
+        let sent: Vec<String> = mock
+            .sent()
+            .iter()
+            .map(|mail| String::from_utf8(mail.formatted()).unwrap())
+            .collect();
+        assert_eq!(sent.len(), 2);
+        assert!(sent[0].contains("\r\nTo: ana@example.com\r\n"));
+        assert!(sent[0].contains("<p>Hi Ana, your link is https://example.com/a/1</p>"));
+        assert!(sent[1].contains("\r\nTo: ion@example.com\r\n"));
+        assert!(sent[1].contains("<p>Hello Ion, your link is https://example.com/start</p>"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn split_recipients() {
+        let mut config = test_config(&["en"]);
+        config.max_recipients_per_message = Some(2);
+        let mock = Arc::new(MockTransport::new());
+        let mailer = Mailer::init_with_transport(config, mock.clone());
+        let addrs = |names: &[&str]| -> Vec<Mailbox> {
+            names
+                .iter()
+                .map(|name| format!("{}@example.com", name).parse().unwrap())
+                .collect()
+        };
+        let message = || {
+            let mut builder = MailMessage::builder()
+                .subject("Minutes")
+                .html("<p>Minutes</p>");
+            for mailbox in addrs(&["ana", "ion", "eva"]) {
+                builder = builder.to(mailbox);
+            }
+            builder
+                .cc("dan@example.com".parse().unwrap())
+                .bcc("ops@example.com".parse().unwrap())
+                .build()
+                .unwrap()
+        };
+
+        let report = mailer.send_message(message()).unwrap();
+        assert_eq!(report.split.len(), 3);
+        assert_eq!(report.message_id, report.split[0].message_id);
+        let sent = mock.sent();
+        let envelopes: Vec<Vec<String>> = sent
+            .iter()
+            .map(|mail| {
+                mail.envelope()
+                    .to()
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            envelopes,
+            vec![
+                vec!["ana@example.com", "ion@example.com"],
+                vec!["eva@example.com", "dan@example.com"],
+                vec!["ops@example.com"],
+            ]
+        );
+        let formatted: Vec<String> = sent
+            .iter()
+            .map(|mail| String::from_utf8(mail.formatted()).unwrap())
+            .collect();
+        assert!(formatted[0].contains("\r\nTo: ana@example.com, ion@example.com\r\n"));
+        assert!(!formatted[0].contains("\r\nCc: "));
+        assert!(formatted[1].contains("\r\nTo: eva@example.com\r\n"));
+        assert!(formatted[1].contains("\r\nCc: dan@example.com\r\n"));
+        assert!(!formatted[2].contains("\r\nTo: ") && !formatted[2].contains("\r\nCc: "));
+
+        // the other messages still go out
+        mock.fail_next(550);
+        match mailer.send_message(message()) {
+            Err(ErrorReport::MailSplitFailed {
+                parts: 3,
+                sent,
+                failed,
+            }) => {
+                assert_eq!(sent.len(), 2);
+                assert_eq!(failed.len(), 1);
+                assert_eq!(failed[0].part, 1);
+                assert_eq!(
+                    failed[0].recipients,
+                    vec!["ana@example.com", "ion@example.com"]
+                );
+                assert!(matches!(
+                    failed[0].error,
+                    ErrorReport::MailSentResponse { code: 550, .. }
+                ));
+            }
+            other => panic!("Expected a failed part, got {:?}", other),
+        }
+        assert_eq!(mock.sent().len(), 5);
+
+        // within the limit the message goes out whole
+        let mut config = test_config(&["en"]);
+        config.max_recipients_per_message = Some(5);
+        let mock = Arc::new(MockTransport::new());
+        let mailer = Mailer::init_with_transport(config, mock.clone());
+        assert!(mailer.send_message(message()).unwrap().split.is_empty());
+        assert_eq!(mock.sent().len(), 1);
+    }
+
+    #[test]
+    fn send_individually() {
+        /// Refuses the mailboxes of the `bounce` domain
+        struct BouncingTransport {
+            sent: Mutex<Vec<Message>>,
+        }
+
+        impl MailTransport for BouncingTransport {
+            fn send(&self, message: &Message) -> Result<Response, ErrorReport> {
+                let bounced = message
+                    .envelope()
+                    .to()
+                    .iter()
+                    .any(|addr| addr.domain() == "bounce.example.com");
+                self.sent.lock().unwrap().push(message.clone());
+                Ok(Response::new(
+                    Code::new(
+                        match bounced {
+                            true => Severity::PermanentNegativeCompletion,
+                            false => Severity::PositiveCompletion,
+                        },
+                        Category::MailSystem,
+                        Detail::Zero,
+                    ),
+                    vec!["OK".to_owned()],
+                ))
+            }
+        }
+
+        let stub = Arc::new(BouncingTransport {
+            sent: Mutex::new(Vec::new()),
+        });
+        let connects = Arc::new(AtomicUsize::new(0));
+        let counted = connects.clone();
+        let transport = stub.clone();
+        let mailer = Mailer::init_with_connector(
+            test_config(&["ro"]),
+            Arc::new(move || {
+                counted.fetch_add(1, Ordering::SeqCst);
+                Ok(transport.clone() as Arc<dyn MailTransport>)
+            }),
+        );
+        let message = MailMessage::builder()
+            .to("placeholder@example.com".parse().unwrap())
+            .bcc("audit@example.com".parse().unwrap())
+            .subject("Your order")
+            .body("Your order shipped.")
+            .build()
+            .unwrap();
+        let recipients: Vec<Mailbox> = [
+            "Ana Pop <ana@example.com>",
+            "ion@bounce.example.com",
+            "maria@example.com",
+        ]
+        .iter()
+        .map(|val| val.parse().unwrap())
+        .collect();
+
+        let results = mailer.send_individually(recipients.clone(), &message);
+        assert_eq!(
+            results.iter().map(|(to, _)| to).collect::<Vec<_>>(),
+            recipients.iter().collect::<Vec<_>>()
+        );
+        assert!(results[0].1.is_ok());
+        assert!(matches!(
+            results[1].1,
+            Err(ErrorReport::MailSentResponse { .. })
+        ));
+        assert!(results[2].1.is_ok());
+
+        let sent = stub.sent.lock().unwrap();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+        for (mail, recipient) in sent.iter().zip(&recipients) {
+            let eml = String::from_utf8(mail.formatted()).unwrap();
+            let to = eml.lines().find(|line| line.starts_with("To: ")).unwrap();
+            assert!(to.contains(&recipient.email.to_string()) && !to.contains(','));
+            assert!(!eml.contains("placeholder@example.com"));
+            assert_eq!(mail.envelope().to().len(), 2);
+        }
+    }
+
+    #[test]
+    fn language_fallback() {
+        let mailer = Mailer::init(test_config(&["ro", "en"]));
+        let lang_of = |language: Option<&str>| {
+            let html = mailer
+                .render_html(&vars(&[("contents", "Body")]), language)
+                .unwrap();
+            html[html.find("lang=\"").unwrap() + 6..][..2].to_owned()
+        };
+        assert_eq!(lang_of(Some("en")), "en");
+        assert_eq!(lang_of(Some(" RO ")), "ro");
+        assert_eq!(lang_of(Some("EN")), "en");
+        // a template exists on disk, but the language is not configured
+        assert_eq!(lang_of(Some("de")), "ro");
+        assert_eq!(lang_of(Some("fr")), "ro");
+        assert_eq!(lang_of(None), "ro");
+
+        let mailer = Mailer::init(test_config(&["ro", "en", "pt-br", "pt", "zh"]));
+        assert_eq!(
+            mailer.language_chain(Some(" pt_BR ")),
+            vec!["pt-br", "pt", "ro"]
+        );
+        assert_eq!(
+            mailer.language_chain(Some("zh-Hant-TW")),
+            vec!["zh-hant-tw", "zh-hant", "zh", "ro"]
+        );
+        assert_eq!(mailer.language_chain(Some("RO")), vec!["ro"]);
+        assert_eq!(mailer.language_chain(None), vec!["ro"]);
+        // exact match, region stripped, then the default
+        assert_eq!(mailer.language(Some("pt-BR")), "pt-br");
+        assert_eq!(mailer.language(Some("pt-PT")), "pt");
+        assert_eq!(mailer.language(Some("zh-Hant-TW")), "zh");
+        assert_eq!(mailer.language(Some("en-GB")), "en");
+        assert_eq!(mailer.language(Some("fr-CA")), "ro");
+
+        assert!(Mailer::try_init(test_config(&["ro", "en"])).is_ok());
+        let mut config = test_config(&["ro", "en"]);
+        config.default_language = "de".to_owned();
+        match Mailer::try_init(config) {
+            Err(ErrorReport::MailConfigInvalid { problems }) => {
+                assert_eq!(
+                    problems,
+                    vec!["default language 'de' is not one of the languages [ro, en]"]
+                )
+            }
+            other => panic!("Expected invalid config, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn config_builder() {
+        let config = Config::builder()
+            .from_addrs("noreply@example.com")
+            .server("smtp.example.com")
+            .build()
+            .unwrap();
+        assert_eq!(config.port, 587);
+        assert_eq!(config.languages, vec!["en".to_owned()]);
+        assert_eq!(config.connect_timeout, Some(Duration::from_secs(30)));
+        assert_eq!(config.command_timeout, Some(Duration::from_secs(30)));
+        assert_eq!(config.reply_to, vec![config.from_addrs.clone()]);
+
+        let config = Config::builder()
+            .from_addrs(r#""Ops, Berlin" <ops@example.com>"#)
+            .reply_to("help@example.com")
+            .from_name("Support")
+            .server("smtp.example.com")
+            .languages(["RO", " en "])
+            .default_language("Ro")
+            .template_dir_path(test_file("mail"))
+            .build()
+            .unwrap();
+        assert_eq!(config.from_addrs.name.as_deref(), Some("Ops, Berlin"));
+        assert_eq!(config.reply_to[0].to_string(), "Support <help@example.com>");
+        assert_eq!(config.languages, vec!["ro".to_owned(), "en".to_owned()]);
+
+        let problems = |builder: super::ConfigBuilder| match builder.build() {
+            Err(ErrorReport::MailConfigInvalid { problems }) => problems,
+            other => panic!("Expected invalid config, got {:?}", other),
+        };
+        let valid = Config::builder()
+            .from_addrs("noreply@example.com")
+            .server("smtp.example.com");
+        assert_eq!(problems(valid.clone().server(" ")), vec!["server is empty"]);
+        assert_eq!(problems(valid.clone().port(0)), vec!["port must not be 0"]);
+        assert!(problems(valid.clone().languages(["ro"]))[0].starts_with("default language 'en'"));
+        assert!(
+            problems(valid.clone().template_dir_path(test_file("missing")))[0]
+                .starts_with("template directory")
+        );
+        assert!(problems(valid.clone().reply_to("not an address"))[0]
+            .starts_with("reply-to address 'not an address' is invalid"));
+        assert_eq!(
+            problems(valid.clone().reply_to("Help <help@example.com")),
+            vec!["reply-to address 'Help <help@example.com' is invalid: '<' is not closed by '>'"]
+        );
+        assert!(problems(
+            valid
+                .clone()
+                .retry_policy(RetryPolicy::new(1, Duration::ZERO, 0.5))
+        )[0]
+        .starts_with("retry multiplier 0.5"));
+        assert_eq!(
+            problems(valid.clone().connect_timeout(Some(Duration::ZERO))),
+            vec!["connect timeout must not be 0"]
+        );
+        assert_eq!(
+            problems(Config::builder().port(0)),
+            vec![
+                "from address is missing",
+                "server is empty",
+                "port must not be 0"
+            ]
+        );
+    }
+
+    #[test]
+    fn smtp_timeouts() {
+        let mut config = test_config(&["ro"]);
+        config.server = "10.255.255.1".to_owned();
+        config.port = 25;
+        config.tls_mode = TlsMode::None;
+        config.user_name = String::new();
+        config.connect_timeout = Some(Duration::from_millis(500));
+        config.command_timeout = Some(Duration::from_millis(500));
+        let message = MailMessage::builder()
+            .to("ana@example.com".parse().unwrap())
+            .subject("Subject")
+            .html("<p>Body</p>")
+            .build()
+            .unwrap();
+
+        // the connection is attempted twice, the second time on a fresh transport
+        let started = Instant::now();
+        let res = Mailer::try_init(config).unwrap().send_message(message);
+        assert!(res.is_err(), "{:?}", res);
+        assert!(
+            started.elapsed() < Duration::from_secs(3),
+            "Took {:?}",
+            started.elapsed()
+        );
+    }
+
+    #[test]
+    fn config_redaction() {
+        let config = test_config(&["ro"]);
+        let debug = format!("{:?}", Mailer::init(config.clone()));
+        assert!(
+            !debug.contains("secret-password"),
+            "Password leaked: {}",
+            debug
+        );
+        assert!(debug.contains("password: \"***\""));
+        assert!(!config.redacted().contains("secret-password"));
+        assert!(config.redacted().contains("localhost:587"));
+
+        let builder = Config::builder()
+            .from_addrs("noreply@example.com")
+            .server("localhost")
+            .credentials("user", "builder-password");
+        #[cfg(feature = "smime")]
+        let builder = builder.smime("cert.pem", "key.pem", Some("smime-passphrase"));
+        let debug = format!("{:?}", builder);
+        assert!(
+            !debug.contains("builder-password"),
+            "Password leaked: {}",
+            debug
+        );
+        assert!(
+            !debug.contains("passphrase"),
+            "Passphrase leaked: {}",
+            debug
+        );
+        assert!(debug.contains("password: \"***\""));
+
+        let mut tenant = config.clone();
+        tenant.password = "tenant-password".to_owned();
+        tenant.languages.push("en".to_owned());
+        assert_eq!(config.password, "secret-password");
+        assert_eq!(config.languages, vec!["ro".to_owned()]);
+    }
+
+    #[test]
+    fn attachment_name_placeholders() {
+        let txt_path = test_file("attachment.txt");
+        let attachment = |name: &'static str| {
+            Some(vec![MailAttachment {
+                source: AttachmentSource::Path(&txt_path),
+                name,
+                mime: "text/plain",
+                content_id: None,
+                disposition: Disposition::Attachment,
+            }])
+        };
+        let values = vars(&[
+            ("contents", "Invoice attached"),
+            ("order_id", "42"),
+            ("customer", "Ștefan"),
+        ]);
+        let render = |config: Config, name: &'static str| {
+            Mailer::init(config)
+                .build_message(&MailMessage::from_parts(
+                    test_recipient(),
+                    None,
+                    None,
+                    "Invoice",
+                    values.clone(),
+                    None,
+                    None,
+                    attachment(name),
+                ))
+                .map(|mail| String::from_utf8(mail.formatted()).unwrap())
+        };
+
+        let mut config = test_config(&["ro"]);
+        let eml = render(config.clone(), "invoice-{{order_id}}-ro.pdf").unwrap();
+        assert!(eml.contains("filename=\"invoice-{{order_id}}-ro.pdf\""));
+
+        config.attachment_name_placeholders = true;
+        let eml = render(config.clone(), "invoice-{{order_id}}-ro.pdf").unwrap();
+        assert!(eml.contains("filename=\"invoice-42-ro.pdf\""));
+        let eml = render(config.clone(), "invoice-{{missing}}42.pdf").unwrap();
+        assert!(eml.contains("filename=\"invoice-42.pdf\""));
+        let eml = render(config.clone(), "factură {{customer}}\r\n.pdf").unwrap();
+        assert!(
+            eml.contains("filename*0*=utf-8''factur%C4%83%20%C8%98tefan.pdf"),
+            "Name not encoded: {}",
+            eml
+        );
+
+        config.strict_placeholders = true;
+        match render(config, "invoice-{{missing}}-{{order_id}}.pdf") {
+            Err(ErrorReport::MailUnresolvedPlaceholders { names }) => {
+                assert_eq!(names, vec!["missing".to_owned()])
+            }
+            other => panic!(
+                "Expected unresolved placeholder error, got {:?}",
+                other.map(|_| ())
+            ),
+        }
+    }
+
+    #[test]
+    fn keep_alive_lifecycle() {
+        let stub = Arc::new(CountingTransport::default());
+        let mut config = test_config(&["ro"]);
+        config.keep_alive_interval = Some(Duration::from_millis(10));
+        let mailer = Mailer::init_with_transport(config, stub.clone());
+
+        mailer.warm_up().unwrap();
+        assert!(stub.checks.load(Ordering::SeqCst) >= 1);
+        thread::sleep(Duration::from_millis(100));
+        assert!(
+            stub.checks.load(Ordering::SeqCst) > 3,
+            "Keep-alive thread did not check the connection"
+        );
+        mailer
+            .send(test_recipient(), None, "Subject", "Body", None, None)
+            .unwrap();
+        assert_eq!(stub.sent.load(Ordering::SeqCst), 1);
+
+        mailer.disconnect();
+        let checks = stub.checks.load(Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(stub.checks.load(Ordering::SeqCst), checks);
+
+        mailer.warm_up().unwrap();
+        let clone = mailer.clone();
+        drop(mailer);
+        thread::sleep(Duration::from_millis(50));
+        assert!(stub.checks.load(Ordering::SeqCst) > checks + 2);
+        drop(clone);
+        let checks = stub.checks.load(Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(stub.checks.load(Ordering::SeqCst), checks);
+        assert_eq!(Arc::strong_count(&stub), 1, "Transport still referenced");
+    }
+
+    #[test]
+    fn shutdown() {
+        let message = || {
+            MailMessage::builder()
+                .to("ana@example.com".parse().unwrap())
+                .subject("Subject")
+                .body("Body")
+                .build()
+                .unwrap()
+        };
+        let mock = Arc::new(MockTransport::new());
+        mock.delay(Some(Duration::from_millis(300)));
+        let mailer = Mailer::init_with_transport(test_config(&["ro"]), mock.clone());
+        let sending = {
+            let mailer = mailer.clone();
+            thread::spawn(move || mailer.send_message(message()))
+        };
+        thread::sleep(Duration::from_millis(50));
+
+        // the send in flight outlives the timeout, and still gets through
+        let started = Instant::now();
+        match mailer.clone().shutdown(Duration::from_millis(50)) {
+            Err(ErrorReport::MailShutdownTimeout { in_flight: 1, .. }) => {}
+            other => panic!("Expected a shutdown timeout, got {:?}", other),
+        }
+        assert!(started.elapsed() < Duration::from_millis(250));
+        assert!(matches!(
+            mailer.send_message(message()),
+            Err(ErrorReport::MailShutDown)
+        ));
+        assert!(sending.join().unwrap().is_ok());
+        assert_eq!(mock.sent().len(), 1);
+
+        // with time enough, shutdown waits for it
+        let mailer = Mailer::init_with_transport(test_config(&["ro"]), mock.clone());
+        let sending = {
+            let mailer = mailer.clone();
+            thread::spawn(move || mailer.send_message(message()))
+        };
+        thread::sleep(Duration::from_millis(50));
+        mailer.shutdown(Duration::from_secs(5)).unwrap();
+        assert_eq!(mock.sent().len(), 2);
+        assert!(sending.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn transport_reuse() {
+        let stub = Arc::new(CountingTransport::default());
+        let connects = Arc::new(AtomicUsize::new(0));
+        let mailer = |config: Config| {
+            let stub = stub.clone();
+            let connects = connects.clone();
+            Mailer::init_with_connector(
+                config,
+                Arc::new(move || {
+                    connects.fetch_add(1, Ordering::SeqCst);
+                    Ok(stub.clone() as Arc<dyn MailTransport>)
+                }),
+            )
+        };
+        let send = |mailer: &Mailer| {
+            mailer
+                .send(test_recipient(), None, "Subject", "Body", None, None)
+                .unwrap()
+        };
+
+        let pooled = mailer(test_config(&["ro"]));
+        for _ in 0..5 {
+            send(&pooled);
+        }
+        send(&pooled.clone());
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+        pooled.reconnect().unwrap();
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+        send(&pooled);
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+
+        let mut config = test_config(&["ro"]);
+        config.transport_per_send = true;
+        let per_send = mailer(config);
+        for _ in 0..5 {
+            send(&per_send);
+        }
+        assert_eq!(connects.load(Ordering::SeqCst), 7);
+        assert_eq!(stub.sent.load(Ordering::SeqCst), 12);
+
+        // builds the relay without connecting
+        Mailer::try_init(test_config(&["ro"])).unwrap();
+    }
+
+    #[test]
+    fn retry_backoff() {
+        let send = |policy: RetryPolicy, codes: &[u16]| {
+            let mock = Arc::new(MockTransport::new());
+            codes.iter().for_each(|code| mock.fail_next(*code));
+            let delays = Arc::new(Mutex::new(Vec::new()));
+            let mut config = test_config(&["ro"]);
+            config.retry_policy = policy;
+            let recorded = delays.clone();
+            let res = Mailer::init_with_transport(config, mock.clone())
+                .with_sleep(move |delay| recorded.lock().unwrap().push(delay.as_millis()))
+                .send_message(
+                    MailMessage::builder()
+                        .to("ana@example.com".parse().unwrap())
+                        .subject("Subject")
+                        .body("Body")
+                        .build()
+                        .unwrap(),
+                );
+            let delays = delays.lock().unwrap().clone();
+            (res, mock.attempts(), mock.sent().len(), delays)
+        };
+        let policy = RetryPolicy::new(3, Duration::from_millis(100), 2.0);
+
+        let (res, attempts, sent, delays) = send(policy, &[421, 451]);
+        assert!(res.is_ok());
+        assert_eq!((attempts, sent), (3, 1));
+        assert_eq!(delays, vec![100, 200]);
+
+        let (res, attempts, sent, delays) = send(policy, &[421; 5]);
+        assert!(matches!(
+            res,
+            Err(ErrorReport::MailAttemptsFailed { attempts: 4, .. })
+        ));
+        assert_eq!((attempts, sent), (4, 0));
+        assert_eq!(delays, vec![100, 200, 400]);
 
-#[derive(Debug)]
-pub struct Config {
-    pub from_addrs: Mailbox,
-    pub reply_to: Mailbox,
-    pub server: String,
-    pub port: u16,
-    pub user_name: String,
-    pub password: String,
-    pub template_dir_path: String,
-    pub template_name_format: String,
-    pub languages: Vec<String>,
-    pub default_language: String,
-}
+        let (res, attempts, _, delays) = send(policy, &[554]);
+        assert!(matches!(res, Err(ErrorReport::MailSentResponse { .. })));
+        assert_eq!(attempts, 1);
+        assert!(delays.is_empty());
 
-#[derive(Debug)]
-pub struct MailAttachment<'a> {
-    pub path: &'a str,
-    pub name: &'a str,
-    pub mime: &'a str,
-}
+        let (res, attempts, _, delays) = send(RetryPolicy::default(), &[421]);
+        assert!(matches!(res, Err(ErrorReport::MailSentResponse { .. })));
+        assert_eq!(attempts, 1);
+        assert!(delays.is_empty());
+    }
 
-#[derive(Debug)]
-pub struct Mailer {
-    config: Config,
-}
+    #[test]
+    fn rate_limited_sends() {
+        let message = || {
+            MailMessage::builder()
+                .to("ana@example.com".parse().unwrap())
+                .subject("Subject")
+                .body("Body")
+                .build()
+                .unwrap()
+        };
+        let window = Duration::from_millis(200);
 
-impl Mailer {
-    pub fn init(config: Config) -> Self {
-        Mailer { config }
+        // the clones on other threads share the budget of 2 messages per window
+        let stub = Arc::new(CountingTransport::default());
+        let mut config = test_config(&["ro"]);
+        config.rate_limit = Some(RateLimit::new(2, window));
+        let mailer = Mailer::init_with_transport(config.clone(), stub.clone());
+        let started = Instant::now();
+        let workers: Vec<_> = (0..3)
+            .map(|_| {
+                let mailer = mailer.clone();
+                thread::spawn(move || mailer.send_message(message()).unwrap())
+            })
+            .collect();
+        workers.into_iter().for_each(|val| {
+            val.join().unwrap();
+        });
+        assert_eq!(stub.sent.load(Ordering::SeqCst), 3);
+        assert!(started.elapsed() >= window, "Took {:?}", started.elapsed());
+
+        config.rate_limit = Some(RateLimit::new(2, window).non_blocking());
+        let stub = Arc::new(CountingTransport::default());
+        let mailer = Mailer::init_with_transport(config, stub.clone());
+        assert!(mailer.send_message(message()).is_ok());
+        assert!(mailer.clone().send_message(message()).is_ok());
+        assert!(matches!(
+            mailer.send_message(message()),
+            Err(ErrorReport::MailRateLimited {
+                max_messages: 2,
+                ..
+            })
+        ));
+        assert_eq!(stub.sent.load(Ordering::SeqCst), 2);
     }
 
-    pub fn send(
-        &self,
-        to_addrs: Vec<Mailbox>,
-        cc_addrs: Option<Vec<Mailbox>>,
-        subject: &String,
-        message: &String,
-        language: Option<&String>,
-        attachments: Option<Vec<MailAttachment>>,
-    ) -> Result<(), ErrorReport> {
-        let html_body = |message: &String,
-                         language: Option<&String>,
-                         default_lang: &String,
-                         template_dir_path: &String,
-                         template_name_format: &String|
-         -> Result<SinglePart, ErrorReport> {
-            let lang = match language {
-                Some(val) => val,
-                None => default_lang,
-            };
-            let path =
-                Path::new(template_dir_path).join(template_name_format.replace("{lang}", lang));
-            let template_text = fs::read_to_string(path)?;
-            let body = template_text.replace("{{contents}}", message);
-            Ok(SinglePart::html(body))
+    #[test]
+    fn tls_modes() {
+        for (mode, user) in [
+            (TlsMode::StartTls, "user"),
+            (TlsMode::Wrapped, "user"),
+            (TlsMode::None, ""),
+        ] {
+            let mut config = test_config(&["ro"]);
+            config.tls_mode = mode;
+            config.user_name = user.to_owned();
+            Mailer::try_init(config).unwrap();
+        }
+
+        let mut config = test_config(&["ro"]);
+        config.tls_mode = TlsMode::None;
+        match Mailer::try_init(config) {
+            Err(ErrorReport::MailConfigInvalid { problems }) => {
+                assert!(problems[0].contains("plaintext"), "{:?}", problems)
+            }
+            other => panic!("Expected invalid config error, got {:?}", other),
+        }
+        assert!(matches!(
+            Config::builder()
+                .from_addrs("noreply@example.com")
+                .server("relay.internal")
+                .port(25)
+                .tls_mode(TlsMode::None)
+                .credentials("user", "secret")
+                .build(),
+            Err(ErrorReport::MailConfigInvalid { problems }) if problems.len() == 1
+        ));
+
+        let config = Config::builder()
+            .from_addrs("noreply@example.com")
+            .server("smtp.example.com")
+            .port(465)
+            .tls_mode("SMTPS".parse().unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(config.tls_mode, TlsMode::Wrapped);
+        assert!(config
+            .redacted()
+            .starts_with("smtps://@smtp.example.com:465 "));
+        assert!("ssl".parse::<TlsMode>().is_err());
+
+        // without a mode the port decides
+        let builder = Config::builder()
+            .from_addrs("noreply@example.com")
+            .server("smtp.example.com");
+        let mode = |builder: super::ConfigBuilder| builder.build().unwrap().tls_mode;
+        assert_eq!(mode(builder.clone().port(465)), TlsMode::Wrapped);
+        assert_eq!(mode(builder.clone().port(587)), TlsMode::StartTls);
+        assert_eq!(mode(builder.clone().port(2525)), TlsMode::StartTls);
+        assert_eq!(
+            mode(builder.port(465).tls_mode(TlsMode::StartTls)),
+            TlsMode::StartTls
+        );
+    }
+
+    #[test]
+    fn xoauth2_token_provider() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let provider_calls = calls.clone();
+        let mut config = test_config(&["ro"]);
+        config.auth = SmtpAuth::Xoauth2(Arc::new(move || {
+            match provider_calls.fetch_add(1, Ordering::SeqCst) {
+                0 => Ok("access-token".to_owned()),
+                _ => Err(std::io::Error::other("token endpoint unreachable").into()),
+            }
+        }));
+        assert!(format!("{:?}", config).contains("auth: Xoauth2(..)"));
+
+        // the token is asked for when the transport is built, a failure surfaces right away
+        Mailer::try_init(config.clone()).unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(matches!(
+            Mailer::try_init(config),
+            Err(ErrorReport::Io(e)) if e.to_string() == "token endpoint unreachable"
+        ));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn allow_invalid_certs() {
+        let mut config = test_config(&["ro"]);
+        assert!(config.invalid_certs_parameters().is_none());
+        assert!(config.tls_parameters().unwrap().is_none());
+
+        config.allow_invalid_certs = true;
+        let parameters = format!("{:?}", config.invalid_certs_parameters().unwrap());
+        assert!(
+            parameters.contains("domain: \"localhost\""),
+            "{}",
+            parameters
+        );
+        assert!(parameters.contains("accept_invalid_certs: true"));
+        assert!(parameters.contains("accept_invalid_hostnames: true"));
+        let parameters = config.tls_parameters().unwrap().unwrap();
+        assert_eq!(parameters.domain(), "localhost");
+        assert!(matches!(config.tls(parameters.clone()), Tls::Required(_)));
+        config.tls_mode = TlsMode::Wrapped;
+        assert!(matches!(config.tls(parameters), Tls::Wrapper(_)));
+        Mailer::try_init(config.clone()).unwrap();
+
+        // plaintext has no certificate to check
+        config.tls_mode = TlsMode::None;
+        config.user_name.clear();
+        assert!(config.invalid_certs_parameters().is_none());
+    }
+
+    #[test]
+    fn audit_log() {
+        let audit_path = Path::new(env!("TEMP_DIR_PATH")).join("mailer_audit.jsonl");
+        let _ = fs::remove_file(&audit_path);
+        let txt_path = test_file("attachment.txt");
+        let stub = Arc::new(CountingTransport::default());
+        let mut config = test_config(&["ro"]);
+        config.audit_log_path = Some(audit_path.clone());
+        let mailer = Mailer::init_with_transport(config, stub.clone());
+
+        mailer
+            .send(
+                test_recipient(),
+                Some(vec!["office@example.com".parse().unwrap()]),
+                "Invoice 42",
+                "Invoice attached",
+                None,
+                Some(vec![MailAttachment {
+                    source: AttachmentSource::Path(&txt_path),
+                    name: "invoice.txt",
+                    mime: "text/plain",
+                    content_id: None,
+                    disposition: Disposition::Attachment,
+                }]),
+            )
+            .unwrap();
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let mailer = mailer.clone();
+                thread::spawn(move || {
+                    mailer
+                        .send(
+                            test_recipient(),
+                            None,
+                            &format!("Parallel {}", i),
+                            "Body",
+                            None,
+                            None,
+                        )
+                        .unwrap()
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert!(mailer
+            .send(
+                test_recipient(),
+                None,
+                "Missing attachment",
+                "Body",
+                None,
+                Some(vec![MailAttachment {
+                    source: AttachmentSource::Path("missing.txt"),
+                    name: "missing.txt",
+                    mime: "text/plain",
+                    content_id: None,
+                    disposition: Disposition::Attachment,
+                }])
+            )
+            .is_err());
+        assert_eq!(stub.sent.load(Ordering::SeqCst), 5);
+
+        let records: Vec<Value> = fs::read_to_string(&audit_path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 6);
+
+        let first = &records[0];
+        assert_eq!(first["outcome"], "ok");
+        assert_eq!(first["from"], "Test Sender <noreply@example.com>");
+        assert_eq!(first["to_count"], 1);
+        assert_eq!(first["to"][0], "Ana Pop <ana@example.com>");
+        assert_eq!(first["cc_count"], 1);
+        assert_eq!(first["subject"], "Invoice 42");
+        assert_eq!(first["attachments"][0]["name"], "invoice.txt");
+        assert_eq!(
+            first["attachments"][0]["size"],
+            fs::metadata(&txt_path).unwrap().len()
+        );
+        assert!(first["message_id"].as_str().unwrap().starts_with('<'));
+        assert_eq!(first["code"], 250);
+        assert!(first["timestamp"].as_str().unwrap().ends_with('Z'));
+        assert!(first["duration_ms"].is_u64());
+        assert!(records[1..5]
+            .iter()
+            .all(|record| record["outcome"] == "ok" && record["cc_count"] == 0));
+
+        let failed = &records[5];
+        assert_eq!(failed["outcome"], "error");
+        assert_eq!(failed["subject"], "Missing attachment");
+        assert!(failed["message_id"].is_null());
+        assert!(failed["error"].as_str().unwrap().starts_with("NotFound"));
+        fs::remove_file(&audit_path).unwrap();
+    }
+
+    #[test]
+    fn subject_prefix_and_footer() {
+        let mut config = test_config(&["ro", "en", "de"]);
+        let values = vars(&[("contents", "Hello"), ("company", "Acme")]);
+        let footer_of = |config: &Config, lang: &str| {
+            let html = Mailer::init(config.clone())
+                .render_html(&values, Some(lang))
+                .unwrap();
+            html.find("<p style")
+                .map(|start| html[start..].split("</p>").next().unwrap().to_owned())
         };
+        assert_eq!(footer_of(&config, "ro"), None);
 
-        let attachement_part = |attachment: &MailAttachment| -> Result<SinglePart, ErrorReport> {
-            Ok(Attachment::new(attachment.name.to_owned())
-                .body(fs::read(attachment.path)?, attachment.mime.parse()?))
+        config.footer_name_format = Some("footer_{lang}.html".to_owned());
+        let html = Mailer::init(config.clone())
+            .render_html(&values, Some("ro"))
+            .unwrap();
+        assert!(
+            html.contains("<p>Hello</p>\n    <p style=\"font-size: 11px\">Acme SRL, J40/1234/2020"),
+            "Footer not before the closing body tag: {}",
+            html
+        );
+        assert!(html.trim_end().ends_with("</body>\n</html>"));
+        assert!(footer_of(&config, "en").unwrap().contains("Acme Ltd"));
+        assert!(
+            footer_of(&config, "de").unwrap().contains("Acme SRL"),
+            "Missing translation did not fall back to the default language"
+        );
+
+        config.subject_prefix = Some("[STAGING]".to_owned());
+        let eml = Mailer::init(config.clone())
+            .build_message(&MailMessage::from_parts(
+                test_recipient(),
+                None,
+                None,
+                "Invoice",
+                values.clone(),
+                None,
+                Some("en"),
+                None,
+            ))
+            .map(|mail| String::from_utf8(mail.formatted()).unwrap())
+            .unwrap();
+        assert!(eml.contains("Subject: [STAGING] Invoice\r\n"));
+        assert!(eml.contains("Acme Ltd"));
+
+        let raw = |footer: bool| {
+            let mut builder = MailMessage::builder()
+                .to("ana@example.com".parse().unwrap())
+                .subject("Raw")
+                .var("company", "Acme")
+                .language("en")
+                .html("<html><body><p>Rendered elsewhere</p></body></html>");
+            if !footer {
+                builder = builder.without_footer();
+            }
+            let message = builder.build().unwrap();
+            let mail = Mailer::init(config.clone())
+                .build_message(&message)
+                .unwrap();
+            String::from_utf8(mail.formatted()).unwrap()
         };
+        let eml = raw(true);
+        assert!(
+            eml.contains("Acme Ltd"),
+            "Raw body without the footer: {}",
+            eml
+        );
+        let eml = raw(false);
+        assert!(eml.contains("Rendered elsewhere") && !eml.contains("Acme"));
+
+        config.footer_name_format = Some("missing_{lang}.html".to_owned());
+        match Mailer::init(config).validate_templates() {
+            Err(ErrorReport::MailTemplatesMissing { paths }) => {
+                assert_eq!(paths.len(), 1);
+                assert!(paths[0].ends_with("missing_ro.html"));
+            }
+            other => panic!("Expected missing footer error, got {:?}", other),
+        }
+    }
 
-        let transport = |server: &String,
-                         port: &u16,
-                         user_name: &String,
-                         password: &String|
-         -> Result<SmtpTransport, ErrorReport> {
-            Ok(SmtpTransport::starttls_relay(server)?
-                .port(*port)
-                .credentials(Credentials::new(user_name.clone(), password.clone()))
-                .build())
+    #[test]
+    fn sent_and_failed_hooks() {
+        let stub = Arc::new(CountingTransport::default());
+        let sent = Arc::new(Mutex::new(Vec::<SentMessage>::new()));
+        let failed = Arc::new(AtomicUsize::new(0));
+        let mailer = {
+            let sent = sent.clone();
+            let failed = failed.clone();
+            Mailer::init_with_transport(test_config(&["ro"]), stub.clone())
+                .on_sent(move |message| sent.lock().unwrap().push(message.clone()))
+                .on_failed(move |message| {
+                    assert!(matches!(message.error, ErrorReport::Io(_)));
+                    assert_eq!(message.subject, "Missing attachment");
+                    failed.fetch_add(1, Ordering::SeqCst);
+                })
         };
 
-        let mut builder = Message::builder()
-            .from(self.config.from_addrs.clone())
-            .reply_to(self.config.reply_to.clone())
-            .subject(subject);
+        for subject in ["First", "Second"] {
+            mailer
+                .send(test_recipient(), None, subject, "Body", None, None)
+                .unwrap();
+        }
+        let res = mailer.send(
+            test_recipient(),
+            None,
+            "Missing attachment",
+            "Body",
+            None,
+            Some(vec![MailAttachment {
+                source: AttachmentSource::Path("missing.txt"),
+                name: "missing.txt",
+                mime: "text/plain",
+                content_id: None,
+                disposition: Disposition::Attachment,
+            }]),
+        );
+        assert!(matches!(res, Err(ErrorReport::Io(_))));
+
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(failed.load(Ordering::SeqCst), 1);
+        assert_eq!(sent[1].subject, "Second");
+        assert_eq!(sent[1].to, test_recipient());
+        assert!(sent[1].finished >= sent[1].started);
+        let eml = String::from_utf8(sent[1].formatted.clone()).unwrap();
+        assert!(eml.contains("Subject: Second\r\n"));
+        assert!(eml.contains(&format!(
+            "Message-ID: {}\r\n",
+            sent[1].message_id.as_ref().unwrap()
+        )));
+
+        let panicking = Mailer::init_with_transport(test_config(&["ro"]), stub.clone())
+            .on_sent(|_| panic!("hook failure"));
+        panicking
+            .send(test_recipient(), None, "Subject", "Body", None, None)
+            .unwrap();
+        assert_eq!(stub.sent.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn send_deadline() {
+        struct HangingTransport {
+            delay: Duration,
+            attempts: AtomicUsize,
+        }
 
-        for addr in to_addrs {
-            builder = builder.to(addr);
+        impl MailTransport for HangingTransport {
+            fn send(&self, _message: &Message) -> Result<Response, ErrorReport> {
+                self.attempts.fetch_add(1, Ordering::SeqCst);
+                thread::sleep(self.delay);
+                Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "read timed out").into())
+            }
         }
 
-        if let Some(addrs) = cc_addrs {
-            for addr in addrs {
-                builder = builder.cc(addr);
+        let send = |delay_ms: u64, deadline_ms: Option<u64>, command_ms: Option<u64>| {
+            let stub = Arc::new(HangingTransport {
+                delay: Duration::from_millis(delay_ms),
+                attempts: AtomicUsize::new(0),
+            });
+            let mut config = test_config(&["ro"]);
+            config.send_deadline = deadline_ms.map(Duration::from_millis);
+            config.command_timeout = command_ms.map(Duration::from_millis);
+            let started = Instant::now();
+            let res = Mailer::init_with_transport(config, stub.clone()).send(
+                test_recipient(),
+                None,
+                "Subject",
+                "Body",
+                None,
+                None,
+            );
+            (res, stub.attempts.load(Ordering::SeqCst), started.elapsed())
+        };
+
+        // the stale connection is replaced once, then the error is returned
+        let (res, attempts, _) = send(20, None, Some(10));
+        assert!(matches!(res, Err(ErrorReport::Io(_))));
+        assert_eq!(attempts, 2);
+
+        // the first attempt hangs past the budget
+        let (res, attempts, elapsed) = send(2000, Some(150), None);
+        assert_eq!(attempts, 1);
+        assert!(elapsed < Duration::from_millis(500), "Took {:?}", elapsed);
+        match res {
+            Err(e @ ErrorReport::MailDeadlineExceeded { .. }) => {
+                assert!(e.is_timeout());
+                assert!(e.to_string().contains("1 attempt(s): attempt 1 after"));
             }
+            other => panic!("Expected deadline error, got {:?}", other),
         }
 
-        let mut part = MultiPart::mixed().singlepart(html_body(
-            message,
-            language,
-            &self.config.default_language,
-            &self.config.template_dir_path,
-            &self.config.template_name_format,
-        )?);
-
-        if let Some(attchs) = attachments {
-            for attch in attchs {
-                part = part.singlepart(attachement_part(&attch)?);
-            }
-        }
-
-        let mail = builder.multipart(part)?;
-        let res = transport(
-            &self.config.server,
-            &self.config.port,
-            &self.config.user_name,
-            &self.config.password,
-        )?
-        .send(&mail)?;
-
-        match res.code().severity {
-            Severity::PositiveCompletion => Ok(()),
-            _ => Err(crate::error::error_mail_sent_response(
-                &res.message().fold(String::new(), |t, s| t + s + "\n"),
-            )),
+        // the reconnect attempt only gets what is left of the budget
+        let (res, attempts, elapsed) = send(100, Some(150), None);
+        assert_eq!(attempts, 2);
+        assert!(elapsed < Duration::from_millis(500), "Took {:?}", elapsed);
+        match res {
+            Err(ErrorReport::MailDeadlineExceeded { budget, attempts }) => {
+                assert_eq!(budget, Duration::from_millis(150));
+                assert_eq!(attempts.len(), 2);
+            }
+            other => panic!("Expected deadline error, got {:?}", other),
+        }
+
+        // an attempt past the command timeout may still deliver, so it is not retried
+        let (res, attempts, elapsed) = send(300, Some(2000), Some(50));
+        assert_eq!(attempts, 1);
+        assert!(elapsed < Duration::from_millis(250), "Took {:?}", elapsed);
+        match res {
+            Err(e @ ErrorReport::MailSendAbandoned { .. }) => {
+                assert!(e.is_timeout());
+                assert!(!e.is_transient());
+            }
+            other => panic!("Expected abandoned send, got {:?}", other),
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::{Config, MailAttachment, Mailer};
-    use crate::envars::{AppConfig, CONFIG_FILE_DELIMITER};
-    use lettre::{message::Mailbox, Address};
-    use std::path::Path;
+    #[test]
+    fn named_placeholders() {
+        let mut config = test_config(&["en"]);
+        config.template_dir_path = Path::new(env!("WORKSPACE_ROOT_PATH"))
+            .join("assets/test/mail_vars")
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let html = |config: Config, values: &[(&str, &str)]| {
+            Mailer::init(config)
+                .build_message(&MailMessage::from_parts(
+                    test_recipient(),
+                    None,
+                    None,
+                    "Order",
+                    vars(values),
+                    Some("plain"),
+                    None,
+                    None,
+                ))
+                .map(|mail| {
+                    let eml = normalize_eml(&mail.formatted());
+                    eml[eml.find("<body>").unwrap()..].to_owned()
+                })
+        };
+        let values = [
+            ("name", "Ana"),
+            ("order_id", "A-17"),
+            ("status", "shipped"),
+            ("contents", "Thank you!"),
+        ];
+
+        let body = html(config.clone(), &values).unwrap();
+        assert!(body.contains("<p>Hello Ana,</p>"));
+        assert!(body.contains("<p>Order A-17 is shipped.</p>"));
+        assert!(body.contains("<p>Thank you!</p>"));
+        assert!(body.contains("<p>Questions about A-17? Reply, Ana.</p>"));
+
+        match html(config.clone(), &values[..2]) {
+            Err(ErrorReport::MailUnresolvedPlaceholders { names }) => {
+                assert_eq!(names, vec!["status", "contents"])
+            }
+            other => panic!("Expected unresolved placeholder error, got {:?}", other),
+        }
+        config.lenient_placeholders = true;
+        let body = html(config.clone(), &values[..2]).unwrap();
+        assert!(body.contains("<p>Order A-17 is .</p>"));
+        assert!(body.contains("<p></p>"));
+
+        config.strict_placeholders = true;
+        assert!(matches!(
+            html(config, &values[..3]),
+            Err(ErrorReport::MailUnresolvedPlaceholders { names }) if names == vec!["contents"]
+        ));
+    }
 
     #[test]
-    fn send_mail() {
-        let root_dir = Path::new(env!("WORKSPACE_ROOT_PATH"));
-        let config_path = Path::new(env!("APP_CONFIG_FILE_PATH"));
-        let app_config = AppConfig::init(config_path, CONFIG_FILE_DELIMITER).unwrap();
-        let config = Config {
-            from_addrs: {
-                let text = app_config.get_var("MAIL:FROM_ADDRS").unwrap();
-                let mut split = text.split('@');
-                Mailbox::new(
-                    app_config
-                        .get_var("MAIL:FROM_NAME")
-                        .map(|val| val.to_owned()),
-                    Address::new(split.next().unwrap(), split.next().unwrap()).unwrap(),
-                )
-            },
-            reply_to: {
-                let text = app_config.get_var("MAIL:REPLY_TO").unwrap();
-                let mut split = text.split('@');
-                Mailbox::new(
-                    app_config
-                        .get_var("MAIL:FROM_NAME")
-                        .map(|val| val.to_owned()),
-                    Address::new(split.next().unwrap(), split.next().unwrap()).unwrap(),
-                )
-            },
-            server: app_config.get_var("MAIL:SMTP_SERVER").unwrap().to_owned(),
-            port: app_config
-                .get_var("MAIL:SMTP_PORT")
-                .unwrap()
-                .parse()
+    fn unresolved_placeholders() {
+        let mut config = test_config(&["en"]);
+        config.template_dir_path = Path::new(env!("WORKSPACE_ROOT_PATH"))
+            .join("assets/test/mail_placeholders")
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let build = |config: Config, vars: &HashMap<String, String>| {
+            Mailer::init(config).build_message(&MailMessage::from_parts(
+                vec!["ana@example.com".parse().unwrap()],
+                None,
+                None,
+                "Placeholders",
+                vars.clone(),
+                None,
+                None,
+                None,
+            ))
+        };
+        let all_vars = vars(&[("name", "Ana"), ("contents", "Your order shipped.")]);
+
+        // CSS, JSON and foreign template syntax in the body are not placeholders
+        assert!(build(config.clone(), &all_vars).is_ok());
+        let html = Mailer::init(config.clone())
+            .render_html(&all_vars, None)
+            .unwrap();
+        assert!(html.contains("Hello Ana,"));
+        assert!(html.contains(r#"{{"id": 42}}"#));
+        assert!(html.contains("{{ user.name | upper }}"));
+
+        match build(config.clone(), &vars(&[("name", "Ana")])) {
+            Err(ErrorReport::MailUnresolvedPlaceholders { names }) => {
+                assert_eq!(names, vec!["contents"])
+            }
+            other => panic!("Expected unresolved placeholder error, got {:?}", other),
+        }
+
+        let mut allowed = config.clone();
+        allowed.allowed_placeholders = vec!["contents".to_owned()];
+        assert!(build(allowed, &vars(&[("name", "Ana")])).is_ok());
+        let mut lenient = config.clone();
+        lenient.lenient_placeholders = true;
+        assert!(build(lenient, &vars(&[])).is_ok());
+
+        let mut brackets = config;
+        brackets.placeholder_delimiters = ("[[".to_owned(), "]]".to_owned());
+        match build(brackets.clone(), &all_vars) {
+            Err(ErrorReport::MailUnresolvedPlaceholders { names }) => {
+                assert_eq!(names, vec!["order_id"])
+            }
+            other => panic!("Expected unresolved placeholder error, got {:?}", other),
+        }
+        let order_vars = vars(&[("order_id", "A-17")]);
+        assert!(build(brackets.clone(), &order_vars).is_ok());
+        let html = Mailer::init(brackets)
+            .render_html(&order_vars, None)
+            .unwrap();
+        assert!(html.contains("Rendered client side: A-17"));
+        assert!(html.contains("Hello {{name}},"));
+
+        assert!(matches!(
+            Config::builder()
+                .from_addrs("noreply@example.com")
+                .placeholder_delimiters("", "}}")
+                .build(),
+            Err(ErrorReport::MailConfigInvalid { problems })
+                if problems.iter().any(|val| val.contains("delimiters"))
+        ));
+    }
+
+    #[test]
+    fn validate_templates() {
+        let orphans = Mailer::init(test_config(&["ro", "en", "de"]))
+            .validate_templates()
+            .unwrap();
+        assert!(orphans.is_empty(), "Unexpected orphans: {:?}", orphans);
+
+        let orphans = Mailer::init(test_config(&["ro", "en"]))
+            .validate_templates()
+            .unwrap();
+        assert_eq!(orphans, vec!["de".to_owned()]);
+
+        match Mailer::init(test_config(&["ro", "it", "fr"])).validate_templates() {
+            Err(ErrorReport::MailTemplatesMissing { paths }) => {
+                assert_eq!(paths.len(), 2);
+                assert!(paths[0].ends_with("email_it.html"));
+                assert!(paths[1].ends_with("email_fr.html"));
+            }
+            other => panic!("Expected missing templates error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn validate() {
+        assert!(Mailer::init(test_config(&["ro", "en", "de"]))
+            .validate()
+            .is_ok());
+
+        let dir = Path::new(env!("TEMP_DIR_PATH")).join("validate");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("email_en.html"), "<p>{{contents}}</p>").unwrap();
+        fs::write(dir.join("email_ro.html"), "<p>{{name}}</p>").unwrap();
+        fs::write(
+            dir.join("email_fr.html"),
+            "{{> missing}}<p>{{contents}}</p>",
+        )
+        .unwrap();
+        fs::write(dir.join("strings_en.properties"), "regards\n").unwrap();
+        let mut config = test_config(&["en", "ro", "de", "fr"]);
+        config.template_dir_path = dir.to_str().unwrap().to_owned();
+        config.catalog_name_format = Some("strings_{lang}.properties".to_owned());
+
+        // every problem is reported, not just the first
+        match Mailer::try_init(config.clone()) {
+            Err(ErrorReport::MailTemplatesInvalid { problems }) => {
+                assert_eq!(problems.len(), 4, "{:?}", problems);
+                assert!(problems[0].ends_with("email_de.html does not exist"));
+                assert!(problems[1].contains("strings_en.properties:1"));
+                assert!(problems[2].ends_with("email_ro.html has no {{contents}} placeholder"));
+                assert!(problems[3].contains("email_fr.html cannot be read: MailTemplateInclude"));
+            }
+            other => panic!("Expected invalid templates, got {:?}", other.map(|_| ())),
+        }
+        config.skip_template_validation = true;
+        assert!(Mailer::try_init(config).is_ok());
+    }
+
+    #[cfg(feature = "handlebars")]
+    #[test]
+    fn handlebars_templates() {
+        use super::TemplateEngine;
+        use serde_json::json;
+
+        let dir = Path::new(env!("TEMP_DIR_PATH")).join("template_handlebars");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("email_en.html"),
+            "<html><body><ul>{{#each items}}<li>{{name}} x{{qty}}</li>{{/each}}</ul>\
+             {{#if gift}}<p>Gift wrapped</p>{{/if}}</body></html>",
+        )
+        .unwrap();
+        fs::write(dir.join("footer_en.html"), "<p>Order {{order}}</p>").unwrap();
+        let mut config = test_config(&["en"]);
+        config.template_dir_path = dir.to_str().unwrap().to_owned();
+        config.footer_name_format = Some("footer_{lang}.html".to_owned());
+        config.template_engine = TemplateEngine::Handlebars;
+        let message = MailMessage::builder()
+            .to("ana@example.com".parse().unwrap())
+            .subject("Your order")
+            .context(json!({
+                "order": 42,
+                "items": [{"name": "Pen & ink", "qty": 2}, {"name": "Paper", "qty": 1}],
+            }))
+            .build()
+            .unwrap();
+        let expected = "<html><body><ul><li>Pen &amp; ink x2</li><li>Paper x1</li></ul>\
+                        <p>Order 42</p></body></html>";
+
+        for mailer in [
+            Mailer::init(config.clone()),
+            Mailer::init_with_preload(config.clone()).unwrap(),
+        ] {
+            assert_eq!(mailer.render_message(&message).unwrap(), expected);
+        }
+
+        // compile errors surface at the preload, or at the send without one
+        fs::write(dir.join("email_en.html"), "<ul>{{#each items}}<li>").unwrap();
+        assert!(matches!(
+            Mailer::init_with_preload(config.clone()),
+            Err(ErrorReport::MailTemplateCompile(_))
+        ));
+        assert!(matches!(
+            Mailer::init(config).render_message(&message),
+            Err(ErrorReport::MailTemplateCompile(_))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn preload_templates() {
+        let dir = Path::new(env!("TEMP_DIR_PATH")).join("template_cache");
+        fs::create_dir_all(&dir).unwrap();
+        for lang in ["ro", "en"] {
+            fs::write(
+                dir.join(format!("email_{}.html", lang)),
+                format!("<p>{} v1 {{{{contents}}}}</p>", lang),
+            )
+            .unwrap();
+        }
+        let mut config = test_config(&["ro", "en"]);
+        config.template_dir_path = dir.to_str().unwrap().to_owned();
+        let body = vars(&[("contents", "hi")]);
+
+        let mailer = Mailer::init_with_preload(config.clone()).unwrap();
+        let clone = mailer.clone();
+        fs::write(dir.join("email_en.html"), "<p>en v2 {{contents}}</p>").unwrap();
+        assert_eq!(
+            mailer.render_html(&body, Some("en")).unwrap(),
+            "<p>en v1 hi</p>"
+        );
+        // without a preload every send reads the file
+        assert_eq!(
+            Mailer::init(config.clone())
+                .render_html(&body, Some("en"))
                 .unwrap(),
-            user_name: app_config.get_var("MAIL:SMTP_USER").unwrap().to_owned(),
-            password: app_config.get_var("MAIL:SMTP_PASS").unwrap().to_owned(),
-            template_dir_path: root_dir
-                .join(app_config.get_var("MAIL:TEMPLATE_DIR").unwrap())
-                .to_str()
-                .unwrap()
-                .to_owned(),
-            template_name_format: app_config
-                .get_var("MAIL:TEMPLATE_NAME_FORMAT")
-                .unwrap()
-                .to_owned(),
-            languages: {
-                let text = app_config.get_var("MAIL:LANGS").unwrap();
-                let res: Vec<String> = text
-                    .split(',')
-                    .map(|val| val.trim().to_lowercase())
-                    .filter(|val| !val.is_empty())
-                    .collect();
-                if res.is_empty() {
-                    panic!("No list of mail languages was provided");
-                }
-                res
-            },
-            default_language: app_config
-                .get_var("MAIL:LANG_DEFAULT")
+            "<p>en v2 hi</p>"
+        );
+
+        mailer.reload_templates().unwrap();
+        assert_eq!(
+            clone.render_html(&body, Some("en")).unwrap(),
+            "<p>en v2 hi</p>"
+        );
+        assert_eq!(
+            clone.render_html(&body, Some("ro")).unwrap(),
+            "<p>ro v1 hi</p>"
+        );
+
+        // a failed reload keeps the cached templates
+        fs::remove_file(dir.join("email_ro.html")).unwrap();
+        assert!(matches!(
+            mailer.reload_templates(),
+            Err(ErrorReport::MailTemplatesMissing { paths }) if paths.len() == 1
+        ));
+        assert_eq!(
+            mailer.render_html(&body, Some("ro")).unwrap(),
+            "<p>ro v1 hi</p>"
+        );
+
+        config.footer_name_format = Some("footer_{lang}.html".to_owned());
+        match Mailer::init_with_preload(config) {
+            Err(ErrorReport::MailTemplatesMissing { paths }) => {
+                assert_eq!(paths.len(), 2);
+                assert!(paths[0].ends_with("email_ro.html"));
+                assert!(paths[1].ends_with("footer_ro.html"));
+            }
+            other => panic!("Expected missing templates error, got {:?}", other),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn default_language_fallback() {
+        let dir = Path::new(env!("TEMP_DIR_PATH")).join("template_fallback");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("email_en.html"), "<p>en {{contents}}</p>").unwrap();
+        let mut config = test_config(&["en", "de"]);
+        config.template_dir_path = dir.to_str().unwrap().to_owned();
+        let mailer = Mailer::init(config);
+        let body = vars(&[("contents", "hi")]);
+
+        assert_eq!(
+            mailer.render_html(&body, Some("de")).unwrap(),
+            "<p>en hi</p>"
+        );
+
+        fs::remove_file(dir.join("email_en.html")).unwrap();
+        match mailer.render_html(&body, Some("de")) {
+            Err(ErrorReport::MailTemplatesMissing { paths }) => {
+                assert_eq!(paths.len(), 2);
+                assert!(paths[0].ends_with("email_de.html"));
+                assert!(paths[1].ends_with("email_en.html"));
+            }
+            other => panic!("Expected missing templates error, got {:?}", other),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn template_partials() {
+        let dir = Path::new(env!("TEMP_DIR_PATH")).join("template_partials");
+        fs::create_dir_all(dir.join("partials")).unwrap();
+        let write = |name: &str, text: &str| fs::write(dir.join(name), text).unwrap();
+        write("email_en.html", "{{> partials/header }}<p>{{contents}}</p>");
+        write("email_ro.html", "{{> partials/header}}<p>{{contents}}</p>");
+        write(
+            "partials/header_en.html",
+            "<h1>{{> partials/logo}} News</h1>",
+        );
+        write(
+            "partials/header_ro.html",
+            "<h1>{{> partials/logo}} Noutăți</h1>",
+        );
+        // no language of its own
+        write("partials/logo.html", "<img src=\"cid:logo\">");
+        let mut config = test_config(&["en", "ro"]);
+        config.template_dir_path = dir.to_str().unwrap().to_owned();
+        config.footer_name_format = Some("footer_{lang}.html".to_owned());
+        write("footer_en.html", "<footer>{{> partials/logo}}</footer>");
+        let mailer = Mailer::init(config.clone());
+        let contents = vars(&[("contents", "Hello")]);
+        assert_eq!(
+            mailer.render_html(&contents, Some("ro")).unwrap(),
+            "<h1><img src=\"cid:logo\"> Noutăți</h1><p>Hello</p><footer><img src=\"cid:logo\"></footer>"
+        );
+        mailer.reload_templates().unwrap();
+        assert!(mailer
+            .render_html(&contents, Some("en"))
+            .unwrap()
+            .starts_with("<h1><img src=\"cid:logo\"> News</h1><p>Hello</p>"));
+
+        write("email_ro.html", "{{> partials/missing}}<p>{{contents}}</p>");
+        match mailer.reload_templates() {
+            Err(ErrorReport::MailTemplateInclude {
+                template,
+                partial,
+                msg,
+            }) => {
+                assert!(template.ends_with("email_ro.html"), "{}", template);
+                assert_eq!(partial, "partials/missing");
+                assert!(msg.contains("partials/missing_ro.html"), "{}", msg);
+            }
+            other => panic!("Expected a missing partial, got {:?}", other),
+        }
+        // the cached templates stay in use
+        assert!(mailer
+            .render_html(&contents, Some("ro"))
+            .unwrap()
+            .contains("Noutăți"));
+
+        write("partials/logo.html", "{{> partials/header}}");
+        assert!(mailer.render_html(&contents, Some("en")).is_ok());
+        let error = Mailer::init(config)
+            .render_html(&contents, Some("en"))
+            .unwrap_err();
+        assert!(
+            matches!(&error, ErrorReport::MailTemplateInclude { template, partial, .. }
+                if template.ends_with("logo.html") && partial == "partials/header"),
+            "{:?}",
+            error
+        );
+        assert!(error
+            .to_string()
+            .contains("include cycle partials/header > partials/logo > partials/header"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn named_templates() {
+        let dir = Path::new(env!("TEMP_DIR_PATH")).join("named_templates");
+        fs::create_dir_all(&dir).unwrap();
+        for (name, lang) in [
+            ("welcome", "en"),
+            ("reset", "en"),
+            ("welcome", "ro"),
+            ("reset", "ro"),
+        ] {
+            fs::write(
+                dir.join(format!("mail_{}_{}.html", name, lang)),
+                format!("<p>{} {} {{{{contents}}}}</p>", name, lang),
+            )
+            .unwrap();
+        }
+        fs::write(dir.join("mail_welcome_de.html"), "<p>de</p>").unwrap();
+        let mut config = test_config(&["en", "ro"]);
+        config.template_dir_path = dir.to_str().unwrap().to_owned();
+        config.template_name_format = "mail_{name}_{lang}.html".to_owned();
+        let mock = Arc::new(MockTransport::new());
+        let mailer = Mailer::init_with_transport(config.clone(), mock.clone());
+        assert_eq!(mailer.template_names().unwrap(), vec!["reset", "welcome"]);
+        assert_eq!(mailer.validate_templates().unwrap(), vec!["de"]);
+        assert!(mailer
+            .named_template_path("reset", "ro")
+            .ends_with("mail_reset_ro.html"));
+
+        let message = |name: &str| {
+            MailMessage::builder()
+                .to("ana@example.com".parse().unwrap())
+                .subject(name)
+                .body("hi")
+                .template(name)
+                .build()
                 .unwrap()
-                .to_lowercase(),
         };
+        mailer.send_message(message("welcome")).unwrap();
+        mailer.send_message(message("reset")).unwrap();
+        let sent: Vec<String> = mock
+            .sent()
+            .iter()
+            .map(|val| String::from_utf8(val.formatted()).unwrap())
+            .collect();
+        assert!(sent[0].contains("<p>welcome en hi</p>"), "{}", sent[0]);
+        assert!(sent[1].contains("<p>reset en hi</p>"), "{}", sent[1]);
+
+        let preloaded = Mailer::init_with_preload(config.clone()).unwrap();
+        fs::remove_file(dir.join("mail_reset_ro.html")).unwrap();
+        assert_eq!(
+            preloaded
+                .render_named_html("reset", &vars(&[("contents", "hi")]), Some("ro"))
+                .unwrap(),
+            "<p>reset ro hi</p>"
+        );
+        match mailer.validate_templates() {
+            Err(ErrorReport::MailTemplatesMissing { paths }) => {
+                assert_eq!(paths.len(), 1);
+                assert!(paths[0].ends_with("mail_reset_ro.html"));
+            }
+            other => panic!("Expected missing templates error, got {:?}", other),
+        }
 
-        let mut to_addrs = Vec::new();
-        to_addrs.push(Mailbox::new(
-            Some("Catalin Mutica".to_owned()),
-            Address::new("cmutica", "artemobinternational.com").unwrap(),
+        let unnamed = MailMessage::builder()
+            .to("ana@example.com".parse().unwrap())
+            .subject("Hi")
+            .body("hi")
+            .build()
+            .unwrap();
+        assert!(matches!(
+            mailer.send_message(unnamed),
+            Err(ErrorReport::MailMessageInvalid { problems }) if problems[0].starts_with("no template name")
         ));
-        to_addrs.push(Mailbox::new(
-            Some("Catalin Mark".to_owned()),
-            Address::new("catalin.mark", "gmail.com").unwrap(),
+        let traversal = MailMessage::builder()
+            .to("ana@example.com".parse().unwrap())
+            .subject("Hi")
+            .body("hi")
+            .template("../secret")
+            .build();
+        assert!(matches!(
+            traversal,
+            Err(ErrorReport::MailMessageInvalid { problems }) if problems[0].starts_with("template name '../secret'")
         ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
 
-        let mut attachments = Vec::new();
-        attachments.push(MailAttachment {
-            path: &config_path.to_str().unwrap(),
-            name: "config.txt",
-            mime: "text/plain",
-        });
-        let cargo_lock_path = (&root_dir).join("Cargo.lock");
-        attachments.push(MailAttachment {
-            path: &cargo_lock_path.to_str().unwrap(),
-            name: "Cargo.lock",
-            mime: "text/plain",
+    #[test]
+    fn test_connection_unreachable() {
+        let mut config = test_config(&["ro"]);
+        config.server = "127.0.0.1".to_owned();
+        config.port = 1;
+        config.tls_mode = TlsMode::None;
+        config.user_name = String::new();
+        config.connect_timeout = Some(Duration::from_millis(500));
+
+        match Mailer::init(config).test_connection() {
+            Err(ErrorReport::MailConnectionCheck { server, msg }) => {
+                assert_eq!(server, "127.0.0.1:1");
+                assert!(msg.starts_with("MailTransportSmtp - "), "{}", msg);
+            }
+            other => panic!("Expected a failed connection check, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn tls_mismatch() {
+        // a server waiting for the TLS handshake says nothing, as SMTPS servers do to STARTTLS
+        // clients
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let held: Vec<_> = listener.incoming().take(2).collect();
+            std::thread::sleep(Duration::from_secs(2));
+            drop(held);
         });
+        let mut config = test_config(&["ro"]);
+        config.server = "127.0.0.1".to_owned();
+        config.port = port;
+        config.tls_mode = TlsMode::None;
+        config.user_name = String::new();
+        config.command_timeout = Some(Duration::from_millis(200));
+        let stalled = || {
+            Mailer::init(config.clone())
+                .transport()
+                .unwrap()
+                .test_connection()
+                .unwrap_err()
+        };
+
+        let mut smtps = config.clone();
+        smtps.port = 465;
+        smtps.tls_mode = TlsMode::StartTls;
+        match smtps.classify(stalled()) {
+            ErrorReport::MailTlsMismatch {
+                server,
+                mode,
+                expected,
+                ..
+            } => {
+                assert_eq!(server, "127.0.0.1:465");
+                assert_eq!((mode.as_str(), expected.as_str()), ("starttls", "wrapped"));
+            }
+            other => panic!("Expected a TLS mismatch, got {:?}", other),
+        }
+        let mut submission = config.clone();
+        submission.port = 587;
+        submission.tls_mode = TlsMode::StartTls;
+        assert!(matches!(
+            submission.classify(stalled()),
+            ErrorReport::MailTransportSmtp(_)
+        ));
+    }
+
+    #[test]
+    fn test_connection_configured() {
+        let root_dir = Path::new(env!("WORKSPACE_ROOT_PATH"));
+        let config_path = Path::new(env!("APP_CONFIG_FILE_PATH"));
+        let app_config =
+            AppConfig::init_with_root(config_path, CONFIG_FILE_DELIMITER, root_dir).unwrap();
+        let config = Config::from_app_config(&app_config).unwrap();
+
+        let res = Mailer::init(config).test_connection();
+        assert!(res.is_ok(), "Error: {}", res.err().unwrap());
+    }
+
+    #[test]
+    fn send_mail() {
+        let root_dir = Path::new(env!("WORKSPACE_ROOT_PATH"));
+        let config_path = Path::new(env!("APP_CONFIG_FILE_PATH"));
+        let app_config =
+            AppConfig::init_with_root(config_path, CONFIG_FILE_DELIMITER, root_dir).unwrap();
+        let config = Config::from_app_config(&app_config).unwrap();
+
+        let to_addrs = vec![
+            Mailbox::new(
+                Some("Catalin Mutica".to_owned()),
+                Address::new("cmutica", "artemobinternational.com").unwrap(),
+            ),
+            Mailbox::new(
+                Some("Catalin Mark".to_owned()),
+                Address::new("catalin.mark", "gmail.com").unwrap(),
+            ),
+        ];
+
+        let cargo_lock_path = root_dir.join("Cargo.lock");
+        let attachments = vec![
+            MailAttachment {
+                source: AttachmentSource::Path(config_path.to_str().unwrap()),
+                name: "config.txt",
+                mime: "text/plain",
+                content_id: None,
+                disposition: Disposition::Attachment,
+            },
+            MailAttachment {
+                source: AttachmentSource::Path(cargo_lock_path.to_str().unwrap()),
+                name: "Cargo.lock",
+                mime: "text/plain",
+                content_id: None,
+                disposition: Disposition::Attachment,
+            },
+        ];
 
         let res = Mailer::init(config).send(
             to_addrs,
             None,
-            &"Testare".to_owned(),
-            &"Rust is the best".to_owned(),
-            Some(&"ro".to_owned()),
+            "Testare",
+            "Rust is the best",
+            Some("ro"),
             Some(attachments),
         );
         assert!(res.is_ok(), "Error: {}", res.err().unwrap());
     }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn send_async_custom_transport() {
+        let stub = Arc::new(CountingTransport::default());
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let sent_hook = sent.clone();
+        let mailer = Mailer::init_with_transport(test_config(&["ro"]), stub.clone())
+            .on_sent(move |msg| sent_hook.lock().unwrap().push(msg.subject.clone()));
+
+        mailer
+            .send_async(test_recipient(), None, "Async", "Body", None, None)
+            .await
+            .unwrap();
+        assert_eq!(stub.sent.load(Ordering::SeqCst), 1);
+        assert_eq!(*sent.lock().unwrap(), vec!["Async"]);
+
+        assert!(matches!(
+            mailer
+                .send_async(
+                    test_recipient(),
+                    None,
+                    "Async",
+                    "Body",
+                    None,
+                    Some(vec![MailAttachment {
+                        source: AttachmentSource::Path("missing.txt"),
+                        name: "missing.txt",
+                        mime: "text/plain",
+                        content_id: None,
+                        disposition: Disposition::Attachment,
+                    }])
+                )
+                .await,
+            Err(ErrorReport::Io(_))
+        ));
+        assert_eq!(stub.sent.load(Ordering::SeqCst), 1);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn send_mail_async() {
+        let root_dir = Path::new(env!("WORKSPACE_ROOT_PATH"));
+        let config_path = Path::new(env!("APP_CONFIG_FILE_PATH"));
+        let app_config =
+            AppConfig::init_with_root(config_path, CONFIG_FILE_DELIMITER, root_dir).unwrap();
+        let config = Config::from_app_config(&app_config).unwrap();
+
+        let attachments = vec![MailAttachment {
+            source: AttachmentSource::Path(config_path.to_str().unwrap()),
+            name: "config.txt",
+            mime: "text/plain",
+            content_id: None,
+            disposition: Disposition::Attachment,
+        }];
+        let res = Mailer::init(config)
+            .send_async(
+                vec![Mailbox::new(
+                    Some("Catalin Mutica".to_owned()),
+                    Address::new("cmutica", "artemobinternational.com").unwrap(),
+                )],
+                None,
+                "Testare async",
+                "Rust is the best",
+                Some("ro"),
+                Some(attachments),
+            )
+            .await;
+        assert!(res.is_ok(), "Error: {}", res.err().unwrap());
+    }
 }
@@ -0,0 +1,345 @@
+//! Directories attached as a single ZIP archive, e.g. the CSV files of a report run
+use super::{AttachmentSource, Disposition, MailAttachment};
+use crate::error::ErrorReport;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Largest archive `from_dir_zipped` builds by default, as big as mail servers usually take
+pub const DEFAULT_MAX_ZIP_BYTES: u64 = 25 * 1024 * 1024;
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+/// ZIP 2.0, the first version with folders
+const VERSION: u16 = 20;
+/// Entry names are UTF-8
+const UTF8_NAMES: u16 = 1 << 11;
+
+/// What `MailAttachment::from_dir_zipped_with` puts in the archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZipOptions {
+    /// Files and folders whose name starts with a dot, skipped by default
+    pub include_hidden: bool,
+    /// Size of the archive at which zipping stops with `MailAttachmentTooLarge`, at most 4 GiB
+    pub max_bytes: u64,
+}
+
+impl Default for ZipOptions {
+    fn default() -> Self {
+        ZipOptions {
+            include_hidden: false,
+            max_bytes: DEFAULT_MAX_ZIP_BYTES,
+        }
+    }
+}
+
+impl<'a> MailAttachment<'a> {
+    /// The files of a directory and its subdirectories in a ZIP archive built in memory, named
+    /// `name`; see `from_dir_zipped_with`
+    pub fn from_dir_zipped(dir: &Path, name: &'a str) -> Result<Self, ErrorReport> {
+        Self::from_dir_zipped_with(dir, name, ZipOptions::default())
+    }
+
+    /// Same as `from_dir_zipped` with the given options. The files are stored as they are, in
+    /// the order of their paths, so the same directory always gives the same archive.
+    pub fn from_dir_zipped_with(
+        dir: &Path,
+        name: &'a str,
+        options: ZipOptions,
+    ) -> Result<Self, ErrorReport> {
+        Ok(MailAttachment {
+            source: AttachmentSource::from(zip_dir(dir, name, options)?),
+            name,
+            mime: "application/zip",
+            content_id: None,
+            disposition: Disposition::Attachment,
+        })
+    }
+}
+
+fn zip_dir(dir: &Path, name: &str, options: ZipOptions) -> Result<Vec<u8>, ErrorReport> {
+    let failed = |msg: String| ErrorReport::MailArchive {
+        dir: dir.to_string_lossy().into_owned(),
+        msg,
+    };
+    if !dir.is_dir() {
+        return Err(failed("not a directory".to_owned()));
+    }
+    let mut files = Vec::new();
+    collect(dir, "", options.include_hidden, &mut files)?;
+    if files.len() > u16::MAX as usize {
+        return Err(failed(format!(
+            "{} files, an archive holds {} at most",
+            files.len(),
+            u16::MAX
+        )));
+    }
+    let limit = options.max_bytes.min(u32::MAX as u64);
+    let too_large = |size: u64| ErrorReport::MailAttachmentTooLarge {
+        name: name.to_owned(),
+        size,
+        limit,
+    };
+
+    let mut archive = Vec::new();
+    let mut central = Vec::new();
+    for (entry, path) in &files {
+        let metadata = fs::metadata(path)?;
+        // checked before reading, so a huge file is never loaded
+        let size = archive.len() as u64 + central.len() as u64 + metadata.len();
+        if size > limit {
+            return Err(too_large(size));
+        }
+        let data = fs::read(path)?;
+        let (time, date) = dos_time(metadata.modified().unwrap_or(UNIX_EPOCH));
+        let mut header = Vec::new();
+        put_u16(&mut header, UTF8_NAMES);
+        // stored, without compression
+        put_u16(&mut header, 0);
+        put_u16(&mut header, time);
+        put_u16(&mut header, date);
+        put_u32(&mut header, crc32(&data));
+        put_u32(&mut header, data.len() as u32);
+        put_u32(&mut header, data.len() as u32);
+        put_u16(&mut header, entry.len() as u16);
+        // no extra field
+        put_u16(&mut header, 0);
+
+        put_u32(&mut central, CENTRAL_HEADER);
+        put_u16(&mut central, VERSION);
+        put_u16(&mut central, VERSION);
+        central.extend_from_slice(&header);
+        // comment, disk, internal and external attributes
+        central.extend_from_slice(&[0; 10]);
+        put_u32(&mut central, archive.len() as u32);
+        central.extend_from_slice(entry.as_bytes());
+
+        put_u32(&mut archive, LOCAL_HEADER);
+        put_u16(&mut archive, VERSION);
+        archive.extend_from_slice(&header);
+        archive.extend_from_slice(entry.as_bytes());
+        archive.extend_from_slice(&data);
+    }
+    let offset = archive.len() as u32;
+    let central_len = central.len() as u32;
+    archive.extend_from_slice(&central);
+    put_u32(&mut archive, END_OF_CENTRAL_DIRECTORY);
+    // this disk and the one with the central directory
+    put_u32(&mut archive, 0);
+    put_u16(&mut archive, files.len() as u16);
+    put_u16(&mut archive, files.len() as u16);
+    put_u32(&mut archive, central_len);
+    put_u32(&mut archive, offset);
+    // no comment
+    put_u16(&mut archive, 0);
+    if archive.len() as u64 > limit {
+        return Err(too_large(archive.len() as u64));
+    }
+    Ok(archive)
+}
+
+/// Files under `dir` by their name in the archive, `/` separated and sorted
+fn collect(
+    dir: &Path,
+    prefix: &str,
+    include_hidden: bool,
+    files: &mut Vec<(String, PathBuf)>,
+) -> Result<(), ErrorReport> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if include_hidden || !name.starts_with('.') {
+            entries.push((name, entry.path()));
+        }
+    }
+    entries.sort();
+    for (name, path) in entries {
+        let entry = format!("{}{}", prefix, name);
+        if path.is_dir() {
+            collect(&path, &format!("{}/", entry), include_hidden, files)?;
+        } else if path.is_file() {
+            files.push((entry, path));
+        }
+    }
+    Ok(())
+}
+
+fn put_u16(buf: &mut Vec<u8>, val: u16) {
+    buf.extend_from_slice(&val.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, val: u32) {
+    buf.extend_from_slice(&val.to_le_bytes());
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut idx = 0;
+    while idx < 256 {
+        let mut crc = idx as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 1 {
+                1 => 0xedb8_8320 ^ (crc >> 1),
+                _ => crc >> 1,
+            };
+            bit += 1;
+        }
+        table[idx] = crc;
+        idx += 1;
+    }
+    table
+};
+
+/// CRC-32 of the ZIP format, as in gzip and PNG
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, byte| {
+        CRC_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// MS-DOS time and date of a UTC timestamp, the oldest being 1980-01-01
+fn dos_time(time: SystemTime) -> (u16, u16) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |val| val.as_secs())
+        .max(315_532_800);
+    let (days, secs) = ((secs / 86_400) as i64, secs % 86_400);
+    // days to the civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    let time = ((secs / 3_600) << 11) | ((secs % 3_600 / 60) << 5) | ((secs % 60) / 2);
+    let date = (((year - 1980).min(127) << 9) | (month << 5) | day) as u16;
+    (time as u16, date)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, dos_time, ZipOptions};
+    use crate::{
+        error::ErrorReport,
+        mailer::{AttachmentSource, MailAttachment},
+    };
+    use std::{
+        collections::BTreeMap,
+        fs,
+        path::Path,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    fn u16_at(buf: &[u8], at: usize) -> usize {
+        u16::from_le_bytes([buf[at], buf[at + 1]]) as usize
+    }
+
+    fn u32_at(buf: &[u8], at: usize) -> usize {
+        u32::from_le_bytes(buf[at..at + 4].try_into().unwrap()) as usize
+    }
+
+    /// Files of a stored archive read through its central directory, their checksums verified
+    fn unzip(archive: &[u8]) -> BTreeMap<String, Vec<u8>> {
+        let end = archive.len() - 22;
+        assert_eq!(u32_at(archive, end), 0x0605_4b50);
+        let (count, mut at) = (u16_at(archive, end + 10), u32_at(archive, end + 16));
+        let mut files = BTreeMap::new();
+        for _ in 0..count {
+            assert_eq!(u32_at(archive, at), 0x0201_4b50);
+            assert_eq!(u16_at(archive, at + 10), 0, "stored");
+            let (crc, size) = (u32_at(archive, at + 16), u32_at(archive, at + 24));
+            let name_len = u16_at(archive, at + 28);
+            let name = String::from_utf8(archive[at + 46..at + 46 + name_len].to_vec()).unwrap();
+            let local = u32_at(archive, at + 42);
+            assert_eq!(u32_at(archive, local), 0x0403_4b50);
+            let start = local + 30 + u16_at(archive, local + 26) + u16_at(archive, local + 28);
+            let data = archive[start..start + size].to_vec();
+            assert_eq!(crc32(&data) as usize, crc, "{}", name);
+            files.insert(name, data);
+            at += 46 + name_len;
+        }
+        files
+    }
+
+    #[test]
+    fn zipped_dir() {
+        let dir = Path::new(env!("TEMP_DIR_PATH")).join("zipped_dir");
+        fs::create_dir_all(dir.join("daily/.cache")).unwrap();
+        fs::create_dir_all(dir.join("empty")).unwrap();
+        fs::write(dir.join("summary.csv"), "day,total\n1,42\n").unwrap();
+        fs::write(dir.join("daily/sales_ăș.csv"), "item,qty\npen,3\n").unwrap();
+        fs::write(dir.join("daily/.cache/state"), "x").unwrap();
+        fs::write(dir.join(".env"), "SECRET=1").unwrap();
+
+        let attachment = MailAttachment::from_dir_zipped(&dir, "report.zip").unwrap();
+        assert_eq!(
+            (attachment.name, attachment.mime),
+            ("report.zip", "application/zip")
+        );
+        let archive = match &attachment.source {
+            AttachmentSource::Bytes(bytes) => bytes.to_vec(),
+            other => panic!("Expected bytes, got {:?}", other),
+        };
+        let files = unzip(&archive);
+        let names: Vec<&str> = files.keys().map(String::as_str).collect();
+        assert_eq!(names, vec!["daily/sales_ăș.csv", "summary.csv"]);
+        assert_eq!(files["summary.csv"], b"day,total\n1,42\n");
+        // the same files give the same archive
+        let again = MailAttachment::from_dir_zipped(&dir, "report.zip").unwrap();
+        assert!(matches!(again.source, AttachmentSource::Bytes(bytes) if *bytes == archive[..]));
+
+        let options = ZipOptions {
+            include_hidden: true,
+            ..ZipOptions::default()
+        };
+        let attachment = MailAttachment::from_dir_zipped_with(&dir, "all.zip", options).unwrap();
+        let files = match attachment.source {
+            AttachmentSource::Bytes(bytes) => unzip(&bytes),
+            other => panic!("Expected bytes, got {:?}", other),
+        };
+        assert_eq!(files.len(), 4);
+        assert_eq!(files[".env"], b"SECRET=1");
+        assert_eq!(files["daily/.cache/state"], b"x");
+
+        let options = ZipOptions {
+            max_bytes: 100,
+            ..ZipOptions::default()
+        };
+        match MailAttachment::from_dir_zipped_with(&dir, "report.zip", options) {
+            Err(ErrorReport::MailAttachmentTooLarge { name, limit, .. }) => {
+                assert_eq!((name.as_str(), limit), ("report.zip", 100))
+            }
+            other => panic!("Expected a size error, got {:?}", other),
+        }
+        assert!(matches!(
+            MailAttachment::from_dir_zipped(&dir.join("summary.csv"), "report.zip"),
+            Err(ErrorReport::MailArchive { .. })
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn checksums_and_dates() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        // 2024-02-29 13:45:58 UTC
+        let time = UNIX_EPOCH + Duration::from_secs(1_709_214_358);
+        assert_eq!(
+            dos_time(time),
+            (
+                (13 << 11) | (45 << 5) | 29,
+                ((2024 - 1980) << 9) | (2 << 5) | 29
+            )
+        );
+        assert_eq!(dos_time(UNIX_EPOCH), (0, (1 << 5) | 1));
+    }
+}
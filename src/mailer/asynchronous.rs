@@ -0,0 +1,221 @@
+use super::{
+    deadline::Deadline, retry::Retries, split, Config, MailAttachment, MailMessage, Mailer,
+    SendReport, TlsMode,
+};
+use crate::error::ErrorReport;
+use lettre::{
+    message::Mailbox, transport::smtp::response::Response, AsyncSmtpTransport, AsyncTransport,
+    Message, Tokio1Executor,
+};
+use std::{collections::HashMap, io, sync::RwLock};
+
+/// Slot holding the async SMTP transport shared by all the clones of a `Mailer`
+pub type AsyncTransportSlot = RwLock<Option<AsyncSmtpTransport<Tokio1Executor>>>;
+
+impl Mailer {
+    /// Same as `send_message`, without blocking the tokio runtime; needs the `async` feature.
+    ///
+    /// The message is built, audited and handed to the hooks exactly as by the blocking send;
+    /// mailers with a custom transport deliver on tokio's blocking thread pool.
+    pub async fn send_message_async(
+        &self,
+        message: MailMessage<'_>,
+    ) -> Result<SendReport, ErrorReport> {
+        let _in_flight = self.lifecycle.enter()?;
+        let message = self.localize(message);
+        match self.split(&message) {
+            Some(parts) => {
+                let mut results = Vec::with_capacity(parts.len());
+                for part in parts {
+                    let recipients = split::recipients(&part);
+                    results.push((recipients, self.send_localized_message_async(part).await));
+                }
+                split::merge(results)
+            }
+            None => self.send_localized_message_async(message).await,
+        }
+    }
+
+    async fn send_localized_message_async(
+        &self,
+        message: MailMessage<'_>,
+    ) -> Result<SendReport, ErrorReport> {
+        let attempt = self.begin(&message);
+        let (mail, res) = match self.build_message(&message) {
+            Ok(mail) => {
+                let res = self.deliver_async(&mail).await;
+                (Some(mail), res)
+            }
+            Err(e) => (None, Err(e)),
+        };
+        self.finish(attempt, mail.as_ref(), res)
+    }
+
+    /// Same as `send`, without blocking the tokio runtime
+    #[deprecated(note = "build a `MailMessage` and use `send_message_async`")]
+    pub async fn send_async(
+        &self,
+        to_addrs: Vec<Mailbox>,
+        cc_addrs: Option<Vec<Mailbox>>,
+        subject: &str,
+        message: &str,
+        language: Option<&str>,
+        attachments: Option<Vec<MailAttachment<'_>>>,
+    ) -> Result<(), ErrorReport> {
+        self.send_message_async(MailMessage::from_parts(
+            to_addrs,
+            cc_addrs,
+            None,
+            subject,
+            HashMap::from([("contents".to_owned(), message.to_owned())]),
+            None,
+            language,
+            attachments,
+        ))
+        .await
+        .map(|_| ())
+    }
+
+    /// Same as `send_with_vars`, without blocking the tokio runtime
+    #[deprecated(note = "build a `MailMessage` with `var` and use `send_message_async`")]
+    pub async fn send_with_vars_async(
+        &self,
+        to_addrs: Vec<Mailbox>,
+        cc_addrs: Option<Vec<Mailbox>>,
+        subject: &str,
+        vars: &HashMap<String, String>,
+        language: Option<&str>,
+        attachments: Option<Vec<MailAttachment<'_>>>,
+    ) -> Result<(), ErrorReport> {
+        self.send_message_async(MailMessage::from_parts(
+            to_addrs,
+            cc_addrs,
+            None,
+            subject,
+            vars.clone(),
+            None,
+            language,
+            attachments,
+        ))
+        .await
+        .map(|_| ())
+    }
+
+    /// Sends within `Config::send_deadline`, retrying as `Config::retry_policy` allows with a
+    /// tokio sleep; the lettre pool replaces dead connections itself
+    async fn deliver_async(&self, mail: &Message) -> Result<Response, ErrorReport> {
+        let mut transport = match self.async_transport()? {
+            Some(val) => val,
+            // the blocking send waits for its slot on the blocking thread
+            None => {
+                let mailer = self.clone();
+                let mail = mail.clone();
+                return tokio::task::spawn_blocking(move || mailer.deliver(&mail))
+                    .await
+                    .map_err(io::Error::other)?;
+            }
+        };
+
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.acquire_async().await?;
+        }
+        let mut deadline = Deadline::new(self.config.send_deadline);
+        let mut retries = Retries::new(self.config.retry_policy);
+        loop {
+            retries.attempt();
+            let sent = match deadline.remaining() {
+                Some(budget) => {
+                    match tokio::time::timeout(budget, transport.send(mail.clone())).await {
+                        Ok(res) => res.map_err(ErrorReport::from),
+                        Err(_) => Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            format!("no answer within {} ms", budget.as_millis()),
+                        )
+                        .into()),
+                    }
+                }
+                None => transport
+                    .send(mail.clone())
+                    .await
+                    .map_err(ErrorReport::from),
+            };
+            let error = match sent {
+                Ok(res) if res.is_positive() => return Ok(res),
+                Ok(res) => crate::error::error_mail_sent_response(&res),
+                Err(e) => self.config.classify(e),
+            };
+            // the next transport logs in with a token asked for anew
+            let token_rejected = matches!(error, ErrorReport::MailTokenRejected { .. });
+            if token_rejected {
+                if let Some(slot) = &self.async_transport {
+                    slot.write().unwrap().take();
+                }
+            }
+            deadline.record(&error);
+            if deadline.is_exhausted() {
+                return Err(deadline.exceeded());
+            }
+            let delay = match retries.next_delay(error.is_transient()) {
+                Some(val) => val,
+                None => return Err(retries.give_up(error)),
+            };
+            if deadline.remaining().is_some_and(|val| val <= delay) {
+                return Err(deadline.exceeded());
+            }
+            self.metrics.retried();
+            tokio::time::sleep(delay).await;
+            if token_rejected {
+                transport = self.async_transport()?.unwrap_or(transport);
+            }
+        }
+    }
+
+    fn async_transport(&self) -> Result<Option<AsyncSmtpTransport<Tokio1Executor>>, ErrorReport> {
+        let slot = match &self.async_transport {
+            Some(val) => val,
+            None => return Ok(None),
+        };
+        if let Some(transport) = slot.read().unwrap().as_ref() {
+            return Ok(Some(transport.clone()));
+        }
+        let mut slot = slot.write().unwrap();
+        if let Some(transport) = slot.as_ref() {
+            return Ok(Some(transport.clone()));
+        }
+        let transport = async_smtp_transport(&self.config)?;
+        *slot = Some(transport.clone());
+        Ok(Some(transport))
+    }
+}
+
+fn async_smtp_transport(
+    config: &Config,
+) -> Result<AsyncSmtpTransport<Tokio1Executor>, ErrorReport> {
+    if let Some(problem) = config.tls_mode.problem(&config.user_name) {
+        return Err(ErrorReport::MailConfigInvalid {
+            problems: vec![problem],
+        });
+    }
+    let builder = match config.tls_mode {
+        TlsMode::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.server)?,
+        TlsMode::Wrapped => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.server)?,
+        TlsMode::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.server),
+    };
+    let mut builder = builder.port(config.port).pool_config(config.pool_config());
+    if let Some(name) = config.client_id()? {
+        builder = builder.hello_name(name);
+    }
+    if let Some(timeout) = config.socket_timeout() {
+        builder = builder.timeout(Some(timeout));
+    }
+    if let Some(parameters) = config.tls_parameters()? {
+        builder = builder.tls(config.tls(parameters));
+    }
+    if config.tls_mode != TlsMode::None {
+        let (credentials, mechanisms) = config
+            .auth
+            .credentials(&config.user_name, &config.password)?;
+        builder = builder.credentials(credentials).authentication(mechanisms);
+    }
+    Ok(builder.build())
+}
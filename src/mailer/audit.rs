@@ -0,0 +1,153 @@
+use super::hooks::SendReport;
+use crate::error::ErrorReport;
+use lettre::message::Mailbox;
+use serde::Serialize;
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// One line of the audit file
+#[derive(Debug, Serialize)]
+pub struct AuditRecord {
+    pub timestamp: String,
+    pub message_id: Option<String>,
+    pub from: String,
+    pub to_count: usize,
+    pub to: Vec<String>,
+    pub cc_count: usize,
+    pub cc: Vec<String>,
+    pub subject: String,
+    pub attachments: Vec<AuditAttachment>,
+    /// `ok` or `error`
+    pub outcome: &'static str,
+    /// SMTP reply code, when the server answered
+    pub code: Option<u16>,
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditAttachment {
+    pub name: String,
+    pub size: u64,
+}
+
+impl AuditRecord {
+    pub fn new(
+        started: SystemTime,
+        from: &Mailbox,
+        to: &[Mailbox],
+        cc: &[Mailbox],
+        subject: &str,
+        attachments: Vec<AuditAttachment>,
+    ) -> Self {
+        AuditRecord {
+            timestamp: format_utc(started),
+            message_id: None,
+            from: from.to_string(),
+            to_count: to.len(),
+            to: to.iter().map(|val| val.to_string()).collect(),
+            cc_count: cc.len(),
+            cc: cc.iter().map(|val| val.to_string()).collect(),
+            subject: subject.to_owned(),
+            attachments,
+            outcome: "ok",
+            code: None,
+            error: None,
+            duration_ms: 0,
+        }
+    }
+
+    pub fn finish(&mut self, duration: Duration, result: &Result<SendReport, ErrorReport>) {
+        self.duration_ms = duration.as_millis() as u64;
+        match result {
+            Ok(report) => self.code = Some(report.code),
+            Err(e) => {
+                self.outcome = "error";
+                self.code = error_code(e);
+                self.error = Some(e.to_string());
+            }
+        }
+    }
+}
+
+/// SMTP reply code carried by an error, if any
+fn error_code(error: &ErrorReport) -> Option<u16> {
+    match error {
+        ErrorReport::MailTransportSmtp(e) => {
+            e.status().and_then(|code| code.to_string().parse().ok())
+        }
+        _ => None,
+    }
+}
+
+/// Append-only JSON lines file; the mutex keeps concurrent sends from interleaving records
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    file: Mutex<Option<File>>,
+}
+
+impl AuditLog {
+    pub fn new(path: &Path) -> Self {
+        AuditLog {
+            path: path.to_path_buf(),
+            file: Mutex::new(None),
+        }
+    }
+
+    pub fn append(&self, record: &AuditRecord) -> Result<(), ErrorReport> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        let mut file = self.file.lock().unwrap();
+        if file.is_none() {
+            *file = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?,
+            );
+        }
+        if let Some(file) = file.as_mut() {
+            file.write_all(&line)?;
+        }
+        Ok(())
+    }
+}
+
+/// RFC 3339 UTC timestamp with millisecond precision
+pub fn format_utc(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let secs_of_day = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Gregorian date of a day count since 1970-01-01 (H. Hinnant's algorithm)
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
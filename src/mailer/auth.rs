@@ -0,0 +1,180 @@
+use crate::error::ErrorReport;
+use lettre::transport::smtp::authentication::{Credentials, Mechanism};
+use std::{fmt, sync::Arc};
+
+/// SMTP reply to a login refused with the credentials given (RFC 4954)
+const AUTH_FAILED: &str = "535";
+
+/// Source of the OAuth2 access tokens of `SmtpAuth::Xoauth2`
+pub trait TokenProvider: Send + Sync {
+    /// A token that is valid now; asked for every time a connection is set up, so a token that
+    /// expired can be refreshed here
+    fn access_token(&self) -> Result<String, ErrorReport>;
+}
+
+impl<F> TokenProvider for F
+where
+    F: Fn() -> Result<String, ErrorReport> + Send + Sync,
+{
+    fn access_token(&self) -> Result<String, ErrorReport> {
+        self()
+    }
+}
+
+struct StaticToken(String);
+
+impl TokenProvider for StaticToken {
+    fn access_token(&self) -> Result<String, ErrorReport> {
+        Ok(self.0.clone())
+    }
+}
+
+/// How the mailer logs in to the SMTP server
+#[derive(Clone, Default)]
+pub enum SmtpAuth {
+    /// `Config::user_name` and `Config::password`, through PLAIN or LOGIN
+    #[default]
+    Password,
+    /// OAuth2 bearer token of `Config::user_name`, e.g. for Microsoft 365; the password is unused
+    Xoauth2(Arc<dyn TokenProvider>),
+}
+
+impl SmtpAuth {
+    /// XOAUTH2 with a token that is never refreshed
+    pub fn static_token(token: impl Into<String>) -> Self {
+        SmtpAuth::Xoauth2(Arc::new(StaticToken(token.into())))
+    }
+
+    /// Credentials and mechanisms of a new transport; XOAUTH2 asks the provider for a token
+    pub(super) fn credentials(
+        &self,
+        user_name: &str,
+        password: &str,
+    ) -> Result<(Credentials, Vec<Mechanism>), ErrorReport> {
+        Ok(match self {
+            SmtpAuth::Password => (
+                Credentials::new(user_name.to_owned(), password.to_owned()),
+                vec![Mechanism::Plain, Mechanism::Login],
+            ),
+            SmtpAuth::Xoauth2(provider) => (
+                Credentials::new(user_name.to_owned(), provider.access_token()?),
+                vec![Mechanism::Xoauth2],
+            ),
+        })
+    }
+
+    /// Turns a refused XOAUTH2 login into `MailTokenRejected`; other errors pass unchanged
+    pub(super) fn classify(&self, error: ErrorReport) -> ErrorReport {
+        match self {
+            SmtpAuth::Xoauth2(_) if token_rejected(&error) => ErrorReport::MailTokenRejected {
+                msg: error.to_string(),
+            },
+            _ => error,
+        }
+    }
+}
+
+impl fmt::Debug for SmtpAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SmtpAuth::Password => f.write_str("Password"),
+            SmtpAuth::Xoauth2(_) => f.write_str("Xoauth2(..)"),
+        }
+    }
+}
+
+/// A 535 answer, or the error challenge a server sends instead of accepting the token, which
+/// lettre refuses to answer
+fn token_rejected(error: &ErrorReport) -> bool {
+    match error {
+        ErrorReport::MailTransportSmtp(e) => {
+            e.status()
+                .is_some_and(|code| code.to_string() == AUTH_FAILED)
+                || (e.is_client() && e.to_string().contains("challenge"))
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{token_rejected, SmtpAuth};
+    use crate::error::ErrorReport;
+    use lettre::{transport::smtp::authentication::Mechanism, Message, SmtpTransport, Transport};
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    /// Error of a send to a local server answering `MAIL FROM` with the given reply
+    fn refused_with(reply: &'static str) -> ErrorReport {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // every connection is served, the lettre pool opens an idle one of its own next to the
+        // one sending
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    stream.write_all(b"220 localhost ESMTP\r\n").unwrap();
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                        let answer = match line.get(..4) {
+                            Some("MAIL") => reply,
+                            Some("QUIT") => "221 Bye\r\n",
+                            _ => "250 localhost\r\n",
+                        };
+                        if stream.write_all(answer.as_bytes()).is_err() {
+                            break;
+                        }
+                        line.clear();
+                    }
+                });
+            }
+        });
+
+        let message = Message::builder()
+            .from("noreply@example.com".parse().unwrap())
+            .to("ana@example.com".parse().unwrap())
+            .subject("Subject")
+            .body("Body".to_owned())
+            .unwrap();
+        let error = SmtpTransport::builder_dangerous("127.0.0.1")
+            .port(port)
+            .build()
+            .send(&message)
+            .unwrap_err();
+        error.into()
+    }
+
+    #[test]
+    fn token_rejection() {
+        let rejected = refused_with("535 5.7.3 Authentication unsuccessful\r\n");
+        assert!(token_rejected(&rejected));
+        assert!(matches!(
+            SmtpAuth::static_token("token").classify(rejected),
+            ErrorReport::MailTokenRejected { msg } if msg.contains("Authentication unsuccessful")
+        ));
+
+        let rejected = refused_with("535 5.7.3 Authentication unsuccessful\r\n");
+        assert!(matches!(
+            SmtpAuth::Password.classify(rejected),
+            ErrorReport::MailTransportSmtp(_)
+        ));
+        assert!(!token_rejected(&refused_with(
+            "550 5.1.1 Mailbox unavailable\r\n"
+        )));
+
+        let (credentials, mechanisms) =
+            SmtpAuth::Xoauth2(std::sync::Arc::new(|| Ok("fresh-token".to_owned())))
+                .credentials("ana@example.com", "unused")
+                .unwrap();
+        assert_eq!(mechanisms, vec![Mechanism::Xoauth2]);
+        assert_eq!(
+            Mechanism::Xoauth2.response(&credentials, None).unwrap(),
+            "user=ana@example.com\x01auth=Bearer fresh-token\x01\x01"
+        );
+    }
+}
@@ -0,0 +1,612 @@
+//! `Serialize` and `Deserialize` of the mailer `Config`, e.g. for a section of a YAML service
+//! config. The document is read through `ConfigBuilder`, so it is validated the same way.
+//!
+//! Addresses are strings like `Name <user@example.com>`, durations are a number of seconds or a
+//! string with a `ms`, `s`, `m` or `h` unit. The delivery directory stands for `Delivery::File`
+//! and the sendmail path, when there is no directory, for `Delivery::Sendmail`. The password is only written by `Config::with_password`. XOAUTH2, DKIM and
+//! S/MIME settings hold providers and keys, they are neither written nor read: a deserialized
+//! config logs in with a password and signs nothing.
+#[cfg(feature = "handlebars")]
+use super::TemplateEngine;
+use super::{
+    Config, Delivery, RateLimit, RetryPolicy, TlsMode, DEFAULT_SENDMAIL_PATH, DEFAULT_TIMEOUT,
+};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{path::PathBuf, time::Duration};
+
+/// Serializes the config with its password, which `Config` leaves out
+pub struct WithPassword<'a>(&'a Config);
+
+impl Config {
+    /// Opt-in to writing the password in plain text, for tooling that regenerates the config
+    pub fn with_password(&self) -> WithPassword<'_> {
+        WithPassword(self)
+    }
+}
+
+impl Serialize for Config {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ConfigDocument::from_config(self, false).serialize(serializer)
+    }
+}
+
+impl Serialize for WithPassword<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ConfigDocument::from_config(self.0, true).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Config {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        ConfigDocument::deserialize(deserializer)?
+            .into_config()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Duration written as `30s` or `500ms`
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DurationText(Duration);
+
+impl Serialize for DurationText {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let text = match self.0.subsec_millis() {
+            0 => format!("{}s", self.0.as_secs()),
+            _ => format!("{}ms", self.0.as_millis()),
+        };
+        serializer.serialize_str(&text)
+    }
+}
+
+impl<'de> Deserialize<'de> for DurationText {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Secs(u64),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Secs(secs) => Ok(DurationText(Duration::from_secs(secs))),
+            Raw::Text(text) => parse_duration(&text)
+                .map(DurationText)
+                .map_err(de::Error::custom),
+        }
+    }
+}
+
+fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (value, unit) = text.split_at(split);
+    let value: u64 = value
+        .parse()
+        .map_err(|_| format!("'{}' is not a duration, e.g. 30s or 500ms", text))?;
+    match unit.trim() {
+        "ms" => Ok(Duration::from_millis(value)),
+        "" | "s" => Ok(Duration::from_secs(value)),
+        "m" => Ok(Duration::from_secs(value * 60)),
+        "h" => Ok(Duration::from_secs(value * 3600)),
+        other => Err(format!(
+            "'{}' is not a duration unit, expected ms, s, m or h",
+            other
+        )),
+    }
+}
+
+fn serialize_tls_mode<S: Serializer>(
+    mode: &Option<TlsMode>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match mode {
+        Some(mode) => serializer.collect_str(mode),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Left out, the mode follows the port as in `ConfigBuilder::tls_mode`
+fn deserialize_tls_mode<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<TlsMode>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|val| val.parse().map_err(de::Error::custom))
+        .transpose()
+}
+
+fn default_port() -> u16 {
+    587
+}
+
+fn default_template_name_format() -> String {
+    "email_{lang}.html".to_owned()
+}
+
+fn default_language() -> String {
+    "en".to_owned()
+}
+
+fn default_delimiters() -> (String, String) {
+    ("{{".to_owned(), "}}".to_owned())
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_timeout() -> Option<DurationText> {
+    Some(DurationText(DEFAULT_TIMEOUT))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RetryDocument {
+    #[serde(default)]
+    max_retries: u32,
+    initial_delay: DurationText,
+    multiplier: f64,
+    #[serde(default)]
+    max_delay: Option<DurationText>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RateLimitDocument {
+    max_messages: u32,
+    per: DurationText,
+    #[serde(default = "default_true")]
+    blocking: bool,
+}
+
+/// The config as written, with the defaults of `ConfigBuilder`
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigDocument {
+    from_addrs: String,
+    #[serde(default)]
+    reply_to: Option<String>,
+    #[serde(default)]
+    envelope_from: Option<String>,
+    #[serde(default)]
+    message_id_domain: Option<String>,
+    #[serde(default)]
+    hello_name: Option<String>,
+    server: String,
+    #[serde(default = "default_port")]
+    port: u16,
+    #[serde(
+        default,
+        serialize_with = "serialize_tls_mode",
+        deserialize_with = "deserialize_tls_mode"
+    )]
+    tls_mode: Option<TlsMode>,
+    #[serde(default)]
+    allow_invalid_certs: bool,
+    #[serde(default)]
+    user_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password: Option<String>,
+    #[serde(default)]
+    template_dir_path: Option<PathBuf>,
+    #[serde(default = "default_template_name_format")]
+    template_name_format: String,
+    #[serde(default)]
+    languages: Vec<String>,
+    #[serde(default = "default_language")]
+    default_language: String,
+    #[serde(default)]
+    attachment_name_placeholders: bool,
+    #[serde(default)]
+    strict_placeholders: bool,
+    #[serde(default = "default_delimiters")]
+    placeholder_delimiters: (String, String),
+    #[serde(default)]
+    allowed_placeholders: Vec<String>,
+    #[serde(default)]
+    lenient_placeholders: bool,
+    #[serde(default)]
+    keep_alive_interval: Option<DurationText>,
+    #[serde(default)]
+    audit_log_path: Option<PathBuf>,
+    #[serde(default)]
+    subject_prefix: Option<String>,
+    #[serde(default)]
+    footer_name_format: Option<String>,
+    #[serde(default)]
+    subjects_name_format: Option<String>,
+    #[serde(default)]
+    catalog_name_format: Option<String>,
+    #[serde(default)]
+    skip_template_validation: bool,
+    #[serde(default = "default_true")]
+    auto_plaintext: bool,
+    #[serde(default = "default_true")]
+    escape_contents: bool,
+    #[serde(default = "default_true")]
+    dedupe_recipients: bool,
+    #[serde(default = "default_true")]
+    mask_log_addresses: bool,
+    #[serde(default)]
+    send_deadline: Option<DurationText>,
+    #[serde(default)]
+    retry_policy: Option<RetryDocument>,
+    #[serde(default)]
+    rate_limit: Option<RateLimitDocument>,
+    #[serde(default)]
+    transport_per_send: bool,
+    #[serde(default)]
+    max_attachment_bytes: Option<u64>,
+    #[serde(default)]
+    max_message_bytes: Option<u64>,
+    #[serde(default)]
+    max_recipients_per_message: Option<usize>,
+    #[serde(default)]
+    delivery_dir: Option<PathBuf>,
+    #[serde(default)]
+    sendmail_path: Option<PathBuf>,
+    /// `null` keeps lettre's default
+    #[serde(default = "default_timeout")]
+    connect_timeout: Option<DurationText>,
+    #[serde(default = "default_timeout")]
+    command_timeout: Option<DurationText>,
+    #[serde(default)]
+    pool_max_size: Option<u32>,
+    #[serde(default)]
+    pool_min_idle: Option<u32>,
+    #[serde(default)]
+    pool_idle_timeout: Option<DurationText>,
+    #[cfg(feature = "handlebars")]
+    #[serde(default)]
+    template_engine: TemplateEngine,
+}
+
+impl ConfigDocument {
+    fn from_config(config: &Config, with_password: bool) -> Self {
+        let policy = config.retry_policy;
+        ConfigDocument {
+            from_addrs: config.from_addrs.to_string(),
+            reply_to: Some(
+                config
+                    .reply_to
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            envelope_from: config.envelope_from.as_ref().map(ToString::to_string),
+            message_id_domain: config.message_id_domain.clone(),
+            hello_name: config.hello_name.clone(),
+            server: config.server.clone(),
+            port: config.port,
+            tls_mode: Some(config.tls_mode),
+            allow_invalid_certs: config.allow_invalid_certs,
+            user_name: config.user_name.clone(),
+            password: with_password.then(|| config.password.clone()),
+            template_dir_path: Some(PathBuf::from(&config.template_dir_path))
+                .filter(|val| !val.as_os_str().is_empty()),
+            template_name_format: config.template_name_format.clone(),
+            languages: config.languages.clone(),
+            default_language: config.default_language.clone(),
+            attachment_name_placeholders: config.attachment_name_placeholders,
+            strict_placeholders: config.strict_placeholders,
+            placeholder_delimiters: config.placeholder_delimiters.clone(),
+            allowed_placeholders: config.allowed_placeholders.clone(),
+            lenient_placeholders: config.lenient_placeholders,
+            keep_alive_interval: config.keep_alive_interval.map(DurationText),
+            audit_log_path: config.audit_log_path.clone(),
+            subject_prefix: config.subject_prefix.clone(),
+            footer_name_format: config.footer_name_format.clone(),
+            subjects_name_format: config.subjects_name_format.clone(),
+            catalog_name_format: config.catalog_name_format.clone(),
+            skip_template_validation: config.skip_template_validation,
+            auto_plaintext: config.auto_plaintext,
+            escape_contents: config.escape_contents,
+            dedupe_recipients: config.dedupe_recipients,
+            mask_log_addresses: config.mask_log_addresses,
+            send_deadline: config.send_deadline.map(DurationText),
+            retry_policy: (policy != RetryPolicy::default()).then_some(RetryDocument {
+                max_retries: policy.max_retries,
+                initial_delay: DurationText(policy.initial_delay),
+                multiplier: policy.multiplier,
+                max_delay: policy.max_delay.map(DurationText),
+            }),
+            rate_limit: config.rate_limit.map(|limit| RateLimitDocument {
+                max_messages: limit.max_messages,
+                per: DurationText(limit.per),
+                blocking: limit.blocking,
+            }),
+            transport_per_send: config.transport_per_send,
+            max_attachment_bytes: config.max_attachment_bytes,
+            max_message_bytes: config.max_message_bytes,
+            max_recipients_per_message: config.max_recipients_per_message,
+            delivery_dir: match &config.delivery {
+                Delivery::File(dir) => Some(dir.clone()),
+                _ => None,
+            },
+            sendmail_path: match &config.delivery {
+                Delivery::Sendmail(path) => Some(
+                    path.clone()
+                        .unwrap_or_else(|| PathBuf::from(DEFAULT_SENDMAIL_PATH)),
+                ),
+                _ => None,
+            },
+            connect_timeout: config.connect_timeout.map(DurationText),
+            command_timeout: config.command_timeout.map(DurationText),
+            pool_max_size: Some(config.pool_max_size),
+            pool_min_idle: Some(config.pool_min_idle),
+            pool_idle_timeout: Some(DurationText(config.pool_idle_timeout)),
+            #[cfg(feature = "handlebars")]
+            template_engine: config.template_engine,
+        }
+    }
+
+    fn into_config(self) -> Result<Config, crate::error::ErrorReport> {
+        let mut builder = Config::builder()
+            .from_addrs(self.from_addrs)
+            .server(self.server)
+            .port(self.port)
+            .allow_invalid_certs(self.allow_invalid_certs)
+            .credentials(self.user_name, self.password.unwrap_or_default())
+            .template_name_format(self.template_name_format)
+            .languages(self.languages)
+            .default_language(self.default_language)
+            .attachment_name_placeholders(self.attachment_name_placeholders)
+            .strict_placeholders(self.strict_placeholders)
+            .placeholder_delimiters(self.placeholder_delimiters.0, self.placeholder_delimiters.1)
+            .allowed_placeholders(self.allowed_placeholders)
+            .lenient_placeholders(self.lenient_placeholders)
+            .skip_template_validation(self.skip_template_validation)
+            .auto_plaintext(self.auto_plaintext)
+            .escape_contents(self.escape_contents)
+            .dedupe_recipients(self.dedupe_recipients)
+            .mask_log_addresses(self.mask_log_addresses)
+            .transport_per_send(self.transport_per_send)
+            .max_attachment_bytes(self.max_attachment_bytes.unwrap_or_default())
+            .max_message_bytes(self.max_message_bytes.unwrap_or_default())
+            .max_recipients_per_message(self.max_recipients_per_message.unwrap_or_default())
+            .connect_timeout(self.connect_timeout.map(|val| val.0))
+            .command_timeout(self.command_timeout.map(|val| val.0));
+        if let Some(size) = self.pool_max_size {
+            builder = builder.pool_max_size(size);
+        }
+        if let Some(size) = self.pool_min_idle {
+            builder = builder.pool_min_idle(size);
+        }
+        if let Some(timeout) = self.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(timeout.0);
+        }
+        if let Some(mode) = self.tls_mode {
+            builder = builder.tls_mode(mode);
+        }
+        if let Some(reply_to) = self.reply_to {
+            builder = builder.reply_to(reply_to);
+        }
+        if let Some(address) = self.envelope_from {
+            builder = builder.envelope_from(address);
+        }
+        if let Some(domain) = self.message_id_domain {
+            builder = builder.message_id_domain(domain);
+        }
+        if let Some(name) = self.hello_name {
+            builder = builder.hello_name(name);
+        }
+        if let Some(dir) = self.template_dir_path {
+            builder = builder.template_dir_path(dir);
+        }
+        if let Some(interval) = self.keep_alive_interval {
+            builder = builder.keep_alive(interval.0);
+        }
+        if let Some(path) = self.audit_log_path {
+            builder = builder.audit_log(path);
+        }
+        if let Some(prefix) = self.subject_prefix {
+            builder = builder.subject_prefix(prefix);
+        }
+        if let Some(format) = self.footer_name_format {
+            builder = builder.footer_name_format(format);
+        }
+        if let Some(format) = self.subjects_name_format {
+            builder = builder.subjects_name_format(format);
+        }
+        if let Some(format) = self.catalog_name_format {
+            builder = builder.catalog_name_format(format);
+        }
+        if let Some(deadline) = self.send_deadline {
+            builder = builder.send_deadline(deadline.0);
+        }
+        if let Some(retry) = self.retry_policy {
+            builder = builder.retry_policy(RetryPolicy {
+                max_retries: retry.max_retries,
+                initial_delay: retry.initial_delay.0,
+                multiplier: retry.multiplier,
+                max_delay: retry.max_delay.map(|val| val.0),
+            });
+        }
+        if let Some(limit) = self.rate_limit {
+            builder = builder.rate_limit(RateLimit {
+                max_messages: limit.max_messages,
+                per: limit.per.0,
+                blocking: limit.blocking,
+            });
+        }
+        if let Some(dir) = self.delivery_dir {
+            builder = builder.delivery(Delivery::File(dir));
+        } else if let Some(path) = self.sendmail_path {
+            builder = builder.delivery(Delivery::Sendmail(Some(path)));
+        }
+        #[cfg(feature = "handlebars")]
+        {
+            builder = builder.template_engine(self.template_engine);
+        }
+        builder.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_duration, Config};
+    use crate::mailer::{Delivery, TlsMode};
+    use serde_json::{json, Value};
+    use std::{path::Path, time::Duration};
+
+    fn document() -> Value {
+        let template_dir = Path::new(env!("WORKSPACE_ROOT_PATH")).join("assets/test/mail");
+        #[allow(unused_mut)]
+        let mut document = json!({
+            "from_addrs": "Mailer Test <noreply@example.com>",
+            "reply_to": "support@example.com, \"Sales, EU\" <sales@example.com>",
+            "envelope_from": "bounces@example.com",
+            "message_id_domain": "mail.example.com",
+            "hello_name": "app01.example.com",
+            "server": "smtp.example.com",
+            "port": 465,
+            "tls_mode": "wrapped",
+            "allow_invalid_certs": false,
+            "user_name": "mailer",
+            "password": "secret-password",
+            "template_dir_path": template_dir,
+            "template_name_format": "email_{lang}.html",
+            "languages": ["ro", "en"],
+            "default_language": "ro",
+            "attachment_name_placeholders": true,
+            "strict_placeholders": false,
+            "placeholder_delimiters": ["[[", "]]"],
+            "allowed_placeholders": ["contents"],
+            "lenient_placeholders": false,
+            "keep_alive_interval": "300s",
+            "audit_log_path": null,
+            "subject_prefix": "[STAGING]",
+            "footer_name_format": "footer_{lang}.html",
+            "subjects_name_format": null,
+            "auto_plaintext": false,
+            "escape_contents": false,
+            "dedupe_recipients": false,
+            "mask_log_addresses": false,
+            "send_deadline": "45s",
+            "retry_policy": {
+                "max_retries": 3,
+                "initial_delay": "500ms",
+                "multiplier": 2.0,
+                "max_delay": "30s",
+            },
+            "rate_limit": {"max_messages": 100, "per": "60s", "blocking": false},
+            "transport_per_send": true,
+            "max_attachment_bytes": 1048576,
+            "max_message_bytes": null,
+            "max_recipients_per_message": 50,
+            "delivery_dir": null,
+            "sendmail_path": null,
+            "connect_timeout": "10s",
+            "command_timeout": null,
+        });
+        // past the recursion limit of a single json! call
+        document["pool_max_size"] = json!(32);
+        document["pool_min_idle"] = json!(2);
+        document["pool_idle_timeout"] = json!("120s");
+        document["catalog_name_format"] = json!("strings_{lang}.properties");
+        document["skip_template_validation"] = json!(true);
+        #[cfg(feature = "handlebars")]
+        {
+            document["template_engine"] = json!("placeholders");
+        }
+        document
+    }
+
+    #[test]
+    fn round_trip() {
+        let config: Config = serde_json::from_value(document()).unwrap();
+        assert_eq!(
+            config.from_addrs.to_string(),
+            "Mailer Test <noreply@example.com>"
+        );
+        assert_eq!(config.reply_to.len(), 2);
+        assert_eq!(config.tls_mode, TlsMode::Wrapped);
+        assert_eq!(config.password, "secret-password");
+        assert_eq!(config.keep_alive_interval, Some(Duration::from_secs(300)));
+        assert_eq!(
+            config.retry_policy.initial_delay,
+            Duration::from_millis(500)
+        );
+        assert_eq!(config.retry_policy.max_delay, Some(Duration::from_secs(30)));
+        assert_eq!(config.connect_timeout, Some(Duration::from_secs(10)));
+        assert_eq!(config.command_timeout, None);
+        assert_eq!(config.pool_max_size, 32);
+        assert_eq!(config.pool_idle_timeout, Duration::from_secs(120));
+        assert_eq!(config.delivery, Delivery::Smtp);
+
+        // the password is only written on request
+        let written = serde_json::to_value(&config).unwrap();
+        assert!(written.get("password").is_none());
+        assert!(!written.to_string().contains("secret-password"));
+        assert_eq!(
+            serde_json::to_value(config.with_password()).unwrap(),
+            document()
+        );
+
+        let reread: Config = serde_json::from_value(written).unwrap();
+        assert_eq!(reread.password, "");
+        assert_eq!(
+            format!("{:?}", reread),
+            format!("{:?}", config),
+            "the password aside, a written config reads back the same"
+        );
+    }
+
+    #[test]
+    fn defaults_and_errors() {
+        let config: Config = serde_json::from_value(json!({
+            "from_addrs": "noreply@example.com",
+            "server": "smtp.example.com",
+        }))
+        .unwrap();
+        assert_eq!(config.port, 587);
+        assert_eq!(config.tls_mode, TlsMode::StartTls);
+        assert_eq!(config.reply_to, vec![config.from_addrs.clone()]);
+        assert_eq!(config.languages, vec!["en"]);
+        assert!(config.auto_plaintext);
+        assert!(config.escape_contents);
+        assert_eq!(config.connect_timeout, Some(Duration::from_secs(30)));
+
+        let smtps: Config = serde_json::from_value(json!({
+            "from_addrs": "noreply@example.com",
+            "server": "smtp.example.com",
+            "port": 465,
+        }))
+        .unwrap();
+        assert_eq!(smtps.tls_mode, TlsMode::Wrapped);
+
+        let sendmail: Config = serde_json::from_value(json!({
+            "from_addrs": "noreply@example.com",
+            "server": "localhost",
+            "sendmail_path": "/usr/lib/sendmail",
+        }))
+        .unwrap();
+        assert_eq!(
+            sendmail.delivery,
+            Delivery::Sendmail(Some("/usr/lib/sendmail".into()))
+        );
+
+        let error = serde_json::from_value::<Config>(json!({
+            "from_addrs": "not an address",
+            "server": "",
+        }))
+        .unwrap_err()
+        .to_string();
+        assert!(error.starts_with("MailConfigInvalid - "), "{}", error);
+        assert!(error.contains("server is empty"), "{}", error);
+
+        let error = serde_json::from_value::<Config>(json!({
+            "from_addrs": "noreply@example.com",
+            "server": "smtp.example.com",
+            "smtp_server": "typo",
+        }))
+        .unwrap_err();
+        assert!(error.to_string().contains("unknown field `smtp_server`"));
+
+        assert_eq!(parse_duration(" 250ms "), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert!(parse_duration("1d").is_err());
+        assert!(parse_duration("soon").is_err());
+    }
+}
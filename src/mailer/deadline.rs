@@ -0,0 +1,54 @@
+use crate::error::ErrorReport;
+use std::time::{Duration, Instant};
+
+/// Time budget of one send, shared by all of its attempts
+pub struct Deadline {
+    started: Instant,
+    budget: Option<Duration>,
+    attempts: Vec<String>,
+}
+
+impl Deadline {
+    pub fn new(budget: Option<Duration>) -> Self {
+        Deadline {
+            started: Instant::now(),
+            budget,
+            attempts: Vec::new(),
+        }
+    }
+
+    /// Time left, `None` when there is no budget
+    pub fn remaining(&self) -> Option<Duration> {
+        self.budget
+            .map(|budget| budget.saturating_sub(self.started.elapsed()))
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.remaining() == Some(Duration::ZERO)
+    }
+
+    /// Timeout of the next attempt: the configured one, bounded by what is left of the budget
+    pub fn attempt_timeout(&self, configured: Option<Duration>) -> Option<Duration> {
+        match (configured, self.remaining()) {
+            (Some(configured), Some(remaining)) => Some(configured.min(remaining)),
+            (configured, remaining) => configured.or(remaining),
+        }
+    }
+
+    /// Notes the outcome of a failed attempt, reported when the budget runs out
+    pub fn record(&mut self, error: &ErrorReport) {
+        self.attempts.push(format!(
+            "attempt {} after {} ms: {}",
+            self.attempts.len() + 1,
+            self.started.elapsed().as_millis(),
+            error
+        ));
+    }
+
+    pub fn exceeded(self) -> ErrorReport {
+        ErrorReport::MailDeadlineExceeded {
+            budget: self.budget.unwrap_or_default(),
+            attempts: self.attempts,
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use crate::error::ErrorReport;
+use lettre::{
+    message::dkim::{DkimConfig, DkimSigningAlgorithm, DkimSigningKey},
+    Message,
+};
+use openssl::rsa::Rsa;
+use std::{fmt, fs, path::PathBuf, sync::Arc};
+use zeroize::Zeroizing;
+
+/// RSA private key signing the messages, in PEM as `RsaKeys` reads it
+#[derive(Clone)]
+pub enum DkimKey {
+    Path(PathBuf),
+    Pem(Vec<u8>),
+}
+
+impl fmt::Debug for DkimKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DkimKey::Path(path) => f.debug_tuple("Path").field(path).finish(),
+            DkimKey::Pem(bytes) => write!(f, "Pem({} bytes)", bytes.len()),
+        }
+    }
+}
+
+/// Adds a `DKIM-Signature` header to every message, covering `From`, `Subject`, `To` and `Date`
+#[derive(Clone)]
+pub struct DkimSigner {
+    selector: String,
+    domain: String,
+    config: Arc<DkimConfig>,
+}
+
+impl DkimSigner {
+    /// Reads and parses the key, decrypted with the passphrase when it is encrypted
+    pub fn new(
+        selector: &str,
+        domain: &str,
+        key: &DkimKey,
+        passphrase: Option<&str>,
+    ) -> Result<Self, ErrorReport> {
+        let pem = Zeroizing::new(match key {
+            DkimKey::Path(path) => fs::read(path)?,
+            DkimKey::Pem(bytes) => bytes.clone(),
+        });
+        let private = match passphrase {
+            Some(passphrase) => Rsa::private_key_from_pem_passphrase(&pem, passphrase.as_bytes())?,
+            None => Rsa::private_key_from_pem(&pem)?,
+        };
+        // lettre only reads unencrypted PKCS#1
+        let pkcs1 = Zeroizing::new(String::from_utf8(private.private_key_to_pem()?)?);
+        let signing_key = DkimSigningKey::new(&pkcs1, DkimSigningAlgorithm::Rsa)?;
+
+        Ok(DkimSigner {
+            selector: selector.to_owned(),
+            domain: domain.to_owned(),
+            config: Arc::new(DkimConfig::default_config(
+                selector.to_owned(),
+                domain.to_owned(),
+                signing_key,
+            )),
+        })
+    }
+
+    pub fn selector(&self) -> &str {
+        &self.selector
+    }
+
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    pub(super) fn sign(&self, message: &mut Message) {
+        message.sign(&self.config);
+    }
+}
+
+impl fmt::Debug for DkimSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DkimSigner")
+            .field("selector", &self.selector)
+            .field("domain", &self.domain)
+            .finish_non_exhaustive()
+    }
+}
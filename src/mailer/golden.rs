@@ -0,0 +1,61 @@
+//! Golden file comparison for rendered mail; run the tests with `UPDATE_GOLDEN=1` to regenerate
+//! the snapshots in `assets/test/golden`
+use std::{env, fs, path::Path};
+
+pub fn assert_golden(name: &str, actual: &str) {
+    let path = Path::new(env!("WORKSPACE_ROOT_PATH"))
+        .join("assets/test/golden")
+        .join(name);
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!(
+            "Golden file {} not readable ({}), run with UPDATE_GOLDEN=1 to create it",
+            path.display(),
+            e
+        )
+    });
+    assert_eq!(
+        actual,
+        expected,
+        "Output differs from golden file {}",
+        path.display()
+    );
+}
+
+/// Formatted message with CRLF turned into LF and the random or time dependent parts
+/// (Date, Message-ID, MIME boundaries) replaced by fixed markers
+pub fn normalize_eml(eml: &[u8]) -> String {
+    let mut text = String::from_utf8_lossy(eml)
+        .split("\r\n")
+        .map(|line| {
+            if line.starts_with("Date: ") {
+                "Date: <DATE>"
+            } else if line.starts_with("Message-ID: ") {
+                "Message-ID: <MESSAGE-ID>"
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<&str>>()
+        .join("\n");
+
+    let mut boundaries: Vec<String> = Vec::new();
+    let mut rest = text.as_str();
+    while let Some(start) = rest.find("boundary=\"") {
+        rest = &rest[start + 10..];
+        if let Some(end) = rest.find('"') {
+            if !boundaries.iter().any(|val| val == &rest[..end]) {
+                boundaries.push(rest[..end].to_owned());
+            }
+        }
+    }
+    for (i, boundary) in boundaries.iter().enumerate() {
+        text = text.replace(boundary, &format!("BOUNDARY-{}", i + 1));
+    }
+    text
+}
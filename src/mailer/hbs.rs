@@ -0,0 +1,103 @@
+//! Handlebars rendering of the mail templates, for conditionals and loops over the message data
+use crate::error::ErrorReport;
+use handlebars::Handlebars;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// How the mail templates are rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum TemplateEngine {
+    /// `{{name}}` placeholders replaced with the message variables
+    #[default]
+    Placeholders,
+    /// Handlebars templates rendered with the message context, or the variables when the message
+    /// has none; values are HTML escaped unless written as `{{{name}}}`
+    Handlebars,
+}
+
+/// Registry name of a template, by its `template::key`
+pub fn body_name(key: &str) -> String {
+    format!("body_{}", key)
+}
+
+/// Registry name of the footer of a language
+pub fn footer_name(lang: &str) -> String {
+    format!("footer_{}", lang)
+}
+
+/// Empty registry; in strict mode a missing value fails the render instead of printing nothing
+pub fn registry(strict: bool) -> Handlebars<'static> {
+    let mut registry = Handlebars::new();
+    registry.set_strict_mode(strict);
+    registry
+}
+
+pub fn compile(
+    registry: &mut Handlebars<'static>,
+    name: &str,
+    source: &str,
+) -> Result<(), ErrorReport> {
+    Ok(registry.register_template_string(name, source)?)
+}
+
+pub fn render(registry: &Handlebars, name: &str, context: &Value) -> Result<String, ErrorReport> {
+    Ok(registry.render(name, context)?)
+}
+
+/// Context made of the message variables, for messages built without one
+pub fn vars_context(vars: &HashMap<String, String>) -> Value {
+    Value::Object(
+        vars.iter()
+            .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compile, registry, render, vars_context};
+    use crate::error::ErrorReport;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    #[test]
+    fn compile_and_render() {
+        let mut hbs = registry(false);
+        compile(
+            &mut hbs,
+            "order",
+            "<ul>{{#each items}}<li>{{name}} x{{qty}}</li>{{/each}}</ul>\
+             {{#if note}}<p>{{note}}</p>{{/if}}",
+        )
+        .unwrap();
+        let context = json!({
+            "items": [{"name": "Pen & ink", "qty": 2}, {"name": "Paper", "qty": 1}],
+        });
+        assert_eq!(
+            render(&hbs, "order", &context).unwrap(),
+            "<ul><li>Pen &amp; ink x2</li><li>Paper x1</li></ul>"
+        );
+
+        assert!(matches!(
+            compile(&mut hbs, "broken", "{{#each items}}<li>"),
+            Err(ErrorReport::MailTemplateCompile(_))
+        ));
+
+        let mut strict = registry(true);
+        compile(&mut strict, "note", "{{note}}").unwrap();
+        assert!(matches!(
+            render(&strict, "note", &json!({})),
+            Err(ErrorReport::MailTemplateRender(_))
+        ));
+        let vars = HashMap::from([("note".to_owned(), "Thanks".to_owned())]);
+        assert_eq!(
+            render(&strict, "note", &vars_context(&vars)).unwrap(),
+            "Thanks"
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use crate::error::ErrorReport;
+use lettre::{message::Mailbox, transport::smtp::response::Response};
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+    time::SystemTime,
+};
+
+/// A message accepted by the server, handed to the `on_sent` hook
+#[derive(Debug, Clone)]
+pub struct SentMessage {
+    /// The whole RFC 5322 message, as sent
+    pub formatted: Vec<u8>,
+    pub message_id: Option<String>,
+    pub to: Vec<Mailbox>,
+    pub cc: Vec<Mailbox>,
+    pub subject: String,
+    pub started: SystemTime,
+    pub finished: SystemTime,
+}
+
+/// What the server said about a message it accepted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SendReport {
+    /// The `Message-ID` header of the message as sent
+    pub message_id: String,
+    /// SMTP reply code, e.g. `250`
+    pub code: u16,
+    pub response_lines: Vec<String>,
+    /// Reports of every message, in order, when the recipients were split over several; see
+    /// `Config::max_recipients_per_message`
+    pub split: Vec<SendReport>,
+}
+
+impl SendReport {
+    pub fn new(message_id: String, response: &Response) -> Self {
+        SendReport {
+            message_id,
+            code: response.code().to_string().parse().unwrap_or_default(),
+            response_lines: response.message().map(|val| val.to_owned()).collect(),
+            split: Vec::new(),
+        }
+    }
+}
+
+/// A send attempt that failed, handed to the `on_failed` hook
+#[derive(Debug)]
+pub struct FailedMessage {
+    pub message_id: Option<String>,
+    pub to: Vec<Mailbox>,
+    pub cc: Vec<Mailbox>,
+    pub subject: String,
+    pub started: SystemTime,
+    pub finished: SystemTime,
+    pub error: ErrorReport,
+}
+
+pub type Hook<T> = Arc<dyn Fn(&T) + Send + Sync>;
+
+/// Calls a hook, swallowing its panics so they never reach the sender
+pub fn invoke<T>(hook: &Option<Hook<T>>, value: &T) {
+    if let Some(hook) = hook {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| hook(value)));
+    }
+}
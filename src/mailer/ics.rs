@@ -0,0 +1,210 @@
+use super::audit::civil_from_days;
+use lettre::message::Mailbox;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Identifies the producer of the calendar objects (RFC 5545 3.7.3)
+const PRODUCT_ID: &str = "-//CataMark//utils mailer//EN";
+
+/// Longest content line in octets, CRLF excluded (RFC 5545 3.1)
+const LINE_OCTETS: usize = 75;
+
+/// Meeting invitation sent as a `text/calendar; method=REQUEST` part, which mail clients show
+/// with their accept and decline buttons
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IcsEvent {
+    /// Stays the same across updates of the event, e.g. `interview-42@example.com`
+    pub uid: String,
+    pub summary: String,
+    pub dtstart: SystemTime,
+    pub dtend: SystemTime,
+    pub organizer: Mailbox,
+    pub attendees: Vec<Mailbox>,
+    pub location: Option<String>,
+}
+
+impl IcsEvent {
+    /// The VCALENDAR object, stamped with the current time
+    pub fn to_ics(&self) -> String {
+        self.render(SystemTime::now())
+    }
+
+    /// Problems that make the event unusable, checked by `MailMessageBuilder::build`
+    pub(super) fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.uid.trim().is_empty() {
+            problems.push("calendar event uid is empty".to_owned());
+        }
+        if self.dtend < self.dtstart {
+            problems.push("calendar event ends before it starts".to_owned());
+        }
+        problems
+    }
+
+    fn render(&self, stamp: SystemTime) -> String {
+        let mut lines = vec![
+            "BEGIN:VCALENDAR".to_owned(),
+            format!("PRODID:{}", PRODUCT_ID),
+            "VERSION:2.0".to_owned(),
+            "CALSCALE:GREGORIAN".to_owned(),
+            "METHOD:REQUEST".to_owned(),
+            "BEGIN:VEVENT".to_owned(),
+            format!("UID:{}", escape_text(&self.uid)),
+            format!("DTSTAMP:{}", format_utc(stamp)),
+            format!("DTSTART:{}", format_utc(self.dtstart)),
+            format!("DTEND:{}", format_utc(self.dtend)),
+            format!("SUMMARY:{}", escape_text(&self.summary)),
+        ];
+        if let Some(location) = &self.location {
+            lines.push(format!("LOCATION:{}", escape_text(location)));
+        }
+        lines.push(format!("ORGANIZER{}", calendar_address(&self.organizer)));
+        for attendee in &self.attendees {
+            lines.push(format!(
+                "ATTENDEE;ROLE=REQ-PARTICIPANT;PARTSTAT=NEEDS-ACTION;RSVP=TRUE{}",
+                calendar_address(attendee)
+            ));
+        }
+        lines.extend(
+            [
+                "SEQUENCE:0",
+                "STATUS:CONFIRMED",
+                "END:VEVENT",
+                "END:VCALENDAR",
+            ]
+            .map(str::to_owned),
+        );
+
+        lines.iter().map(|line| fold(line)).collect()
+    }
+}
+
+/// UTC date-time in the basic format, e.g. `20240301T093000Z`
+fn format_utc(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let secs_of_day = secs % 86_400;
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+/// TEXT value with backslashes, semicolons, commas and line breaks escaped (RFC 5545 3.3.11)
+fn escape_text(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\r' | '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// `;CN=...:mailto:...` tail of an organizer or attendee line; a parameter value cannot escape
+/// double quotes, so they are dropped from the name
+fn calendar_address(mailbox: &Mailbox) -> String {
+    let name = mailbox
+        .name
+        .as_deref()
+        .map(|val| val.replace(['"', '\r', '\n'], ""))
+        .filter(|val| !val.trim().is_empty());
+    match name {
+        Some(name) => format!(";CN=\"{}\":mailto:{}", name, mailbox.email),
+        None => format!(":mailto:{}", mailbox.email),
+    }
+}
+
+/// Content line ending in CRLF, folded into continuation lines of at most 75 octets without
+/// splitting a UTF-8 character
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 2);
+    let mut octets = 0;
+    for c in line.chars() {
+        if octets + c.len_utf8() > LINE_OCTETS {
+            folded.push_str("\r\n ");
+            octets = 1;
+        }
+        folded.push(c);
+        octets += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_text, fold, IcsEvent};
+    use crate::mailer::golden::assert_golden;
+    use lettre::message::Mailbox;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn escaping_and_folding() {
+        assert_eq!(
+            escape_text("Room 2; floor 3, left\\right\r\nBring ID\n"),
+            "Room 2\\; floor 3\\, left\\\\right\\nBring ID\\n"
+        );
+
+        let line = format!("SUMMARY:{}", "ă".repeat(40));
+        let folded = fold(&line);
+        assert!(folded.ends_with("\r\n"));
+        assert!(folded
+            .trim_end_matches("\r\n")
+            .split("\r\n")
+            .all(|val| val.len() <= 75));
+        assert_eq!(folded.replace("\r\n ", ""), format!("{}\r\n", line));
+    }
+
+    #[test]
+    fn invite_snapshot() {
+        // 2024-03-01 09:30 UTC
+        let start = UNIX_EPOCH + Duration::from_secs(1_709_285_400);
+        let event = IcsEvent {
+            uid: "interview-42@example.com".to_owned(),
+            summary: "Interview: Backend, round 2; technical".to_owned(),
+            dtstart: start,
+            dtend: start + Duration::from_secs(3600),
+            organizer: Mailbox::new(
+                Some("Recrutare \"HR\"".to_owned()),
+                "hr@example.com".parse().unwrap(),
+            ),
+            attendees: vec![
+                "Ana Pop <ana@example.com>".parse().unwrap(),
+                "ion@example.com".parse().unwrap(),
+            ],
+            location: Some("Str. Lungă 5, Brașov\nFloor 3".to_owned()),
+        };
+        assert!(event.problems().is_empty());
+
+        let ics = event.render(start - Duration::from_secs(86_400));
+        assert!(ics.split_inclusive('\n').all(|line| line.ends_with("\r\n")));
+        assert_golden("invite.ics", &ics.replace("\r\n", "\n"));
+
+        let broken = IcsEvent {
+            uid: " ".to_owned(),
+            dtend: start - Duration::from_secs(1),
+            ..event
+        };
+        assert_eq!(
+            broken.problems(),
+            vec![
+                "calendar event uid is empty",
+                "calendar event ends before it starts"
+            ]
+        );
+    }
+}
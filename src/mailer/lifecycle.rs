@@ -0,0 +1,95 @@
+//! Sends in flight of a mailer and its clones, so `Mailer::shutdown` can wait for them
+use crate::error::ErrorReport;
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+#[derive(Debug, Default)]
+struct State {
+    closed: bool,
+    in_flight: usize,
+    /// Whether any send went through, for the warning of a mailer dropped without shutdown
+    used: bool,
+}
+
+/// Shared by a mailer and its clones
+#[derive(Debug, Default)]
+pub(super) struct Lifecycle {
+    state: Mutex<State>,
+    idle: Condvar,
+}
+
+/// Held for the duration of a send
+#[derive(Debug)]
+pub(super) struct InFlight(Arc<Lifecycle>);
+
+impl Lifecycle {
+    /// Counts a send in, refused with `MailShutDown` once the mailer is closed
+    pub fn enter(self: &Arc<Self>) -> Result<InFlight, ErrorReport> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(ErrorReport::MailShutDown);
+        }
+        state.in_flight += 1;
+        state.used = true;
+        Ok(InFlight(self.clone()))
+    }
+
+    /// Refuses new sends and waits up to `timeout` for the ones in flight, returning how many
+    /// are still running
+    pub fn close(&self, timeout: Duration) -> usize {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        let (state, _) = self
+            .idle
+            .wait_timeout_while(state, timeout, |val| val.in_flight > 0)
+            .unwrap();
+        state.in_flight
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.in_flight -= 1;
+        if state.in_flight == 0 {
+            self.0.idle.notify_all();
+        }
+    }
+}
+
+impl Drop for Lifecycle {
+    /// The last clone of the mailer is gone; its transports close their connections as they are
+    /// dropped, without any send left to wait for
+    fn drop(&mut self) {
+        let state = self.state.get_mut().unwrap_or_else(|e| e.into_inner());
+        if state.used && !state.closed {
+            log::warn!("Mailer dropped without shutdown, closing its connections");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lifecycle;
+    use crate::error::ErrorReport;
+    use std::{sync::Arc, thread, time::Duration};
+
+    #[test]
+    fn close_waits_for_sends() {
+        let lifecycle = Arc::new(Lifecycle::default());
+        let first = lifecycle.enter().unwrap();
+        let second = lifecycle.enter().unwrap();
+        drop(first);
+        assert_eq!(lifecycle.close(Duration::from_millis(20)), 1);
+        assert!(matches!(lifecycle.enter(), Err(ErrorReport::MailShutDown)));
+
+        let worker = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            drop(second);
+        });
+        assert_eq!(lifecycle.close(Duration::from_secs(5)), 0);
+        worker.join().unwrap();
+    }
+}
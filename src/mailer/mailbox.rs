@@ -0,0 +1,223 @@
+//! Addresses written as text in config files and APIs, e.g. `"Ops, Berlin" <ops@example.com>`
+use crate::error::ErrorReport;
+use lettre::{message::Mailbox, Address};
+
+/// Mailbox from a bare address or the `Name <address>` form; the name may be quoted, which it
+/// has to be when it contains a comma or semicolon
+pub fn parse_mailbox(text: &str) -> Result<Mailbox, ErrorReport> {
+    parse(text).map_err(|msg| invalid(text, msg))
+}
+
+/// Mailboxes separated by commas or semicolons; separators inside quoted names or angle brackets
+/// do not count and empty entries are skipped
+pub fn parse_mailbox_list(text: &str) -> Result<Vec<Mailbox>, ErrorReport> {
+    split(text).into_iter().map(parse_mailbox).collect()
+}
+
+/// Entries of a mailbox list, trimmed, the empty ones left out
+pub(super) fn split(text: &str) -> Vec<&str> {
+    let mut entries = Vec::new();
+    let mut start = 0;
+    let mut scanner = Scanner::default();
+    for (idx, c) in text.char_indices() {
+        if scanner.outside(c) && (c == ',' || c == ';') {
+            entries.push(text[start..idx].trim());
+            start = idx + c.len_utf8();
+        }
+    }
+    entries.push(text[start..].trim());
+    entries.retain(|val| !val.is_empty());
+    entries
+}
+
+/// Address with the local part hidden but its first and last character, for the log records,
+/// e.g. `c***a@example.com`
+pub(super) fn mask(address: &Address) -> String {
+    let mut chars = address.user().chars();
+    let first = chars.next().map(String::from).unwrap_or_default();
+    let last = chars.next_back().map(String::from).unwrap_or_default();
+    format!("{}***{}@{}", first, last, address.domain())
+}
+
+fn invalid(token: &str, msg: String) -> ErrorReport {
+    ErrorReport::MailAddressInvalid {
+        token: token.trim().to_owned(),
+        msg,
+    }
+}
+
+/// Problem of a single mailbox, for the messages of `ConfigBuilder::build`
+pub(super) fn parse(text: &str) -> Result<Mailbox, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err("the address is empty".to_owned());
+    }
+    let mut scanner = Scanner::default();
+    let open = text
+        .char_indices()
+        .find(|(_, c)| scanner.outside(*c) && *c == '<')
+        .map(|(idx, _)| idx);
+    if scanner.quoted {
+        return Err("a quoted name is not closed".to_owned());
+    }
+    let (name, address) = match open {
+        Some(open) => {
+            let rest = &text[open + 1..];
+            let close = rest
+                .find('>')
+                .ok_or_else(|| "'<' is not closed by '>'".to_owned())?;
+            if !rest[close + 1..].trim().is_empty() {
+                return Err(format!(
+                    "unexpected '{}' after the address",
+                    rest[close + 1..].trim()
+                ));
+            }
+            (display_name(&text[..open])?, &rest[..close])
+        }
+        None => (None, text),
+    };
+    let address = address.trim().parse::<Address>().map_err(|e| match open {
+        Some(_) => format!("address '{}': {}", address.trim(), e),
+        None => e.to_string(),
+    })?;
+    Ok(Mailbox::new(name, address))
+}
+
+/// Name in front of the address, without the quotes and their escapes
+fn display_name(text: &str) -> Result<Option<String>, String> {
+    let text = text.trim();
+    let name = match text.strip_prefix('"') {
+        Some(quoted) => {
+            let mut name = String::with_capacity(quoted.len());
+            let mut chars = quoted.chars();
+            loop {
+                match chars.next() {
+                    Some('\\') => name.extend(chars.next()),
+                    Some('"') => break,
+                    Some(c) => name.push(c),
+                    None => return Err("a quoted name is not closed".to_owned()),
+                }
+            }
+            let rest: String = chars.collect();
+            if !rest.trim().is_empty() {
+                return Err(format!(
+                    "unexpected '{}' after the quoted name",
+                    rest.trim()
+                ));
+            }
+            name
+        }
+        None if text.contains('"') => return Err("a name is only partly quoted".to_owned()),
+        None => text.to_owned(),
+    };
+    Ok(Some(name.trim().to_owned()).filter(|val| !val.is_empty()))
+}
+
+/// Tracks whether a character is inside a quoted name or the angle brackets of an address
+#[derive(Default)]
+struct Scanner {
+    quoted: bool,
+    escaped: bool,
+    bracketed: bool,
+}
+
+impl Scanner {
+    fn outside(&mut self, c: char) -> bool {
+        if self.escaped {
+            self.escaped = false;
+            return false;
+        }
+        match c {
+            '\\' if self.quoted => self.escaped = true,
+            '"' if !self.bracketed => {
+                self.quoted = !self.quoted;
+                return false;
+            }
+            '<' if !self.quoted => self.bracketed = true,
+            '>' if !self.quoted && self.bracketed => {
+                self.bracketed = false;
+                return false;
+            }
+            _ => {}
+        }
+        !self.quoted && !self.escaped && (c == '<' || !self.bracketed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mask, parse_mailbox, parse_mailbox_list};
+    use crate::error::ErrorReport;
+
+    #[test]
+    fn single_mailbox() {
+        let mailbox = parse_mailbox("  Ops Team <ops@example.com> ").unwrap();
+        assert_eq!(mailbox.name.as_deref(), Some("Ops Team"));
+        assert_eq!(mailbox.email.to_string(), "ops@example.com");
+
+        let mailbox = parse_mailbox("ops@example.com").unwrap();
+        assert_eq!(mailbox.name, None);
+        let mailbox = parse_mailbox("<ops@example.com>").unwrap();
+        assert_eq!(mailbox.name, None);
+
+        let mailbox = parse_mailbox(r#""Pop, Ana \"Ani\"" <ana@example.com>"#).unwrap();
+        assert_eq!(mailbox.name.as_deref(), Some(r#"Pop, Ana "Ani""#));
+        // lettre quotes the name again when writing the header
+        assert_eq!(
+            mailbox.to_string(),
+            r#""Pop, Ana \"Ani\"" <ana@example.com>"#
+        );
+
+        for (text, problem) in [
+            ("", "the address is empty"),
+            ("Ops <ops@example.com", "not closed"),
+            ("Ops <ops@example.com> x", "unexpected 'x'"),
+            ("\"Ops <ops@example.com>", "quoted name is not closed"),
+            ("Ops \"Team\" <ops@example.com>", "partly quoted"),
+            ("Ops <ops.example.com>", "'ops.example.com'"),
+        ] {
+            match parse_mailbox(text) {
+                Err(ErrorReport::MailAddressInvalid { token, msg }) => {
+                    assert_eq!(token, text.trim());
+                    assert!(msg.contains(problem), "{}: {}", text, msg);
+                }
+                other => panic!("Expected an invalid address for {}, got {:?}", text, other),
+            }
+        }
+    }
+
+    #[test]
+    fn mailbox_list() {
+        let mailboxes = parse_mailbox_list(
+            r#" "Ops, Berlin" <ops@example.com>; ana@example.com ,Ion <ion@example.com>;"#,
+        )
+        .unwrap();
+        let listed: Vec<String> = mailboxes.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            listed,
+            vec![
+                r#""Ops, Berlin" <ops@example.com>"#,
+                "ana@example.com",
+                "Ion <ion@example.com>"
+            ]
+        );
+        assert!(parse_mailbox_list(" ").unwrap().is_empty());
+
+        match parse_mailbox_list("ana@example.com, Ion <ion@example>x, ops@example.com") {
+            Err(ErrorReport::MailAddressInvalid { token, .. }) => {
+                assert_eq!(token, "Ion <ion@example>x")
+            }
+            other => panic!("Expected an invalid address, got {:?}", other),
+        }
+        let error = parse_mailbox_list("ana@example.com; not an address").unwrap_err();
+        assert!(error.to_string().contains("'not an address'"), "{}", error);
+    }
+
+    #[test]
+    fn masked_addresses() {
+        let masked = |text: &str| mask(&text.parse().unwrap());
+        assert_eq!(masked("catalina@example.com"), "c***a@example.com");
+        assert_eq!(masked("al@example.com"), "a***l@example.com");
+        assert_eq!(masked("a@example.com"), "a***@example.com");
+    }
+}
@@ -0,0 +1,572 @@
+use super::{template, IcsEvent, MailAttachment};
+use crate::error::ErrorReport;
+use lettre::message::{
+    header::{self, Header, HeaderName, HeaderValue, Headers},
+    Mailbox,
+};
+use lettre::Address;
+#[cfg(feature = "handlebars")]
+use serde::Serialize;
+#[cfg(feature = "handlebars")]
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+};
+
+/// Headers the mailer writes itself, which a message may not set
+const RESERVED_HEADERS: [&str; 14] = [
+    "From",
+    "To",
+    "Cc",
+    "Bcc",
+    "Reply-To",
+    "Subject",
+    "Date",
+    "Message-ID",
+    "In-Reply-To",
+    "References",
+    "MIME-Version",
+    "Content-Type",
+    "Content-Transfer-Encoding",
+    "Content-Disposition",
+];
+
+/// How urgent a message is to mail clients such as Outlook
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Priority {
+    High,
+    /// No priority headers at all
+    #[default]
+    Normal,
+    Low,
+}
+
+impl Priority {
+    /// `X-Priority` and `Importance` headers of the priority
+    pub(super) fn headers(&self) -> Vec<RawHeader> {
+        let (x_priority, importance) = match self {
+            Priority::High => ("1 (Highest)", "High"),
+            Priority::Normal => return Vec::new(),
+            Priority::Low => ("5 (Lowest)", "Low"),
+        };
+        vec![
+            RawHeader::new("X-Priority", x_priority),
+            RawHeader::new("Importance", importance),
+        ]
+    }
+}
+
+/// Where a read receipt is asked to be sent, through `Disposition-Notification-To` and the older
+/// `Return-Receipt-To`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadReceipt {
+    /// The configured sender
+    From,
+    /// The first configured reply-to address, the sender when there is none
+    ReplyTo,
+    To(Mailbox),
+}
+
+/// A mail for `Mailer::send_message`; create it with `MailMessage::builder()`
+#[derive(Debug, Clone)]
+pub struct MailMessage<'a> {
+    pub(super) to: Vec<Mailbox>,
+    pub(super) cc: Vec<Mailbox>,
+    pub(super) bcc: Vec<Mailbox>,
+    pub(super) subject: String,
+    /// Key of the subject in the subjects file of the language, replacing `subject`
+    pub(super) subject_key: Option<String>,
+    pub(super) vars: HashMap<String, String>,
+    /// Replaces `{name}` in `Config::template_name_format`
+    pub(super) template: Option<String>,
+    pub(super) plain_text: Option<String>,
+    pub(super) language: Option<String>,
+    pub(super) attachments: Vec<MailAttachment<'a>>,
+    pub(super) headers: Vec<RawHeader>,
+    pub(super) priority: Priority,
+    pub(super) read_receipt: Option<ReadReceipt>,
+    /// Rendered HTML body that replaces the template
+    pub(super) html: Option<String>,
+    /// Leave the configured footer out of the `html` body
+    pub(super) without_footer: bool,
+    pub(super) calendar: Option<IcsEvent>,
+    pub(super) unsubscribe_mailto: Option<Address>,
+    pub(super) unsubscribe_url: Option<String>,
+    /// Replaces `Config::envelope_from` for this message
+    pub(super) envelope_from: Option<Address>,
+    /// Replaces `Config::escape_contents` for this message
+    pub(super) escape_contents: Option<bool>,
+    /// Preview text clients show next to the subject
+    pub(super) preheader: Option<String>,
+    /// `Message-ID` of the message this one answers
+    pub(super) in_reply_to: Option<String>,
+    /// `Message-ID`s of the conversation so far, oldest first
+    pub(super) references: Vec<String>,
+    /// Data of the Handlebars templates
+    #[cfg(feature = "handlebars")]
+    pub(super) context: Option<Value>,
+}
+
+impl<'a> MailMessage<'a> {
+    /// Preferred way of creating a message, validated by `MailMessageBuilder::build`
+    pub fn builder() -> MailMessageBuilder<'a> {
+        MailMessageBuilder::default()
+    }
+
+    pub fn to(&self) -> &[Mailbox] {
+        &self.to
+    }
+
+    pub fn cc(&self) -> &[Mailbox] {
+        &self.cc
+    }
+
+    pub fn bcc(&self) -> &[Mailbox] {
+        &self.bcc
+    }
+
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    /// Message of the positional sends, which skipped the validation
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn from_parts(
+        to_addrs: Vec<Mailbox>,
+        cc_addrs: Option<Vec<Mailbox>>,
+        bcc_addrs: Option<Vec<Mailbox>>,
+        subject: &str,
+        vars: HashMap<String, String>,
+        plain_text: Option<&str>,
+        language: Option<&str>,
+        attachments: Option<Vec<MailAttachment<'a>>>,
+    ) -> Self {
+        MailMessage {
+            to: to_addrs,
+            cc: cc_addrs.unwrap_or_default(),
+            bcc: bcc_addrs.unwrap_or_default(),
+            subject: subject.to_owned(),
+            subject_key: None,
+            vars,
+            template: None,
+            plain_text: plain_text.map(|val| val.to_owned()),
+            language: language.map(|val| val.to_owned()),
+            attachments: attachments.unwrap_or_default(),
+            headers: Vec::new(),
+            priority: Priority::Normal,
+            read_receipt: None,
+            html: None,
+            without_footer: false,
+            calendar: None,
+            unsubscribe_mailto: None,
+            unsubscribe_url: None,
+            envelope_from: None,
+            escape_contents: None,
+            preheader: None,
+            in_reply_to: None,
+            references: Vec::new(),
+            #[cfg(feature = "handlebars")]
+            context: None,
+        }
+    }
+
+    /// Keeps the first of the mailboxes sharing an address, To before Cc before Bcc; the local
+    /// part is compared as is, the domain ignoring case
+    pub(super) fn dedupe_recipients(&mut self) {
+        let mut seen = HashSet::new();
+        for list in [&mut self.to, &mut self.cc, &mut self.bcc] {
+            list.retain(|mailbox| {
+                seen.insert((
+                    mailbox.email.user().to_owned(),
+                    mailbox.email.domain().to_lowercase(),
+                ))
+            });
+        }
+    }
+
+    /// `List-Unsubscribe`, and `List-Unsubscribe-Post` for one-click unsubscribing when there is
+    /// a URL (RFC 8058)
+    pub(super) fn unsubscribe_headers(&self) -> Vec<RawHeader> {
+        let targets: Vec<String> = self
+            .unsubscribe_mailto
+            .iter()
+            .map(|val| format!("<mailto:{}>", val))
+            .chain(self.unsubscribe_url.iter().map(|val| format!("<{}>", val)))
+            .collect();
+        let mut headers = Vec::new();
+        if !targets.is_empty() {
+            headers.push(RawHeader::new("List-Unsubscribe", &targets.join(", ")));
+        }
+        if self.unsubscribe_url.is_some() {
+            headers.push(RawHeader::new(
+                "List-Unsubscribe-Post",
+                "List-Unsubscribe=One-Click",
+            ));
+        }
+        headers
+    }
+}
+
+/// Fluent construction of a `MailMessage`; recipients and attachments add up with every call
+#[derive(Debug, Default)]
+pub struct MailMessageBuilder<'a> {
+    to: Vec<Mailbox>,
+    cc: Vec<Mailbox>,
+    bcc: Vec<Mailbox>,
+    subject: String,
+    subject_key: Option<String>,
+    vars: HashMap<String, String>,
+    template: Option<String>,
+    plain_text: Option<String>,
+    language: Option<String>,
+    attachments: Vec<MailAttachment<'a>>,
+    headers: Vec<(String, String)>,
+    priority: Priority,
+    read_receipt: Option<ReadReceipt>,
+    html: Option<String>,
+    without_footer: bool,
+    calendar: Option<IcsEvent>,
+    unsubscribe_mailto: Option<Address>,
+    unsubscribe_url: Option<String>,
+    envelope_from: Option<Address>,
+    escape_contents: Option<bool>,
+    preheader: Option<String>,
+    in_reply_to: Option<String>,
+    references: Vec<String>,
+    #[cfg(feature = "handlebars")]
+    context: Option<Result<Value, String>>,
+}
+
+impl<'a> MailMessageBuilder<'a> {
+    pub fn to(mut self, mailbox: Mailbox) -> Self {
+        self.to.push(mailbox);
+        self
+    }
+
+    pub fn cc(mut self, mailbox: Mailbox) -> Self {
+        self.cc.push(mailbox);
+        self
+    }
+
+    /// Recipient kept out of the headers, known to the server only
+    pub fn bcc(mut self, mailbox: Mailbox) -> Self {
+        self.bcc.push(mailbox);
+        self
+    }
+
+    pub fn subject(mut self, subject: impl Into<String>) -> Self {
+        self.subject = subject.into();
+        self
+    }
+
+    /// Subject looked up in the subjects file of the language, see `Config::subjects_name_format`;
+    /// replaces a subject given with `subject`
+    pub fn subject_key(mut self, key: impl Into<String>) -> Self {
+        self.subject_key = Some(key.into());
+        self
+    }
+
+    /// The message, put in the template's `contents` placeholder
+    pub fn body(self, message: impl Into<String>) -> Self {
+        self.var("contents", message)
+    }
+
+    /// Whether the body is HTML-escaped, overriding `Config::escape_contents`; pass `false` for a
+    /// body that is markup on purpose
+    pub fn escape_contents(mut self, enabled: bool) -> Self {
+        self.escape_contents = Some(enabled);
+        self
+    }
+
+    /// HTML body rendered elsewhere, used instead of the template; only the footer of the
+    /// language is added, see `without_footer`
+    pub fn html(mut self, html: impl Into<String>) -> Self {
+        self.html = Some(html.into());
+        self
+    }
+
+    /// Sends the `html` body without the configured footer, e.g. when it already carries one
+    pub fn without_footer(mut self) -> Self {
+        self.without_footer = true;
+        self
+    }
+
+    /// Preview text shown next to the subject in the inbox, put hidden at the start of the HTML
+    /// body and in front of the plain text
+    pub fn preheader(mut self, text: impl Into<String>) -> Self {
+        self.preheader = Some(text.into());
+        self
+    }
+
+    /// `Message-ID` of the message this one answers, e.g. `<a1b2@mail.example.com>` from
+    /// `SendReport::message_id`, so clients show both in one thread
+    pub fn in_reply_to(mut self, message_id: impl Into<String>) -> Self {
+        self.in_reply_to = Some(message_id.into());
+        self
+    }
+
+    /// `Message-ID` of an earlier message of the conversation, sent in `References`; call it for
+    /// each one, oldest first
+    pub fn reference(mut self, message_id: impl Into<String>) -> Self {
+        self.references.push(message_id.into());
+        self
+    }
+
+    /// Template of the message, e.g. `password_reset`, for a `Config::template_name_format` with
+    /// a `{name}`
+    pub fn template(mut self, name: impl Into<String>) -> Self {
+        self.template = Some(name.into());
+        self
+    }
+
+    /// Value of another `{{name}}` placeholder of the template
+    pub fn var(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.vars.insert(name.into(), value.into());
+        self
+    }
+
+    /// Plain-text alternative, derived from the HTML when not given
+    pub fn plain_text(mut self, text: impl Into<String>) -> Self {
+        self.plain_text = Some(text.into());
+        self
+    }
+
+    /// Template language, the configured default when not given
+    pub fn language(mut self, language: impl Into<String>) -> Self {
+        self.language = Some(language.into());
+        self
+    }
+
+    pub fn attachment(mut self, attachment: MailAttachment<'a>) -> Self {
+        self.attachments.push(attachment);
+        self
+    }
+
+    /// Extra header, e.g. `Auto-Submitted: auto-generated`; a later value of the same header
+    /// replaces the earlier one. The headers the mailer writes itself are refused.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Emits the `X-Priority` and `Importance` headers unless `Normal`, overriding custom headers
+    /// of the same names
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Asks the mail client of the recipient for a read receipt
+    pub fn read_receipt(mut self, receipt: ReadReceipt) -> Self {
+        self.read_receipt = Some(receipt);
+        self
+    }
+
+    /// Meeting invitation sent next to the HTML body, which mail clients offer to add to the
+    /// calendar
+    pub fn calendar(mut self, event: IcsEvent) -> Self {
+        self.calendar = Some(event);
+        self
+    }
+
+    /// Address receiving unsubscribe requests, sent in `List-Unsubscribe`
+    pub fn unsubscribe_mailto(mut self, address: Address) -> Self {
+        self.unsubscribe_mailto = Some(address);
+        self
+    }
+
+    /// HTTPS link that unsubscribes the recipient when opened or posted to; it also enables
+    /// one-click unsubscribing through `List-Unsubscribe-Post`
+    pub fn unsubscribe_url(mut self, url: impl Into<String>) -> Self {
+        self.unsubscribe_url = Some(url.into());
+        self
+    }
+
+    /// Bounce address of this message given in `MAIL FROM`, e.g. one per campaign
+    pub fn envelope_from(mut self, address: Address) -> Self {
+        self.envelope_from = Some(address);
+        self
+    }
+
+    /// Data the Handlebars templates are rendered with, e.g. the items of an order; the variables
+    /// are used when not given
+    #[cfg(feature = "handlebars")]
+    pub fn context(mut self, context: impl Serialize) -> Self {
+        self.context = Some(serde_json::to_value(context).map_err(|e| e.to_string()));
+        self
+    }
+
+    /// Validates the message, reporting every problem found
+    pub fn build(self) -> Result<MailMessage<'a>, ErrorReport> {
+        let mut problems = Vec::new();
+        if self.to.is_empty() && self.cc.is_empty() && self.bcc.is_empty() {
+            problems.push("no recipient".to_owned());
+        }
+        let subject_key = self
+            .subject_key
+            .map(|val| val.trim().to_owned())
+            .filter(|val| !val.is_empty());
+        if self.subject.trim().is_empty() && subject_key.is_none() {
+            problems.push("subject is empty".to_owned());
+        }
+        if let Some(event) = &self.calendar {
+            problems.extend(event.problems());
+        }
+        if let Some(name) = self.template.as_ref().filter(|val| !template::is_name(val)) {
+            problems.push(format!(
+                "template name '{}' may only have letters, digits, '-', '_' and '.' inside",
+                name
+            ));
+        }
+        let unsubscribe_url = self.unsubscribe_url.map(|val| val.trim().to_owned());
+        if let Some(url) = &unsubscribe_url {
+            if !url.starts_with("https://")
+                || url.len() == "https://".len()
+                || url.contains(|c: char| c.is_whitespace() || c == '<' || c == '>')
+            {
+                problems.push(format!("unsubscribe URL '{}' is not an https URL", url));
+            }
+        }
+        #[cfg(feature = "handlebars")]
+        let context = match self.context {
+            Some(Ok(context)) => Some(context),
+            Some(Err(e)) => {
+                problems.push(format!("render context could not be serialized: {}", e));
+                None
+            }
+            None => None,
+        };
+        let in_reply_to = self.in_reply_to.map(|val| val.trim().to_owned());
+        let references: Vec<String> = self
+            .references
+            .iter()
+            .map(|val| val.trim().to_owned())
+            .collect();
+        for id in in_reply_to.iter().chain(&references) {
+            if !is_message_id(id) {
+                problems.push(format!(
+                    "message id '{}' is not of the form <id@domain>",
+                    id
+                ));
+            }
+        }
+        let mut headers = Vec::new();
+        for (name, value) in self.headers {
+            match raw_header(&name, value) {
+                Ok(header) => headers.push(header),
+                Err(problem) => problems.push(problem),
+            }
+        }
+        if !problems.is_empty() {
+            return Err(ErrorReport::MailMessageInvalid { problems });
+        }
+        Ok(MailMessage {
+            to: self.to,
+            cc: self.cc,
+            bcc: self.bcc,
+            subject: self.subject,
+            subject_key,
+            vars: self.vars,
+            template: self.template,
+            plain_text: self.plain_text,
+            language: self.language,
+            attachments: self.attachments,
+            headers,
+            priority: self.priority,
+            read_receipt: self.read_receipt,
+            html: self.html,
+            without_footer: self.without_footer,
+            calendar: self.calendar,
+            unsubscribe_mailto: self.unsubscribe_mailto,
+            unsubscribe_url,
+            envelope_from: self.envelope_from,
+            escape_contents: self.escape_contents,
+            preheader: self.preheader.filter(|val| !val.trim().is_empty()),
+            in_reply_to,
+            references,
+            #[cfg(feature = "handlebars")]
+            context,
+        })
+    }
+}
+
+/// Whether the text is a `<left@right>` message identifier, as RFC 5322 writes them
+fn is_message_id(text: &str) -> bool {
+    let Some(id) = text.strip_prefix('<').and_then(|val| val.strip_suffix('>')) else {
+        return false;
+    };
+    let valid = |part: &str| {
+        !part.is_empty()
+            && part
+                .bytes()
+                .all(|b| b.is_ascii_graphic() && !matches!(b, b'<' | b'>' | b'@' | b'"' | b'\\'))
+    };
+    matches!(id.split_once('@'), Some((left, right)) if valid(left) && valid(right))
+}
+
+fn raw_header(name: &str, value: String) -> Result<RawHeader, String> {
+    let name = name.trim();
+    if name.is_empty()
+        || name.len() > 76
+        || !name.bytes().all(|b| b.is_ascii_graphic() && b != b':')
+    {
+        return Err(format!("header name '{}' is invalid", name));
+    }
+    if RESERVED_HEADERS
+        .iter()
+        .any(|val| val.eq_ignore_ascii_case(name))
+    {
+        return Err(format!("header '{}' is set by the mailer", name));
+    }
+    if value.contains(['\r', '\n']) {
+        return Err(format!("header '{}' value contains a line break", name));
+    }
+    let name = HeaderName::new_from_ascii(name.to_owned())
+        .map_err(|e| format!("header name '{}' is invalid: {}", name, e))?;
+    Ok(RawHeader(HeaderValue::new(name, value)))
+}
+
+/// A header whose name is only known at runtime; lettre takes headers by type, but files them
+/// under the name of the value they display
+#[derive(Debug, Clone)]
+pub(super) struct RawHeader(HeaderValue);
+
+impl RawHeader {
+    fn new(name: &'static str, value: &str) -> Self {
+        RawHeader(HeaderValue::new(
+            HeaderName::new_from_ascii_str(name),
+            value.to_owned(),
+        ))
+    }
+
+    /// Header holding a mailbox, its display name encoded as lettre does for `From`; encoding
+    /// the whole value as text would garble quoted names that are not ASCII
+    pub(super) fn mailbox(name: &'static str, mailbox: &Mailbox) -> Self {
+        let mut headers = Headers::new();
+        headers.set(header::Sender::from(mailbox.clone()));
+        let encoded = headers.to_string();
+        let encoded = encoded
+            .trim_start_matches("Sender: ")
+            .trim_end_matches("\r\n");
+        RawHeader(HeaderValue::dangerous_new_pre_encoded(
+            HeaderName::new_from_ascii_str(name),
+            mailbox.to_string(),
+            encoded.to_owned(),
+        ))
+    }
+}
+
+impl Header for RawHeader {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("X-Raw-Header")
+    }
+
+    fn parse(s: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Ok(RawHeader(HeaderValue::new(Self::name(), s.to_owned())))
+    }
+
+    fn display(&self) -> HeaderValue {
+        self.0.clone()
+    }
+}
@@ -0,0 +1,143 @@
+//! Counters of the sends of a mailer and its clones, for export to a metrics system
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Upper bounds of the latency buckets of `MetricsSnapshot::latency_buckets`; the last bucket
+/// counts the sends slower than all of them
+pub const LATENCY_BUCKETS: [Duration; 6] = [
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_secs(5),
+    Duration::from_secs(30),
+];
+
+/// Copy of the counters of a mailer at one point in time, see `Mailer::metrics`. A message split
+/// over several by `Config::max_recipients_per_message` counts once per part.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MetricsSnapshot {
+    /// Messages the server accepted
+    pub sent: u64,
+    /// Messages that could not be built or were not accepted, after any retries
+    pub failed: u64,
+    /// Retries of sends that failed transiently, as the retry policy allows
+    pub retried: u64,
+    /// Time of all the sends, failed ones included, from building the message to the answer
+    pub latency_total: Duration,
+    /// Fastest send, zero before the first one
+    pub latency_min: Duration,
+    /// Slowest send
+    pub latency_max: Duration,
+    /// Sends per bucket of `LATENCY_BUCKETS`, not cumulative, with the slower ones last
+    pub latency_buckets: [u64; LATENCY_BUCKETS.len() + 1],
+}
+
+impl MetricsSnapshot {
+    /// Sends finished, sent or failed
+    pub fn count(&self) -> u64 {
+        self.sent + self.failed
+    }
+
+    /// Mean time of a send, zero before the first one
+    pub fn latency_avg(&self) -> Duration {
+        match self.count() {
+            0 => Duration::ZERO,
+            count => Duration::from_micros((self.latency_total.as_micros() / count as u128) as u64),
+        }
+    }
+}
+
+/// Thread-safe counters updated by the send paths
+#[derive(Debug)]
+pub(super) struct MailerMetrics {
+    sent: AtomicU64,
+    failed: AtomicU64,
+    retried: AtomicU64,
+    total_micros: AtomicU64,
+    min_micros: AtomicU64,
+    max_micros: AtomicU64,
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+}
+
+impl Default for MailerMetrics {
+    fn default() -> Self {
+        MailerMetrics {
+            sent: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            retried: AtomicU64::new(0),
+            total_micros: AtomicU64::new(0),
+            min_micros: AtomicU64::new(u64::MAX),
+            max_micros: AtomicU64::new(0),
+            buckets: Default::default(),
+        }
+    }
+}
+
+impl MailerMetrics {
+    /// Counts a finished send and its latency
+    pub fn record(&self, sent: bool, latency: Duration) {
+        let counter = match sent {
+            true => &self.sent,
+            false => &self.failed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        self.total_micros.fetch_add(micros, Ordering::Relaxed);
+        self.min_micros.fetch_min(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| latency <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn retried(&self) {
+        self.retried.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The counters are read one by one, so a snapshot taken during a send may be a count apart
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let load = |val: &AtomicU64| val.load(Ordering::Relaxed);
+        let min = load(&self.min_micros);
+        MetricsSnapshot {
+            sent: load(&self.sent),
+            failed: load(&self.failed),
+            retried: load(&self.retried),
+            latency_total: Duration::from_micros(load(&self.total_micros)),
+            latency_min: Duration::from_micros(if min == u64::MAX { 0 } else { min }),
+            latency_max: Duration::from_micros(load(&self.max_micros)),
+            latency_buckets: std::array::from_fn(|idx| load(&self.buckets[idx])),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MailerMetrics, MetricsSnapshot};
+    use std::time::Duration;
+
+    #[test]
+    fn latencies() {
+        let metrics = MailerMetrics::default();
+        assert_eq!(metrics.snapshot(), MetricsSnapshot::default());
+
+        metrics.record(true, Duration::from_millis(50));
+        metrics.record(true, Duration::from_millis(300));
+        metrics.record(false, Duration::from_secs(60));
+        metrics.retried();
+        let snapshot = metrics.snapshot();
+        assert_eq!(
+            (snapshot.sent, snapshot.failed, snapshot.retried),
+            (2, 1, 1)
+        );
+        assert_eq!(snapshot.count(), 3);
+        assert_eq!(snapshot.latency_min, Duration::from_millis(50));
+        assert_eq!(snapshot.latency_max, Duration::from_secs(60));
+        assert_eq!(snapshot.latency_avg(), Duration::from_micros(20_116_666));
+        assert_eq!(snapshot.latency_buckets, [1, 0, 1, 0, 0, 0, 1]);
+    }
+}
@@ -0,0 +1,105 @@
+use std::path::Path;
+
+/// Content type for an attachment, by the extension of its file name
+pub fn from_name(name: &str) -> &'static str {
+    let extension = Path::new(name)
+        .extension()
+        .and_then(|val| val.to_str())
+        .map(|val| val.to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "pdf" => "application/pdf",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "csv" => "text/csv",
+        "txt" | "log" => "text/plain",
+        "html" | "htm" => "text/html",
+        "xml" => "application/xml",
+        "json" => "application/json",
+        "zip" => "application/zip",
+        "ics" => "text/calendar",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xls" => "application/vnd.ms-excel",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Content type with the file name as `name` parameter, which clients that ignore the RFC 2231
+/// name of `Content-Disposition` show instead; a non-ASCII name goes in RFC 2047 encoded-words
+pub fn with_name(content_type: &str, name: &str) -> String {
+    let name = match name.is_ascii() {
+        true => name.to_owned(),
+        false => encoded_words(name),
+    };
+    format!("{}; name=\"{}\"", content_type, name)
+}
+
+/// Q-encoded words of at most 75 characters, a character never split between two of them
+fn encoded_words(text: &str) -> String {
+    const OPEN: &str = "=?utf-8?q?";
+    let mut words = vec![String::from(OPEN)];
+    let mut buf = [0; 4];
+    for c in text.chars() {
+        let piece: String = match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '!' | '*' | '+' => c.to_string(),
+            ' ' => "_".to_owned(),
+            _ => c
+                .encode_utf8(&mut buf)
+                .bytes()
+                .map(|val| format!("={:02X}", val))
+                .collect(),
+        };
+        let word = words.last_mut().unwrap();
+        if word.len() > OPEN.len() && word.len() + piece.len() + 2 > 75 {
+            word.push_str("?=");
+            words.push(String::from(OPEN));
+        }
+        words.last_mut().unwrap().push_str(&piece);
+    }
+    words.last_mut().unwrap().push_str("?=");
+    words.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_name, with_name};
+
+    #[test]
+    fn extensions() {
+        assert_eq!(from_name("invoice.pdf"), "application/pdf");
+        assert_eq!(from_name("/tmp/logo.PNG"), "image/png");
+        assert_eq!(from_name("photo.jpeg"), "image/jpeg");
+        assert_eq!(from_name("report.csv"), "text/csv");
+        assert_eq!(from_name("archive.tar.zip"), "application/zip");
+        assert_eq!(
+            from_name("sheet.xlsx"),
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+        );
+        assert_eq!(from_name("data.bin"), "application/octet-stream");
+        assert_eq!(from_name("README"), "application/octet-stream");
+    }
+
+    #[test]
+    fn name_parameter() {
+        assert_eq!(
+            with_name("application/pdf", "Invoice 42.pdf"),
+            "application/pdf; name=\"Invoice 42.pdf\""
+        );
+        assert_eq!(
+            with_name("application/pdf", "Rechnungsübersicht 2024.pdf"),
+            "application/pdf; name=\"=?utf-8?q?Rechnungs=C3=BCbersicht_2024.pdf?=\""
+        );
+        let long = with_name("text/plain", &"ü".repeat(30));
+        let words: Vec<&str> = long["text/plain; name=\"".len()..long.len() - 1]
+            .split(' ')
+            .collect();
+        assert_eq!(words.len(), 3);
+        assert!(words
+            .iter()
+            .all(|val| val.len() <= 75 && val.ends_with("?=")));
+    }
+}
@@ -0,0 +1,279 @@
+//! Fire-and-forget sending: messages queued by request handlers are delivered in order by a
+//! worker thread, over the transport the mailer keeps between sends
+use super::{MailMessage, Mailer};
+use crate::error::ErrorReport;
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+/// Identifies a queued message in the failures reported by the worker
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct QueueTicket(u64);
+
+impl QueueTicket {
+    /// Tickets are numbered from 1 in the order the messages were queued
+    pub fn id(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for QueueTicket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// What `MailQueue::enqueue` does when the queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backpressure {
+    /// Wait until the worker takes a message
+    #[default]
+    Block,
+    /// Fail with `MailQueueFull` right away
+    Reject,
+}
+
+type Job = (QueueTicket, MailMessage<'static>);
+
+/// Messages delivered in the background by a worker thread with the mailer's retry policy;
+/// the messages still failing are reported on the channel returned by `start`.
+///
+/// Dropping the queue lets the worker send what is left and exit; `shutdown` waits for it.
+pub struct MailQueue {
+    sender: Mutex<Option<SyncSender<Job>>>,
+    backpressure: Backpressure,
+    capacity: usize,
+    next_ticket: AtomicU64,
+    pending: Arc<AtomicUsize>,
+    done: Mutex<Receiver<()>>,
+}
+
+impl MailQueue {
+    /// Starts the worker; at most `capacity` messages wait besides the one being sent, at least 1
+    pub fn start(
+        mailer: Mailer,
+        capacity: usize,
+        backpressure: Backpressure,
+    ) -> Result<(Self, Receiver<(QueueTicket, ErrorReport)>), ErrorReport> {
+        let capacity = capacity.max(1);
+        let (sender, jobs) = mpsc::sync_channel::<Job>(capacity);
+        let (failed, failures) = mpsc::channel();
+        let (finished, done) = mpsc::channel();
+        let pending = Arc::new(AtomicUsize::new(0));
+
+        let worker_pending = pending.clone();
+        thread::Builder::new()
+            .name("mail-queue".to_owned())
+            .spawn(move || {
+                for (ticket, message) in jobs {
+                    if let Err(e) = mailer.send_message(message) {
+                        // the mailer logged the error already
+                        log::error!("Queued mail {} was not sent", ticket);
+                        // nobody listening is not a reason to stop sending
+                        let _ = failed.send((ticket, e));
+                    }
+                    worker_pending.fetch_sub(1, Ordering::SeqCst);
+                }
+                let _ = finished.send(());
+            })?;
+
+        let queue = MailQueue {
+            sender: Mutex::new(Some(sender)),
+            backpressure,
+            capacity,
+            next_ticket: AtomicU64::new(1),
+            pending,
+            done: Mutex::new(done),
+        };
+        Ok((queue, failures))
+    }
+
+    /// Queues the message for the worker; fails with `MailQueueFull` when the queue is full and
+    /// rejects, and with `MailQueueClosed` after `shutdown`
+    pub fn enqueue(&self, message: MailMessage<'static>) -> Result<QueueTicket, ErrorReport> {
+        let sender = match self.sender.lock().unwrap().as_ref() {
+            Some(val) => val.clone(),
+            None => return Err(ErrorReport::MailQueueClosed),
+        };
+        let ticket = QueueTicket(self.next_ticket.fetch_add(1, Ordering::SeqCst));
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        let queued = match self.backpressure {
+            Backpressure::Block => sender
+                .send((ticket, message))
+                .map_err(|_| ErrorReport::MailQueueClosed),
+            Backpressure::Reject => sender.try_send((ticket, message)).map_err(|e| match e {
+                TrySendError::Full(_) => ErrorReport::MailQueueFull {
+                    capacity: self.capacity,
+                },
+                TrySendError::Disconnected(_) => ErrorReport::MailQueueClosed,
+            }),
+        };
+        if queued.is_err() {
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+        }
+        queued.map(|_| ticket)
+    }
+
+    /// Messages queued and not handled yet, the one being sent included
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    /// Stops taking messages and waits for the worker to send the queued ones; on timeout the
+    /// worker keeps going in the background and `MailQueueTimeout` tells how many are left
+    pub fn shutdown(&self, timeout: Duration) -> Result<(), ErrorReport> {
+        self.sender.lock().unwrap().take();
+        match self.done.lock().unwrap().recv_timeout(timeout) {
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => Ok(()),
+            Err(RecvTimeoutError::Timeout) => Err(ErrorReport::MailQueueTimeout {
+                pending: self.pending(),
+                timeout,
+            }),
+        }
+    }
+}
+
+impl fmt::Debug for MailQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MailQueue")
+            .field("capacity", &self.capacity)
+            .field("backpressure", &self.backpressure)
+            .field("pending", &self.pending())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backpressure, MailQueue, QueueTicket};
+    use crate::{
+        error::ErrorReport,
+        mailer::{testing::MockTransport, Config, MailMessage, MailTransport, Mailer, RetryPolicy},
+    };
+    use lettre::{transport::smtp::response::Response, Message};
+    use std::{
+        sync::{mpsc, Arc, Mutex},
+        time::Duration,
+    };
+
+    fn mailer(transport: Arc<dyn MailTransport>) -> Mailer {
+        let config = Config::builder()
+            .from_addrs("noreply@example.com")
+            .server("localhost")
+            .retry_policy(RetryPolicy::new(2, Duration::from_millis(1), 1.0))
+            .build()
+            .unwrap();
+        Mailer::init_with_transport(config, transport)
+    }
+
+    fn message(subject: &str) -> MailMessage<'static> {
+        MailMessage::builder()
+            .to("ana@example.com".parse().unwrap())
+            .subject(subject)
+            .html("<p>Body</p>")
+            .build()
+            .unwrap()
+    }
+
+    fn subject(message: &Message) -> String {
+        let formatted = String::from_utf8(message.formatted()).unwrap();
+        let start = formatted.find("Subject: ").unwrap() + "Subject: ".len();
+        formatted[start..start + formatted[start..].find("\r\n").unwrap()].to_owned()
+    }
+
+    #[test]
+    fn ordering_retries_and_shutdown() {
+        let mock = Arc::new(MockTransport::new());
+        // the first message goes through on its second retry
+        mock.fail_next(421);
+        mock.fail_next(451);
+        let (queue, failures) =
+            MailQueue::start(mailer(mock.clone()), 10, Backpressure::Block).unwrap();
+
+        let mut tickets = Vec::new();
+        for name in ["one", "two"] {
+            tickets.push(queue.enqueue(message(name)).unwrap());
+        }
+        queue.shutdown(Duration::from_secs(5)).unwrap();
+        assert_eq!(queue.pending(), 0);
+        assert_eq!(tickets, vec![QueueTicket(1), QueueTicket(2)]);
+        assert_eq!(mock.attempts(), 4);
+        let sent: Vec<String> = mock.sent().iter().map(subject).collect();
+        assert_eq!(sent, vec!["one", "two"]);
+        assert!(failures.try_recv().is_err());
+
+        assert!(matches!(
+            queue.enqueue(message("late")),
+            Err(ErrorReport::MailQueueClosed)
+        ));
+        queue.shutdown(Duration::from_secs(1)).unwrap();
+
+        let mock = Arc::new(MockTransport::new());
+        mock.fail_with(Some(554));
+        let (queue, failures) =
+            MailQueue::start(mailer(mock.clone()), 10, Backpressure::Block).unwrap();
+        let ticket = queue.enqueue(message("refused")).unwrap();
+        queue.shutdown(Duration::from_secs(5)).unwrap();
+        let (failed, error) = failures.recv().unwrap();
+        assert_eq!(failed, ticket);
+        assert!(
+            matches!(error, ErrorReport::MailSentResponse { code: 554, .. }),
+            "{:?}",
+            error
+        );
+    }
+
+    /// Holds every send until the gate opens
+    struct GatedTransport {
+        entered: Mutex<mpsc::Sender<()>>,
+        gate: Mutex<mpsc::Receiver<()>>,
+        mock: MockTransport,
+    }
+
+    impl MailTransport for GatedTransport {
+        fn send(&self, message: &Message) -> Result<Response, ErrorReport> {
+            self.entered.lock().unwrap().send(()).unwrap();
+            let _ = self.gate.lock().unwrap().recv();
+            self.mock.send(message)
+        }
+    }
+
+    #[test]
+    fn backpressure_and_timeout() {
+        let (entered_tx, entered) = mpsc::channel();
+        let (open, gate) = mpsc::channel();
+        let transport = Arc::new(GatedTransport {
+            entered: Mutex::new(entered_tx),
+            gate: Mutex::new(gate),
+            mock: MockTransport::new(),
+        });
+        let (queue, _failures) =
+            MailQueue::start(mailer(transport.clone()), 1, Backpressure::Reject).unwrap();
+
+        queue.enqueue(message("one")).unwrap();
+        entered.recv().unwrap();
+        queue.enqueue(message("two")).unwrap();
+        assert!(matches!(
+            queue.enqueue(message("three")),
+            Err(ErrorReport::MailQueueFull { capacity: 1 })
+        ));
+        assert_eq!(queue.pending(), 2);
+
+        match queue.shutdown(Duration::from_millis(50)) {
+            Err(ErrorReport::MailQueueTimeout { pending: 2, .. }) => {}
+            other => panic!("Expected a shutdown timeout, got {:?}", other),
+        }
+        open.send(()).unwrap();
+        open.send(()).unwrap();
+        queue.shutdown(Duration::from_secs(5)).unwrap();
+        let sent: Vec<String> = transport.mock.sent().iter().map(subject).collect();
+        assert_eq!(sent, vec!["one", "two"]);
+    }
+}
@@ -0,0 +1,160 @@
+use crate::error::ErrorReport;
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Most messages sent within a sliding window, e.g. 100 per minute as SMTP providers allow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub max_messages: u32,
+    pub per: Duration,
+    /// Wait for a free slot when the budget is spent; otherwise fail with `MailRateLimited`
+    pub blocking: bool,
+}
+
+impl RateLimit {
+    /// Blocking limit, see `non_blocking`
+    pub fn new(max_messages: u32, per: Duration) -> Self {
+        RateLimit {
+            max_messages,
+            per,
+            blocking: true,
+        }
+    }
+
+    /// Fails the sends over the budget right away instead of waiting
+    pub fn non_blocking(mut self) -> Self {
+        self.blocking = false;
+        self
+    }
+
+    pub(super) fn problem(&self) -> Option<String> {
+        match self.max_messages > 0 && !self.per.is_zero() {
+            true => None,
+            false => Some(
+                "rate limit must allow at least 1 message in a window longer than 0".to_owned(),
+            ),
+        }
+    }
+}
+
+/// Send times within the window, shared by the clones of a mailer and the threads using them
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    sent: Mutex<VecDeque<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        RateLimiter {
+            limit,
+            sent: Mutex::new(VecDeque::with_capacity(limit.max_messages as usize)),
+        }
+    }
+
+    /// Takes a slot now, or tells how long until one frees
+    pub fn try_acquire(&self) -> Result<(), Duration> {
+        self.try_acquire_at(Instant::now())
+    }
+
+    fn try_acquire_at(&self, now: Instant) -> Result<(), Duration> {
+        let mut sent = self.sent.lock().unwrap();
+        while sent
+            .front()
+            .is_some_and(|val| now.duration_since(*val) >= self.limit.per)
+        {
+            sent.pop_front();
+        }
+        match sent.front() {
+            Some(oldest) if sent.len() >= self.limit.max_messages as usize => {
+                Err(self.limit.per - now.duration_since(*oldest))
+            }
+            _ => {
+                sent.push_back(now);
+                Ok(())
+            }
+        }
+    }
+
+    /// Takes a slot, sleeping until one frees when the limit is blocking
+    pub fn acquire(&self) -> Result<(), ErrorReport> {
+        loop {
+            match self.try_acquire() {
+                Ok(()) => return Ok(()),
+                Err(wait) if self.limit.blocking => std::thread::sleep(wait),
+                Err(wait) => return Err(self.limited(wait)),
+            }
+        }
+    }
+
+    /// Same as `acquire`, without blocking the runtime while waiting
+    #[cfg(feature = "async")]
+    pub async fn acquire_async(&self) -> Result<(), ErrorReport> {
+        loop {
+            match self.try_acquire() {
+                Ok(()) => return Ok(()),
+                Err(wait) if self.limit.blocking => tokio::time::sleep(wait).await,
+                Err(wait) => return Err(self.limited(wait)),
+            }
+        }
+    }
+
+    fn limited(&self, wait: Duration) -> ErrorReport {
+        ErrorReport::MailRateLimited {
+            max_messages: self.limit.max_messages,
+            per: self.limit.per,
+            retry_after: wait,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimit, RateLimiter};
+    use crate::error::ErrorReport;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn sliding_window() {
+        let limiter = RateLimiter::new(RateLimit::new(2, Duration::from_secs(60)));
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        assert_eq!(limiter.try_acquire_at(at(0)), Ok(()));
+        assert_eq!(limiter.try_acquire_at(at(10)), Ok(()));
+        assert_eq!(limiter.try_acquire_at(at(20)), Err(Duration::from_secs(40)));
+        // the first slot frees a minute after it was taken, the second ten seconds later
+        assert_eq!(limiter.try_acquire_at(at(60)), Ok(()));
+        assert_eq!(limiter.try_acquire_at(at(65)), Err(Duration::from_secs(5)));
+        assert_eq!(limiter.try_acquire_at(at(70)), Ok(()));
+
+        assert!(RateLimit::new(0, Duration::from_secs(1))
+            .problem()
+            .is_some());
+        assert!(RateLimit::new(1, Duration::ZERO).problem().is_some());
+    }
+
+    #[test]
+    fn blocking_and_failing() {
+        let window = Duration::from_millis(100);
+        let limiter = RateLimiter::new(RateLimit::new(1, window));
+        let started = Instant::now();
+        limiter.acquire().unwrap();
+        limiter.acquire().unwrap();
+        assert!(started.elapsed() >= window, "Took {:?}", started.elapsed());
+
+        let limiter = RateLimiter::new(RateLimit::new(1, window).non_blocking());
+        limiter.acquire().unwrap();
+        match limiter.acquire() {
+            Err(ErrorReport::MailRateLimited {
+                max_messages: 1,
+                retry_after,
+                ..
+            }) => assert!(retry_after <= window),
+            other => panic!("Expected a rate limited send, got {:?}", other),
+        }
+    }
+}
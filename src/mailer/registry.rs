@@ -0,0 +1,280 @@
+use super::{Config, Mailer};
+use crate::{envars::AppConfig, error::ErrorReport};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+type Builder = Box<dyn Fn(&str, Config) -> Result<Mailer, ErrorReport> + Send + Sync>;
+type Slot = Arc<Mutex<Option<Arc<Mailer>>>>;
+
+/// One `Mailer` per tenant, read from the `MAIL:*` keys of its config section, e.g.
+/// `TENANT:acme:MAIL:SMTP_SERVER` for the key pattern `TENANT:{id}:`.
+///
+/// Mailers are built on first use and cached; concurrent first requests for the same tenant wait
+/// for a single build, other tenants are not blocked meanwhile.
+pub struct MailerRegistry {
+    app_config: AppConfig,
+    key_pattern: String,
+    builder: Builder,
+    mailers: Mutex<HashMap<String, Slot>>,
+}
+
+impl fmt::Debug for MailerRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MailerRegistry")
+            .field("key_pattern", &self.key_pattern)
+            .field("tenants", &self.mailers.lock().unwrap().len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl MailerRegistry {
+    const ID_PLACEHOLDER: &'static str = "{id}";
+
+    /// `key_pattern` is the prefix of the tenant keys, with `{id}` standing for the tenant id
+    pub fn new(app_config: AppConfig, key_pattern: &str) -> Result<Self, ErrorReport> {
+        Self::with_builder(app_config, key_pattern, |_, config| {
+            Ok(Mailer::init(config))
+        })
+    }
+
+    /// Same as `new`, building the mailers with the given function, e.g. to add hooks
+    pub fn with_builder(
+        app_config: AppConfig,
+        key_pattern: &str,
+        builder: impl Fn(&str, Config) -> Result<Mailer, ErrorReport> + Send + Sync + 'static,
+    ) -> Result<Self, ErrorReport> {
+        if key_pattern.matches(Self::ID_PLACEHOLDER).count() != 1 {
+            return Err(ErrorReport::ConfigInvalidValue {
+                key: key_pattern.to_owned(),
+                msg: "the tenant key pattern must contain {id} once".to_owned(),
+            });
+        }
+        Ok(MailerRegistry {
+            app_config,
+            key_pattern: key_pattern.to_owned(),
+            builder: Box::new(builder),
+            mailers: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Ids of the tenants that have keys in the config, sorted
+    pub fn tenant_ids(&self) -> Vec<String> {
+        let (before, after) = self
+            .key_pattern
+            .split_once(Self::ID_PLACEHOLDER)
+            .unwrap_or_default();
+        let marker = format!("{}MAIL:", after);
+        let mut ids: Vec<String> = self
+            .app_config
+            .keys()
+            .filter_map(|key| key.strip_prefix(before))
+            .filter_map(|rest| rest.find(&marker).map(|end| &rest[..end]))
+            .filter(|id| !id.is_empty())
+            .map(|id| id.to_owned())
+            .collect();
+        ids.sort();
+        ids.dedup();
+        ids
+    }
+
+    /// Mailer of the tenant, built on first use
+    pub fn get(&self, tenant_id: &str) -> Result<Arc<Mailer>, ErrorReport> {
+        let slot = self
+            .mailers
+            .lock()
+            .unwrap()
+            .entry(tenant_id.to_owned())
+            .or_default()
+            .clone();
+
+        let mut mailer = slot.lock().unwrap();
+        if let Some(mailer) = mailer.as_ref() {
+            return Ok(mailer.clone());
+        }
+        let built = match self.build(tenant_id) {
+            Ok(built) => Arc::new(built),
+            Err(e) => {
+                drop(mailer);
+                self.forget(tenant_id, &slot);
+                return Err(e);
+            }
+        };
+        *mailer = Some(built.clone());
+        Ok(built)
+    }
+
+    /// Removes the slot a failed build left empty, so unknown tenants are not kept; a slot
+    /// another thread is building in is left to it
+    fn forget(&self, tenant_id: &str, slot: &Slot) {
+        let mut mailers = self.mailers.lock().unwrap();
+        let empty = matches!(slot.try_lock().as_deref(), Ok(None));
+        if empty
+            && mailers
+                .get(tenant_id)
+                .is_some_and(|val| Arc::ptr_eq(val, slot))
+        {
+            mailers.remove(tenant_id);
+        }
+    }
+
+    /// Drops the cached mailer of the tenant, the next `get` builds a new one, e.g. after its
+    /// credentials were rotated
+    pub fn invalidate(&self, tenant_id: &str) {
+        if let Some(slot) = self.mailers.lock().unwrap().remove(tenant_id) {
+            if let Some(mailer) = slot.lock().unwrap().take() {
+                mailer.disconnect();
+            }
+        }
+    }
+
+    fn build(&self, tenant_id: &str) -> Result<Mailer, ErrorReport> {
+        let tenant_error = |msg: String| ErrorReport::ConfigTenant {
+            tenant: tenant_id.to_owned(),
+            msg,
+        };
+        let prefix = self.key_pattern.replace(Self::ID_PLACEHOLDER, tenant_id);
+        let section = format!("{}MAIL:", prefix);
+        if !self.app_config.keys().any(|key| key.starts_with(&section)) {
+            return Err(tenant_error(format!("no {}* keys in the config", section)));
+        }
+        let config = Config::from_app_config_section(&self.app_config, &prefix)
+            .map_err(|e| tenant_error(e.to_string()))?;
+        (self.builder)(tenant_id, config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MailerRegistry;
+    use crate::{
+        envars::{AppConfig, CONFIG_FILE_DELIMITER},
+        error::ErrorReport,
+        mailer::Mailer,
+    };
+    use std::{
+        path::Path,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Barrier,
+        },
+        thread,
+        time::Duration,
+    };
+
+    fn registry(builds: Arc<AtomicUsize>) -> MailerRegistry {
+        let root_dir = Path::new(env!("WORKSPACE_ROOT_PATH"));
+        let app_config = AppConfig::init_with_root(
+            &root_dir.join("assets/test/config_tenants.txt"),
+            CONFIG_FILE_DELIMITER,
+            root_dir,
+        )
+        .unwrap();
+        MailerRegistry::with_builder(app_config, "TENANT:{id}:", move |_, config| {
+            builds.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20));
+            Ok(Mailer::init(config))
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn tenants() {
+        let builds = Arc::new(AtomicUsize::new(0));
+        let registry = registry(builds.clone());
+        assert_eq!(
+            registry.tenant_ids(),
+            vec!["acme", "broken", "globex", "initech"]
+        );
+
+        let acme = registry.get("acme").unwrap();
+        assert_eq!(acme.get_config().server, "smtp.acme.example");
+        assert_eq!(
+            acme.get_config().from_addrs.to_string(),
+            "Acme <mail@acme.example>"
+        );
+        assert_eq!(
+            acme.get_config().reply_to[0].to_string(),
+            "Acme <support@acme.example>"
+        );
+        let globex = registry.get("globex").unwrap();
+        assert_eq!(globex.get_config().port, 465);
+        assert!(globex.get_config().reply_to.is_empty());
+        assert_eq!(
+            registry.get("initech").unwrap().get_config().languages,
+            vec!["en", "de"]
+        );
+        assert!(Arc::ptr_eq(&acme, &registry.get("acme").unwrap()));
+        assert_eq!(builds.load(Ordering::SeqCst), 3);
+
+        registry.invalidate("acme");
+        assert!(!Arc::ptr_eq(&acme, &registry.get("acme").unwrap()));
+        assert_eq!(builds.load(Ordering::SeqCst), 4);
+
+        match registry.get("umbrella") {
+            Err(ErrorReport::ConfigTenant { tenant, msg }) => {
+                assert_eq!(tenant, "umbrella");
+                assert!(msg.contains("TENANT:umbrella:MAIL:"));
+            }
+            other => panic!("Expected unknown tenant error, got {:?}", other),
+        }
+        match registry.get("broken") {
+            Err(ErrorReport::ConfigTenant { tenant, msg }) => {
+                assert_eq!(tenant, "broken");
+                assert!(msg.contains("TENANT:broken:MAIL:SMTP_PORT"), "{}", msg);
+            }
+            other => panic!("Expected malformed tenant error, got {:?}", other),
+        }
+        let mut cached: Vec<String> = registry.mailers.lock().unwrap().keys().cloned().collect();
+        cached.sort();
+        assert_eq!(cached, vec!["acme", "globex", "initech"]);
+
+        assert!(matches!(
+            MailerRegistry::new(
+                AppConfig::init(
+                    &Path::new(env!("WORKSPACE_ROOT_PATH"))
+                        .join("assets/test/config_healthcheck.txt"),
+                    CONFIG_FILE_DELIMITER
+                )
+                .unwrap(),
+                "TENANT:"
+            ),
+            Err(ErrorReport::ConfigInvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn concurrent_first_use() {
+        let builds = Arc::new(AtomicUsize::new(0));
+        let registry = Arc::new(registry(builds.clone()));
+        let barrier = Arc::new(Barrier::new(8));
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let registry = registry.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    let tenant = if i % 2 == 0 { "acme" } else { "globex" };
+                    registry.get(tenant).unwrap()
+                })
+            })
+            .collect();
+        let mailers: Vec<_> = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect();
+
+        assert_eq!(builds.load(Ordering::SeqCst), 2);
+        assert!(mailers
+            .iter()
+            .step_by(2)
+            .all(|val| Arc::ptr_eq(val, &mailers[0])));
+        assert!(mailers
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .all(|val| Arc::ptr_eq(val, &mailers[1])));
+    }
+}
@@ -0,0 +1,227 @@
+use crate::error::ErrorReport;
+use std::{sync::Arc, thread, time::Duration};
+
+/// How often and how late a send is retried after a transient failure: a 4xx answer of the
+/// server or a lost connection. Permanent failures are never retried.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt, 0 disables retrying
+    pub max_retries: u32,
+    /// Wait before the first retry
+    pub initial_delay: Duration,
+    /// Factor applied to the wait before every further retry
+    pub multiplier: f64,
+    /// Longest wait before a retry, `None` for no limit
+    pub max_delay: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 0,
+            initial_delay: Duration::from_secs(1),
+            multiplier: 2.0,
+            max_delay: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32, initial_delay: Duration, multiplier: f64) -> Self {
+        RetryPolicy {
+            max_retries,
+            initial_delay,
+            multiplier,
+            max_delay: None,
+        }
+    }
+
+    /// Caps the wait before a retry
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = Some(max_delay);
+        self
+    }
+
+    /// Wait before the given retry, counted from 0; `Duration::MAX` once it no longer fits,
+    /// unless capped by `max_delay`
+    pub fn delay(&self, retry: u32) -> Duration {
+        let delay = match self.initial_delay.is_zero() {
+            true => Duration::ZERO,
+            false => Duration::try_from_secs_f64(
+                self.initial_delay.as_secs_f64()
+                    * self.multiplier.powi(retry.min(i32::MAX as u32) as i32),
+            )
+            .unwrap_or(Duration::MAX),
+        };
+        self.max_delay.map_or(delay, |max| delay.min(max))
+    }
+
+    pub(super) fn problem(&self) -> Option<String> {
+        match self.multiplier.is_finite() && self.multiplier >= 1.0 {
+            true => None,
+            false => Some(format!(
+                "retry multiplier {} must be a number of at least 1",
+                self.multiplier
+            )),
+        }
+    }
+}
+
+/// Waits between retries; `thread::sleep` unless replaced with `Mailer::with_sleep`
+pub type Sleep = Arc<dyn Fn(Duration) + Send + Sync>;
+
+pub fn thread_sleep() -> Sleep {
+    Arc::new(thread::sleep)
+}
+
+/// Retry bookkeeping of one send
+pub struct Retries {
+    policy: RetryPolicy,
+    attempts: u32,
+    retries: u32,
+}
+
+impl Retries {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Retries {
+            policy,
+            attempts: 0,
+            retries: 0,
+        }
+    }
+
+    /// Counts an attempt that is about to be made
+    pub fn attempt(&mut self) {
+        self.attempts += 1;
+    }
+
+    /// Wait before the next retry, `None` when the failure is permanent or the retries are
+    /// used up
+    pub fn next_delay(&mut self, transient: bool) -> Option<Duration> {
+        if !transient || self.retries >= self.policy.max_retries {
+            return None;
+        }
+        self.retries += 1;
+        Some(self.policy.delay(self.retries - 1))
+    }
+
+    /// The error of the last attempt, with the number of attempts when the policy retried; an
+    /// immediate reconnect alone does not count as retrying
+    pub fn give_up(&self, error: ErrorReport) -> ErrorReport {
+        match self.retries {
+            0 => error,
+            _ => ErrorReport::MailAttemptsFailed {
+                attempts: self.attempts,
+                last: Box::new(error),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Retries, RetryPolicy};
+    use crate::error::{error_mail_sent_response, ErrorReport};
+    use lettre::transport::smtp::response::{Response, Severity};
+    use std::{io, time::Duration};
+
+    #[test]
+    fn schedule() {
+        let policy = RetryPolicy::new(3, Duration::from_millis(100), 2.5);
+        assert_eq!(policy.delay(0), Duration::from_millis(100));
+        assert_eq!(policy.delay(1), Duration::from_millis(250));
+        assert_eq!(policy.delay(2), Duration::from_millis(625));
+        assert!(policy.problem().is_none());
+        assert!(RetryPolicy::new(3, Duration::ZERO, 0.5).problem().is_some());
+        assert!(RetryPolicy::new(3, Duration::ZERO, f64::NAN)
+            .problem()
+            .is_some());
+
+        // far retries saturate instead of overflowing
+        let long = RetryPolicy::new(70, Duration::from_secs(1), 2.0);
+        assert_eq!(long.delay(63), Duration::from_secs(1 << 63));
+        assert_eq!(long.delay(64), Duration::MAX);
+        assert_eq!(long.delay(u32::MAX), Duration::MAX);
+        assert_eq!(
+            RetryPolicy::new(70, Duration::ZERO, 2.0).delay(u32::MAX),
+            Duration::ZERO
+        );
+        let capped = long.with_max_delay(Duration::from_secs(60));
+        assert_eq!(capped.delay(2), Duration::from_secs(4));
+        assert_eq!(capped.delay(6), Duration::from_secs(60));
+        assert_eq!(capped.delay(64), Duration::from_secs(60));
+        let mut retries = Retries::new(capped);
+        retries.attempt();
+        let delays: Vec<Duration> = std::iter::from_fn(|| retries.next_delay(true)).collect();
+        assert_eq!(delays.len(), 70);
+        assert_eq!(delays[69], Duration::from_secs(60));
+
+        let mut retries = Retries::new(policy);
+        let mut delays = Vec::new();
+        retries.attempt();
+        while let Some(delay) = retries.next_delay(true) {
+            delays.push(delay.as_millis());
+            retries.attempt();
+        }
+        assert_eq!(delays, vec![100, 250, 625]);
+        assert!(matches!(
+            retries.give_up(ErrorReport::MailConnection {
+                server: "smtp.example.com".to_owned()
+            }),
+            ErrorReport::MailAttemptsFailed { attempts: 4, .. }
+        ));
+
+        let mut retries = Retries::new(policy);
+        retries.attempt();
+        assert_eq!(retries.next_delay(false), None);
+        assert!(matches!(
+            retries.give_up(ErrorReport::MailConnection {
+                server: "smtp.example.com".to_owned()
+            }),
+            ErrorReport::MailConnection { .. }
+        ));
+    }
+
+    #[test]
+    fn transient_and_permanent() {
+        let answer = |code: u16| {
+            let res: Response = format!("{} 4.7.0 try later\r\n", code).parse().unwrap();
+            error_mail_sent_response(&res)
+        };
+        for (code, severity, transient) in [
+            (421, Severity::TransientNegativeCompletion, true),
+            (450, Severity::TransientNegativeCompletion, true),
+            (550, Severity::PermanentNegativeCompletion, false),
+        ] {
+            let error = answer(code);
+            assert!(
+                matches!(&error, ErrorReport::MailSentResponse { code: val, severity: sev, msg }
+                    if *val == code && *sev == severity && msg == "4.7.0 try later\n"),
+                "{:?}",
+                error
+            );
+            assert_eq!(error.is_transient(), transient, "{}", code);
+        }
+        assert_eq!(
+            answer(421).to_string(),
+            "MailSentResponse - 421 4.7.0 try later\n"
+        );
+
+        let lost: ErrorReport = io::Error::new(io::ErrorKind::ConnectionReset, "reset").into();
+        assert!(lost.is_connection_error() && lost.is_transient());
+        let missing: ErrorReport = io::Error::new(io::ErrorKind::NotFound, "gone").into();
+        assert!(!missing.is_transient());
+        assert!(ErrorReport::MailAttemptsFailed {
+            attempts: 3,
+            last: Box::new(answer(450)),
+        }
+        .is_transient());
+        assert!(!ErrorReport::MailTlsMismatch {
+            server: "smtp.example.com:465".to_owned(),
+            mode: "starttls".to_owned(),
+            expected: "wrapped".to_owned(),
+            msg: String::new(),
+        }
+        .is_transient());
+    }
+}
@@ -0,0 +1,106 @@
+use crate::error::ErrorReport;
+use lettre::message::{
+    header::{ContentDisposition, ContentTransferEncoding, ContentType},
+    MultiPart, SinglePart,
+};
+use openssl::{
+    pkcs7::{Pkcs7, Pkcs7Flags},
+    pkey::{PKey, Private},
+    stack::Stack,
+    x509::X509,
+};
+use std::{fmt, fs, path::Path, sync::Arc};
+use zeroize::Zeroizing;
+
+/// Wraps the body of every message in a `multipart/signed` part with a detached PKCS#7 signature,
+/// as S/MIME clients expect
+#[derive(Clone)]
+pub struct SmimeSigner {
+    certificate: X509,
+    key: PKey<Private>,
+    chain: Arc<Stack<X509>>,
+}
+
+impl SmimeSigner {
+    /// Reads the signer certificate, followed by any intermediate ones in the same file, and its
+    /// private key, decrypted with the passphrase when it is encrypted; all in PEM
+    pub fn from_pem_files(
+        certificate_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+        passphrase: Option<&str>,
+    ) -> Result<Self, ErrorReport> {
+        let key = Zeroizing::new(fs::read(key_path)?);
+        Self::from_pem(&fs::read(certificate_path)?, &key, passphrase)
+    }
+
+    /// Same as `from_pem_files`, with the PEM already read
+    pub fn from_pem(
+        certificate: &[u8],
+        key: &[u8],
+        passphrase: Option<&str>,
+    ) -> Result<Self, ErrorReport> {
+        let mut certificates = X509::stack_from_pem(certificate)?.into_iter();
+        let certificate = certificates
+            .next()
+            .ok_or_else(|| ErrorReport::MailSmimeKey {
+                msg: "no certificate in the PEM".to_owned(),
+            })?;
+        let key = match passphrase {
+            Some(passphrase) => PKey::private_key_from_pem_passphrase(key, passphrase.as_bytes())?,
+            None => PKey::private_key_from_pem(key)?,
+        };
+        if !certificate.public_key()?.public_eq(&key) {
+            return Err(ErrorReport::MailSmimeKey {
+                msg: "the private key does not belong to the certificate".to_owned(),
+            });
+        }
+        let mut chain = Stack::new()?;
+        for val in certificates {
+            chain.push(val)?;
+        }
+        Ok(SmimeSigner {
+            certificate,
+            key,
+            chain: Arc::new(chain),
+        })
+    }
+
+    pub fn certificate(&self) -> &X509 {
+        &self.certificate
+    }
+
+    /// The entity and its signature; the line break before the closing boundary of the signed
+    /// part belongs to the boundary, so it is left out of the signed bytes
+    pub(super) fn sign(&self, entity: MultiPart) -> Result<MultiPart, ErrorReport> {
+        let formatted = entity.formatted();
+        let content = formatted.strip_suffix(b"\r\n").unwrap_or(&formatted);
+        let signature = Pkcs7::sign(
+            &self.certificate,
+            &self.key,
+            &self.chain,
+            content,
+            Pkcs7Flags::DETACHED | Pkcs7Flags::BINARY,
+        )?;
+        let signature = SinglePart::builder()
+            .header(ContentType::parse(
+                "application/pkcs7-signature; name=\"smime.p7s\"",
+            )?)
+            .header(ContentDisposition::attachment("smime.p7s"))
+            .header(ContentTransferEncoding::Base64)
+            .body(signature.to_der()?);
+        Ok(MultiPart::signed(
+            "application/pkcs7-signature".to_owned(),
+            "sha-256".to_owned(),
+        )
+        .multipart(entity)
+        .singlepart(signature))
+    }
+}
+
+impl fmt::Debug for SmimeSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SmimeSigner")
+            .field("subject", self.certificate.subject_name())
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,70 @@
+//! Messages with more recipients than the relay takes, sent as several with a share of them each
+use super::{MailMessage, SendReport};
+use crate::error::{ErrorReport, SplitFailure};
+
+/// Copies of the message with at most `limit` recipients each, taken in the order To, Cc, Bcc;
+/// every recipient keeps its role
+pub(super) fn split<'a>(message: &MailMessage<'a>, limit: usize) -> Vec<MailMessage<'a>> {
+    let recipients: Vec<_> = [&message.to, &message.cc, &message.bcc]
+        .into_iter()
+        .enumerate()
+        .flat_map(|(role, list)| list.iter().map(move |mailbox| (role, mailbox)))
+        .collect();
+    recipients
+        .chunks(limit.max(1))
+        .map(|share| {
+            let mut roles = [Vec::new(), Vec::new(), Vec::new()];
+            for (role, mailbox) in share {
+                roles[*role].push((*mailbox).clone());
+            }
+            let [to, cc, bcc] = roles;
+            MailMessage {
+                to,
+                cc,
+                bcc,
+                ..message.clone()
+            }
+        })
+        .collect()
+}
+
+/// Addresses of all the recipients of a message, as the failures list them
+pub(super) fn recipients(message: &MailMessage) -> Vec<String> {
+    message
+        .to
+        .iter()
+        .chain(&message.cc)
+        .chain(&message.bcc)
+        .map(|val| val.email.to_string())
+        .collect()
+}
+
+/// Report of the first message with all of them in `split`, or `MailSplitFailed` when any failed
+pub(super) fn merge(
+    results: Vec<(Vec<String>, Result<SendReport, ErrorReport>)>,
+) -> Result<SendReport, ErrorReport> {
+    let parts = results.len();
+    let mut sent = Vec::new();
+    let mut failed = Vec::new();
+    for (idx, (recipients, res)) in results.into_iter().enumerate() {
+        match res {
+            Ok(report) => sent.push(report),
+            Err(error) => failed.push(SplitFailure {
+                part: idx + 1,
+                recipients,
+                error,
+            }),
+        }
+    }
+    if !failed.is_empty() {
+        return Err(ErrorReport::MailSplitFailed {
+            parts,
+            sent: sent.into_iter().map(|val| val.message_id).collect(),
+            failed,
+        });
+    }
+    // a split message has at least two parts
+    let mut report = sent[0].clone();
+    report.split = sent;
+    Ok(report)
+}
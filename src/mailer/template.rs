@@ -0,0 +1,552 @@
+use crate::error::ErrorReport;
+use std::{collections::HashMap, fs, path::Path};
+
+/// Default placeholder delimiters, as in `{{name}}`
+pub const DELIMITERS: (&str, &str) = ("{{", "}}");
+const FOOTER_NAME: &str = "footer";
+/// Longest chain of partials including one another
+pub const MAX_INCLUDE_DEPTH: usize = 10;
+
+/// Result of replacing the `{{name}}` placeholders of a text
+pub struct Rendered {
+    pub text: String,
+    /// Names of the placeholders without a value, in order of appearance
+    pub unresolved: Vec<String>,
+}
+
+/// Replaces every `{{name}}` placeholder that has a value in a single pass, so values are never
+/// rendered again. Placeholders without a value are kept, or dropped when `remove_unresolved`.
+///
+/// Only an identifier between the delimiters makes a placeholder, so CSS rules or JSON samples
+/// are left alone.
+pub fn render(
+    template: &str,
+    vars: &HashMap<String, String>,
+    remove_unresolved: bool,
+    (open, close): (&str, &str),
+) -> Rendered {
+    let mut text = String::with_capacity(template.len());
+    let mut unresolved = Vec::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find(open) {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let after_open = &rest[open.len()..];
+        let name = after_open
+            .find(close)
+            .map(|end| &after_open[..end])
+            .filter(|name| is_identifier(name));
+        match name {
+            Some(name) => {
+                let placeholder_len = open.len() + name.len() + close.len();
+                match vars.get(name) {
+                    Some(value) => text.push_str(value),
+                    None => {
+                        if !unresolved.iter().any(|val| val == name) {
+                            unresolved.push(name.to_owned());
+                        }
+                        if !remove_unresolved {
+                            text.push_str(&rest[..placeholder_len]);
+                        }
+                    }
+                }
+                rest = &rest[placeholder_len..];
+            }
+            None => {
+                let skip = rest.chars().next().map_or(1, char::len_utf8);
+                text.push_str(&rest[..skip]);
+                rest = &rest[skip..];
+            }
+        }
+    }
+    text.push_str(rest);
+
+    Rendered { text, unresolved }
+}
+
+/// Names of the placeholders left in a text, in order of appearance
+pub fn unresolved(text: &str, delimiters: (&str, &str)) -> Vec<String> {
+    render(text, &HashMap::new(), false, delimiters).unresolved
+}
+
+/// Templates and footers read ahead of the first send, by language
+#[derive(Debug, Default)]
+pub struct TemplateCache {
+    /// By `key`, so per name too
+    pub templates: HashMap<String, String>,
+    pub footers: HashMap<String, String>,
+    pub subjects: HashMap<String, HashMap<String, String>>,
+    /// String catalogs by language, as written in each file
+    pub catalogs: HashMap<String, HashMap<String, String>>,
+    /// Both of them compiled, with `TemplateEngine::Handlebars`
+    #[cfg(feature = "handlebars")]
+    pub compiled: Option<handlebars::Handlebars<'static>>,
+}
+
+/// Reads a template file as UTF-8, dropping a leading byte order mark.
+///
+/// Files in another encoding fail with `MailTemplateEncoding` naming the likely encoding; with the
+/// `encodings` feature UTF-16 and Windows-1252 files are decoded instead, with a logged warning.
+pub fn load(path: &Path) -> Result<String, ErrorReport> {
+    let bytes = match String::from_utf8(fs::read(path)?) {
+        Ok(text) => {
+            return Ok(match text.strip_prefix('\u{feff}') {
+                Some(val) => val.to_owned(),
+                None => text,
+            })
+        }
+        Err(e) => e.into_bytes(),
+    };
+    let detected = detect_encoding(&bytes);
+
+    #[cfg(feature = "encodings")]
+    if let Some(text) = decode(&bytes, detected) {
+        log::warn!(
+            "Mail template {} is {} encoded, save it as UTF-8",
+            path.display(),
+            detected
+        );
+        return Ok(text);
+    }
+
+    Err(ErrorReport::MailTemplateEncoding {
+        path: path.to_string_lossy().into_owned(),
+        detected: detected.to_owned(),
+    })
+}
+
+/// Replaces every `{{> path}}` directive of a template read from `including` with the partial it
+/// names under `dir`: `path_{lang}.html`, else `path.html`. Partials may include others up to
+/// `MAX_INCLUDE_DEPTH` deep; a partial including itself, directly or not, is an error.
+pub fn expand_includes(
+    source: &str,
+    including: &Path,
+    dir: &Path,
+    lang: &str,
+    delimiters: (&str, &str),
+) -> Result<String, ErrorReport> {
+    expand(source, including, dir, lang, delimiters, &mut Vec::new())
+}
+
+fn expand(
+    source: &str,
+    including: &Path,
+    dir: &Path,
+    lang: &str,
+    (open, close): (&str, &str),
+    chain: &mut Vec<String>,
+) -> Result<String, ErrorReport> {
+    let mut text = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some(start) = rest.find(open) {
+        let after_open = &rest[start + open.len()..];
+        let directive = after_open.find(close).and_then(|end| {
+            let partial = after_open[..end].trim_start().strip_prefix('>')?;
+            Some((partial.trim(), end + close.len()))
+        });
+        let Some((partial, len)) = directive else {
+            text.push_str(&rest[..start + open.len()]);
+            rest = after_open;
+            continue;
+        };
+        text.push_str(&rest[..start]);
+        rest = &after_open[len..];
+
+        let fail = |msg: String| ErrorReport::MailTemplateInclude {
+            template: including.to_string_lossy().into_owned(),
+            partial: partial.to_owned(),
+            msg,
+        };
+        if !partial.split('/').all(is_name) {
+            return Err(fail(
+                "not a path of names under the template directory".to_owned(),
+            ));
+        }
+        if chain.iter().any(|val| val == partial) {
+            chain.push(partial.to_owned());
+            return Err(fail(format!("include cycle {}", chain.join(" > "))));
+        }
+        if chain.len() == MAX_INCLUDE_DEPTH {
+            return Err(fail(format!(
+                "partials nested deeper than {}",
+                MAX_INCLUDE_DEPTH
+            )));
+        }
+        let paths = [
+            dir.join(format!("{}_{}.html", partial, lang)),
+            dir.join(format!("{}.html", partial)),
+        ];
+        let path = match paths.iter().find(|val| val.is_file()) {
+            Some(val) => val,
+            None => {
+                let tried: Vec<String> = paths
+                    .iter()
+                    .map(|val| val.to_string_lossy().into_owned())
+                    .collect();
+                return Err(fail(format!("no file at {}", tried.join(" or "))));
+            }
+        };
+        chain.push(partial.to_owned());
+        text.push_str(&expand(
+            &load(path)?,
+            path,
+            dir,
+            lang,
+            (open, close),
+            chain,
+        )?);
+        chain.pop();
+    }
+    text.push_str(rest);
+    Ok(text)
+}
+
+/// Best guess at the encoding of text that is not valid UTF-8: UTF-16 when there is a byte order
+/// mark, binary data when there are NUL bytes or many control characters, Windows-1252 otherwise
+fn detect_encoding(bytes: &[u8]) -> &'static str {
+    if bytes.starts_with(&[0xff, 0xfe]) {
+        return "UTF-16LE";
+    }
+    if bytes.starts_with(&[0xfe, 0xff]) {
+        return "UTF-16BE";
+    }
+    let controls = bytes
+        .iter()
+        .filter(|val| **val < 0x20 && !matches!(val, b'\t' | b'\n' | b'\r' | 0x0c))
+        .count();
+    if bytes.contains(&0) || controls * 20 > bytes.len() {
+        "binary data"
+    } else {
+        "windows-1252"
+    }
+}
+
+#[cfg(feature = "encodings")]
+fn decode(bytes: &[u8], detected: &str) -> Option<String> {
+    let encoding = match detected {
+        "UTF-16LE" => encoding_rs::UTF_16LE,
+        "UTF-16BE" => encoding_rs::UTF_16BE,
+        "windows-1252" => encoding_rs::WINDOWS_1252,
+        _ => return None,
+    };
+    let (text, _, had_errors) = encoding.decode(bytes);
+    match had_errors {
+        true => None,
+        false => Some(text.into_owned()),
+    }
+}
+
+/// Places an HTML footer at the `{{footer}}` placeholder of the template, when present, or right
+/// before the closing `</body>` tag; appended at the end of documents without one
+pub fn inject_footer(html: &str, footer: &str, (open, close): (&str, &str)) -> String {
+    let placeholder = format!("{}{}{}", open, FOOTER_NAME, close);
+    if html.contains(&placeholder) {
+        return html.replacen(&placeholder, footer, 1);
+    }
+    let end = html
+        .to_ascii_lowercase()
+        .rfind("</body>")
+        .unwrap_or(html.len());
+    let mut res = String::with_capacity(html.len() + footer.len());
+    res.push_str(&html[..end]);
+    res.push_str(footer);
+    res.push_str(&html[end..]);
+    res
+}
+
+/// Puts the preview text in a hidden span right after the opening `<body>` tag, where clients
+/// look for it; a document without one is left as is
+pub fn inject_preheader(html: &str, preheader: &str) -> String {
+    let Some(start) = body_content_start(html) else {
+        return html.to_owned();
+    };
+    let span = format!(
+        "<span style=\"display:none !important;visibility:hidden;mso-hide:all;font-size:1px;\
+         line-height:1px;max-height:0;max-width:0;opacity:0;overflow:hidden;\">{}</span>",
+        escape_html(preheader)
+    );
+    let mut res = String::with_capacity(html.len() + span.len());
+    res.push_str(&html[..start]);
+    res.push_str(&span);
+    res.push_str(&html[start..]);
+    res
+}
+
+/// Index right after the `>` of the opening body tag, attributes and their quoted values skipped
+fn body_content_start(html: &str) -> Option<usize> {
+    let lower = html.to_ascii_lowercase();
+    let mut from = 0;
+    let tag = loop {
+        let idx = from + lower[from..].find("<body")?;
+        match lower[idx + "<body".len()..].chars().next() {
+            Some(c) if c == '>' || c == '/' || c.is_ascii_whitespace() => break idx,
+            _ => from = idx + "<body".len(),
+        }
+    };
+    let mut quote = None;
+    for (idx, c) in html[tag..].char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None, '>') => return Some(tag + idx + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Subjects by key of a subjects file: `key = subject` lines, blank lines and `#` comments are
+/// skipped, as are lines without `=`
+pub fn parse_subjects(text: &str) -> HashMap<String, String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, subject)| (key.trim().to_owned(), subject.trim().to_owned()))
+        .filter(|(key, _)| !key.is_empty())
+        .collect()
+}
+
+/// Entries of a string catalog: `key=value` lines as in the application config, blank lines and
+/// `#` comments skipped. Unlike subjects files, a line that is not an entry fails with
+/// `MailCatalogInvalid`, as does a key that is not a placeholder name or is repeated.
+pub fn parse_catalog(text: &str, path: &Path) -> Result<HashMap<String, String>, ErrorReport> {
+    let mut entries = HashMap::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |msg: String| ErrorReport::MailCatalogInvalid {
+            path: path.to_string_lossy().into_owned(),
+            line: idx + 1,
+            msg,
+        };
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| invalid(format!("'{}' is not a key=value line", line)))?;
+        let key = key.trim();
+        if !is_identifier(key) {
+            return Err(invalid(format!("key '{}' is not a placeholder name", key)));
+        }
+        if entries
+            .insert(key.to_owned(), value.trim().to_owned())
+            .is_some()
+        {
+            return Err(invalid(format!("key '{}' is repeated", key)));
+        }
+    }
+    Ok(entries)
+}
+
+/// Placeholder names start with a letter or underscore and continue with letters, digits,
+/// underscores or dots
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Text with the characters that are markup in HTML, or end an attribute value, written as
+/// entities
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Whether a template name is safe to put in a file name: no path separators, nor dots at the
+/// ends
+pub fn is_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && !name.ends_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+/// Cache and registry key of a template: the language, behind the name of a named template
+pub fn key(name: Option<&str>, lang: &str) -> String {
+    match name {
+        Some(name) => format!("{}/{}", name, lang),
+        None => lang.to_owned(),
+    }
+}
+
+/// Makes a file name safe for the `Content-Disposition` header: control characters (CR/LF
+/// included) are dropped, quotes and path separators replaced. Non-ASCII names are RFC 2231
+/// encoded by lettre when the header is written.
+pub fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| !c.is_control())
+        .map(|c| match c {
+            '"' | '\\' | '/' => '_',
+            _ => c,
+        })
+        .collect::<String>()
+        .trim()
+        .to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        escape_html, inject_footer, inject_preheader, load, parse_catalog, parse_subjects,
+        DELIMITERS,
+    };
+    use crate::error::ErrorReport;
+    use std::path::Path;
+
+    fn test_file(name: &str) -> std::path::PathBuf {
+        Path::new(env!("WORKSPACE_ROOT_PATH"))
+            .join("assets/test")
+            .join(name)
+    }
+
+    #[test]
+    fn template_encodings() {
+        let text = load(&test_file("mail/email_ro.html")).unwrap();
+        assert!(text.starts_with("<!DOCTYPE html>"));
+
+        let detected = |name: &str| match load(&test_file(name)) {
+            Err(ErrorReport::MailTemplateEncoding { path, detected }) => {
+                assert!(path.ends_with(name));
+                detected
+            }
+            other => panic!("Expected encoding error, got {:?}", other),
+        };
+        assert_eq!(detected("attachment.bin"), "binary data");
+
+        #[cfg(not(feature = "encodings"))]
+        {
+            assert_eq!(detected("encoding/template_cp1252.html"), "windows-1252");
+            assert_eq!(detected("encoding/template_utf16.html"), "UTF-16LE");
+        }
+        #[cfg(feature = "encodings")]
+        {
+            let text = load(&test_file("encoding/template_cp1252.html")).unwrap();
+            assert!(text.contains("<p>Grüße aus München – {{contents}}</p>"));
+            let text = load(&test_file("encoding/template_utf16.html")).unwrap();
+            assert_eq!(text, "<p>Grüße – {{contents}}</p>\n");
+        }
+    }
+
+    #[test]
+    fn footer_injection() {
+        let footer = "<p>footer</p>";
+        assert_eq!(
+            inject_footer("<html><BODY><p>hi</p></BODY></html>", footer, DELIMITERS),
+            "<html><BODY><p>hi</p><p>footer</p></BODY></html>"
+        );
+        assert_eq!(
+            inject_footer("<div>{{footer}}</div></body>", footer, DELIMITERS),
+            "<div><p>footer</p></div></body>"
+        );
+        assert_eq!(
+            inject_footer("<p>hi</p>", footer, DELIMITERS),
+            "<p>hi</p><p>footer</p>"
+        );
+    }
+
+    #[test]
+    fn preheader_injection() {
+        let span = |html: &str| {
+            let start = html.find("<span").unwrap();
+            html[start..html.find("</span>").unwrap() + "</span>".len()].to_owned()
+        };
+        let html = inject_preheader(
+            "<html><BODY class=\"a>b\" style='margin:0'>\n<p>hi</p></BODY></html>",
+            "Sale <ends> today",
+        );
+        assert!(html.starts_with("<html><BODY class=\"a>b\" style='margin:0'><span style="));
+        assert!(span(&html).ends_with(">Sale &lt;ends&gt; today</span>"));
+        assert!(span(&html).contains("display:none"));
+        assert!(html.ends_with("</span>\n<p>hi</p></BODY></html>"));
+
+        let html = inject_preheader("<bodyguard><body>x</body>", "Preview");
+        assert!(html.starts_with("<bodyguard><body><span"));
+        assert!(html.ends_with(">Preview</span>x</body>"));
+
+        for html in ["<p>no body</p>", "<body class=\"open"] {
+            assert_eq!(inject_preheader(html, "Preview"), html);
+        }
+    }
+
+    #[test]
+    fn subjects_file() {
+        let subjects = parse_subjects(
+            "# order mails\n\norder_confirmed = Comanda {{order}} a fost confirmată\n\
+             broken line\n = no key\nshipped=Expediată = azi\n",
+        );
+        assert_eq!(subjects.len(), 2);
+        assert_eq!(
+            subjects["order_confirmed"],
+            "Comanda {{order}} a fost confirmată"
+        );
+        assert_eq!(subjects["shipped"], "Expediată = azi");
+    }
+
+    #[test]
+    fn catalog_file() {
+        let path = Path::new("strings_ro.properties");
+        let catalog = parse_catalog(
+            "# closing lines\n\nregards = Cu stimă\norder_title=Comanda ta = gata\n",
+            path,
+        )
+        .unwrap();
+        assert_eq!(catalog.len(), 2);
+        assert_eq!(catalog["regards"], "Cu stimă");
+        assert_eq!(catalog["order_title"], "Comanda ta = gata");
+
+        for (text, line, msg) in [
+            (
+                "regards = Cu stimă\n\nbroken line\n",
+                3,
+                "'broken line' is not a key=value line",
+            ),
+            (" = no key\n", 1, "key '' is not a placeholder name"),
+            (
+                "best regards = Cu stimă\n",
+                1,
+                "key 'best regards' is not a placeholder name",
+            ),
+            (
+                "regards = Cu stimă\nregards = Salut\n",
+                2,
+                "key 'regards' is repeated",
+            ),
+        ] {
+            match parse_catalog(text, path) {
+                Err(ErrorReport::MailCatalogInvalid {
+                    path,
+                    line: at,
+                    msg: found,
+                }) => {
+                    assert_eq!(path, "strings_ro.properties");
+                    assert_eq!((at, found.as_str()), (line, msg));
+                }
+                other => panic!("Expected an invalid catalog, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn html_escaping() {
+        assert_eq!(
+            escape_html("<b>hi</b> & \"x\" it's"),
+            "&lt;b&gt;hi&lt;/b&gt; &amp; &quot;x&quot; it&#39;s"
+        );
+        assert_eq!(escape_html("Grüße – plain"), "Grüße – plain");
+    }
+}
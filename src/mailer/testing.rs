@@ -0,0 +1,133 @@
+//! Helpers for testing code that sends mail, without an SMTP server
+
+use super::MailTransport;
+use crate::error::ErrorReport;
+use lettre::{transport::smtp::response::Response, Message};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+/// Transport for `Mailer::init_with_transport` that keeps the messages it accepts and answers
+/// with the SMTP codes it was programmed with, `250` otherwise
+#[derive(Debug, Default)]
+pub struct MockTransport {
+    sent: Mutex<Vec<Message>>,
+    attempts: AtomicUsize,
+    next_codes: Mutex<VecDeque<u16>>,
+    code: Mutex<Option<u16>>,
+    delay: Mutex<Option<Duration>>,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers every send with the code, e.g. `554`, until called with `None`; panics when the
+    /// code is not an SMTP reply code
+    pub fn fail_with(&self, code: Option<u16>) {
+        code.iter().for_each(|val| Self::check(*val));
+        *self.code.lock().unwrap() = code;
+    }
+
+    /// Answers the next send with the code, e.g. `421`, after the ones queued before; panics when
+    /// the code is not an SMTP reply code
+    pub fn fail_next(&self, code: u16) {
+        Self::check(code);
+        self.next_codes.lock().unwrap().push_back(code);
+    }
+
+    /// Waits this long before answering every send, e.g. to have sends still in flight when a
+    /// test shuts the mailer down; `None` to answer right away
+    pub fn delay(&self, delay: Option<Duration>) {
+        *self.delay.lock().unwrap() = delay;
+    }
+
+    /// Messages the transport accepted, oldest first
+    pub fn sent(&self) -> Vec<Message> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    /// Sends tried, the failed ones included
+    pub fn attempts(&self) -> usize {
+        self.attempts.load(Ordering::SeqCst)
+    }
+
+    fn check(code: u16) {
+        assert!(
+            (200..600).contains(&code),
+            "{} is not an SMTP reply code",
+            code
+        );
+    }
+
+    fn response(code: u16, text: &str) -> Response {
+        format!("{} {}\r\n", code, text)
+            .parse()
+            .expect("checked reply code")
+    }
+}
+
+impl MailTransport for MockTransport {
+    fn send(&self, message: &Message) -> Result<Response, ErrorReport> {
+        self.attempts.fetch_add(1, Ordering::SeqCst);
+        if let Some(delay) = *self.delay.lock().unwrap() {
+            thread::sleep(delay);
+        }
+        let code = self
+            .next_codes
+            .lock()
+            .unwrap()
+            .pop_front()
+            .or(*self.code.lock().unwrap());
+        match code {
+            Some(code) => Ok(Self::response(code, "mock failure")),
+            None => {
+                self.sent.lock().unwrap().push(message.clone());
+                Ok(Self::response(250, "OK"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MockTransport;
+    use crate::mailer::MailTransport;
+    use lettre::Message;
+
+    #[test]
+    fn programmed_codes() {
+        let message = Message::builder()
+            .from("noreply@example.com".parse().unwrap())
+            .to("ana@example.com".parse().unwrap())
+            .body(String::from("Body"))
+            .unwrap();
+        let mock = MockTransport::new();
+        mock.fail_next(421);
+        mock.fail_next(450);
+        let codes: Vec<String> = (0..3)
+            .map(|_| mock.send(&message).unwrap().code().to_string())
+            .collect();
+        assert_eq!(codes, vec!["421", "450", "250"]);
+
+        mock.fail_with(Some(554));
+        assert!(!mock.send(&message).unwrap().is_positive());
+        mock.fail_with(None);
+        assert!(mock.send(&message).unwrap().is_positive());
+        assert_eq!(mock.attempts(), 5);
+        assert_eq!(mock.sent().len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "99 is not an SMTP reply code")]
+    fn invalid_code() {
+        MockTransport::new().fail_next(99);
+    }
+}
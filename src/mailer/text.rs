@@ -0,0 +1,345 @@
+/// Converts an HTML document into readable plain text.
+///
+/// Links are kept as `text (url)`, list items are prefixed with dashes, block elements and `<br>`
+/// start new lines, whitespace is collapsed and entities are decoded. Contents of `head`,
+/// `style`, `script` and `title` are dropped, as are comments. Malformed markup is tolerated.
+pub fn html_to_text(html: &str) -> String {
+    let mut out = TextWriter::default();
+    let mut skip_until: Option<String> = None;
+    let mut links: Vec<(usize, Option<String>)> = Vec::new();
+    let mut list_depth = 0usize;
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = match comment.find("-->") {
+                Some(end) => &comment[end + 3..],
+                None => "",
+            };
+            continue;
+        }
+        let is_tag = rest.starts_with('<')
+            && rest[1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '/' || c == '!');
+        let tag_end = match is_tag {
+            true => rest.find('>'),
+            false => None,
+        };
+        let end = match tag_end {
+            Some(end) => end,
+            None => {
+                let first = rest.chars().next().map_or(1, char::len_utf8);
+                let text_end = rest[first..]
+                    .find('<')
+                    .map(|val| val + first)
+                    .unwrap_or(rest.len());
+                if skip_until.is_none() {
+                    out.text(&decode_entities(&rest[..text_end]));
+                }
+                rest = &rest[text_end..];
+                continue;
+            }
+        };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        let closing = tag.starts_with('/');
+        let name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+
+        if let Some(skipped) = &skip_until {
+            if closing && &name == skipped {
+                skip_until = None;
+            }
+            continue;
+        }
+
+        match (name.as_str(), closing) {
+            ("head" | "style" | "script" | "title", false) if !tag.ends_with('/') => {
+                skip_until = Some(name);
+            }
+            ("br", _) => out.line_break(),
+            ("p", _) => out.paragraph(),
+            ("ul" | "ol", false) => {
+                list_depth += 1;
+                out.new_line();
+            }
+            ("ul" | "ol", true) => {
+                list_depth = list_depth.saturating_sub(1);
+                out.new_line();
+            }
+            ("li", false) => {
+                out.new_line();
+                out.raw(&"  ".repeat(list_depth.saturating_sub(1)));
+                out.raw("- ");
+            }
+            ("td" | "th", false) => out.separator(),
+            ("a", false) => links.push((out.len(), attribute(tag, "href"))),
+            ("a", true) => {
+                if let Some((start, Some(href))) = links.pop() {
+                    let label = out.since(start).trim().to_owned();
+                    if !href.is_empty() && !href.starts_with('#') && label != href {
+                        if label.is_empty() {
+                            out.text(&href);
+                        } else {
+                            out.text(&format!(" ({})", href));
+                        }
+                    }
+                }
+            }
+            (
+                "div" | "li" | "tr" | "table" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6"
+                | "blockquote" | "hr",
+                _,
+            ) => out.new_line(),
+            _ => {}
+        }
+    }
+
+    out.finish()
+}
+
+#[derive(Default)]
+struct TextWriter {
+    buf: String,
+}
+
+impl TextWriter {
+    fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    fn since(&self, start: usize) -> &str {
+        self.buf.get(start..).unwrap_or("")
+    }
+
+    fn at_line_start(&self) -> bool {
+        self.buf.is_empty() || self.buf.ends_with('\n')
+    }
+
+    fn raw(&mut self, text: &str) {
+        self.buf.push_str(text);
+    }
+
+    /// Appends text, collapsing any whitespace run into a single space
+    fn text(&mut self, text: &str) {
+        let mut words = text.split_whitespace().peekable();
+        if words.peek().is_none() || text.starts_with(char::is_whitespace) {
+            self.separator();
+        }
+        while let Some(word) = words.next() {
+            self.buf.push_str(word);
+            if words.peek().is_some() {
+                self.buf.push(' ');
+            }
+        }
+        if text.ends_with(char::is_whitespace) {
+            self.separator();
+        }
+    }
+
+    fn trim_line_end(&mut self) {
+        while self.buf.ends_with(' ') {
+            self.buf.pop();
+        }
+    }
+
+    fn line_break(&mut self) {
+        self.trim_line_end();
+        self.buf.push('\n');
+    }
+
+    fn new_line(&mut self) {
+        self.trim_line_end();
+        if !self.at_line_start() {
+            self.buf.push('\n');
+        }
+    }
+
+    fn paragraph(&mut self) {
+        self.new_line();
+        if !self.buf.is_empty() && !self.buf.ends_with("\n\n") {
+            self.buf.push('\n');
+        }
+    }
+
+    fn separator(&mut self) {
+        if !self.at_line_start() && !self.buf.ends_with(' ') {
+            self.buf.push(' ');
+        }
+    }
+
+    fn finish(self) -> String {
+        let mut res = String::with_capacity(self.buf.len());
+        let mut empty_lines = 0;
+        for line in self.buf.lines().map(|line| line.trim_end()) {
+            if line.trim().is_empty() {
+                empty_lines += 1;
+                if empty_lines > 1 || res.is_empty() {
+                    continue;
+                }
+                res.push('\n');
+            } else {
+                empty_lines = 0;
+                res.push_str(line);
+                res.push('\n');
+            }
+        }
+        res.trim_end().to_owned()
+    }
+}
+
+/// Value of an attribute in the contents of a tag, with entities decoded
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let mut rest = tag
+        .trim_start_matches(|c: char| c.is_ascii_alphanumeric() || c == '/')
+        .trim_end_matches('/');
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return None;
+        }
+        let name_end = rest
+            .find(|c: char| c == '=' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let attr_name = &rest[..name_end];
+        rest = rest[name_end..].trim_start();
+        let value = match rest.strip_prefix('=') {
+            Some(val) => {
+                let val = val.trim_start();
+                let (value, remaining) = match val.chars().next() {
+                    Some(quote @ ('"' | '\'')) => match val[1..].find(quote) {
+                        Some(end) => (&val[1..end + 1], &val[end + 2..]),
+                        None => (&val[1..], ""),
+                    },
+                    _ => {
+                        let end = val.find(char::is_whitespace).unwrap_or(val.len());
+                        (&val[..end], &val[end..])
+                    }
+                };
+                rest = remaining;
+                value
+            }
+            None => "",
+        };
+        if attr_name.eq_ignore_ascii_case(name) {
+            return Some(decode_entities(value.trim()));
+        }
+    }
+}
+
+/// Decodes named and numeric character references; unknown ones are kept verbatim
+fn decode_entities(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        res.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest[1..]
+            .find(';')
+            .filter(|end| *end <= 10)
+            .and_then(|end| decode_entity(&rest[1..end + 1]).map(|c| (c, end + 2)));
+        match decoded {
+            Some((c, len)) => {
+                res.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                res.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    res.push_str(rest);
+    res
+}
+
+fn decode_entity(name: &str) -> Option<char> {
+    if let Some(num) = name.strip_prefix('#') {
+        let code = match num.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => num.parse().ok()?,
+        };
+        return char::from_u32(code);
+    }
+    Some(match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => ' ',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "euro" => '€',
+        "hellip" => '…',
+        "ndash" => '–',
+        "mdash" => '—',
+        "laquo" => '«',
+        "raquo" => '»',
+        "bull" => '•',
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::html_to_text;
+
+    #[test]
+    fn representative_template() {
+        let html = r#"<!DOCTYPE html>
+<html>
+    <head>
+        <title>Ignored</title>
+        <style> td { padding: 0; } </style>
+    </head>
+    <body>
+        <!--[if mso]><table><tr><td><![endif]-->
+        <div class="header"><div>Hello&nbsp;<b>Ana</b>,</div></div>
+        <p>Your   order   is
+           ready &amp; waiting.<br>Thank you!</p>
+        <ul>
+            <li>Item &lt;one&gt;</li>
+            <li>Item two
+                <ol><li>nested</li></ol>
+            </li>
+        </ul>
+        <p>Please <a href="https://example.com/orders?id=1&amp;lang=ro">check your order</a>
+           or write to <a href='mailto:help@example.com'>mailto:help@example.com</a>.</p>
+        <table><tr><td>Total:</td><td>10 &euro;</td></tr></table>
+    </body>
+</html>"#;
+        let expected = "Hello Ana,
+
+Your order is ready & waiting.
+Thank you!
+
+- Item <one>
+- Item two
+  - nested
+
+Please check your order (https://example.com/orders?id=1&lang=ro) or write to mailto:help@example.com.
+
+Total: 10 €";
+        assert_eq!(html_to_text(html), expected);
+    }
+
+    #[test]
+    fn malformed_markup() {
+        assert_eq!(html_to_text("a < b and <b>c"), "a < b and c");
+        assert_eq!(
+            html_to_text("<p>unclosed <a href=\"x"),
+            "unclosed <a href=\"x"
+        );
+        assert_eq!(
+            html_to_text("fish &chips; &#x263A; &#9731;"),
+            "fish &chips; ☺ ☃"
+        );
+        assert_eq!(html_to_text("<!-- never closed"), "");
+    }
+}
@@ -0,0 +1,210 @@
+use super::audit;
+use crate::error::ErrorReport;
+use lettre::{
+    transport::smtp::response::{Category, Code, Detail, Response, Severity},
+    FileTransport, Message, SendmailTransport, SmtpTransport, Transport,
+};
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, RwLock, Weak,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+/// Delivery backend used by the `Mailer`
+pub trait MailTransport: Send + Sync {
+    fn send(&self, message: &Message) -> Result<Response, ErrorReport>;
+
+    /// Checks the connection to the server, opening (and authenticating) one when needed; a
+    /// transport without one to check is always ready
+    fn test_connection(&self) -> Result<bool, ErrorReport> {
+        Ok(true)
+    }
+}
+
+impl MailTransport for SmtpTransport {
+    fn send(&self, message: &Message) -> Result<Response, ErrorReport> {
+        Ok(Transport::send(self, message)?)
+    }
+
+    fn test_connection(&self) -> Result<bool, ErrorReport> {
+        Ok(SmtpTransport::test_connection(self)?)
+    }
+}
+
+/// Writes every message as an `.eml` file into a directory instead of sending it, named after the
+/// time it was written and a unique id
+pub struct FileDelivery {
+    dir: PathBuf,
+    transport: FileTransport,
+}
+
+impl FileDelivery {
+    /// Creates the directory when missing
+    pub fn new(dir: PathBuf) -> Result<FileDelivery, ErrorReport> {
+        fs::create_dir_all(&dir)?;
+        Ok(FileDelivery {
+            transport: FileTransport::new(&dir),
+            dir,
+        })
+    }
+}
+
+impl MailTransport for FileDelivery {
+    fn send(&self, message: &Message) -> Result<Response, ErrorReport> {
+        let id = Transport::send(&self.transport, message)?;
+        let written =
+            format!("{}-{}.eml", audit::format_utc(SystemTime::now()), id).replace(':', "");
+        fs::rename(
+            self.dir.join(format!("{}.eml", id)),
+            self.dir.join(&written),
+        )?;
+        Ok(Response::new(
+            Code::new(
+                Severity::PositiveCompletion,
+                Category::MailSystem,
+                Detail::Zero,
+            ),
+            vec![format!("written to {}", written)],
+        ))
+    }
+
+    fn test_connection(&self) -> Result<bool, ErrorReport> {
+        Ok(self.dir.is_dir())
+    }
+}
+
+/// Pipes every message into a sendmail binary, which queues it for delivery
+pub struct SendmailDelivery {
+    command: PathBuf,
+    transport: SendmailTransport,
+}
+
+impl SendmailDelivery {
+    pub fn new(command: PathBuf) -> SendmailDelivery {
+        SendmailDelivery {
+            transport: SendmailTransport::new_with_command(&command),
+            command,
+        }
+    }
+}
+
+impl MailTransport for SendmailDelivery {
+    fn send(&self, message: &Message) -> Result<Response, ErrorReport> {
+        Transport::send(&self.transport, message).map_err(|e| {
+            // the source holds what sendmail wrote to stderr, or why it could not be started
+            let msg = match std::error::Error::source(&e) {
+                Some(source) => source.to_string(),
+                None => e.to_string(),
+            };
+            ErrorReport::MailSendmail {
+                command: self.command.to_string_lossy().into_owned(),
+                msg: msg.trim().to_owned(),
+            }
+        })?;
+        Ok(Response::new(
+            Code::new(
+                Severity::PositiveCompletion,
+                Category::MailSystem,
+                Detail::Zero,
+            ),
+            vec![format!("accepted by {}", self.command.display())],
+        ))
+    }
+
+    fn test_connection(&self) -> Result<bool, ErrorReport> {
+        Ok(self.command.is_file())
+    }
+}
+
+/// Sends on a worker thread when there is a timeout, so a hung server cannot block the caller
+/// past it; the worker is left to finish in the background and may still deliver the message,
+/// so `MailSendAbandoned` must not be retried
+pub fn send_within(
+    transport: Arc<dyn MailTransport>,
+    message: &Message,
+    timeout: Option<Duration>,
+) -> Result<Response, ErrorReport> {
+    let timeout = match timeout {
+        Some(val) => val,
+        None => return transport.send(message),
+    };
+    let (done, result) = mpsc::channel();
+    let message = message.clone();
+    thread::Builder::new()
+        .name("mailer-send".to_owned())
+        .spawn(move || {
+            let _ = done.send(transport.send(&message));
+        })?;
+    match result.recv_timeout(timeout) {
+        Ok(res) => res,
+        Err(_) => Err(ErrorReport::MailSendAbandoned { timeout }),
+    }
+}
+
+/// Builds the transport of a `Mailer`
+pub type Connector = Arc<dyn Fn() -> Result<Arc<dyn MailTransport>, ErrorReport> + Send + Sync>;
+
+/// Slot holding the transport shared by all the clones of a `Mailer`; empty until first use
+/// and after a dead connection was recycled
+pub type TransportSlot = RwLock<Option<Arc<dyn MailTransport>>>;
+
+/// Background thread checking the pooled connection at a fixed interval; a failed check empties
+/// the transport slot, so the next use builds a fresh transport. Stopped and joined on drop.
+pub struct KeepAlive {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl KeepAlive {
+    pub fn start(
+        interval: Duration,
+        slot: Weak<TransportSlot>,
+        connector: Connector,
+    ) -> Result<KeepAlive, ErrorReport> {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let handle = thread::Builder::new()
+            .name("mailer-keep-alive".to_owned())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    let slot = match slot.upgrade() {
+                        Some(val) => val,
+                        None => break,
+                    };
+                    let transport = match slot.read().unwrap().clone() {
+                        Some(val) => Ok(val),
+                        None => connector(),
+                    };
+                    match transport {
+                        Ok(transport) if transport.test_connection().unwrap_or(false) => {
+                            slot.write().unwrap().get_or_insert(transport);
+                        }
+                        _ => {
+                            slot.write().unwrap().take();
+                        }
+                    }
+                }
+            })?;
+        Ok(KeepAlive {
+            stop: Some(stop),
+            handle: Some(handle),
+        })
+    }
+
+    pub fn stop(&mut self) {
+        self.stop.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for KeepAlive {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
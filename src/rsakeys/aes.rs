@@ -0,0 +1,123 @@
+use super::RsaKeys;
+use crate::error::ErrorReport;
+use openssl::rand::rand_bytes;
+use std::fmt;
+use zeroize::Zeroizing;
+
+/// AES data key, wiped from memory on drop
+#[derive(Clone, PartialEq, Eq)]
+pub struct AesKey {
+    bytes: Zeroizing<Vec<u8>>,
+}
+
+impl fmt::Debug for AesKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AesKey")
+            .field("len", &self.bytes.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl AesKey {
+    const LENGTHS: [usize; 3] = [16, 24, 32];
+
+    pub(super) fn check_len(len: usize) -> Result<(), ErrorReport> {
+        match Self::LENGTHS.contains(&len) {
+            true => Ok(()),
+            false => Err(ErrorReport::CryptoKeyLength { len }),
+        }
+    }
+
+    /// Random key of 16, 24 or 32 bytes
+    pub fn generate(len: usize) -> Result<AesKey, ErrorReport> {
+        Self::check_len(len)?;
+        let mut bytes = Zeroizing::new(vec![0; len]);
+        rand_bytes(&mut bytes)?;
+        Ok(AesKey { bytes })
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<AesKey, ErrorReport> {
+        Self::check_len(bytes.len())?;
+        Ok(AesKey {
+            bytes: Zeroizing::new(bytes.to_vec()),
+        })
+    }
+
+    /// Key stored with `wrap`
+    pub fn from_wrapped(wrapped: &str, rsa: &RsaKeys) -> Result<AesKey, ErrorReport> {
+        Ok(AesKey {
+            bytes: rsa.unwrap_key(wrapped)?,
+        })
+    }
+
+    /// The key encrypted for the holder of the RSA private key, see `RsaKeys::wrap_key`
+    pub fn wrap(&self, rsa: &RsaKeys) -> Result<String, ErrorReport> {
+        rsa.wrap_key(&self.bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AesKey;
+    use crate::{error::ErrorReport, rsakeys::RsaKeys};
+    use data_encoding::BASE64URL_NOPAD;
+    use openssl::rsa::Padding;
+    use std::path::Path;
+
+    #[test]
+    fn wrap_unwrap() {
+        let keys_dir = Path::new(env!("WORKSPACE_ROOT_PATH")).join("assets/test/keys");
+        let rsa = RsaKeys::init(
+            "test",
+            keys_dir.join("private.pem"),
+            keys_dir.join("public.pem"),
+        )
+        .unwrap();
+
+        for len in [16, 24, 32] {
+            let key = AesKey::generate(len).unwrap();
+            let wrapped = rsa.wrap_key(key.as_bytes()).unwrap();
+            assert_ne!(
+                wrapped,
+                rsa.wrap_key(key.as_bytes()).unwrap(),
+                "OAEP is not randomized"
+            );
+            assert_eq!(*rsa.unwrap_key(&wrapped).unwrap(), key.as_bytes());
+            assert_eq!(
+                AesKey::from_wrapped(&key.wrap(&rsa).unwrap(), &rsa).unwrap(),
+                key
+            );
+        }
+
+        assert!(matches!(
+            rsa.wrap_key(&[7; 20]),
+            Err(ErrorReport::CryptoKeyLength { len: 20 })
+        ));
+        assert!(matches!(
+            AesKey::generate(20),
+            Err(ErrorReport::CryptoKeyLength { len: 20 })
+        ));
+        let mut buf = vec![0; rsa.get_public_key().size() as usize];
+        let bytes = rsa
+            .get_public_key()
+            .public_encrypt(&[7; 20], &mut buf, Padding::PKCS1_OAEP)
+            .unwrap();
+        assert!(matches!(
+            rsa.unwrap_key(&BASE64URL_NOPAD.encode(&buf[..bytes])),
+            Err(ErrorReport::CryptoKeyLength { len: 20 })
+        ));
+        assert!(!format!("{:?}", AesKey::generate(32).unwrap()).contains('['));
+    }
+}
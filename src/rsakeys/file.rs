@@ -0,0 +1,266 @@
+//! Files of any size encrypted in chunks with one AES-256-GCM key, wrapped with RSA (OAEP)
+//!
+//! Layout: header `RSF`, version, wrapped key length (u16 BE), wrapped key, nonce prefix; then one
+//! record per chunk: last flag, ciphertext length (u32 BE), ciphertext, tag. The nonce of a chunk
+//! is the prefix, its index (u32 BE) and the last flag, so dropped, reordered or truncated chunks
+//! fail the authentication; the header is the associated data of every chunk.
+use crate::error::ErrorReport;
+use openssl::{
+    pkey::{Private, Public},
+    rand::rand_bytes,
+    rsa::{Padding, Rsa},
+    symm::{decrypt_aead, encrypt_aead, Cipher},
+};
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+};
+use zeroize::Zeroizing;
+
+const MAGIC: &[u8; 3] = b"RSF";
+const VERSION: u8 = 1;
+const KEY_LEN: usize = 32;
+const PREFIX_LEN: usize = 7;
+const TAG_LEN: usize = 16;
+/// Plaintext bytes per chunk
+const CHUNK_LEN: usize = 64 * 1024;
+
+fn invalid(msg: &str) -> ErrorReport {
+    ErrorReport::CryptoEnvelope {
+        msg: msg.to_owned(),
+    }
+}
+
+fn nonce(prefix: &[u8], index: u32, last: bool) -> [u8; 12] {
+    let mut res = [0; 12];
+    res[..PREFIX_LEN].copy_from_slice(prefix);
+    res[PREFIX_LEN..11].copy_from_slice(&index.to_be_bytes());
+    res[11] = last as u8;
+    res
+}
+
+/// Fills `buf` as far as the reader goes, returning the bytes read
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize, ErrorReport> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(bytes) => len += bytes,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(len)
+}
+
+/// Removes the output of a failed run, so no partial file is left behind
+fn or_remove<T>(res: Result<T, ErrorReport>, dst: &Path) -> Result<T, ErrorReport> {
+    if res.is_err() {
+        let _ = fs::remove_file(dst);
+    }
+    res
+}
+
+pub(super) fn encrypt(public: &Rsa<Public>, src: &Path, dst: &Path) -> Result<(), ErrorReport> {
+    let reader = BufReader::with_capacity(CHUNK_LEN, File::open(src)?);
+    let writer = BufWriter::new(File::create(dst)?);
+    or_remove(encrypt_stream(public, reader, writer), dst)
+}
+
+fn encrypt_stream(
+    public: &Rsa<Public>,
+    mut reader: impl BufRead,
+    mut writer: impl Write,
+) -> Result<(), ErrorReport> {
+    let mut key = Zeroizing::new([0; KEY_LEN]);
+    rand_bytes(&mut key[..])?;
+    let mut prefix = [0; PREFIX_LEN];
+    rand_bytes(&mut prefix)?;
+    let mut wrapped_key = vec![0; public.size() as usize];
+    let bytes = public.public_encrypt(&key[..], &mut wrapped_key, Padding::PKCS1_OAEP)?;
+    wrapped_key.truncate(bytes);
+
+    let mut header = Vec::with_capacity(MAGIC.len() + 3 + bytes + PREFIX_LEN);
+    header.extend_from_slice(MAGIC);
+    header.push(VERSION);
+    header.extend_from_slice(&(bytes as u16).to_be_bytes());
+    header.extend_from_slice(&wrapped_key);
+    header.extend_from_slice(&prefix);
+    writer.write_all(&header)?;
+
+    let mut buf = vec![0; CHUNK_LEN];
+    let mut tag = [0; TAG_LEN];
+    for index in 0.. {
+        let len = read_full(&mut reader, &mut buf)?;
+        let last = len < CHUNK_LEN || reader.fill_buf()?.is_empty();
+        let ciphertext = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &key[..],
+            Some(&nonce(&prefix, index, last)),
+            &header,
+            &buf[..len],
+            &mut tag,
+        )?;
+        writer.write_all(&[last as u8])?;
+        writer.write_all(&(ciphertext.len() as u32).to_be_bytes())?;
+        writer.write_all(&ciphertext)?;
+        writer.write_all(&tag)?;
+        if last {
+            break;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub(super) fn decrypt(private: &Rsa<Private>, src: &Path, dst: &Path) -> Result<(), ErrorReport> {
+    let reader = BufReader::with_capacity(CHUNK_LEN, File::open(src)?);
+    let writer = BufWriter::new(File::create(dst)?);
+    or_remove(decrypt_stream(private, reader, writer), dst)
+}
+
+fn decrypt_stream(
+    private: &Rsa<Private>,
+    mut reader: impl BufRead,
+    mut writer: impl Write,
+) -> Result<(), ErrorReport> {
+    let truncated = |e: std::io::Error| match e.kind() {
+        ErrorKind::UnexpectedEof => invalid("encrypted file is truncated"),
+        _ => e.into(),
+    };
+    let mut header = vec![0; MAGIC.len() + 3];
+    reader.read_exact(&mut header).map_err(truncated)?;
+    if &header[..MAGIC.len()] != MAGIC {
+        return Err(invalid("not an encrypted file, magic bytes are missing"));
+    }
+    if header[MAGIC.len()] != VERSION {
+        return Err(invalid(&format!(
+            "encrypted file version {} is not supported",
+            header[MAGIC.len()]
+        )));
+    }
+    let key_len = u16::from_be_bytes([header[4], header[5]]) as usize;
+    header.resize(header.len() + key_len + PREFIX_LEN, 0);
+    reader.read_exact(&mut header[6..]).map_err(truncated)?;
+    let (wrapped_key, prefix) = header[6..].split_at(key_len);
+
+    let mut key = Zeroizing::new(vec![0; private.size() as usize]);
+    let bytes = private
+        .private_decrypt(wrapped_key, &mut key, Padding::PKCS1_OAEP)
+        .map_err(|_| {
+            invalid("the data key could not be decrypted, the private key does not match")
+        })?;
+    if bytes != KEY_LEN {
+        return Err(invalid(&format!("unexpected data key length {}", bytes)));
+    }
+
+    let mut record = [0; 5];
+    let mut tag = [0; TAG_LEN];
+    let mut ciphertext = Vec::with_capacity(CHUNK_LEN);
+    for index in 0.. {
+        reader.read_exact(&mut record).map_err(truncated)?;
+        let last = record[0] == 1;
+        let len = u32::from_be_bytes([record[1], record[2], record[3], record[4]]) as usize;
+        if len > CHUNK_LEN {
+            return Err(invalid("chunk is longer than the chunk size"));
+        }
+        ciphertext.resize(len, 0);
+        reader.read_exact(&mut ciphertext).map_err(truncated)?;
+        reader.read_exact(&mut tag).map_err(truncated)?;
+        let plaintext = decrypt_aead(
+            Cipher::aes_256_gcm(),
+            &key[..bytes],
+            Some(&nonce(prefix, index, last)),
+            &header,
+            &ciphertext,
+            &tag,
+        )
+        .map_err(|_| invalid("authentication failed, the data was modified"))?;
+        writer.write_all(&plaintext)?;
+        if last {
+            break;
+        }
+    }
+    if !reader.fill_buf()?.is_empty() {
+        return Err(invalid("encrypted file has data past its last chunk"));
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CHUNK_LEN;
+    use crate::{error::ErrorReport, rsakeys::RsaKeys};
+    use openssl::{rand::rand_bytes, sha::sha256};
+    use std::{fs, path::Path};
+
+    #[test]
+    fn encrypt_decrypt_file() {
+        let dir = Path::new(env!("TEMP_DIR_PATH")).join("rsa_file");
+        fs::create_dir_all(&dir).unwrap();
+        let (plain, sealed, opened) = (
+            dir.join("backup.tar"),
+            dir.join("backup.tar.enc"),
+            dir.join("backup.out"),
+        );
+        let mut data = vec![0; 3 * CHUNK_LEN + 1000];
+        rand_bytes(&mut data).unwrap();
+        fs::write(&plain, &data).unwrap();
+
+        let rsa = RsaKeys::generate(2048).unwrap();
+        rsa.encrypt_file(&plain, &sealed).unwrap();
+        rsa.decrypt_file(&sealed, &opened).unwrap();
+        assert_eq!(sha256(&fs::read(&opened).unwrap()), sha256(&data));
+
+        // a whole number of chunks, and an empty file
+        for len in [CHUNK_LEN, 0] {
+            fs::write(&plain, &data[..len]).unwrap();
+            rsa.encrypt_file(&plain, &sealed).unwrap();
+            rsa.decrypt_file(&sealed, &opened).unwrap();
+            assert_eq!(fs::read(&opened).unwrap(), &data[..len]);
+        }
+
+        fs::write(&plain, &data).unwrap();
+        rsa.encrypt_file(&plain, &sealed).unwrap();
+        let encrypted = fs::read(&sealed).unwrap();
+        let record = 5 + CHUNK_LEN + 16;
+        let header = encrypted.len() - 3 * record - (5 + 1000 + 16);
+        for len in [
+            2,
+            header,
+            header + record,
+            encrypted.len() - 1,
+            encrypted.len() - (5 + 1000 + 16),
+        ] {
+            fs::write(&sealed, &encrypted[..len]).unwrap();
+            match rsa.decrypt_file(&sealed, &opened) {
+                Err(ErrorReport::CryptoEnvelope { .. }) => assert!(!opened.exists()),
+                other => panic!("Expected truncation at {} to fail, got {:?}", len, other),
+            }
+        }
+
+        let mut modified = encrypted.clone();
+        modified[header + 100] ^= 1;
+        fs::write(&sealed, &modified).unwrap();
+        assert!(matches!(
+            rsa.decrypt_file(&sealed, &opened),
+            Err(ErrorReport::CryptoEnvelope { .. })
+        ));
+        modified = encrypted.clone();
+        modified[3] = 2;
+        fs::write(&sealed, &modified).unwrap();
+        assert!(matches!(
+            rsa.decrypt_file(&sealed, &opened),
+            Err(ErrorReport::CryptoEnvelope { .. })
+        ));
+        fs::write(&sealed, &encrypted).unwrap();
+        assert!(matches!(
+            RsaKeys::generate(2048)
+                .unwrap()
+                .decrypt_file(&sealed, &opened),
+            Err(ErrorReport::CryptoEnvelope { .. })
+        ));
+    }
+}
@@ -0,0 +1,282 @@
+use crate::error::ErrorReport;
+use data_encoding::{BASE64, BASE64URL_NOPAD};
+use openssl::{
+    bn::BigNum,
+    hash::MessageDigest,
+    pkey::{PKey, Public},
+    rsa::{Padding, Rsa},
+    sign::{RsaPssSaltlen, Verifier},
+};
+use serde_json::Value;
+
+/// RSA public key used to check signatures, in any of the formats partners hand out
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifierKey {
+    /// `PUBLIC KEY` (SubjectPublicKeyInfo) or `RSA PUBLIC KEY` (PKCS#1) PEM
+    Pem(Vec<u8>),
+    /// SubjectPublicKeyInfo or PKCS#1 DER
+    Der(Vec<u8>),
+    /// JSON Web Key with `kty` `RSA`
+    Jwk(Value),
+    /// OpenSSH `ssh-rsa AAAA... comment` line
+    OpenSsh(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureScheme {
+    RsaPkcs1Sha256,
+    RsaPkcs1Sha512,
+    /// RSASSA-PSS with MGF1 and a salt as long as the digest
+    RsaPssSha256,
+}
+
+impl VerifierKey {
+    fn format(&self) -> &'static str {
+        match self {
+            VerifierKey::Pem(_) => "PEM",
+            VerifierKey::Der(_) => "DER",
+            VerifierKey::Jwk(_) => "JWK",
+            VerifierKey::OpenSsh(_) => "OpenSSH",
+        }
+    }
+
+    /// Parses the key, whatever its format
+    pub fn public_key(&self) -> Result<PKey<Public>, ErrorReport> {
+        let rsa = match self {
+            VerifierKey::Pem(pem) => Rsa::public_key_from_pem(pem)
+                .or_else(|_| Rsa::public_key_from_pem_pkcs1(pem))
+                .map_err(|e| e.to_string()),
+            VerifierKey::Der(der) => Rsa::public_key_from_der(der)
+                .or_else(|_| Rsa::public_key_from_der_pkcs1(der))
+                .map_err(|e| e.to_string()),
+            VerifierKey::Jwk(jwk) => rsa_from_jwk(jwk),
+            VerifierKey::OpenSsh(line) => rsa_from_openssh(line),
+        };
+        rsa.and_then(|rsa| PKey::from_rsa(rsa).map_err(|e| e.to_string()))
+            .map_err(|msg| ErrorReport::VerifierKey {
+                format: self.format().to_owned(),
+                msg,
+            })
+    }
+}
+
+fn rsa_from_jwk(jwk: &Value) -> Result<Rsa<Public>, String> {
+    if jwk["kty"] != "RSA" {
+        return Err(format!("key type {} is not RSA", jwk["kty"]));
+    }
+    let component = |name: &str| -> Result<BigNum, String> {
+        let value = jwk[name]
+            .as_str()
+            .ok_or_else(|| format!("member {} is missing", name))?;
+        let bytes = BASE64URL_NOPAD
+            .decode(value.trim_end_matches('=').as_bytes())
+            .map_err(|e| format!("member {}: {}", name, e))?;
+        BigNum::from_slice(&bytes).map_err(|e| e.to_string())
+    };
+    Rsa::from_public_components(component("n")?, component("e")?).map_err(|e| e.to_string())
+}
+
+fn rsa_from_openssh(line: &str) -> Result<Rsa<Public>, String> {
+    let mut fields = line.split_whitespace();
+    if fields.next() != Some("ssh-rsa") {
+        return Err("only ssh-rsa keys are supported".to_owned());
+    }
+    let blob = BASE64
+        .decode(fields.next().unwrap_or_default().as_bytes())
+        .map_err(|e| e.to_string())?;
+
+    // RFC 4253: string "ssh-rsa", mpint e, mpint n, each with a u32 length prefix
+    let mut rest = &blob[..];
+    let mut next = || -> Result<&[u8], String> {
+        let too_short = || "key blob is truncated".to_owned();
+        let len = rest.get(..4).ok_or_else(too_short)?;
+        let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+        let value = rest.get(4..4 + len).ok_or_else(too_short)?;
+        rest = &rest[4 + len..];
+        Ok(value)
+    };
+    if next()? != b"ssh-rsa" {
+        return Err("key blob is not ssh-rsa".to_owned());
+    }
+    let e = BigNum::from_slice(next()?).map_err(|e| e.to_string())?;
+    let n = BigNum::from_slice(next()?).map_err(|e| e.to_string())?;
+    Rsa::from_public_components(n, e).map_err(|e| e.to_string())
+}
+
+impl TryFrom<Value> for VerifierKey {
+    type Error = ErrorReport;
+
+    fn try_from(jwk: Value) -> Result<Self, Self::Error> {
+        let key = VerifierKey::Jwk(jwk);
+        key.public_key()?;
+        Ok(key)
+    }
+}
+
+/// Detects PEM, JWK and OpenSSH keys from their text
+impl TryFrom<&str> for VerifierKey {
+    type Error = ErrorReport;
+
+    fn try_from(text: &str) -> Result<Self, Self::Error> {
+        let text = text.trim();
+        let key = if text.starts_with("-----BEGIN") {
+            VerifierKey::Pem(text.as_bytes().to_vec())
+        } else if text.starts_with("ssh-") {
+            VerifierKey::OpenSsh(text.to_owned())
+        } else if text.starts_with('{') {
+            VerifierKey::Jwk(
+                serde_json::from_str(text).map_err(|e| ErrorReport::VerifierKey {
+                    format: "JWK".to_owned(),
+                    msg: e.to_string(),
+                })?,
+            )
+        } else {
+            return Err(ErrorReport::VerifierKey {
+                format: "text".to_owned(),
+                msg: "not a PEM, JWK or OpenSSH key".to_owned(),
+            });
+        };
+        key.public_key()?;
+        Ok(key)
+    }
+}
+
+/// Text keys as for `&str`, anything else is taken as DER
+impl TryFrom<&[u8]> for VerifierKey {
+    type Error = ErrorReport;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if let Ok(text) = std::str::from_utf8(bytes) {
+            if let Ok(key) = VerifierKey::try_from(text) {
+                return Ok(key);
+            }
+        }
+        let key = VerifierKey::Der(bytes.to_vec());
+        key.public_key()?;
+        Ok(key)
+    }
+}
+
+/// Checks a base64url signature of the data; fails with `VerifierKey` when the key cannot be
+/// parsed and with `SignatureInvalid` when the signature does not check out
+pub fn verify_signature(
+    key: &VerifierKey,
+    data: &[u8],
+    signature_b64url: &str,
+    scheme: SignatureScheme,
+) -> Result<(), ErrorReport> {
+    let public = key.public_key()?;
+    let invalid = |msg: String| ErrorReport::SignatureInvalid { msg };
+    let signature = BASE64URL_NOPAD
+        .decode(signature_b64url.trim().trim_end_matches('=').as_bytes())
+        .map_err(|e| invalid(format!("signature is not base64url: {}", e)))?;
+
+    let digest = match scheme {
+        SignatureScheme::RsaPkcs1Sha256 | SignatureScheme::RsaPssSha256 => MessageDigest::sha256(),
+        SignatureScheme::RsaPkcs1Sha512 => MessageDigest::sha512(),
+    };
+    let verified = Verifier::new(digest, &public).and_then(|mut verifier| {
+        if scheme == SignatureScheme::RsaPssSha256 {
+            verifier.set_rsa_padding(Padding::PKCS1_PSS)?;
+            verifier.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)?;
+        }
+        verifier.verify_oneshot(&signature, data)
+    });
+    match verified {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(invalid("signature does not match the data".to_owned())),
+        Err(e) => Err(invalid(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_signature, SignatureScheme, VerifierKey};
+    use crate::{error::ErrorReport, rsakeys::RsaKeys};
+    use data_encoding::BASE64URL_NOPAD;
+    use openssl::{
+        hash::MessageDigest,
+        pkey::PKey,
+        rsa::Padding,
+        sign::{RsaPssSaltlen, Signer},
+    };
+    use std::{fs, path::Path};
+
+    #[test]
+    fn all_key_formats() {
+        let keys_dir = Path::new(env!("WORKSPACE_ROOT_PATH")).join("assets/test/keys");
+        let rsa = RsaKeys::init(
+            "test",
+            keys_dir.join("private.pem"),
+            keys_dir.join("public.pem"),
+        )
+        .unwrap();
+        let private = PKey::from_rsa(rsa.get_private_key().unwrap().clone()).unwrap();
+        let data = br#"{"event":"invoice.paid","id":42}"#;
+        let sign = |scheme: SignatureScheme| {
+            let digest = match scheme {
+                SignatureScheme::RsaPkcs1Sha512 => MessageDigest::sha512(),
+                _ => MessageDigest::sha256(),
+            };
+            let mut signer = Signer::new(digest, &private).unwrap();
+            if scheme == SignatureScheme::RsaPssSha256 {
+                signer.set_rsa_padding(Padding::PKCS1_PSS).unwrap();
+                signer
+                    .set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)
+                    .unwrap();
+            }
+            BASE64URL_NOPAD.encode(&signer.sign_oneshot_to_vec(data).unwrap())
+        };
+
+        let read = |name: &str| fs::read(keys_dir.join(name)).unwrap();
+        let keys = [
+            VerifierKey::try_from(&read("public.pem")[..]).unwrap(),
+            VerifierKey::try_from(&read("public.der")[..]).unwrap(),
+            VerifierKey::try_from(&read("public.jwk.json")[..]).unwrap(),
+            VerifierKey::try_from(String::from_utf8(read("public.ssh")).unwrap().as_str()).unwrap(),
+        ];
+        assert!(matches!(keys[0], VerifierKey::Pem(_)));
+        assert!(matches!(keys[1], VerifierKey::Der(_)));
+        assert!(matches!(keys[2], VerifierKey::Jwk(_)));
+        assert!(matches!(keys[3], VerifierKey::OpenSsh(_)));
+
+        for scheme in [
+            SignatureScheme::RsaPkcs1Sha256,
+            SignatureScheme::RsaPkcs1Sha512,
+            SignatureScheme::RsaPssSha256,
+        ] {
+            let signature = sign(scheme);
+            for key in &keys {
+                verify_signature(key, data, &signature, scheme).unwrap();
+                assert!(matches!(
+                    verify_signature(key, b"tampered", &signature, scheme),
+                    Err(ErrorReport::SignatureInvalid { .. })
+                ));
+            }
+        }
+
+        let signature = sign(SignatureScheme::RsaPkcs1Sha256);
+        match verify_signature(
+            &VerifierKey::OpenSsh("ssh-rsa AAAAB3NzaC1yc2E=".to_owned()),
+            data,
+            &signature,
+            SignatureScheme::RsaPkcs1Sha256,
+        ) {
+            Err(ErrorReport::VerifierKey { format, .. }) => assert_eq!(format, "OpenSSH"),
+            other => panic!("Expected key parse error, got {:?}", other),
+        }
+        assert!(matches!(
+            verify_signature(
+                &keys[0],
+                data,
+                "not base64!",
+                SignatureScheme::RsaPkcs1Sha256
+            ),
+            Err(ErrorReport::SignatureInvalid { .. })
+        ));
+        assert!(matches!(
+            VerifierKey::try_from(serde_json::json!({"kty": "EC"})),
+            Err(ErrorReport::VerifierKey { .. })
+        ));
+    }
+}
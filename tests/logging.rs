@@ -0,0 +1,33 @@
+//! The file logger; a binary of its own, so no other test logs into the file it counts
+#![cfg(feature = "logger")]
+
+use std::{fs, io::BufRead, thread, time};
+use utils::logger::init_logger;
+
+#[test]
+fn logging_async() {
+    let dir_path = env!("TEMP_DIR_PATH");
+    let _logger = init_logger(dir_path).unwrap();
+
+    log::info!("Testing info logging {}", 1);
+    log::warn!("Testing warn logging {}", 2);
+    log::error!("Testing error logging {}", 3);
+
+    thread::sleep(time::Duration::from_secs(3)); //wait for the async logger to finish the job
+
+    let file_path = fs::read_dir(dir_path)
+        .unwrap()
+        .find(|entry| {
+            let entry = entry.as_ref().unwrap();
+            let meta = &entry.metadata().unwrap();
+            meta.is_file() && entry.file_name().to_str().unwrap().ends_with("CURRENT.log")
+        })
+        .unwrap()
+        .unwrap()
+        .path();
+
+    let text = fs::read(&file_path).unwrap();
+    let line_count = text.lines().count();
+    fs::remove_file(&file_path).unwrap();
+    assert_eq!(line_count, 3, "Log file didn't contain all logs");
+}
@@ -0,0 +1,100 @@
+//! The records `Mailer` logs; a binary of its own, since it installs the global logger
+#![cfg(feature = "mailer")]
+
+use lettre::message::Mailbox;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::sync::{Arc, Mutex};
+use utils::mailer::{testing::MockTransport, Config, MailMessage, Mailer};
+
+static RECORDS: Mutex<Vec<(Level, String)>> = Mutex::new(Vec::new());
+
+struct Capture;
+
+impl Log for Capture {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        if record.target().starts_with("utils::mailer") {
+            let line = record.args().to_string();
+            RECORDS.lock().unwrap().push((record.level(), line));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+fn message(to: &str) -> MailMessage<'static> {
+    MailMessage::builder()
+        .to(to.parse::<Mailbox>().unwrap())
+        .cc("ion@example.com".parse().unwrap())
+        .subject("Order 42")
+        .html("<p>Shipped</p>")
+        .language("ro")
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn send_lifecycle() {
+    log::set_logger(&Capture).unwrap();
+    log::set_max_level(LevelFilter::Debug);
+    let builder = || {
+        Config::builder()
+            .from_addrs("noreply@example.com")
+            .server("localhost")
+            .languages(["en", "ro"])
+    };
+    let mock = Arc::new(MockTransport::new());
+    let mailer = Mailer::init_with_transport(builder().build().unwrap(), mock.clone());
+
+    let report = mailer
+        .send_message(message("catalina@example.com"))
+        .unwrap();
+    mock.fail_with(Some(550));
+    let error = mailer
+        .send_message(message("catalina@example.com"))
+        .unwrap_err();
+    let records = std::mem::take(&mut *RECORDS.lock().unwrap());
+    assert_eq!(
+        records,
+        vec![
+            (
+                Level::Debug,
+                "Building mail for 2 recipient(s) in 'ro' with 0 attachment(s)".to_owned()
+            ),
+            (
+                Level::Info,
+                format!(
+                    "Mail {} sent to c***a@example.com, i***n@example.com: 250",
+                    report.message_id
+                )
+            ),
+            (
+                Level::Debug,
+                "Building mail for 2 recipient(s) in 'ro' with 0 attachment(s)".to_owned()
+            ),
+            (
+                Level::Error,
+                format!(
+                    "Mail to c***a@example.com, i***n@example.com not sent: {}",
+                    error
+                )
+            ),
+        ]
+    );
+
+    let unmasked = builder().mask_log_addresses(false).build().unwrap();
+    Mailer::init_with_transport(unmasked, Arc::new(MockTransport::new()))
+        .send_message(message("catalina@example.com"))
+        .unwrap();
+    let records = std::mem::take(&mut *RECORDS.lock().unwrap());
+    assert!(
+        records[1]
+            .1
+            .ends_with("sent to catalina@example.com, ion@example.com: 250"),
+        "{:?}",
+        records
+    );
+}